@@ -0,0 +1,85 @@
+//! Compares running programs with the current folding (see `ExtendedInstr`)
+//! against running them with folding disabled (see `Program::unfolded`). Run
+//! with `cargo +nightly bench`.
+//!
+//! Each benchmark runs a halting program to completion. The throughput that is
+//! reported in MB/s is actually millions of real steps per second, since the
+//! number of "bytes" processed per iteration is set to the number of steps.
+//!
+//! These use libtest's `#[bench]` rather than criterion. The crate already
+//! needs nightly (see lib.rs), so this costs nothing extra, and it keeps the
+//! dependencies down to what the crate itself uses. The folded and unfolded
+//! runs differ by far more than the noise in libtest's measurements.
+#![feature(test)]
+extern crate test;
+
+use std::convert::TryFrom;
+
+use bf_beavers::bf::{ExecutionContext, ExecutionStatus, Program};
+use test::Bencher;
+
+// Mostly "[-]" loops, which are folded into SetToZeroMinus.
+const CLEAR_HEAVY: &str = "-[>-[-]<-]";
+// A loop which moves a value four cells over, so most of its steps are runs of
+// moves, which are folded into Move.
+const MOVE_LOOP: &str = "-[>-[>>>>+<<<<-]<-]";
+// Grows a run of nonzero cells and scans back and forth across it with "[>]"
+// and "[<]", which are not folded.
+const SCAN_HEAVY: &str = "-[>>[>]+[<]<-]";
+// Four loops nested inside each other, with a folded "[-]" innermost.
+const DEEP_NEST: &str = "++++++++[>++++++++[>++++++++[>-[-]<-]<-]<-]";
+
+// Run the program until it halts and return the number of real steps it took.
+fn run(program: &Program) -> usize {
+    let mut ctx = ExecutionContext::new(program);
+    let (_, steps, status) = ctx.run_until(usize::MAX, |_| false);
+    assert_eq!(status, ExecutionStatus::Halted);
+    steps
+}
+
+fn bench_program(b: &mut Bencher, source: &str, folded: bool) {
+    let program = Program::try_from(source).unwrap();
+    let program = if folded { program } else { program.unfolded() };
+    b.bytes = run(&program) as u64;
+    b.iter(|| run(test::black_box(&program)));
+}
+
+#[bench]
+fn clear_heavy_folded(b: &mut Bencher) {
+    bench_program(b, CLEAR_HEAVY, true);
+}
+
+#[bench]
+fn clear_heavy_unfolded(b: &mut Bencher) {
+    bench_program(b, CLEAR_HEAVY, false);
+}
+
+#[bench]
+fn move_loop_folded(b: &mut Bencher) {
+    bench_program(b, MOVE_LOOP, true);
+}
+
+#[bench]
+fn move_loop_unfolded(b: &mut Bencher) {
+    bench_program(b, MOVE_LOOP, false);
+}
+
+#[bench]
+fn scan_heavy_folded(b: &mut Bencher) {
+    bench_program(b, SCAN_HEAVY, true);
+}
+
+#[bench]
+fn scan_heavy_unfolded(b: &mut Bencher) {
+    bench_program(b, SCAN_HEAVY, false);
+}
+
+#[bench]
+fn deep_nest_folded(b: &mut Bencher) {
+    bench_program(b, DEEP_NEST, true);
+}
+
+#[bench]
+fn deep_nest_unfolded(b: &mut Bencher) {
+    bench_program(b, DEEP_NEST, false);
+}
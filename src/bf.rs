@@ -1,38 +1,690 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Display;
+use std::io::Read;
+use std::ops::{Range, RangeInclusive};
+use std::time::{Duration, Instant};
 
 const INITAL_MEMORY: usize = 1;
 const EXTEND_MEMORY_AMOUNT: usize = 1;
 
+// The most iterations of a loop the growing run detector follows before giving
+// up. This is enough for the cells before the run to repeat if only one of them
+// changes, by any amount.
+const GROWING_RUN_MAX_ITERATIONS: usize = 256;
+
+/// The largest count `Program::from_extended` accepts after a Move or
+/// SetToZeroLong glyph. Each is expanded into that many base instructions, so
+/// larger counts would make the program huge.
+pub const MAX_EXTENDED_COUNT: usize = 100_000;
+
 #[derive(Debug, Clone)]
 pub struct ExecutionContext {
-    memory: Vec<u8>,
-    memory_pointer: usize,
+    tape: Tape,
     program: Program,
     program_pointer: usize,
     loop_span_history: LoopSpanHistory,
+    // Profiling information. This is None unless profiling has been enabled.
+    profile: Option<Profile>,
+    detectors: Detectors,
+    // Bytes which have been provided to the program but not yet read by an Input instruction.
+    input: VecDeque<u8>,
+    // Bytes written by Output instructions which have not yet been taken by take_output.
+    output: Vec<u8>,
+    // For each extended instruction, whether it has been executed at least once.
+    executed: Vec<bool>,
+    // The total number of real steps taken so far (see ExtendedInstr::base_step_cost).
+    steps_taken: usize,
+    // The tape and memory pointer when the outer loop currently running was
+    // entered, if any. See Detectors::returned_to_start.
+    start_state: Option<(Vec<u8>, usize)>,
+    // The number of times the loop in Program::growing_run_loops currently
+    // running has jumped back to its start. These loops can't be nested inside
+    // each other, so only one runs at a time. See Detectors::growing_run.
+    growing_run_iterations: usize,
+    hooks: Hooks,
+}
+
+// A callback invoked with the index of a cell, its old value and its new value.
+type CellWriteHook = Box<dyn FnMut(usize, u8, u8) + Send + Sync>;
+// A callback invoked with the old and new memory pointer.
+type PointerMoveHook = Box<dyn FnMut(usize, usize) + Send + Sync>;
+
+// Callbacks registered with ExecutionContext::set_on_cell_write and
+// ExecutionContext::set_on_pointer_move. Hooks are not cloned, so a clone of an
+// ExecutionContext has no hooks.
+#[derive(Default)]
+struct Hooks {
+    on_cell_write: Option<CellWriteHook>,
+    on_pointer_move: Option<PointerMoveHook>,
+}
+
+impl Hooks {
+    fn is_empty(&self) -> bool {
+        self.on_cell_write.is_none() && self.on_pointer_move.is_none()
+    }
+}
+
+impl Clone for Hooks {
+    fn clone(&self) -> Self {
+        Hooks::default()
+    }
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_cell_write", &self.on_cell_write.is_some())
+            .field("on_pointer_move", &self.on_pointer_move.is_some())
+            .finish()
+    }
+}
+
+/// The raw state of an ExecutionContext. See `ExecutionContext::into_parts` and
+/// `ExecutionContext::from_parts`.
+#[derive(Debug, Clone)]
+pub struct ContextParts {
+    pub program: Program,
+    /// An index into `program.extended_instrs()`.
+    pub program_pointer: usize,
+    pub memory: Vec<u8>,
+    pub memory_pointer: usize,
+}
+
+/// Selects how the memory pointer behaves at the edges of the tape, and how
+/// the tape grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TapePolicy {
+    /// The tape is infinite to the right and grows as the pointer moves onto
+    /// new cells. Moving left from the first cell does nothing (the pointer
+    /// saturates at zero). This is the default.
+    #[default]
+    Unbounded,
+    /// The tape is a ring of the given number of cells, so moving left from the
+    /// first cell moves to the last cell and moving right from the last cell
+    /// moves to the first. Since there are only finitely many states, every
+    /// program either halts or is eventually detected as an infinite loop by
+    /// the loop span detector.
+    Ring(usize),
+    /// The tape has exactly the given number of cells. Moving left from the
+    /// first cell or right from the last cell does nothing. Like `Ring`, there
+    /// are only finitely many states.
+    Bounded(usize),
+    /// The tape is infinite in both directions. Moving left from the first
+    /// cell grows the tape to the left, which shifts the index of every cell,
+    /// so `memory_pointer` and `tape` are relative to the leftmost cell visited
+    /// so far rather than the starting cell.
+    TwoWay,
+    /// The tape behaves as the given `TapeEdges` says at its edges. Only the
+    /// detectors which it says are sound are used on such a tape (see
+    /// `TapeEdges::sound_detectors`), and never the loop span or growing run
+    /// detectors.
+    Custom(&'static dyn TapeEdges),
+}
+
+/// How the memory pointer behaves at the edges of a tape. The pointer moves
+/// freely between the cells of the tape, and these methods are only called
+/// for moves which would go past the first or last cell. Every `TapePolicy`
+/// implements this, and `TapePolicy::Custom` uses any other implementation.
+pub trait TapeEdges: std::any::Any + std::fmt::Debug + Sync {
+    /// The number of cells the tape starts out with, which must be at least one.
+    fn initial_length(&self) -> usize {
+        INITAL_MEMORY
+    }
+
+    /// Called when the pointer moves `distance` cells left from `pointer`, which
+    /// is less than `distance`, on a tape of `length` cells.
+    fn on_left(&self, pointer: usize, distance: usize, length: usize) -> EdgeMove;
+
+    /// Called when the pointer moves `distance` cells right from `pointer`,
+    /// which takes it past the last of the tape's `length` cells.
+    fn on_right(&self, pointer: usize, distance: usize, length: usize) -> EdgeMove;
+
+    /// The detectors which can only prove that a program loops if it really
+    /// does on this tape. `ExecutionContext::with_tape_policy` turns off every
+    /// other detector. The default is every detector which doesn't depend on
+    /// how the pointer moves at the edges: loop_if_nonzero and
+    /// returned_to_start. The no-op loop detector is also sound if moving right
+    /// from the last cell always grows the tape, since it already checks that
+    /// the loop can't reach the first cell. The loop span and growing run
+    /// detectors are never used on a custom tape, even if this includes them,
+    /// since they track the pointer the way the built in tapes move it.
+    fn sound_detectors(&self) -> Detectors {
+        Detectors {
+            loop_span: false,
+            no_op_loop: false,
+            growing_run: false,
+            ..Detectors::ALL
+        }
+    }
+}
+
+// Custom tapes are compared by identity, since there is nothing else to compare.
+// Statics of zero sized types can share an address, so the types are compared
+// too.
+impl PartialEq for dyn TapeEdges {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(self, other) && self.type_id() == other.type_id()
+    }
+}
+
+impl Eq for dyn TapeEdges {}
+
+/// What happens when the memory pointer moves past an edge of the tape (see
+/// `TapeEdges`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeMove {
+    /// The pointer stops on the given cell, as if it hit a wall. Stopping at
+    /// the left edge counts as hitting it (see `Tape::hit_left_edge`).
+    Stop(usize),
+    /// The pointer wraps around to the given cell.
+    Wrap(usize),
+    /// The tape grows by enough blank cells for the pointer to move the whole
+    /// distance. Growing on the left shifts the index of every cell, as on a
+    /// `TapePolicy::TwoWay` tape.
+    Grow,
+}
+
+impl TapeEdges for TapePolicy {
+    fn initial_length(&self) -> usize {
+        match self {
+            TapePolicy::Ring(length) | TapePolicy::Bounded(length) => *length,
+            TapePolicy::Unbounded | TapePolicy::TwoWay => INITAL_MEMORY,
+            TapePolicy::Custom(edges) => edges.initial_length(),
+        }
+    }
+
+    fn on_left(&self, pointer: usize, distance: usize, length: usize) -> EdgeMove {
+        match self {
+            TapePolicy::Unbounded | TapePolicy::Bounded(_) => EdgeMove::Stop(0),
+            TapePolicy::Ring(_) => {
+                EdgeMove::Wrap(ring_position(pointer, -(distance as isize), length))
+            }
+            TapePolicy::TwoWay => EdgeMove::Grow,
+            TapePolicy::Custom(edges) => edges.on_left(pointer, distance, length),
+        }
+    }
+
+    fn on_right(&self, pointer: usize, distance: usize, length: usize) -> EdgeMove {
+        match self {
+            TapePolicy::Unbounded | TapePolicy::TwoWay => EdgeMove::Grow,
+            TapePolicy::Ring(_) => {
+                EdgeMove::Wrap(ring_position(pointer, distance as isize, length))
+            }
+            TapePolicy::Bounded(_) => EdgeMove::Stop(length - 1),
+            TapePolicy::Custom(edges) => edges.on_right(pointer, distance, length),
+        }
+    }
+
+    fn sound_detectors(&self) -> Detectors {
+        match self {
+            TapePolicy::Unbounded => Detectors::ALL,
+            // The growing run detector relies on the tape being endless to the
+            // right and stopping at the left edge.
+            TapePolicy::Ring(_) | TapePolicy::TwoWay => Detectors {
+                growing_run: false,
+                ..Detectors::ALL
+            },
+            // A Right does nothing on the last cell, which breaks the no-op loop
+            // analysis the same way a Left on the first cell does.
+            TapePolicy::Bounded(_) => Detectors {
+                no_op_loop: false,
+                growing_run: false,
+                ..Detectors::ALL
+            },
+            TapePolicy::Custom(edges) => Detectors {
+                loop_span: false,
+                growing_run: false,
+                ..edges.sound_detectors()
+            },
+        }
+    }
+}
+
+/// The cells of a tape and the memory pointer into them, which moves according
+/// to a TapePolicy. Every ExecutionContext keeps its tape in one of these.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tape {
+    cells: Vec<u8>,
+    pointer: usize,
+    policy: TapePolicy,
+    // Whether the pointer has tried to move left from the first cell of a tape
+    // which stops it there, such as an Unbounded or Bounded tape.
+    hit_left_edge: bool,
+    // One more than the index of the rightmost cell the pointer has been on.
+    high_water: usize,
+}
+
+impl Tape {
+    /// Create a blank tape which follows `policy`, with the pointer on the
+    /// first cell. Ring and bounded tapes start out with all of their cells.
+    /// Panics if the policy is a ring or bounded tape with no cells, or a custom
+    /// tape whose `initial_length` is zero.
+    pub fn new(policy: TapePolicy) -> Tape {
+        let length = policy.initial_length();
+        assert!(length != 0, "tapes must start out with at least one cell");
+        Tape {
+            policy,
+            ..Tape::with_cells(vec![0; length], 0)
+        }
+    }
+
+    /// Create an unbounded tape which starts out as `cells`, with the pointer at
+    /// `pointer`. The tape is extended with zeros if the pointer is past the
+    /// end of `cells`.
+    pub fn with_cells(mut cells: Vec<u8>, pointer: usize) -> Tape {
+        if pointer >= cells.len() {
+            cells.resize(pointer + EXTEND_MEMORY_AMOUNT, 0);
+        }
+        Tape {
+            cells,
+            pointer,
+            policy: TapePolicy::Unbounded,
+            hit_left_edge: false,
+            high_water: pointer + 1,
+        }
+    }
+
+    /// The cell under the pointer. The pointer is always on the tape, but with
+    /// the checked_tape feature, this is checked with a descriptive panic (see
+    /// `check_on_tape`) instead of an opaque index out of bounds.
+    pub fn get(&self) -> u8 {
+        if cfg!(feature = "checked_tape") {
+            check_on_tape(self.pointer, self.cells.len());
+        }
+        self.cells[self.pointer]
+    }
+
+    /// Set the cell under the pointer.
+    pub fn set(&mut self, value: u8) {
+        if cfg!(feature = "checked_tape") {
+            check_on_tape(self.pointer, self.cells.len());
+        }
+        self.cells[self.pointer] = value;
+    }
+
+    /// Move the pointer `n` cells to the left (see `move_by`, which also
+    /// returns how much the tape grew on the left).
+    pub fn move_left(&mut self, n: usize) {
+        self.move_by(-(n as isize));
+    }
+
+    /// Move the pointer `n` cells to the right (see `move_by`).
+    pub fn move_right(&mut self, n: usize) {
+        self.move_by(n as isize);
+    }
+
+    /// Move the pointer by `offset` cells, which is positive for right and
+    /// negative for left. Moves past either edge of the tape are handled as the
+    /// tape policy says (see `TapeEdges`). Returns the number of cells added to
+    /// the left of the tape, which is only nonzero on a TwoWay tape (or a custom
+    /// tape which grows to the left), where it shifts the index of every cell
+    /// (including the pointer) right by that much.
+    pub fn move_by(&mut self, offset: isize) -> usize {
+        let distance = offset.unsigned_abs();
+        let length = self.cells.len();
+        let mut grown_left = 0;
+        if offset < 0 && self.pointer < distance {
+            match self.policy.on_left(self.pointer, distance, length) {
+                EdgeMove::Stop(pointer) => {
+                    self.hit_left_edge = true;
+                    self.pointer = pointer;
+                }
+                EdgeMove::Wrap(pointer) => self.pointer = pointer,
+                EdgeMove::Grow => {
+                    grown_left = (distance - self.pointer).max(EXTEND_MEMORY_AMOUNT);
+                    self.cells.splice(0..0, vec![0; grown_left]);
+                    self.pointer = self.pointer + grown_left - distance;
+                    self.high_water += grown_left;
+                }
+            }
+        } else if offset > 0 && self.pointer + distance >= length {
+            match self.policy.on_right(self.pointer, distance, length) {
+                EdgeMove::Stop(pointer) | EdgeMove::Wrap(pointer) => self.pointer = pointer,
+                EdgeMove::Grow => {
+                    self.pointer += distance;
+                    let extend_amount = (self.pointer + 1 - length).max(EXTEND_MEMORY_AMOUNT);
+                    self.cells.resize(length + extend_amount, 0);
+                }
+            }
+        } else if offset < 0 {
+            self.pointer -= distance;
+        } else {
+            self.pointer += distance;
+        }
+        self.high_water = self.high_water.max(self.pointer + 1);
+        grown_left
+    }
+
+    /// The index of the cell under the pointer.
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn policy(&self) -> TapePolicy {
+        self.policy
+    }
+
+    /// Every cell allocated so far.
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+
+    /// Every cell allocated so far, mutably. The tape can't be resized
+    /// through this.
+    pub fn cells_mut(&mut self) -> &mut [u8] {
+        &mut self.cells
+    }
+
+    /// Returns the cells up to and including the last nonzero cell. This is
+    /// empty if every cell is zero. Note that the pointer may point past the
+    /// end of the trimmed cells.
+    pub fn trimmed(&self) -> &[u8] {
+        let length = self
+            .cells
+            .iter()
+            .rposition(|&cell| cell != 0)
+            .map_or(0, |last_nonzero| last_nonzero + 1);
+        &self.cells[..length]
+    }
+
+    /// The number of cells allocated so far.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Returns true if no cells are allocated, which never happens for a tape
+    /// created by `new`.
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// One more than the index of the rightmost cell the pointer has been on,
+    /// which is how much of the tape the program has actually visited. This
+    /// can be less than `len` if the tape started out longer, such as a ring
+    /// tape or one created by `with_cells`.
+    pub fn high_water(&self) -> usize {
+        self.high_water
+    }
+
+    /// Returns true if the pointer has tried to move left from the first cell
+    /// of a tape which stops it there (see `EdgeMove::Stop`), such as an
+    /// Unbounded or Bounded tape.
+    pub fn hit_left_edge(&self) -> bool {
+        self.hit_left_edge
+    }
+
+    /// Returns the number of nonzero cells.
+    pub fn nonzero_cells(&self) -> usize {
+        self.cells.iter().filter(|&&cell| cell != 0).count()
+    }
+}
+
+/// Selects which methods an ExecutionContext uses to detect that a program
+/// will never halt. Disabling a detector never causes a halting program to be
+/// reported as non-halting, but may cause non-halting programs to run forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detectors {
+    /// Detect LoopIfNonzero instructions executed on a nonzero cell.
+    pub loop_if_nonzero: bool,
+    /// Detect repeated loop spans. See LOOP_SPAN.md for more information.
+    pub loop_span: bool,
+    /// Detect loops whose body statically leaves the tape and memory pointer
+    /// unchanged, such as "[>+<-+]", as soon as they are entered.
+    pub no_op_loop: bool,
+    /// Detect a program which jumps back to the start of an outer loop (one
+    /// which isn't inside any other loop) with the tape and memory pointer
+    /// exactly as they were when the loop was entered. This costs a copy of
+    /// the tape each time an outer loop is entered, and a single comparison
+    /// per iteration of it.
+    pub returned_to_start: bool,
+    /// Detect a loop which, like "+++[>>[>]+[<]<--]", keeps adding to a run of
+    /// nonzero cells at the end of the tape in a way that can never make it
+    /// exit. Only loops whose inner loops are scans such as "[>]" and "[<]" are
+    /// checked, after their 1st, 2nd, 4th, 8th... iteration. See "Loops which
+    /// Count Down while the Tape Grows" in LOOP_SPAN.md.
+    pub growing_run: bool,
+    /// When comparing loop spans, don't compare the exact value of the cell the
+    /// loop started on (which is always nonzero), in the narrow cases where the
+    /// value can't affect whether the loop halts. See "Ignoring the Guard Cell"
+    /// in LOOP_SPAN.md. This only has an effect if `loop_span` is also on. It
+    /// is opt-in, so it is off even in `ALL`.
+    pub guard_insensitive_loop_span: bool,
+}
+
+impl Detectors {
+    /// Every detector, except for the opt-in `guard_insensitive_loop_span`.
+    pub const ALL: Detectors = Detectors {
+        loop_if_nonzero: true,
+        loop_span: true,
+        no_op_loop: true,
+        returned_to_start: true,
+        growing_run: true,
+        guard_insensitive_loop_span: false,
+    };
+
+    pub const NONE: Detectors = Detectors {
+        loop_if_nonzero: false,
+        loop_span: false,
+        no_op_loop: false,
+        returned_to_start: false,
+        growing_run: false,
+        guard_insensitive_loop_span: false,
+    };
+
+    /// The detectors which are on in both `self` and `other`.
+    pub fn intersection(self, other: Detectors) -> Detectors {
+        Detectors {
+            loop_if_nonzero: self.loop_if_nonzero && other.loop_if_nonzero,
+            loop_span: self.loop_span && other.loop_span,
+            no_op_loop: self.no_op_loop && other.no_op_loop,
+            returned_to_start: self.returned_to_start && other.returned_to_start,
+            growing_run: self.growing_run && other.growing_run,
+            guard_insensitive_loop_span: self.guard_insensitive_loop_span
+                && other.guard_insensitive_loop_span,
+        }
+    }
+}
+
+impl Default for Detectors {
+    fn default() -> Self {
+        Detectors::ALL
+    }
+}
+
+// Extra statistics collected about a program's execution.
+#[derive(Debug, Clone, Default)]
+struct Profile {
+    // Maps the extended instruction index of a loop to the number of iterations of that loop
+    loop_iteration_counts: HashMap<usize, usize>,
+}
+
+impl Profile {
+    fn record_loop_iterations(&mut self, loop_index: usize, iterations: usize) {
+        *self.loop_iteration_counts.entry(loop_index).or_insert(0) += iterations;
+    }
 }
 
 impl ExecutionContext {
     pub fn new(program: &Program) -> ExecutionContext {
         ExecutionContext {
-            memory: vec![0; INITAL_MEMORY],
-            memory_pointer: 0,
+            tape: Tape::new(TapePolicy::Unbounded),
             program_pointer: 0,
             program: program.clone(),
             loop_span_history: LoopSpanHistory::new(program),
+            profile: None,
+            detectors: Detectors::ALL,
+            input: VecDeque::new(),
+            output: vec![],
+            executed: vec![false; program.extended_instrs.len()],
+            steps_taken: 0,
+            start_state: None,
+            growing_run_iterations: 0,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Create an ExecutionContext whose Input instructions read from `input`.
+    /// The input is read until EOF before this returns, so this blocks on
+    /// streams such as stdin. For interactive use, create the context with
+    /// `new` and provide input incrementally with `push_input` instead.
+    pub fn with_input(
+        program: &Program,
+        mut input: impl Read,
+    ) -> std::io::Result<ExecutionContext> {
+        let mut buffer = vec![];
+        input.read_to_end(&mut buffer)?;
+        let mut ctx = ExecutionContext::new(program);
+        ctx.push_input(&buffer);
+        Ok(ctx)
+    }
+
+    /// Provide more input to the program. This is appended after any input
+    /// which has not been read yet.
+    pub fn push_input(&mut self, input: &[u8]) {
+        self.input.extend(input);
+    }
+
+    /// Return all output written by the program since the last call to
+    /// `take_output`, and clear the output buffer.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Returns the output written by the program since the last call to
+    /// `take_output` as text, without clearing it. Bytes which aren't valid
+    /// UTF-8 are shown as the replacement character U+FFFD.
+    pub fn output_as_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    /// Register a callback which is called with `(index, old, new)` whenever a
+    /// step changes the value of a cell. Replaces any previous callback.
+    pub fn set_on_cell_write(&mut self, hook: impl FnMut(usize, u8, u8) + Send + Sync + 'static) {
+        self.hooks.on_cell_write = Some(Box::new(hook));
+    }
+
+    /// Register a callback which is called with `(old, new)` whenever a step
+    /// moves the memory pointer. Replaces any previous callback.
+    pub fn set_on_pointer_move(&mut self, hook: impl FnMut(usize, usize) + Send + Sync + 'static) {
+        self.hooks.on_pointer_move = Some(Box::new(hook));
+    }
+
+    /// Create an ExecutionContext which only uses the given detectors.
+    /// If the LoopIfNonzero detector is disabled, "[]" is executed as an
+    /// ordinary loop instead of being compiled to a LoopIfNonzero instruction,
+    /// so the other detectors can still catch it. This means `program()` of
+    /// the returned context may have different extended instructions than
+    /// `program`.
+    pub fn with_detectors(program: &Program, detectors: Detectors) -> ExecutionContext {
+        let program = if detectors.loop_if_nonzero {
+            program.clone()
+        } else {
+            program.without_loop_if_nonzero()
+        };
+        ExecutionContext {
+            detectors,
+            ..ExecutionContext::new(&program)
         }
     }
 
     pub fn with_memory(program: Program, memory: Vec<u8>) -> ExecutionContext {
+        ExecutionContext::with_memory_and_pointer(program, memory, 0)
+    }
+
+    /// Create an ExecutionContext whose tape starts out as `memory`, with the
+    /// memory pointer at `pointer`. The tape is extended with zeros if the
+    /// pointer is past the end of `memory`. Execution still starts at the first
+    /// instruction, and loop spans are recorded from whatever the tape holds
+    /// when each loop is entered, so detection is unaffected by the initial
+    /// tape contents.
+    pub fn with_memory_and_pointer(
+        program: Program,
+        memory: Vec<u8>,
+        pointer: usize,
+    ) -> ExecutionContext {
         let loop_span_history = LoopSpanHistory::new(&program);
+        let executed = vec![false; program.extended_instrs.len()];
         ExecutionContext {
-            memory,
-            memory_pointer: 0,
+            tape: Tape::with_cells(memory, pointer),
             program_pointer: 0,
             program,
             loop_span_history,
+            profile: None,
+            detectors: Detectors::ALL,
+            input: VecDeque::new(),
+            output: vec![],
+            executed,
+            steps_taken: 0,
+            start_state: None,
+            growing_run_iterations: 0,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Create an ExecutionContext from raw state, such as state returned by
+    /// `into_parts` or provided by another tool. The loop span history starts
+    /// out empty, as in `clone_light`. The tape is extended with zeros if the
+    /// memory pointer is past the end of `memory`. Panics if the program
+    /// pointer is past the end of `program.extended_instrs()`.
+    pub fn from_parts(parts: ContextParts) -> ExecutionContext {
+        let ContextParts {
+            program,
+            program_pointer,
+            memory,
+            memory_pointer,
+        } = parts;
+        assert!(
+            program_pointer <= program.extended_instrs.len(),
+            "program pointer {} is past the end of the program",
+            program_pointer
+        );
+        let mut ctx = ExecutionContext::with_memory_and_pointer(program, memory, memory_pointer);
+        ctx.program_pointer = program_pointer;
+        ctx
+    }
+
+    /// Take apart this ExecutionContext into its raw state. Everything else,
+    /// such as the loop span history, is discarded.
+    pub fn into_parts(self) -> ContextParts {
+        ContextParts {
+            program: self.program,
+            program_pointer: self.program_pointer,
+            memory: self.tape.cells,
+            memory_pointer: self.tape.pointer,
+        }
+    }
+
+    /// Create an ExecutionContext whose tape follows the given policy. Panics if
+    /// the policy is a ring or bounded tape with no cells. Only the detectors
+    /// which are sound on the tape are turned on (see
+    /// `TapeEdges::sound_detectors`).
+    pub fn with_tape_policy(program: &Program, tape_policy: TapePolicy) -> ExecutionContext {
+        let mut ctx = ExecutionContext::new(program);
+        ctx.tape = Tape::new(tape_policy);
+        ctx.loop_span_history.tape_policy = tape_policy;
+        ctx.detectors = ctx.detectors.intersection(tape_policy.sound_detectors());
+        ctx
+    }
+
+    /// Keep at most `max_past_spans` past loop spans for each loop, or any
+    /// number if it is None, which is the default. Without a limit, a loop which
+    /// runs many iterations before repeating a span stores a span for each of
+    /// them, and compares each new span against all of them. With a limit, only
+    /// the most recent spans are kept, which bounds the memory and time used,
+    /// but an infinite loop whose spans only repeat after more iterations than
+    /// that is never detected by the loop span detector. The oldest spans are
+    /// forgotten immediately if there are already too many.
+    pub fn set_max_past_spans(&mut self, max_past_spans: Option<usize>) {
+        self.loop_span_history.set_max_past_spans(max_past_spans);
+    }
+
+    /// Start collecting profiling information, such as loop iteration counts.
+    /// This is off by default, since it slows down execution. Only steps taken
+    /// after this is called are counted.
+    pub fn enable_profiling(&mut self) {
+        if self.profile.is_none() {
+            self.profile = Some(Profile::default());
         }
     }
 
@@ -44,9 +696,69 @@ impl ExecutionContext {
         }
 
         let instruction = instruction.unwrap();
+        // A StartLoop is only executed when the loop is entered, since jumping
+        // back to a loop lands on the first instruction of its body.
+        if self.detectors.returned_to_start
+            && instruction == ExtendedInstr::BaseInstr(Instr::StartLoop)
+            && self.cell() != 0
+            && !self.program.reads_input
+            && self.program.outer_loops.contains(&self.program_pointer)
+        {
+            self.start_state = Some((self.tape.cells.clone(), self.tape.pointer));
+        }
+        if self.detectors.growing_run
+            && instruction == ExtendedInstr::BaseInstr(Instr::StartLoop)
+            && self.cell() != 0
+            && self
+                .program
+                .growing_run_loops
+                .contains(&self.program_pointer)
+        {
+            self.growing_run_iterations = 0;
+        }
+        self.executed[self.program_pointer] = true;
+
+        let cell = self.cell();
+        if let Some(profile) = &mut self.profile {
+            match instruction {
+                ExtendedInstr::BaseInstr(Instr::StartLoop) if cell != 0 => {
+                    profile.record_loop_iterations(self.program_pointer, 1);
+                }
+                ExtendedInstr::BaseInstr(Instr::EndLoop) if cell != 0 => {
+                    let start_loop = self
+                        .program
+                        .matching_loop(self.program_pointer)
+                        .expect("missing EndLoop dict entry!");
+                    profile.record_loop_iterations(start_loop, 1);
+                }
+                // Folded loops run all of their iterations in a single step.
+                // The body of "[-]" runs once per unit of the cell value.
+                ExtendedInstr::SetToZeroMinus if cell != 0 => {
+                    let iterations = cell as usize;
+                    profile.record_loop_iterations(self.program_pointer, iterations);
+                }
+                ExtendedInstr::SetToZeroPlus if cell != 0 => {
+                    let iterations = 0_u8.wrapping_sub(cell) as usize;
+                    profile.record_loop_iterations(self.program_pointer, iterations);
+                }
+                ExtendedInstr::SetToZeroLong { increment, .. } if cell != 0 => {
+                    let iterations = clear_iterations(increment, cell);
+                    profile.record_loop_iterations(self.program_pointer, iterations);
+                }
+                // A taken "[]" is entered once before the infinite loop is detected.
+                ExtendedInstr::LoopIfNonzero if cell != 0 => {
+                    profile.record_loop_iterations(self.program_pointer, 1);
+                }
+                _ => (),
+            }
+        }
 
         // First, update the loop-spans, checking if the loop span history detects an infinite loop
         let maybe_loop_reason = match instruction {
+            // Skip recording loop spans entirely if the detector is disabled.
+            // Loop spans only look at the memory, so they also can't be used if the program
+            // reads input, since the input affects execution too.
+            _ if !self.detectors.loop_span || self.program.reads_input => None,
             ExtendedInstr::BaseInstr(instruction) => match instruction {
                 Instr::Left => {
                     self.loop_span_history.record_left();
@@ -56,12 +768,16 @@ impl ExecutionContext {
                     self.loop_span_history.record_right();
                     None
                 }
+                Instr::Plus | Instr::Minus if self.detectors.guard_insensitive_loop_span => {
+                    self.loop_span_history.record_write(self.tape.pointer);
+                    None
+                }
                 // StartLoop taken. Start recording a loop span.
-                Instr::StartLoop if self.memory[self.memory_pointer] != 0 => {
+                Instr::StartLoop if self.cell() != 0 => {
                     let start_loop = self.program_pointer;
                     self.loop_span_history.start_recording_loop_span(
-                        self.memory.clone(),
-                        self.memory_pointer,
+                        self.tape.cells.clone(),
+                        self.tape.pointer,
                         start_loop,
                     );
                     None
@@ -69,17 +785,19 @@ impl ExecutionContext {
                 // StartLoop not taken. (Ignored, nothing special happens for this)
                 Instr::StartLoop => None,
                 // EndLoop taken, stop the old loop-span recording and start a new one
-                Instr::EndLoop if self.memory[self.memory_pointer] != 0 => {
+                Instr::EndLoop if self.cell() != 0 => {
                     let start_loop = self
                         .program
                         .matching_loop(self.program_pointer)
                         .expect("missing EndLoop dict entry!");
 
-                    let check_span_result =
-                        self.loop_span_history.end_recording_loop_span(start_loop);
+                    let check_span_result = self.loop_span_history.end_recording_loop_span(
+                        start_loop,
+                        self.detectors.guard_insensitive_loop_span,
+                    );
                     self.loop_span_history.start_recording_loop_span(
-                        self.memory.clone(),
-                        self.memory_pointer,
+                        self.tape.cells.clone(),
+                        self.tape.pointer,
                         start_loop,
                     );
 
@@ -98,38 +816,80 @@ impl ExecutionContext {
                         .matching_loop(self.program_pointer)
                         .expect("missing EndLoop dict entry!");
 
-                    self.loop_span_history.end_recording_loop_span(start_loop);
+                    self.loop_span_history
+                        .end_recording_loop_span(start_loop, false);
                     self.loop_span_history.reset_past_loop_spans(start_loop);
                     None
                 }
                 _ => None,
             },
+            ExtendedInstr::Move(offset) => {
+                self.loop_span_history.record_move(offset);
+                None
+            }
+            ExtendedInstr::SetToZeroPlus
+            | ExtendedInstr::SetToZeroMinus
+            | ExtendedInstr::SetToZeroLong { .. }
+                if self.detectors.guard_insensitive_loop_span =>
+            {
+                self.loop_span_history.record_write(self.tape.pointer);
+                None
+            }
             _ => None,
         };
 
-        let (steps_run, status) = match instruction {
+        let steps_run = instruction.base_step_cost(self.cell());
+        // Step counts saturate instead of wrapping, so that an overflowing program
+        // is still reported as the longest running program rather than a short one.
+        self.steps_taken = self.steps_taken.saturating_add(steps_run);
+
+        // Entering a loop whose body does nothing means the loop never exits.
+        // (This detector is off on tapes where it isn't sound, such as bounded
+        // tapes; see TapeEdges::sound_detectors.)
+        if self.detectors.no_op_loop
+            && instruction == ExtendedInstr::BaseInstr(Instr::StartLoop)
+            && self.cell() != 0
+            && self
+                .program
+                .no_op_loops
+                .get(&self.program_pointer)
+                .is_some_and(|&min_pointer| self.tape.pointer >= min_pointer)
+        {
+            return (
+                steps_run,
+                ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop),
+            );
+        }
+        // No instruction both writes a cell and moves the pointer, so the cell
+        // under the pointer is the only one which can change.
+        let before_hooks = if self.hooks.is_empty() {
+            None
+        } else {
+            Some((self.tape.pointer, self.tape.get()))
+        };
+        // The number of cells added to the left of the tape by this step.
+        let mut grown_left = 0;
+        let status = match instruction {
             ExtendedInstr::BaseInstr(instruction) => {
                 // Now actually execute the instruction
                 match instruction {
                     Instr::Plus => {
-                        self.memory[self.memory_pointer] =
-                            self.memory[self.memory_pointer].wrapping_add(1);
+                        self.tape.set(self.tape.get().wrapping_add(1));
                     }
                     Instr::Minus => {
-                        self.memory[self.memory_pointer] =
-                            self.memory[self.memory_pointer].wrapping_sub(1);
-                    }
-                    Instr::Left => {
-                        self.memory_pointer = self.memory_pointer.saturating_sub(1);
+                        self.tape.set(self.tape.get().wrapping_sub(1));
                     }
-                    Instr::Right => {
-                        self.memory_pointer += 1;
-                        if self.memory_pointer >= self.memory.len() {
-                            self.memory.extend([0; EXTEND_MEMORY_AMOUNT].iter());
+                    Instr::Left => grown_left = self.move_pointer(-1),
+                    Instr::Right => grown_left = self.move_pointer(1),
+                    Instr::Output => self.output.push(self.cell()),
+                    // If there is no more input, the cell is left unchanged.
+                    Instr::Input => {
+                        if let Some(byte) = self.input.pop_front() {
+                            self.tape.set(byte);
                         }
                     }
                     // StartLoop not taken -- Jump past corresponding EndLoop
-                    Instr::StartLoop if self.memory[self.memory_pointer] == 0 => {
+                    Instr::StartLoop if self.cell() == 0 => {
                         let start_loop = self.program_pointer;
                         let end_loop = self
                             .program
@@ -138,7 +898,7 @@ impl ExecutionContext {
                         self.program_pointer = end_loop;
                     }
                     // EndLoop taken -- Jump past corresponding StartLoop
-                    Instr::EndLoop if self.memory[self.memory_pointer] != 0 => {
+                    Instr::EndLoop if self.cell() != 0 => {
                         let start_loop = self
                             .program
                             .matching_loop(self.program_pointer)
@@ -147,29 +907,70 @@ impl ExecutionContext {
                     }
                     _ => (),
                 }
-                (1, ExecutionStatus::Running)
+                ExecutionStatus::Running
             }
             ExtendedInstr::LoopIfNonzero => {
-                if self.memory[self.memory_pointer] == 0 {
-                    (1, ExecutionStatus::Running)
+                if self.cell() == 0 {
+                    ExecutionStatus::Running
                 } else {
                     // If we execute the loop, then immediately return--this is a static loop.
-                    return (2, ExecutionStatus::InfiniteLoop(LoopReason::LoopIfNonzero));
+                    return (
+                        steps_run,
+                        ExecutionStatus::InfiniteLoop(LoopReason::LoopIfNonzero),
+                    );
                 }
             }
-            ExtendedInstr::SetToZeroPlus => {
-                let steps_run =
-                    1 + 2 * (0_u8.wrapping_sub(self.memory[self.memory_pointer]) as usize);
-                self.memory[self.memory_pointer] = 0;
-                (steps_run, ExecutionStatus::Running)
+            ExtendedInstr::SetToZeroPlus
+            | ExtendedInstr::SetToZeroMinus
+            | ExtendedInstr::SetToZeroLong { .. } => {
+                self.tape.set(0);
+                ExecutionStatus::Running
             }
-            ExtendedInstr::SetToZeroMinus => {
-                let steps_run = 1 + 2 * self.memory[self.memory_pointer] as usize;
-                self.memory[self.memory_pointer] = 0;
-                (steps_run, ExecutionStatus::Running)
+            ExtendedInstr::Move(offset) => {
+                grown_left = self.move_pointer(offset);
+                ExecutionStatus::Running
+            }
+        };
+        // Jumping back to an outer loop is the same as entering it again if the
+        // tape and pointer are unchanged, and since the program does not read
+        // input, it will do exactly the same thing again.
+        let status = if instruction == ExtendedInstr::BaseInstr(Instr::EndLoop)
+            && self.program.outer_loops.contains(&self.program_pointer)
+            && self.is_start_state()
+        {
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+        } else {
+            status
+        };
+        // A taken EndLoop leaves the program pointer on its StartLoop. Checking
+        // only after the 1st, 2nd, 4th... iteration keeps the cost of checking
+        // low for loops which run for a long time.
+        let status = if status.is_running()
+            && self.detectors.growing_run
+            && instruction == ExtendedInstr::BaseInstr(Instr::EndLoop)
+            && self.cell() != 0
+            && self
+                .program
+                .growing_run_loops
+                .contains(&self.program_pointer)
+        {
+            self.growing_run_iterations += 1;
+            if self.growing_run_iterations.is_power_of_two()
+                && self.grows_run_forever(self.program_pointer)
+            {
+                ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun)
+            } else {
+                status
             }
+        } else {
+            status
         };
 
+        if let Some((old_pointer, old_cell)) = before_hooks {
+            // The cell the pointer was on moves right if the tape grew to the left.
+            self.run_hooks(old_pointer + grown_left, old_cell);
+        }
+
         // Finally, increment the program counter and check if the program halted.
         self.program_pointer += 1;
         if self.program.get(self.program_pointer).is_none() {
@@ -183,6 +984,133 @@ impl ExecutionContext {
         }
     }
 
+    /// Step until `predicate` returns true after a step, the program halts or is
+    /// detected as an infinite loop, or `max_steps` calls to `step` have been
+    /// made. Returns the number of calls to `step`, the total number of real
+    /// steps run, and the status returned by the last step. For example, to run
+    /// until the tape grows:
+    /// ```
+    /// # use bf_beavers::bf::{ExecutionContext, Program};
+    /// # use std::convert::TryFrom;
+    /// let mut ctx = ExecutionContext::new(&Program::try_from("+[>+]").unwrap());
+    /// let tape_length = ctx.tape_length();
+    /// let (steps, _, _) = ctx.run_until(1000, |ctx| ctx.tape_length() > tape_length);
+    /// assert_eq!(steps, 3);
+    /// ```
+    pub fn run_until(
+        &mut self,
+        max_steps: usize,
+        mut predicate: impl FnMut(&ExecutionContext) -> bool,
+    ) -> (usize, usize, ExecutionStatus) {
+        let mut total_real_steps: usize = 0;
+        let mut status = ExecutionStatus::Running;
+        for i in 1..=max_steps {
+            let (real_steps, new_status) = self.step();
+            total_real_steps = total_real_steps.saturating_add(real_steps);
+            status = new_status;
+            if !status.is_running() || predicate(self) {
+                return (i, total_real_steps, status);
+            }
+        }
+        (max_steps, total_real_steps, status)
+    }
+
+    // Returns true if the tape and memory pointer are the same as when the
+    // current outer loop was entered. Cells allocated since then must still be
+    // zero.
+    fn is_start_state(&self) -> bool {
+        match &self.start_state {
+            Some((memory, memory_pointer)) => {
+                *memory_pointer == self.tape.pointer
+                    && self.tape.cells.starts_with(memory)
+                    && self.tape.cells[memory.len()..]
+                        .iter()
+                        .all(|&cell| cell == 0)
+            }
+            None => false,
+        }
+    }
+
+    // Returns true if the loop at `start_loop`, which is in
+    // Program::growing_run_loops and has just jumped back to its start, can
+    // never exit. The tape is treated as its cells up to the last run of
+    // nonzero cells, followed by that run with any length and nonzero values
+    // (see RunTape). If running the body on such a tape always ends back before
+    // the run with a nonzero cell under the pointer, and the cells before the
+    // run repeat, then every iteration after this one behaves the same way.
+    fn grows_run_forever(&self, start_loop: usize) -> bool {
+        let end_loop = self.program.loop_dict[&start_loop];
+        let body = &self.program.extended_instrs[start_loop + 1..end_loop];
+        let cells = &self.tape.cells;
+        let pointer = self.tape.pointer;
+        // The run is the last run of nonzero cells on the tape. The pointer has
+        // to be before it, so if the run reaches back to the pointer, the run
+        // starts out empty after the last nonzero cell instead.
+        let last_nonzero = match cells.iter().rposition(|&cell| cell != 0) {
+            Some(last_nonzero) => last_nonzero,
+            None => return false,
+        };
+        let run_start = cells[..last_nonzero]
+            .iter()
+            .rposition(|&cell| cell == 0)
+            .map_or(0, |zero| zero + 1);
+        let run_start = if run_start > pointer {
+            run_start
+        } else {
+            last_nonzero + 1
+        };
+
+        let mut tape = RunTape {
+            prefix: cells[..run_start].to_vec(),
+            suffix: vec![],
+            pointer: RunPointer::Prefix(pointer),
+        };
+        let mut seen = HashSet::from([(tape.prefix.clone(), tape.pointer)]);
+        for _ in 0..GROWING_RUN_MAX_ITERATIONS {
+            if tape.run_iteration(body).is_none() {
+                return false;
+            }
+            if !seen.insert((tape.prefix.clone(), tape.pointer)) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // The cell under the memory pointer.
+    fn cell(&self) -> u8 {
+        self.tape.get()
+    }
+
+    // Call the hooks for whatever changed since the pointer was at `old_pointer`
+    // and the cell there held `old_cell`.
+    fn run_hooks(&mut self, old_pointer: usize, old_cell: u8) {
+        let new_cell = self.tape.cells[old_pointer];
+        match &mut self.hooks.on_cell_write {
+            Some(hook) if new_cell != old_cell => hook(old_pointer, old_cell, new_cell),
+            _ => (),
+        }
+        match &mut self.hooks.on_pointer_move {
+            Some(hook) if self.tape.pointer != old_pointer => hook(old_pointer, self.tape.pointer),
+            _ => (),
+        }
+    }
+
+    // Move the memory pointer according to the tape policy (see
+    // `Tape::move_by`). Returns the number of cells added to the left of the
+    // tape, which is only nonzero on a TwoWay tape.
+    fn move_pointer(&mut self, offset: isize) -> usize {
+        let grown_left = self.tape.move_by(offset);
+        if grown_left != 0 {
+            // Every recorded loop span refers to cells by their old indices, so
+            // detection has to start over. The start state does too, so it is
+            // forgotten until the next outer loop is entered.
+            self.loop_span_history.clear();
+            self.start_state = None;
+        }
+        grown_left
+    }
+
     /// Returns the program indicies of the StartLoop and EndLoop instructions of
     /// the innermost loop that program pointer is currently inside. If execution
     /// is not in any loops, then this returns None.
@@ -210,30 +1138,780 @@ impl ExecutionContext {
         self.program_pointer
     }
 
+    /// Returns the range of indices into `program().original_instrs()` covered
+    /// by the instruction at the program pointer. Unlike `program_pointer`, this
+    /// can be used to find the current instruction in the original source. Returns
+    /// None if the program has halted.
+    pub fn program_pointer_to_source(&self) -> Option<Range<usize>> {
+        self.program.source_span(self.program_pointer)
+    }
+
+    /// Returns the byte offset of the instruction at the program pointer in the
+    /// program's text (see `Program::text_offset`). For a program compiled with
+    /// `Program::from_source`, this is an offset into the original source,
+    /// comments included. Returns None if the program has halted.
+    pub fn program_text_pointer(&self) -> Option<usize> {
+        self.program.text_offset(self.program_pointer)
+    }
+
+    /// Returns true if the program has tried to move left from the first cell
+    /// of the tape, which does nothing on `TapePolicy::Unbounded`. On a tape
+    /// which is infinite in both directions, such a program could behave
+    /// differently, so its behavior depends on the tape model.
+    pub fn hit_left_edge(&self) -> bool {
+        self.tape.hit_left_edge()
+    }
+
     pub fn memory_pointer(&self) -> usize {
-        self.memory_pointer
+        self.tape.pointer()
     }
 
-    pub fn tape(&self) -> &[u8] {
-        &self.memory
+    /// The total number of real steps taken by every call to `step` so far,
+    /// saturating at usize::MAX. This is the sum of the steps returned by
+    /// `step`, not the number of calls, so folded instructions such as "[-]"
+    /// count for more than one step.
+    pub fn steps_taken(&self) -> usize {
+        self.steps_taken
     }
 
-    pub fn program(&self) -> &Program {
-        &self.program
+    /// If the program can be shown to halt from here without entering a loop,
+    /// returns the total number of real steps it will have taken when it halts
+    /// (the value of `steps_taken` then). Otherwise, returns None.
+    ///
+    /// This runs through the rest of the program once, skipping any loop whose
+    /// guard is zero when it is reached, and gives up at the first loop which
+    /// would be entered or jumped back to, so it never takes more than one pass
+    /// over the program. This resolves more programs than `Program::is_loop_free`
+    /// does, since loops which are never entered don't stop a program from
+    /// halting trivially. It is conservative: a program which halts only after
+    /// running a loop gets None, even if that loop is a folded one like "[-]".
+    pub fn can_halt_trivially(&self) -> Option<usize> {
+        self.trivial_halt().map(|(steps, _, _)| steps)
     }
 
-    pub fn loop_span_history(&self) -> &LoopSpanHistory {
-        &self.loop_span_history
+    // Like `can_halt_trivially`, but also returns the number of calls to `step`
+    // needed to halt and the final tape length.
+    fn trivial_halt(&self) -> Option<(usize, usize, usize)> {
+        let mut tape = self.tape.clone();
+        let mut input = self.input.iter();
+        let mut steps = self.steps_taken;
+        let mut program_pointer = self.program_pointer;
+        let mut calls = 0;
+        while let Some(instruction) = self.program.get(program_pointer) {
+            let cell = tape.get();
+            match instruction {
+                ExtendedInstr::BaseInstr(Instr::Plus) => tape.set(cell.wrapping_add(1)),
+                ExtendedInstr::BaseInstr(Instr::Minus) => tape.set(cell.wrapping_sub(1)),
+                ExtendedInstr::BaseInstr(Instr::Left) => tape.move_left(1),
+                ExtendedInstr::BaseInstr(Instr::Right) => tape.move_right(1),
+                ExtendedInstr::BaseInstr(Instr::Output) => (),
+                ExtendedInstr::BaseInstr(Instr::Input) => {
+                    if let Some(&byte) = input.next() {
+                        tape.set(byte);
+                    }
+                }
+                ExtendedInstr::Move(offset) => {
+                    tape.move_by(offset);
+                }
+                ExtendedInstr::BaseInstr(Instr::StartLoop) if cell == 0 => {
+                    program_pointer = self
+                        .program
+                        .matching_loop(program_pointer)
+                        .expect("missing StartLoop dict entry!");
+                }
+                // Folded loops which aren't entered do nothing, as does
+                // falling out of the loop execution started in.
+                ExtendedInstr::BaseInstr(Instr::EndLoop)
+                | ExtendedInstr::LoopIfNonzero
+                | ExtendedInstr::SetToZeroPlus
+                | ExtendedInstr::SetToZeroMinus
+                | ExtendedInstr::SetToZeroLong { .. }
+                    if cell == 0 => {}
+                // A loop would run.
+                ExtendedInstr::BaseInstr(Instr::StartLoop | Instr::EndLoop)
+                | ExtendedInstr::LoopIfNonzero
+                | ExtendedInstr::SetToZeroPlus
+                | ExtendedInstr::SetToZeroMinus
+                | ExtendedInstr::SetToZeroLong { .. } => return None,
+            }
+            steps = steps.saturating_add(instruction.base_step_cost(cell));
+            calls += 1;
+            program_pointer += 1;
+        }
+        // Even a program which has already halted takes a call to say so.
+        Some((steps, calls.max(1), tape.len()))
     }
 
-    pub fn tape_length(&self) -> usize {
-        self.memory.len()
+    pub fn tape(&self) -> &[u8] {
+        self.tape.cells()
     }
 
-    pub fn total_cells_allocated(&self) -> usize {
-        self.memory.len() + self.loop_span_history.total_cells_allocated()
+    /// Returns the tape along with the memory pointer and tape policy.
+    pub fn tape_state(&self) -> &Tape {
+        &self.tape
     }
-}
+
+    /// Returns the tape mutably, so that it can be changed between steps for
+    /// experiments, such as seeing how the loop detectors react to a poked in
+    /// value. The tape can't be resized through this.
+    ///
+    /// Note that the loop detectors assume the tape only changes by executing
+    /// the program. Changing a cell in the middle of a loop can make the loop
+    /// spans being recorded wrong, so a program which halts may be reported as
+    /// looping afterwards (and vice versa). Only use this for experimenting.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.tape.cells_mut()
+    }
+
+    /// Returns the tape up to and including the last nonzero cell. This is empty
+    /// if every cell is zero. Note that the memory pointer may point past the
+    /// end of the trimmed tape.
+    pub fn tape_trimmed(&self) -> &[u8] {
+        self.tape.trimmed()
+    }
+
+    /// Returns `tape_trimmed()` as text, for programs which write text to the
+    /// tape instead of outputting it. Bytes which aren't valid UTF-8 are shown
+    /// as the replacement character U+FFFD. Zero cells before the last nonzero
+    /// cell are kept, as NUL characters.
+    pub fn tape_as_string_lossy(&self) -> String {
+        String::from_utf8_lossy(self.tape_trimmed()).into_owned()
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn loop_span_history(&self) -> &LoopSpanHistory {
+        &self.loop_span_history
+    }
+
+    /// Returns the loop span currently being recorded for the loop whose
+    /// StartLoop is at `loop_index` in `program().extended_instrs()`, or None if
+    /// the program is not inside that loop (or the loop span detector is off).
+    pub fn loop_span_for(&self, loop_index: usize) -> Option<&LoopSpan> {
+        self.loop_span_history.active_loop_spans.get(&loop_index)
+    }
+
+    /// Returns a map from each loop to the number of times the body of that
+    /// loop has been entered, either by taking the StartLoop or by jumping back
+    /// from the matching EndLoop. Loops are keyed by their index into
+    /// `program().extended_instrs()`, which is the StartLoop for ordinary loops
+    /// and the SetToZeroPlus/SetToZeroMinus/LoopIfNonzero instruction for
+    /// folded ones. Since folding shortens the program, these indices are not
+    /// positions in the original source. Loops which have never been entered
+    /// do not appear in the map. Returns None if profiling is not enabled (see
+    /// `enable_profiling`).
+    pub fn loop_iteration_counts(&self) -> Option<&HashMap<usize, usize>> {
+        self.profile
+            .as_ref()
+            .map(|profile| &profile.loop_iteration_counts)
+    }
+
+    /// Returns, for each instruction in `program().extended_instrs()`, whether
+    /// that instruction has been executed at least once. Instructions which are
+    /// never executed are dead code.
+    pub fn executed_mask(&self) -> &[bool] {
+        &self.executed
+    }
+
+    pub fn tape_length(&self) -> usize {
+        self.tape.len()
+    }
+
+    /// Returns the number of nonzero cells on the tape.
+    pub fn nonzero_cells(&self) -> usize {
+        self.tape.nonzero_cells()
+    }
+
+    pub fn total_cells_allocated(&self) -> usize {
+        self.tape.len() + self.loop_span_history.total_cells_allocated()
+    }
+
+    /// Returns true if both contexts are in the same logical state, meaning that
+    /// they have the same program (both the original and extended instructions),
+    /// the same program pointer, the same memory pointer and the same
+    /// `tape_trimmed()`. Everything else is ignored, including how much of the
+    /// tape has been allocated, the loop span history, profiling information,
+    /// detectors, tape policy, and pending input and output.
+    pub fn logically_eq(&self, other: &ExecutionContext) -> bool {
+        self.program.original_instrs == other.program.original_instrs
+            && self.program.extended_instrs == other.program.extended_instrs
+            && self.program_pointer == other.program_pointer
+            && self.tape.pointer == other.tape.pointer
+            && self.tape_trimmed() == other.tape_trimmed()
+    }
+
+    /// Clone this ExecutionContext without its loop span history, which is
+    /// usually most of its size. This is useful for a throwaway context which
+    /// only needs to step forward, such as for counting steps.
+    ///
+    /// Loop span detection restarts from scratch in the returned context, so
+    /// an infinite loop may be detected later than it would be in the original
+    /// context, but never incorrectly.
+    pub fn clone_light(&self) -> ExecutionContext {
+        let mut loop_span_history = LoopSpanHistory::new(&self.program);
+        loop_span_history.tape_policy = self.loop_span_history.tape_policy;
+        loop_span_history.max_past_spans = self.loop_span_history.max_past_spans;
+        ExecutionContext {
+            tape: self.tape.clone(),
+            program: self.program.clone(),
+            program_pointer: self.program_pointer,
+            loop_span_history,
+            profile: self.profile.clone(),
+            detectors: self.detectors,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            executed: self.executed.clone(),
+            steps_taken: self.steps_taken,
+            start_state: self.start_state.clone(),
+            growing_run_iterations: self.growing_run_iterations,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Capture the state needed to resume execution from this point: the tape,
+    /// the pointers, the loop span history and the steps taken, along with any
+    /// pending input and output and profiling information. This is smaller
+    /// than cloning the entire ExecutionContext, since the program is not
+    /// included and loops with no past spans are not stored.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut active_loop_spans: Vec<_> = self
+            .loop_span_history
+            .active_loop_spans
+            .iter()
+            .map(|(&loop_index, span)| (loop_index, span.clone()))
+            .collect();
+        active_loop_spans.sort_by_key(|(loop_index, _)| *loop_index);
+
+        let mut single_loop_spans: Vec<_> = self
+            .loop_span_history
+            .single_loop_spans
+            .iter()
+            .filter(|(_, spans)| !spans.is_empty())
+            .map(|(&loop_index, spans)| (loop_index, spans.clone()))
+            .collect();
+        single_loop_spans.sort_by_key(|(loop_index, _)| *loop_index);
+
+        Snapshot {
+            memory: self.tape.cells.clone(),
+            memory_pointer: self.tape.pointer,
+            program_pointer: self.program_pointer,
+            active_loop_spans,
+            single_loop_spans,
+            start_state: self.start_state.clone(),
+            growing_run_iterations: self.growing_run_iterations,
+            profile: self.profile.clone(),
+            input: self.input.clone(),
+            output: self.output.clone(),
+            executed: self.executed.clone(),
+            hit_left_edge: self.tape.hit_left_edge,
+            high_water: self.tape.high_water,
+            steps_taken: self.steps_taken,
+        }
+    }
+
+    /// Restore this ExecutionContext to the state captured by `snapshot`. The
+    /// snapshot must have been taken from an ExecutionContext running the same
+    /// program, and continuing execution after restoring behaves identically
+    /// to continuing from the point the snapshot was taken, including when an
+    /// infinite loop is detected.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let mut loop_span_history = LoopSpanHistory::new(&self.program);
+        loop_span_history.tape_policy = self.loop_span_history.tape_policy;
+        loop_span_history
+            .active_loop_spans
+            .extend(snapshot.active_loop_spans.iter().cloned());
+        loop_span_history
+            .single_loop_spans
+            .extend(snapshot.single_loop_spans.iter().cloned());
+        loop_span_history.set_max_past_spans(self.loop_span_history.max_past_spans);
+
+        self.tape.cells = snapshot.memory.clone();
+        self.tape.pointer = snapshot.memory_pointer;
+        self.program_pointer = snapshot.program_pointer;
+        self.loop_span_history = loop_span_history;
+        self.profile = snapshot.profile.clone();
+        self.input = snapshot.input.clone();
+        self.output = snapshot.output.clone();
+        self.executed = snapshot.executed.clone();
+        // A snapshot of a prefix of this program covers fewer instructions. (See
+        // search::PrefixCache.)
+        self.executed
+            .resize(self.program.extended_instrs.len(), false);
+        self.tape.hit_left_edge = snapshot.hit_left_edge;
+        self.tape.high_water = snapshot.high_water;
+        self.steps_taken = snapshot.steps_taken;
+        self.start_state = snapshot.start_state.clone();
+        self.growing_run_iterations = snapshot.growing_run_iterations;
+    }
+}
+
+/// The state of an ExecutionContext at a particular point in time, created by
+/// ExecutionContext::snapshot and used by ExecutionContext::restore. Snapshots
+/// can be saved with `to_bytes` and loaded with `from_bytes`.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    memory: Vec<u8>,
+    memory_pointer: usize,
+    program_pointer: usize,
+    // The loop span history, sorted by loop index. Loops with no past spans are
+    // omitted.
+    active_loop_spans: Vec<(usize, LoopSpan)>,
+    single_loop_spans: Vec<(usize, Vec<LoopSpan>)>,
+    // See ExecutionContext::start_state.
+    start_state: Option<(Vec<u8>, usize)>,
+    growing_run_iterations: usize,
+    profile: Option<Profile>,
+    // Unread input and untaken output.
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    executed: Vec<bool>,
+    hit_left_edge: bool,
+    high_water: usize,
+    steps_taken: usize,
+}
+
+impl Snapshot {
+    /// Encode the snapshot as bytes, which can be decoded with `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder(vec![]);
+        encoder.bytes(&self.memory);
+        encoder.usize(self.memory_pointer);
+        encoder.usize(self.program_pointer);
+        encoder.usize(self.active_loop_spans.len());
+        for (loop_index, span) in &self.active_loop_spans {
+            encoder.usize(*loop_index);
+            encoder.loop_span(span);
+        }
+        encoder.usize(self.single_loop_spans.len());
+        for (loop_index, spans) in &self.single_loop_spans {
+            encoder.usize(*loop_index);
+            encoder.loop_spans(spans);
+        }
+        encoder.bool(self.start_state.is_some());
+        if let Some((memory, memory_pointer)) = &self.start_state {
+            encoder.bytes(memory);
+            encoder.usize(*memory_pointer);
+        }
+        encoder.usize(self.growing_run_iterations);
+        encoder.bool(self.profile.is_some());
+        if let Some(profile) = &self.profile {
+            let mut counts: Vec<_> = profile.loop_iteration_counts.iter().collect();
+            counts.sort();
+            encoder.usize(counts.len());
+            for (&loop_index, &iterations) in counts {
+                encoder.usize(loop_index);
+                encoder.usize(iterations);
+            }
+        }
+        let input: Vec<u8> = self.input.iter().copied().collect();
+        encoder.bytes(&input);
+        encoder.bytes(&self.output);
+        let executed: Vec<u8> = self
+            .executed
+            .iter()
+            .map(|&executed| executed as u8)
+            .collect();
+        encoder.bytes(&executed);
+        encoder.bool(self.hit_left_edge);
+        encoder.usize(self.high_water);
+        encoder.usize(self.steps_taken);
+        encoder.0
+    }
+
+    /// Decode a snapshot encoded by `to_bytes`. The snapshot should only be
+    /// restored into a context running the same program it was taken from.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, DecodeError> {
+        let mut decoder = Decoder(bytes);
+        let memory = decoder.bytes()?;
+        let memory_pointer = decoder.usize()?;
+        if memory_pointer >= memory.len() {
+            return Err(DecodeError::PointerOutOfBounds);
+        }
+        let program_pointer = decoder.usize()?;
+        let active_loop_spans = (0..decoder.usize()?)
+            .map(|_| Ok((decoder.usize()?, decoder.loop_span()?)))
+            .collect::<Result<_, _>>()?;
+        let single_loop_spans = (0..decoder.usize()?)
+            .map(|_| Ok((decoder.usize()?, decoder.loop_spans()?)))
+            .collect::<Result<_, _>>()?;
+        let start_state = if decoder.bool()? {
+            Some((decoder.bytes()?, decoder.usize()?))
+        } else {
+            None
+        };
+        let growing_run_iterations = decoder.usize()?;
+        let profile = if decoder.bool()? {
+            let loop_iteration_counts = (0..decoder.usize()?)
+                .map(|_| Ok((decoder.usize()?, decoder.usize()?)))
+                .collect::<Result<_, _>>()?;
+            Some(Profile {
+                loop_iteration_counts,
+            })
+        } else {
+            None
+        };
+        let input = decoder.bytes()?.into();
+        let output = decoder.bytes()?;
+        let executed = decoder
+            .bytes()?
+            .into_iter()
+            .map(|executed| executed != 0)
+            .collect();
+        let hit_left_edge = decoder.bool()?;
+        let high_water = decoder.usize()?;
+        let steps_taken = decoder.usize()?;
+        decoder.finish()?;
+        Ok(Snapshot {
+            memory,
+            memory_pointer,
+            program_pointer,
+            active_loop_spans,
+            single_loop_spans,
+            start_state,
+            growing_run_iterations,
+            profile,
+            input,
+            output,
+            executed,
+            hit_left_edge,
+            high_water,
+            steps_taken,
+        })
+    }
+
+    /// The number of real steps the context had taken when the snapshot was
+    /// taken. See ExecutionContext::steps_taken.
+    pub fn steps_taken(&self) -> usize {
+        self.steps_taken
+    }
+
+    /// The number of cells stored by this snapshot. This is comparable to
+    /// ExecutionContext::total_cells_allocated.
+    pub fn total_cells_allocated(&self) -> usize {
+        self.memory.len()
+            + self
+                .active_loop_spans
+                .iter()
+                .map(|(_, span)| span.total_cells_allocated())
+                .sum::<usize>()
+            + self
+                .single_loop_spans
+                .iter()
+                .flat_map(|(_, spans)| spans)
+                .map(|span| span.total_cells_allocated())
+                .sum::<usize>()
+    }
+}
+
+/// Run a program for at most `max_steps` calls to `ExecutionContext::step`.
+/// Returns the final execution status, the total number of real steps taken
+/// (or None if the program did not resolve in time), and the final tape length.
+pub fn step_count(program: &Program, max_steps: usize) -> (ExecutionStatus, Option<usize>, usize) {
+    step_count_with_detectors(program, max_steps, Detectors::ALL)
+}
+
+/// Limits on how far `step_count_with_budget` runs a program before giving up
+/// on it, and the detectors it uses to prove that the program never halts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    /// The maximum number of calls to `ExecutionContext::step`, as in `step_count`.
+    pub max_steps: usize,
+    /// Give up once the tape is longer than this many cells.
+    pub max_tape_length: Option<usize>,
+    /// Give up once the program has run for this long. This is only checked
+    /// every TIME_CHECK_INTERVAL steps, since checking the time is slow.
+    pub max_time: Option<Duration>,
+    /// The infinite loop detectors to use. With `Detectors::NONE`, a program
+    /// which doesn't halt always runs until one of the limits runs out.
+    pub detectors: Detectors,
+}
+
+impl Budget {
+    /// A budget which only limits the number of steps, and uses every detector.
+    pub fn steps(max_steps: usize) -> Budget {
+        Budget {
+            max_steps,
+            max_tape_length: None,
+            max_time: None,
+            detectors: Detectors::ALL,
+        }
+    }
+}
+
+/// Which limit of a `Budget` ran out before a program was resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnknownReason {
+    Steps,
+    TapeLength,
+    Time,
+}
+
+impl Display for UnknownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnknownReason::Steps => write!(f, "step limit"),
+            UnknownReason::TapeLength => write!(f, "tape length limit"),
+            UnknownReason::Time => write!(f, "time limit"),
+        }
+    }
+}
+
+// The number of steps between checks of the time limit of a Budget.
+const TIME_CHECK_INTERVAL: usize = 1024;
+
+/// Like `step_count`, but stops at whichever limit of the budget runs out
+/// first. If the program is not resolved, also returns which limit that was.
+pub fn step_count_with_budget(
+    program: &Program,
+    budget: Budget,
+) -> (ExecutionStatus, Option<usize>, usize, Option<UnknownReason>) {
+    if budget.max_tape_length.is_none() && budget.max_time.is_none() {
+        let (status, steps, tape_length) =
+            step_count_with_detectors(program, budget.max_steps, budget.detectors);
+        let reason = status.is_running().then_some(UnknownReason::Steps);
+        return (status, steps, tape_length, reason);
+    }
+
+    let start = Instant::now();
+    let mut ctx = ExecutionContext::with_detectors(program, budget.detectors);
+    for i in 1..budget.max_steps {
+        let (_, status) = ctx.step();
+        if !status.is_running() {
+            return (status, Some(ctx.steps_taken()), ctx.tape_length(), None);
+        }
+        let reason = match (budget.max_tape_length, budget.max_time) {
+            (Some(max_tape_length), _) if ctx.tape_length() > max_tape_length => {
+                UnknownReason::TapeLength
+            }
+            (_, Some(max_time)) if i % TIME_CHECK_INTERVAL == 0 && start.elapsed() >= max_time => {
+                UnknownReason::Time
+            }
+            _ => continue,
+        };
+        return (
+            ExecutionStatus::Running,
+            None,
+            ctx.tape_length(),
+            Some(reason),
+        );
+    }
+    (
+        ExecutionStatus::Running,
+        None,
+        ctx.tape_length(),
+        Some(UnknownReason::Steps),
+    )
+}
+
+/// Like `step_count`, but only uses the given infinite loop detectors.
+pub fn step_count_with_detectors(
+    program: &Program,
+    max_steps: usize,
+    detectors: Detectors,
+) -> (ExecutionStatus, Option<usize>, usize) {
+    let mut ctx = ExecutionContext::with_detectors(program, detectors);
+    // None of the detectors can fire without entering a loop, so a program which
+    // halts trivially (including every loop-free program) halts the same way
+    // when it is run.
+    if let Some((steps, calls, tape_length)) = ctx.trivial_halt() {
+        if calls < max_steps {
+            return (ExecutionStatus::Halted, Some(steps), tape_length);
+        }
+    }
+    run_until_resolved(&mut ctx, max_steps)
+}
+
+/// Run the program for at most `max_steps` and return the number of nonzero
+/// cells on the tape when it halts, or None if it does not halt in time. This
+/// is the "sigma" score of busy beaver variants which count the marks left on
+/// the tape instead of the number of steps.
+pub fn sigma(program: &Program, max_steps: usize) -> Option<usize> {
+    let mut ctx = ExecutionContext::new(program);
+    match run_until_resolved(&mut ctx, max_steps) {
+        (ExecutionStatus::Halted, _, _) => Some(ctx.nonzero_cells()),
+        _ => None,
+    }
+}
+
+/// Run the program for at most `max_steps` and return true if it tried to move
+/// left from the first cell of the tape (see `ExecutionContext::hit_left_edge`).
+/// Results for such programs are specific to a tape which is only infinite to
+/// the right.
+pub fn hits_left_edge(program: &Program, max_steps: usize) -> bool {
+    let mut ctx = ExecutionContext::new(program);
+    for _ in 1..max_steps {
+        if ctx.hit_left_edge() || !ctx.step().1.is_running() {
+            break;
+        }
+    }
+    ctx.hit_left_edge()
+}
+
+/// Details of where and when a non-halting program was detected as looping,
+/// for diagnosing programs which take a long time to prove. See `proof_profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofProfile {
+    /// The number of real steps run before the infinite loop was detected.
+    /// This is the same as the step count returned by `step_count`.
+    pub steps: usize,
+    pub reason: LoopReason,
+    /// The loop in which the infinite loop was detected, as an index into
+    /// `extended_instrs()`. This is keyed the same way as `loop_iterations`.
+    pub detected_loop: usize,
+    /// The number of times each loop was entered before the infinite loop was
+    /// detected, as returned by `ExecutionContext::loop_iteration_counts`.
+    pub loop_iterations: HashMap<usize, usize>,
+}
+
+impl ProofProfile {
+    /// The number of times the detected loop was entered before it was
+    /// detected. A large number means the detector needed many iterations of
+    /// the loop before it repeated.
+    pub fn iterations_before_detection(&self) -> usize {
+        self.loop_iterations
+            .get(&self.detected_loop)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Run the program with profiling enabled. If it is detected as an infinite
+/// loop within `max_steps`, returns where and after how many iterations it was
+/// detected. Returns None if the program halts or is still running.
+pub fn proof_profile(program: &Program, max_steps: usize) -> Option<ProofProfile> {
+    let mut ctx = ExecutionContext::new(program);
+    ctx.enable_profiling();
+    for _ in 1..max_steps {
+        let program_pointer = ctx.program_pointer();
+        let (_, state) = ctx.step();
+        match state {
+            ExecutionStatus::Running => (),
+            ExecutionStatus::Halted => return None,
+            ExecutionStatus::InfiniteLoop(reason) => {
+                // Loop spans, start states and growing runs are matched at the EndLoop, but
+                // the loop is keyed by its StartLoop. The other detectors fire
+                // at the loop itself.
+                let detected_loop = match reason {
+                    LoopReason::LoopSpan { .. }
+                    | LoopReason::ReturnedToStart
+                    | LoopReason::GrowingRun => program.matching_loop(program_pointer).unwrap(),
+                    _ => program_pointer,
+                };
+                return Some(ProofProfile {
+                    steps: ctx.steps_taken(),
+                    reason,
+                    detected_loop,
+                    loop_iterations: ctx.loop_iteration_counts().unwrap().clone(),
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Return a canonical representative of the programs which are equivalent to
+/// `program` under reflection (see `Program::reflected`). Two programs with the
+/// same canonical form take the same number of steps and leave the same tape,
+/// up to mirroring.
+///
+/// Since the tape has a left edge at the starting cell, moving left from the
+/// starting cell does nothing, while moving right from it does not. So the
+/// reflection is only treated as equivalent if neither the program nor its
+/// reflection ever moves the pointer off the starting cell within `max_steps`
+/// (for example, because all of its moves are in loops that are never
+/// entered). Otherwise, the program is its own canonical form.
+pub fn canonical_form(program: &Program, max_steps: usize) -> Program {
+    let reflected = program.reflected();
+    if moves_off_origin(program, max_steps) || moves_off_origin(&reflected, max_steps) {
+        return program.clone();
+    }
+    if reflected.to_string() < program.to_string() {
+        reflected
+    } else {
+        program.clone()
+    }
+}
+
+/// Remove programs which are reflections of an earlier program in the list
+/// (see `canonical_form`). The first program of each equivalence class is kept,
+/// and the order of the kept programs is preserved.
+pub fn dedup_reflections(programs: Vec<Program>, max_steps: usize) -> Vec<Program> {
+    let mut seen = HashSet::new();
+    programs
+        .into_iter()
+        .filter(|program| seen.insert(canonical_form(program, max_steps).to_string()))
+        .collect()
+}
+
+// Returns true if the program executes a Left or Right instruction before it
+// resolves, or if it doesn't resolve within max_steps.
+fn moves_off_origin(program: &Program, max_steps: usize) -> bool {
+    let mut ctx = ExecutionContext::new(program);
+    for _ in 1..max_steps {
+        if let Some(ExtendedInstr::BaseInstr(Instr::Left | Instr::Right) | ExtendedInstr::Move(_)) =
+            program.instruction_at(ctx.program_pointer())
+        {
+            return true;
+        }
+        if !ctx.step().1.is_running() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Run two programs in lockstep for at most `max_steps` calls to
+/// `ExecutionContext::step` each, and return the first step after which their
+/// memory pointers, tapes, or execution statuses differ. Returns None if the programs
+/// behave identically until both have resolved or `max_steps` is reached.
+///
+/// Since steps are calls to `step`, two programs which compute the same thing
+/// but fold into different extended instructions are not considered identical.
+pub fn diff_execution(a: &Program, b: &Program, max_steps: usize) -> Option<usize> {
+    let mut a = ExecutionContext::new(a);
+    let mut b = ExecutionContext::new(b);
+    for step in 1..max_steps {
+        let (_, a_status) = a.step();
+        let (_, b_status) = b.step();
+        if a_status != b_status || a.memory_pointer() != b.memory_pointer() || a.tape() != b.tape()
+        {
+            return Some(step);
+        }
+        if !a_status.is_running() {
+            return None;
+        }
+    }
+    None
+}
+
+// Step the context until it resolves or max_steps is reached. The step count
+// includes any steps the context had already taken.
+fn run_until_resolved(
+    ctx: &mut ExecutionContext,
+    max_steps: usize,
+) -> (ExecutionStatus, Option<usize>, usize) {
+    for _ in 1..max_steps {
+        let (_, state) = ctx.step();
+        if !state.is_running() {
+            return (state, Some(ctx.steps_taken()), ctx.tape_length());
+        }
+    }
+    (ExecutionStatus::Running, None, ctx.tape_length())
+}
+
+// Panic if `memory_pointer` is not a cell of a tape with `tape_length` cells,
+// saying which it was. The pointer is kept on the tape by `move_pointer`, so
+// this only fires if that invariant is broken.
+fn check_on_tape(memory_pointer: usize, tape_length: usize) {
+    assert!(
+        memory_pointer < tape_length,
+        "memory pointer {} is off the end of the tape ({} cells long)",
+        memory_pointer,
+        tape_length
+    );
+}
 
 // TODO: Use prior subhistories. This currently only checks the most recent subhistory.
 #[derive(Debug, Clone)]
@@ -247,6 +1925,12 @@ pub struct LoopSpanHistory {
     // any time execution leaves the loop that the loop span list is associated
     // with.
     single_loop_spans: HashMap<usize, Vec<LoopSpan>>,
+    // The tape policy of the ExecutionContext, which determines how loop spans
+    // are compared (see LoopSpan).
+    tape_policy: TapePolicy,
+    // The most past loop spans kept for each loop, or None for no limit (see
+    // ExecutionContext::set_max_past_spans).
+    max_past_spans: Option<usize>,
 }
 
 impl LoopSpanHistory {
@@ -263,6 +1947,16 @@ impl LoopSpanHistory {
         LoopSpanHistory {
             active_loop_spans,
             single_loop_spans: past_loop_spans,
+            tape_policy: TapePolicy::Unbounded,
+            max_past_spans: None,
+        }
+    }
+
+    // Forget every active and past loop span.
+    fn clear(&mut self) {
+        self.active_loop_spans.clear();
+        for spans in self.single_loop_spans.values_mut() {
+            spans.clear();
         }
     }
 
@@ -278,6 +1972,22 @@ impl LoopSpanHistory {
         }
     }
 
+    fn record_move(&mut self, offset: isize) {
+        for loop_span in self.active_loop_spans.values_mut() {
+            loop_span.record_move(offset);
+        }
+    }
+
+    // Record that the cell at `memory_pointer` was written to. This is only
+    // tracked for `LoopSpan::matches_ignoring_guard`.
+    fn record_write(&mut self, memory_pointer: usize) {
+        for loop_span in self.active_loop_spans.values_mut() {
+            if loop_span.starting_memory_pointer == memory_pointer {
+                loop_span.guard_written = true;
+            }
+        }
+    }
+
     // Start recording a new loop span. There must not be another active loop span
     // recording or else this function will panic.
     fn start_recording_loop_span(
@@ -292,7 +2002,7 @@ impl LoopSpanHistory {
             loop_index,
             self.active_loop_spans
         );
-        let loop_span = LoopSpan::new(memory, starting_position);
+        let loop_span = LoopSpan::new(memory, starting_position, self.tape_policy);
 
         let old_value = self.active_loop_spans.insert(loop_index, loop_span);
         assert!(old_value.is_none());
@@ -302,34 +2012,57 @@ impl LoopSpanHistory {
     // and adds the recording to the loop_index's history.
     // A prior loop span recording must have been started at the same loop index
     // or else this function will panic. Returns Some if the recorded loop span
-    // matches a previously recorded loop span.
-    fn end_recording_loop_span(&mut self, loop_index: usize) -> Option<(LoopSpan, LoopSpan)> {
+    // matches a previously recorded loop span. If `ignore_guard` is true, spans
+    // also match if they only differ in their guard cells, when that is safe
+    // (see `LoopSpan::matches_ignoring_guard`).
+    fn end_recording_loop_span(
+        &mut self,
+        loop_index: usize,
+        ignore_guard: bool,
+    ) -> Option<(LoopSpan, LoopSpan)> {
         fn check_loop_spans(
             prior_spans: &[LoopSpan],
             current_span: &LoopSpan,
+            ignore_guard: bool,
         ) -> Option<(LoopSpan, LoopSpan)> {
-            prior_spans.iter().find_map(|span| {
-                if span == current_span {
+            prior_spans.iter().enumerate().find_map(|(i, span)| {
+                if span == current_span
+                    || (ignore_guard
+                        && span.matches_ignoring_guard(current_span, &prior_spans[i + 1..]))
+                {
                     Some((span.clone(), current_span.clone()))
                 } else {
                     None
                 }
             })
         }
-        assert!(self.active_loop_spans.contains_key(&loop_index));
+        // If the history was cleared by ExecutionContext::clone_light while this
+        // loop was running, then there is no span to end. A new span is started
+        // at the loop's next iteration, so detection resumes from there.
+        let loop_span = self.active_loop_spans.remove(&loop_index)?;
 
-        let loop_span = self.active_loop_spans.remove(&loop_index).unwrap();
-
-        let loop_span_check = check_loop_spans(&self.single_loop_spans[&loop_index], &loop_span);
+        let loop_span_check = check_loop_spans(
+            &self.single_loop_spans[&loop_index],
+            &loop_span,
+            ignore_guard,
+        );
 
-        self.single_loop_spans
-            .get_mut(&loop_index)
-            .unwrap()
-            .push(loop_span);
+        let past_spans = self.single_loop_spans.get_mut(&loop_index).unwrap();
+        past_spans.push(loop_span);
+        forget_oldest_spans(past_spans, self.max_past_spans);
 
         loop_span_check
     }
 
+    // Limit the number of past spans kept for each loop, forgetting the oldest
+    // ones if there are already more than that.
+    fn set_max_past_spans(&mut self, max_past_spans: Option<usize>) {
+        self.max_past_spans = max_past_spans;
+        for spans in self.single_loop_spans.values_mut() {
+            forget_oldest_spans(spans, max_past_spans);
+        }
+    }
+
     fn reset_past_loop_spans(&mut self, loop_index: usize) {
         self.single_loop_spans.get_mut(&loop_index).unwrap().clear()
     }
@@ -347,6 +2080,19 @@ impl LoopSpanHistory {
                 .sum::<usize>()
     }
 
+    /// Returns statistics about how many loop spans are currently stored.
+    pub fn stats(&self) -> LoopSpanStats {
+        LoopSpanStats {
+            num_active_spans: self.active_loop_spans.len(),
+            num_past_spans: self
+                .single_loop_spans
+                .iter()
+                .map(|(&loop_index, spans)| (loop_index, spans.len()))
+                .collect(),
+            total_cells_allocated: self.total_cells_allocated(),
+        }
+    }
+
     pub fn active_loop_spans(&self) -> &HashMap<usize, LoopSpan> {
         &self.active_loop_spans
     }
@@ -356,50 +2102,174 @@ impl LoopSpanHistory {
     }
 }
 
+// Remove the oldest spans from the past spans of a loop, so that there are at
+// most `max_past_spans` left. The spans are in the order they were recorded, so
+// the remaining spans are still consecutive iterations of the loop.
+fn forget_oldest_spans(spans: &mut Vec<LoopSpan>, max_past_spans: Option<usize>) {
+    if let Some(max_past_spans) = max_past_spans {
+        if spans.len() > max_past_spans {
+            spans.drain(..spans.len() - max_past_spans);
+        }
+    }
+}
+
+/// Statistics about the contents of a LoopSpanHistory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopSpanStats {
+    /// The number of loop spans currently being recorded.
+    pub num_active_spans: usize,
+    /// Maps the index of each StartLoop to the number of past loop spans stored for that loop.
+    pub num_past_spans: HashMap<usize, usize>,
+    /// The total number of memory cells stored across all loop spans.
+    pub total_cells_allocated: usize,
+}
+
+impl LoopSpanStats {
+    /// The total number of past loop spans stored across all loops.
+    pub fn total_past_spans(&self) -> usize {
+        self.num_past_spans.values().sum()
+    }
+}
+
 #[derive(Debug, Clone)]
 /// A LoopSpan is a special snapshot of memory that represents the set of cells
 /// which could ever affect the future execution of a given loop at some point
 /// in time. See LOOP_SPAN.md for more information.
+///
+/// The interpreter keeps these invariants, which spans built with `from_parts`
+/// must also satisfy:
+/// - `starting_memory_pointer` is a cell of `memory_at_loop_start`, since the
+///   snapshot is the whole tape when the loop starts.
+/// - `min_index <= starting_memory_pointer <= max_index`, and the same for
+///   `current_memory_pointer`, since the touched cells include every cell the
+///   memory pointer has been on. `max_index` may be past the end of the
+///   snapshot if the loop grew the tape.
 pub struct LoopSpan {
-    // A snapshot of memory at the start of the loop
+    /// A snapshot of memory at the start of the loop
     pub memory_at_loop_start: Vec<u8>,
-    // An index into the program memory denoting the position of the memory pointer at the start of the loop.
+    /// An index into the program memory denoting the position of the memory pointer at the start of the loop.
     pub starting_memory_pointer: usize,
-    // An index into the program memory denoting the position of the memory pointer at the current point in the loop.
+    /// An index into the program memory denoting the position of the memory pointer at the current point in the loop.
     pub current_memory_pointer: usize,
-    // The currently lowest index the memory pointer touched during the loop
+    /// The currently lowest index the memory pointer touched during the loop
     pub min_index: usize,
-    // The currently highest index the memory pointer touched during the loop
+    /// The currently highest index the memory pointer touched during the loop
     pub max_index: usize,
+    // The tape policy the span was recorded with.
+    tape_policy: TapePolicy,
+    // Whether the cell at starting_memory_pointer was written to during the
+    // loop. This is only tracked if the guard_insensitive_loop_span detector is
+    // on, and is otherwise always false.
+    guard_written: bool,
 }
 
 impl LoopSpan {
-    fn new(memory: Vec<u8>, starting_position: usize) -> LoopSpan {
+    /// Create a span with the given fields, as if it had been recorded on an
+    /// unbounded tape without the guard_insensitive_loop_span detector. This is
+    /// for checking `PartialEq` and `masked_window` directly, without running a
+    /// program to record the span. Panics if the fields break the invariants
+    /// listed on LoopSpan.
+    pub fn from_parts(
+        memory_at_loop_start: Vec<u8>,
+        starting_memory_pointer: usize,
+        current_memory_pointer: usize,
+        min_index: usize,
+        max_index: usize,
+    ) -> LoopSpan {
+        assert!(
+            starting_memory_pointer < memory_at_loop_start.len(),
+            "the starting memory pointer must be on the snapshot"
+        );
+        for pointer in [starting_memory_pointer, current_memory_pointer] {
+            assert!(
+                (min_index..=max_index).contains(&pointer),
+                "memory pointer {} is outside the touched cells {}..={}",
+                pointer,
+                min_index,
+                max_index
+            );
+        }
+        LoopSpan {
+            current_memory_pointer,
+            min_index,
+            max_index,
+            ..LoopSpan::new(
+                memory_at_loop_start,
+                starting_memory_pointer,
+                TapePolicy::Unbounded,
+            )
+        }
+    }
+
+    fn new(memory: Vec<u8>, starting_position: usize, tape_policy: TapePolicy) -> LoopSpan {
         LoopSpan {
             memory_at_loop_start: memory,
             starting_memory_pointer: starting_position,
             current_memory_pointer: starting_position,
             min_index: starting_position,
             max_index: starting_position,
+            tape_policy,
+            guard_written: false,
         }
     }
 
     fn record_left(&mut self) {
-        self.current_memory_pointer = self.current_memory_pointer.saturating_sub(1);
-        if self.current_memory_pointer < self.min_index {
-            self.min_index = self.current_memory_pointer;
-        }
+        self.record_move(-1);
     }
 
     fn record_right(&mut self) {
-        self.current_memory_pointer += 1;
-        if self.current_memory_pointer > self.max_index {
-            self.max_index = self.current_memory_pointer;
+        self.record_move(1);
+    }
+
+    // Equivalent to calling record_right `offset` times, or record_left
+    // `-offset` times. Since the moves are all in one direction, only the final
+    // position can extend the span.
+    fn record_move(&mut self, offset: isize) {
+        match self.tape_policy {
+            // Finite tapes are compared using the whole tape instead (see
+            // PartialEq), so every cell counts as touched.
+            TapePolicy::Ring(length) => {
+                self.current_memory_pointer =
+                    ring_position(self.current_memory_pointer, offset, length);
+                self.min_index = 0;
+                self.max_index = length - 1;
+            }
+            TapePolicy::Bounded(length) => {
+                self.current_memory_pointer =
+                    bounded_position(self.current_memory_pointer, offset, length);
+                self.min_index = 0;
+                self.max_index = length - 1;
+            }
+            // On a TwoWay tape, moving past the first cell clears every span, so
+            // this is only reached for moves which stay on the tape.
+            TapePolicy::Unbounded | TapePolicy::TwoWay if offset < 0 => {
+                self.current_memory_pointer = self
+                    .current_memory_pointer
+                    .saturating_sub(offset.unsigned_abs());
+                self.min_index = self.min_index.min(self.current_memory_pointer);
+            }
+            TapePolicy::Unbounded | TapePolicy::TwoWay => {
+                self.current_memory_pointer += offset as usize;
+                self.max_index = self.max_index.max(self.current_memory_pointer);
+            }
+            TapePolicy::Custom(_) => unreachable!("loop spans are not recorded on custom tapes"),
         }
     }
 
-    // Return the slice of memory that is considered part of the loop span.
-    fn memory_mask(&self) -> &[u8] {
+    /// Returns the part of `memory_at_loop_start` which is compared when
+    /// checking if two loop spans match. The touched cells are
+    /// `min_index..=max_index`, except that neither end goes past the last
+    /// nonzero cell of the snapshot. If the displacement is zero, the window is
+    /// the touched cells. If it is negative, the window runs from the first
+    /// cell of the tape to the last touched cell, and if it is positive, from
+    /// the first touched cell to the end of the snapshot. See LOOP_SPAN.md for
+    /// why this is enough to prove the loop never halts.
+    pub fn masked_window(&self) -> &[u8] {
+        &self.memory_at_loop_start[self.memory_mask_range()]
+    }
+
+    // Return the indices of `memory_at_loop_start` returned by `masked_window`.
+    pub(crate) fn memory_mask_range(&self) -> RangeInclusive<usize> {
         // Remove trailing zeros from memory snap shot
         let first_nonzero = self
             .memory_at_loop_start
@@ -415,9 +2285,9 @@ impl LoopSpan {
         // Otherwise include everything to the right of the touched region. If the displacement is
         // zero, then don't include anything extra and just return the touched region as is.
         match self.displacement().cmp(&0) {
-            std::cmp::Ordering::Less => &self.memory_at_loop_start[0..=max_index],
-            std::cmp::Ordering::Greater => &self.memory_at_loop_start[min_index..],
-            std::cmp::Ordering::Equal => &self.memory_at_loop_start[min_index..=max_index],
+            std::cmp::Ordering::Less => 0..=max_index,
+            std::cmp::Ordering::Greater => min_index..=self.memory_at_loop_start.len() - 1,
+            std::cmp::Ordering::Equal => min_index..=max_index,
         }
     }
 
@@ -425,6 +2295,59 @@ impl LoopSpan {
         self.current_memory_pointer as isize - self.starting_memory_pointer as isize
     }
 
+    // Returns true if this span matches the later span `current`, except that
+    // the cells the loop started on (the guard cells) may differ, as long as
+    // both are nonzero. `between` is the spans of the iterations between the two.
+    //
+    // The guard cell is nonzero at the start of every iteration, so its exact
+    // value can only matter if something writes to it, since after that it
+    // might be tested for zero. So the guard cells are only ignored if:
+    // - Neither iteration wrote to its guard cell.
+    // - The loop drifts, and the guard cell is at the edge of the touched region
+    //   which the loop drifts away from.
+    // - None of the later iterations went back as far as the earlier guard
+    //   cell. Since the iterations after `current` repeat the ones after this
+    //   span, they never go back to either guard cell.
+    // - The tape is unbounded to the right, since spans on other tapes are
+    //   compared by the entire state.
+    fn matches_ignoring_guard(&self, current: &LoopSpan, between: &[LoopSpan]) -> bool {
+        let drift = self.displacement();
+        let guard_at_trailing_edge = |span: &LoopSpan| {
+            span.tape_policy == TapePolicy::Unbounded
+                && !span.guard_written
+                && match drift.cmp(&0) {
+                    std::cmp::Ordering::Greater => span.min_index == span.starting_memory_pointer,
+                    std::cmp::Ordering::Less => span.max_index == span.starting_memory_pointer,
+                    std::cmp::Ordering::Equal => false,
+                }
+        };
+        let stays_past_guard = |span: &LoopSpan| {
+            if drift > 0 {
+                span.min_index > self.starting_memory_pointer
+            } else {
+                span.max_index < self.starting_memory_pointer
+            }
+        };
+        if current.displacement() != drift
+            || !guard_at_trailing_edge(self)
+            || !guard_at_trailing_edge(current)
+            || !between.iter().chain([current]).all(stays_past_guard)
+        {
+            return false;
+        }
+        // The guard is the first cell of the window of a rightward drift, and
+        // the last cell of the window of a leftward one.
+        fn without_guard(span: &LoopSpan, drift: isize) -> &[u8] {
+            let window = span.masked_window();
+            if drift > 0 {
+                &window[1..]
+            } else {
+                &window[..window.len() - 1]
+            }
+        }
+        without_guard(self, drift) == without_guard(current, drift)
+    }
+
     fn total_cells_allocated(&self) -> usize {
         self.memory_at_loop_start.len()
     }
@@ -432,8 +2355,25 @@ impl LoopSpan {
 
 impl PartialEq for LoopSpan {
     fn eq(&self, other: &Self) -> bool {
+        // On a ring tape, a drifting loop eventually wraps around onto the cells
+        // it already modified, so the displacement argument in LOOP_SPAN.md
+        // doesn't apply. Instead, two spans only match if the entire state at
+        // the start of the loop was the same, which means the loop is in a cycle.
+        // The same is true of a bounded tape, where a drifting loop eventually
+        // gets stuck against the edge.
+        match self.tape_policy {
+            TapePolicy::Ring(_) | TapePolicy::Bounded(_) => {
+                return self.memory_at_loop_start == other.memory_at_loop_start
+                    && self.starting_memory_pointer == other.starting_memory_pointer;
+            }
+            // The window for a leftward drift relies on the left edge of the
+            // tape, which a TwoWay tape does not have.
+            TapePolicy::TwoWay if self.displacement() < 0 => return false,
+            TapePolicy::Unbounded | TapePolicy::TwoWay => (),
+            TapePolicy::Custom(_) => unreachable!("loop spans are not recorded on custom tapes"),
+        }
         let displacements_match = self.displacement() == other.displacement();
-        let masks_match = self.memory_mask() == other.memory_mask();
+        let masks_match = self.masked_window() == other.masked_window();
 
         displacements_match && masks_match
     }
@@ -453,6 +2393,69 @@ pub enum ExecutionStatus {
     InfiniteLoop(LoopReason),
 }
 
+impl ExecutionStatus {
+    pub fn is_halted(&self) -> bool {
+        matches!(self, ExecutionStatus::Halted)
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self, ExecutionStatus::Running)
+    }
+
+    /// Returns true if an infinite loop was detected.
+    pub fn is_looping(&self) -> bool {
+        matches!(self, ExecutionStatus::InfiniteLoop(_))
+    }
+
+    /// Encode the status as bytes, which can be decoded with `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder(vec![]);
+        match self {
+            ExecutionStatus::Running => encoder.0.push(0),
+            ExecutionStatus::Halted => encoder.0.push(1),
+            ExecutionStatus::InfiniteLoop(LoopReason::LoopIfNonzero) => encoder.0.push(2),
+            ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { prior, current }) => {
+                encoder.0.push(3);
+                encoder.loop_span(prior);
+                encoder.loop_span(current);
+            }
+            ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop) => encoder.0.push(4),
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart) => encoder.0.push(5),
+            ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun) => encoder.0.push(6),
+        }
+        encoder.0
+    }
+
+    /// Decode a status encoded by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ExecutionStatus, DecodeError> {
+        let mut decoder = Decoder(bytes);
+        let status = match decoder.tag()? {
+            0 => ExecutionStatus::Running,
+            1 => ExecutionStatus::Halted,
+            2 => ExecutionStatus::InfiniteLoop(LoopReason::LoopIfNonzero),
+            3 => ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan {
+                prior: decoder.loop_span()?,
+                current: decoder.loop_span()?,
+            }),
+            4 => ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop),
+            5 => ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart),
+            6 => ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun),
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        decoder.finish()?;
+        Ok(status)
+    }
+
+    /// Returns how the infinite loop was detected, or None if no infinite loop
+    /// was detected.
+    pub fn loop_reason(&self) -> Option<&LoopReason> {
+        match self {
+            ExecutionStatus::InfiniteLoop(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// Details how the ExecutionContext detected that a given program will never
 /// halt.
@@ -461,6 +2464,36 @@ pub enum LoopReason {
     LoopIfNonzero,
     /// A loop span cycle was detected between the following LoopSpans.
     LoopSpan { prior: LoopSpan, current: LoopSpan },
+    /// A loop was entered whose body never changes the tape or memory pointer.
+    NoOpLoop,
+    /// The program jumped back to the start of an outer loop with the tape and
+    /// memory pointer as they were when it entered the loop.
+    ReturnedToStart,
+    /// A loop was shown to only ever add to a run of nonzero cells at the end of
+    /// the tape, without exiting. See Detectors::growing_run.
+    GrowingRun,
+}
+
+impl Display for LoopReason {
+    /// Write a one line summary of the reason. The visualizer shows the loop
+    /// spans in full.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoopReason::LoopIfNonzero => write!(f, "LoopIfNonzero on a nonzero cell"),
+            LoopReason::LoopSpan { current, .. } => write!(
+                f,
+                "Repeated loop span (cells {}..={}, displacement {})",
+                current.min_index,
+                current.max_index,
+                current.displacement()
+            ),
+            LoopReason::NoOpLoop => write!(f, "Loop body does nothing"),
+            LoopReason::ReturnedToStart => {
+                write!(f, "Returned to the state an outer loop started in")
+            }
+            LoopReason::GrowingRun => write!(f, "Loop only grows a run of nonzero cells"),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -472,6 +2505,22 @@ pub struct Program {
     // key-value pairs represent the index into extended_instrs for the
     // corresponding start and end loops.
     loop_dict: HashMap<usize, usize>,
+    // For each extended instruction, the range of original_instrs it was
+    // created from.
+    source_spans: Vec<Range<usize>>,
+    // For each of original_instrs, its byte offset in the text the program was
+    // compiled from. See `Program::from_source`.
+    text_offsets: Vec<usize>,
+    // Maps the StartLoop of each loop whose body is a no-op (see no_op_loops) to
+    // the memory pointer needed for the body to stay on the tape.
+    no_op_loops: HashMap<usize, usize>,
+    // The StartLoop of each loop which isn't inside any other loop.
+    outer_loops: HashSet<usize>,
+    // The StartLoop of each loop the growing run detector checks (see
+    // growing_run_loops).
+    growing_run_loops: HashSet<usize>,
+    // True if the program contains any Input instructions.
+    reads_input: bool,
 }
 
 impl Program {
@@ -479,32 +2528,610 @@ impl Program {
     /// braces, a CompileError is returned.
     pub fn new(instrs: impl IntoIterator<Item = Instr>) -> Result<Program, CompileError> {
         let original_instrs: Vec<_> = instrs.into_iter().collect();
-        let extended_instrs = ExtendedInstr::new(&original_instrs);
+        let (extended_instrs, source_spans) = ExtendedInstr::new(&original_instrs, true);
         let loop_dict = loop_dict(&extended_instrs)?;
+        let no_op_loops = no_op_loops(&extended_instrs, &loop_dict);
+        let outer_loops = outer_loops(&extended_instrs);
+        let growing_run_loops = growing_run_loops(&extended_instrs, &loop_dict);
+        let reads_input = original_instrs.contains(&Instr::Input);
+        let text_offsets = (0..original_instrs.len()).collect();
         Ok(Program {
             original_instrs,
             extended_instrs,
             loop_dict,
+            source_spans,
+            text_offsets,
+            no_op_loops,
+            outer_loops,
+            growing_run_loops,
+            reads_input,
         })
     }
 
-    fn get(&self, i: usize) -> Option<ExtendedInstr> {
-        self.extended_instrs.get(i).cloned()
+    /// Compile a program which may use the I/O instructions "." (Output) and
+    /// "," (Input). `Program::try_from` treats these characters as comments,
+    /// since the busy beaver search only considers programs without I/O.
+    pub fn try_from_with_io(string: &str) -> Result<Program, CompileError> {
+        Program::new(
+            string
+                .chars()
+                .filter_map(|x| Instr::try_from_with_io(x).ok()),
+        )
     }
 
-    fn matching_loop(&self, i: usize) -> Option<usize> {
-        self.loop_dict.get(&i).copied()
+    /// Compile a program from source text, treating every character which is
+    /// not an instruction as a comment, like `Program::try_from`. Unlike
+    /// `try_from`, this remembers where each instruction was in `source`, so
+    /// `text_offset` gives offsets into `source` including the comments.
+    pub fn from_source(source: &str) -> Result<Program, CompileError> {
+        let (text_offsets, instrs): (Vec<_>, Vec<_>) = source
+            .char_indices()
+            .filter_map(|(i, x)| Instr::try_from(x).ok().map(|instr| (i, instr)))
+            .unzip();
+        Ok(Program {
+            text_offsets,
+            ..Program::new(instrs)?
+        })
     }
 
-    pub fn extended_instrs(&self) -> &[ExtendedInstr] {
-        &self.extended_instrs
+    /// Like `from_source`, but also treats each "#" in `source` as a breakpoint,
+    /// which is only meant for the visualizer. Returns the program and the
+    /// index into `extended_instrs()` of the instruction after each "#", which
+    /// is where execution should stop, before running that instruction. A "#"
+    /// inside a folded instruction such as "[-#]" stops before the whole
+    /// folded instruction, and a "#" at the end stops once the program halts,
+    /// when the program pointer is `extended_instrs().len()`. The breakpoints
+    /// are not part of the program, so they have no effect on how it runs.
+    pub fn from_source_with_breakpoints(
+        source: &str,
+    ) -> Result<(Program, BTreeSet<usize>), CompileError> {
+        let program = Program::from_source(source)?;
+        let breakpoints = source
+            .char_indices()
+            .filter(|&(_, c)| c == '#')
+            .map(|(offset, _)| {
+                // The first original instruction after the "#".
+                let instr = program
+                    .text_offsets
+                    .partition_point(|&text_offset| text_offset < offset);
+                program
+                    .source_spans
+                    .iter()
+                    .position(|span| span.contains(&instr))
+                    .unwrap_or(program.extended_instrs.len())
+            })
+            .collect();
+        Ok((program, breakpoints))
     }
 
-    pub fn original_instrs(&self) -> &[Instr] {
-        &self.original_instrs
+    /// Recompile the program with every instruction left as a base
+    /// instruction, so that nothing is folded into an extended instruction
+    /// such as SetToZeroMinus or Move. The program runs for exactly the same
+    /// number of steps, but each call to `step` only runs one of them. This is
+    /// only useful for measuring how much folding speeds up execution.
+    pub fn unfolded(&self) -> Program {
+        let extended_instrs: Vec<_> = self
+            .original_instrs
+            .iter()
+            .map(|&instr| ExtendedInstr::BaseInstr(instr))
+            .collect();
+        let source_spans = (0..extended_instrs.len()).map(|i| i..i + 1).collect();
+        let loop_dict = loop_dict(&extended_instrs).expect("program already compiled");
+        let no_op_loops = no_op_loops(&extended_instrs, &loop_dict);
+        let outer_loops = outer_loops(&extended_instrs);
+        let growing_run_loops = growing_run_loops(&extended_instrs, &loop_dict);
+        Program {
+            extended_instrs,
+            loop_dict,
+            source_spans,
+            no_op_loops,
+            outer_loops,
+            growing_run_loops,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the extended instructions of the program as text, in the form
+    /// the visualizer shows them, such as "+⊟>2" for "+[-]>>". This can be
+    /// turned back into a program with `from_extended`.
+    pub fn to_extended_string(&self) -> String {
+        self.extended_instrs
+            .iter()
+            .map(|instr| instr.to_string())
+            .collect()
+    }
+
+    /// Compile a program from its extended instructions written as text (see
+    /// `to_extended_string`). Each extended instruction is expanded back into
+    /// base instructions (see `ExtendedInstr::to_base_instrs`), so the program
+    /// folds into the same extended instructions and runs for the same number
+    /// of steps, although a long clear loop such as "[+-+]" comes back as
+    /// "[++-]". Like `try_from_with_io`, characters which are not instructions
+    /// are ignored, apart from the counts after Move and SetToZeroLong glyphs,
+    /// which can be at most `MAX_EXTENDED_COUNT`.
+    pub fn from_extended(string: &str) -> Result<Program, CompileError> {
+        let mut instrs = vec![];
+        let mut chars = string.chars().enumerate().peekable();
+        while let Some((index, c)) = chars.next() {
+            let instr = match ExtendedInstr::try_from(c) {
+                Ok(instr) => instr,
+                Err(()) => continue,
+            };
+            let mut count = None;
+            while let Some(digit) = chars.peek().and_then(|(_, c)| c.to_digit(10)) {
+                chars.next();
+                count = Some(
+                    count
+                        .unwrap_or(0_usize)
+                        .saturating_mul(10)
+                        .saturating_add(digit as usize),
+                );
+            }
+            if count.is_some_and(|count| count > MAX_EXTENDED_COUNT) {
+                return Err(CompileError::CountTooLarge { index });
+            }
+            let instr = match (instr, count) {
+                (instr, None) => instr,
+                (ExtendedInstr::BaseInstr(Instr::Right), Some(count)) => {
+                    ExtendedInstr::Move(count as isize)
+                }
+                (ExtendedInstr::BaseInstr(Instr::Left), Some(count)) => {
+                    ExtendedInstr::Move(-(count as isize))
+                }
+                // A body which changes the cell by exactly one has an odd length.
+                (ExtendedInstr::SetToZeroPlus, Some(count)) if count % 2 == 1 => {
+                    ExtendedInstr::SetToZeroLong {
+                        increment: true,
+                        body_length: count,
+                    }
+                }
+                (ExtendedInstr::SetToZeroMinus, Some(count)) if count % 2 == 1 => {
+                    ExtendedInstr::SetToZeroLong {
+                        increment: false,
+                        body_length: count,
+                    }
+                }
+                (_, Some(_)) => return Err(CompileError::InvalidCount { index }),
+            };
+            instrs.extend(instr.to_base_instrs());
+        }
+        Program::new(instrs)
+    }
+
+    // Recompile the program so that "[]" is left as two base instructions
+    // instead of being transformed into LoopIfNonzero.
+    fn without_loop_if_nonzero(&self) -> Program {
+        let (extended_instrs, source_spans) = ExtendedInstr::new(&self.original_instrs, false);
+        let loop_dict = loop_dict(&extended_instrs).expect("program already compiled");
+        let no_op_loops = no_op_loops(&extended_instrs, &loop_dict);
+        let outer_loops = outer_loops(&extended_instrs);
+        let growing_run_loops = growing_run_loops(&extended_instrs, &loop_dict);
+        Program {
+            extended_instrs,
+            loop_dict,
+            source_spans,
+            no_op_loops,
+            outer_loops,
+            growing_run_loops,
+            ..self.clone()
+        }
+    }
+
+    fn get(&self, i: usize) -> Option<ExtendedInstr> {
+        self.instruction_at(i).copied()
+    }
+
+    /// Returns the extended instruction at index `i`, which is what the program
+    /// pointer of an ExecutionContext indexes into. Returns None if `i` is out
+    /// of bounds, which is where the program pointer is once the program halts.
+    pub fn instruction_at(&self, i: usize) -> Option<&ExtendedInstr> {
+        self.extended_instrs.get(i)
+    }
+
+    /// Returns the instruction at index `i` as it is displayed by the
+    /// visualizer (see `instruction_at`).
+    pub fn display_at(&self, i: usize) -> Option<String> {
+        self.instruction_at(i).map(|instr| instr.to_string())
+    }
+
+    /// The number of extended instructions in the program. This may be less
+    /// than the number of original instructions (see `ExtendedInstr::new`).
+    pub fn len(&self) -> usize {
+        self.extended_instrs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extended_instrs.is_empty()
+    }
+
+    fn matching_loop(&self, i: usize) -> Option<usize> {
+        self.loop_dict.get(&i).copied()
+    }
+
+    pub fn extended_instrs(&self) -> &[ExtendedInstr] {
+        &self.extended_instrs
+    }
+
+    pub fn original_instrs(&self) -> &[Instr] {
+        &self.original_instrs
+    }
+
+    /// Returns the range of indices into `original_instrs` that the extended
+    /// instruction at index `i` was created from, or None if `i` is out of
+    /// bounds. For example, the SetToZeroMinus in "+[-]" covers `1..4`.
+    pub fn source_span(&self, i: usize) -> Option<Range<usize>> {
+        self.source_spans.get(i).cloned()
+    }
+
+    /// Returns the byte offset in the program's text of the first original
+    /// instruction that the extended instruction at index `i` was created from,
+    /// or None if `i` is out of bounds. The text is the source passed to
+    /// `from_source`, or `to_string()` for programs compiled any other way.
+    /// For example, the SetToZeroMinus in "+ [-]" is at offset 2 if compiled
+    /// with `from_source`.
+    pub fn text_offset(&self, i: usize) -> Option<usize> {
+        self.source_span(i)
+            .map(|span| self.text_offsets[span.start])
+    }
+
+    /// Returns every extended instruction in the program along with the range
+    /// of indices into `original_instrs` it was created from, in program
+    /// order. This shows exactly how the source was folded. For example,
+    /// "+[-]>>" gives `[(0..1, BaseInstr(Plus)), (1..4, SetToZeroMinus),
+    /// (4..6, Move(2))]`.
+    pub fn folding_report(&self) -> Vec<(Range<usize>, ExtendedInstr)> {
+        self.source_spans
+            .iter()
+            .cloned()
+            .zip(self.extended_instrs.iter().copied())
+            .collect()
+    }
+
+    /// Returns true if the program contains any Input instructions.
+    pub fn reads_input(&self) -> bool {
+        self.reads_input
+    }
+
+    /// Returns true if the program contains no loops. Such a program always
+    /// halts after executing each of its instructions exactly once.
+    pub fn is_loop_free(&self) -> bool {
+        !self.uses(Instr::StartLoop)
+    }
+
+    /// Returns true if `instr` appears anywhere in the program's source. This
+    /// looks at the original instructions, so "[-]" uses Minus even though it
+    /// is folded into SetToZeroMinus.
+    pub fn uses(&self, instr: Instr) -> bool {
+        self.original_instrs.contains(&instr)
+    }
+
+    /// Count how many times each base instruction appears in the program's
+    /// source, in the order Plus, Minus, Left, Right, StartLoop, EndLoop (the
+    /// order of `Instr`). Output and Input are not counted. This is static,
+    /// unlike the profile, which counts how often each instruction runs.
+    pub fn instruction_histogram(&self) -> [usize; 6] {
+        let mut histogram = [0; 6];
+        for &instr in &self.original_instrs {
+            if let Some(count) = histogram.get_mut(instr.to_packed() as usize) {
+                *count += 1;
+            }
+        }
+        histogram
+    }
+
+    /// Return the maximum number of loops which are open at once. For example,
+    /// "[[[]]]" has a nesting depth of 3, "+[-]" has a depth of 1 and a program
+    /// with no loops has a depth of 0. Folded loops such as "[-]" still count as
+    /// a level of nesting, so this is the same as the depth of the source.
+    pub fn nesting_depth(&self) -> usize {
+        let mut depth = 0;
+        let mut max_depth = 0;
+        for instr in &self.extended_instrs {
+            match instr {
+                ExtendedInstr::BaseInstr(Instr::StartLoop) => {
+                    depth += 1;
+                    max_depth = max_depth.max(depth);
+                }
+                ExtendedInstr::BaseInstr(Instr::EndLoop) => depth -= 1,
+                ExtendedInstr::LoopIfNonzero
+                | ExtendedInstr::SetToZeroPlus
+                | ExtendedInstr::SetToZeroMinus
+                | ExtendedInstr::SetToZeroLong { .. } => max_depth = max_depth.max(depth + 1),
+                ExtendedInstr::BaseInstr(_) | ExtendedInstr::Move(_) => (),
+            }
+        }
+        max_depth
+    }
+
+    /// Return the program with every Left and Right instruction swapped. On a
+    /// tape which is infinite in both directions, this program behaves exactly
+    /// like the original, mirrored around the starting cell. This tape is only
+    /// infinite to the right, so see `canonical_form` for when the two programs
+    /// are actually equivalent.
+    pub fn reflected(&self) -> Program {
+        let instrs = self.original_instrs.iter().map(|instr| match instr {
+            Instr::Left => Instr::Right,
+            Instr::Right => Instr::Left,
+            instr => *instr,
+        });
+        Program::new(instrs).expect("reflection preserves loop structure")
+    }
+
+    /// Return the program with adjacent pairs of instructions which cancel out,
+    /// such as "+-", "-+" and "><", removed. Removing a pair can make another
+    /// pair adjacent, so "+><-" simplifies to the empty program. This is meant
+    /// for presenting programs, such as busy beavers, more readably.
+    ///
+    /// The simplified program halts exactly when the original does, and leaves
+    /// the same tape, but it takes fewer steps, so it is not a busy beaver of
+    /// its new length. "<>" is not removed, since a "<" on the first cell does
+    /// nothing, so "<>" moves right there.
+    pub fn simplify_identity_runs(&self) -> Program {
+        let mut instrs: Vec<Instr> = vec![];
+        for &instr in &self.original_instrs {
+            let cancels = matches!(
+                (instrs.last(), instr),
+                (Some(Instr::Plus), Instr::Minus)
+                    | (Some(Instr::Minus), Instr::Plus)
+                    | (Some(Instr::Right), Instr::Left)
+            );
+            if cancels {
+                instrs.pop();
+            } else {
+                instrs.push(instr);
+            }
+        }
+        Program::new(instrs).expect("removing pairs preserves loop structure")
+    }
+
+    /// Render the program with one loop brace per line, indenting the body of
+    /// each loop by four spaces. Runs of non-brace instructions stay on one
+    /// line. Unlike the Display impl, this is intended for reading long programs.
+    pub fn pretty(&self) -> String {
+        fn push_line(lines: &mut Vec<String>, depth: usize, text: &str) {
+            lines.push(format!("{}{}", "    ".repeat(depth), text));
+        }
+
+        let mut lines = vec![];
+        let mut depth = 0;
+        let mut run = String::new();
+        for &instr in &self.original_instrs {
+            match instr {
+                Instr::StartLoop | Instr::EndLoop => {
+                    if !run.is_empty() {
+                        push_line(&mut lines, depth, &run);
+                        run.clear();
+                    }
+                    if instr == Instr::StartLoop {
+                        push_line(&mut lines, depth, "[");
+                        depth += 1;
+                    } else {
+                        depth -= 1;
+                        push_line(&mut lines, depth, "]");
+                    }
+                }
+                _ => run.push_str(&instr.to_string()),
+            }
+        }
+        if !run.is_empty() {
+            push_line(&mut lines, depth, &run);
+        }
+        lines.join("\n")
+    }
+
+    /// Pack a list of programs into a compact binary corpus. The layout is a
+    /// sequence of records, one per program, with no header or padding between
+    /// records. Each record is:
+    /// - The number of instructions `n` in the program, as a little-endian u32.
+    /// - `ceil(3 * n / 8)` bytes containing the instructions, 3 bits each. The
+    ///   instructions are packed starting from the least significant bit of the
+    ///   first byte, and any unused bits in the final byte are zero.
+    ///
+    /// Each instruction is encoded as follows:
+    /// `+` = 0, `-` = 1, `<` = 2, `>` = 3, `[` = 4, `]` = 5, `.` = 6, `,` = 7
+    ///
+    /// Returns an error if a program has more than u32::MAX instructions.
+    pub fn pack_many(programs: &[Program]) -> Result<Vec<u8>, PackError> {
+        let mut bytes = vec![];
+        for program in programs {
+            let instrs = program.original_instrs();
+            let length = u32::try_from(instrs.len()).map_err(|_| PackError::ProgramTooLong {
+                length: instrs.len(),
+            })?;
+            bytes.extend(length.to_le_bytes());
+
+            let mut packed = vec![0; packed_length(instrs.len())];
+            for (i, instr) in instrs.iter().enumerate() {
+                let code = instr.to_packed() as u32;
+                let bit = i * 3;
+                // An instruction may straddle two bytes, so spill any high bits into the next byte.
+                let byte = bit / 8;
+                let shifted = code << (bit % 8);
+                packed[byte] |= shifted as u8;
+                if shifted > 0xFF {
+                    packed[byte + 1] |= (shifted >> 8) as u8;
+                }
+            }
+            bytes.extend(packed);
+        }
+        Ok(bytes)
+    }
+
+    /// Unpack a corpus created by `pack_many`. See `pack_many` for the layout.
+    pub fn unpack_many(mut bytes: &[u8]) -> Result<Vec<Program>, UnpackError> {
+        let mut programs = vec![];
+        while !bytes.is_empty() {
+            if bytes.len() < 4 {
+                return Err(UnpackError::Truncated);
+            }
+            let (length, rest) = bytes.split_at(4);
+            let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+
+            let packed_length = packed_length(length);
+            if rest.len() < packed_length {
+                return Err(UnpackError::Truncated);
+            }
+            let (packed, rest) = rest.split_at(packed_length);
+
+            let mut instrs = Vec::with_capacity(length);
+            for i in 0..length {
+                let bit = i * 3;
+                let byte = bit / 8;
+                let mut code = (packed[byte] as u32) >> (bit % 8);
+                if let Some(&next_byte) = packed.get(byte + 1) {
+                    code |= (next_byte as u32) << (8 - bit % 8);
+                }
+                let code = (code & 0b111) as u8;
+                let instr = Instr::from_packed(code).ok_or(UnpackError::InvalidInstr(code))?;
+                instrs.push(instr);
+            }
+            programs.push(Program::new(instrs).map_err(UnpackError::CompileError)?);
+            bytes = rest;
+        }
+        Ok(programs)
+    }
+}
+
+// The position reached by moving `offset` cells from `position` on a ring of
+// `length` cells.
+fn ring_position(position: usize, offset: isize, length: usize) -> usize {
+    (position as isize + offset).rem_euclid(length as isize) as usize
+}
+
+// The position reached by moving `offset` cells from `position` on a bounded
+// tape of `length` cells, stopping at the edges.
+fn bounded_position(position: usize, offset: isize, length: usize) -> usize {
+    (position as isize + offset).clamp(0, length as isize - 1) as usize
+}
+
+// Writes the values of a Snapshot or ExecutionStatus. Every integer is written
+// as a little endian u64, and every list is prefixed with its length.
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn usize(&mut self, value: usize) {
+        self.0.extend((value as u64).to_le_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.0.extend(bytes);
+    }
+
+    fn tape_policy(&mut self, tape_policy: TapePolicy) {
+        match tape_policy {
+            TapePolicy::Unbounded => self.0.push(0),
+            TapePolicy::Ring(length) => {
+                self.0.push(1);
+                self.usize(length);
+            }
+            TapePolicy::Bounded(length) => {
+                self.0.push(2);
+                self.usize(length);
+            }
+            TapePolicy::TwoWay => self.0.push(3),
+            TapePolicy::Custom(_) => unreachable!("loop spans are not recorded on custom tapes"),
+        }
+    }
+
+    fn loop_span(&mut self, span: &LoopSpan) {
+        self.bytes(&span.memory_at_loop_start);
+        self.usize(span.starting_memory_pointer);
+        self.usize(span.current_memory_pointer);
+        self.usize(span.min_index);
+        self.usize(span.max_index);
+        self.tape_policy(span.tape_policy);
+        self.bool(span.guard_written);
+    }
+
+    fn loop_spans(&mut self, spans: &[LoopSpan]) {
+        self.usize(spans.len());
+        for span in spans {
+            self.loop_span(span);
+        }
+    }
+}
+
+// Reads the values written by an Encoder.
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        if self.0.len() < length {
+            return Err(DecodeError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn tag(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.tag()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let length = self.usize()?;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    fn tape_policy(&mut self) -> Result<TapePolicy, DecodeError> {
+        match self.tag()? {
+            0 => Ok(TapePolicy::Unbounded),
+            1 => Ok(TapePolicy::Ring(self.usize()?)),
+            2 => Ok(TapePolicy::Bounded(self.usize()?)),
+            3 => Ok(TapePolicy::TwoWay),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn loop_span(&mut self) -> Result<LoopSpan, DecodeError> {
+        Ok(LoopSpan {
+            memory_at_loop_start: self.bytes()?,
+            starting_memory_pointer: self.usize()?,
+            current_memory_pointer: self.usize()?,
+            min_index: self.usize()?,
+            max_index: self.usize()?,
+            tape_policy: self.tape_policy()?,
+            guard_written: self.bool()?,
+        })
+    }
+
+    fn loop_spans(&mut self) -> Result<Vec<LoopSpan>, DecodeError> {
+        // Don't trust the length for the capacity, since it may be corrupt.
+        (0..self.usize()?).map(|_| self.loop_span()).collect()
+    }
+
+    fn finish(&self) -> Result<(), DecodeError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingBytes)
+        }
     }
 }
 
+// The number of bytes needed to hold `length` instructions packed at 3 bits each.
+fn packed_length(length: usize) -> usize {
+    (length * 3).div_ceil(8)
+}
+
 impl Display for Program {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", Instr::to_string(&self.original_instrs),)
@@ -519,6 +3146,14 @@ impl TryFrom<&str> for Program {
     }
 }
 
+impl std::str::FromStr for Program {
+    type Err = CompileError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        Program::try_from(string)
+    }
+}
+
 impl TryFrom<&[u8]> for Program {
     type Error = CompileError;
 
@@ -539,19 +3174,44 @@ pub enum ExtendedInstr {
     LoopIfNonzero,
     SetToZeroPlus,
     SetToZeroMinus,
+    /// A loop whose body only contains Plus and Minus instructions and adds
+    /// exactly one to the cell (if `increment` is true) or subtracts exactly
+    /// one from it, such as "[+-+]" or "[--+]". Like "[+]" and "[-]", this
+    /// always clears the cell, no matter what value the cell starts at, since
+    /// the cell is only checked at the end of each pass through the body.
+    /// `body_length` is the number of instructions between the braces.
+    SetToZeroLong {
+        increment: bool,
+        body_length: usize,
+    },
+    /// Move the memory pointer by the given number of cells, which is positive
+    /// for Right and negative for Left. This represents a run of two or more
+    /// consecutive Right or Left instructions.
+    Move(isize),
 }
 
 impl ExtendedInstr {
     /// Transform a list of base Brainfuck instructions into a list of extended
     /// Brainfuck instructions. The following constructs are transformed:
-    /// [] -> LoopIfNonzero
-    fn new(program: &[Instr]) -> Vec<ExtendedInstr> {
+    /// [] -> LoopIfNonzero (only if fold_loop_if_nonzero is true)
+    /// [+] -> SetToZeroPlus
+    /// [-] -> SetToZeroMinus
+    /// [+-+], [--+], etc. -> SetToZeroLong (see `clear_loop_body`)
+    /// n consecutive Right or Left instructions (n >= 2) -> Move(n) or Move(-n)
+    /// Also returns, for each extended instruction, the range of indices into
+    /// `program` of the base instructions it was created from.
+    fn new(
+        program: &[Instr],
+        fold_loop_if_nonzero: bool,
+    ) -> (Vec<ExtendedInstr>, Vec<Range<usize>>) {
         let mut extended_instrs = vec![];
+        let mut source_spans = vec![];
         let mut i = 0;
         while i < program.len() {
             let this_instr = program[i];
             let next_instr = program.get(i + 1);
             let next_next_instr = program.get(i + 2);
+            let start = i;
             let extended_instr = match (this_instr, next_instr, next_next_instr) {
                 (Instr::StartLoop, Some(Instr::Plus), Some(Instr::EndLoop)) => {
                     i += 3;
@@ -561,118 +3221,594 @@ impl ExtendedInstr {
                     i += 3;
                     ExtendedInstr::SetToZeroMinus
                 }
-                (Instr::StartLoop, Some(Instr::EndLoop), _) => {
+                (Instr::StartLoop, Some(Instr::EndLoop), _) if fold_loop_if_nonzero => {
                     i += 2;
                     ExtendedInstr::LoopIfNonzero
                 }
+                (Instr::StartLoop, _, _) if clear_loop_body(&program[i + 1..]).is_some() => {
+                    let (increment, body_length) = clear_loop_body(&program[i + 1..]).unwrap();
+                    i += body_length + 2;
+                    ExtendedInstr::SetToZeroLong {
+                        increment,
+                        body_length,
+                    }
+                }
+                (Instr::Left | Instr::Right, Some(&next_instr), _) if next_instr == this_instr => {
+                    let run_length = program[i..]
+                        .iter()
+                        .take_while(|&&instr| instr == this_instr)
+                        .count();
+                    i += run_length;
+                    let run_length = run_length as isize;
+                    if this_instr == Instr::Right {
+                        ExtendedInstr::Move(run_length)
+                    } else {
+                        ExtendedInstr::Move(-run_length)
+                    }
+                }
                 (instr, _, _) => {
                     i += 1;
                     ExtendedInstr::BaseInstr(instr)
                 }
             };
             extended_instrs.push(extended_instr);
+            source_spans.push(start..i);
         }
-        extended_instrs
+        (extended_instrs, source_spans)
     }
 }
 
-impl Display for ExtendedInstr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ExtendedInstr::BaseInstr(base_instr) => write!(f, "{}", base_instr),
-            ExtendedInstr::LoopIfNonzero => write!(f, "L"),
-            ExtendedInstr::SetToZeroPlus => write!(f, "⊞"),
-            ExtendedInstr::SetToZeroMinus => write!(f, "⊟"),
-        }
+// The number of iterations a loop which adds one to the cell (if `increment` is
+// true) or subtracts one from it needs to clear a cell with the given value.
+fn clear_iterations(increment: bool, cell_value: u8) -> usize {
+    if increment {
+        0_u8.wrapping_sub(cell_value) as usize
+    } else {
+        cell_value as usize
     }
 }
 
-fn loop_dict(program: &[ExtendedInstr]) -> Result<HashMap<usize, usize>, CompileError> {
-    use Instr::*;
-    let mut hashmap = HashMap::new();
-    let mut startloop_locs = Vec::new();
-    for (i, &instr) in program.iter().enumerate() {
+// If `rest` starts with a loop body which is statically guaranteed to clear the
+// cell, followed by an EndLoop, return whether the body increments the cell and
+// the length of the body. Only bodies made entirely of Plus and Minus which
+// change the cell by exactly one are recognized. Bodies with pointer motions
+// that net to zero, such as "[-<>]", are not: on an Unbounded tape a Left
+// does nothing on the first cell and a Right can grow the tape, so neither is
+// statically a no-op.
+fn clear_loop_body(rest: &[Instr]) -> Option<(bool, usize)> {
+    let mut net: isize = 0;
+    for (body_length, instr) in rest.iter().enumerate() {
         match instr {
-            ExtendedInstr::BaseInstr(instr) => match instr {
-                Plus | Minus | Left | Right => (),
-                StartLoop => {
-                    startloop_locs.push(i);
-                }
-                EndLoop => {
-                    match startloop_locs.pop() {
-                        Some(start_loop) => {
-                            hashmap.insert(i, start_loop);
-                            hashmap.insert(start_loop, i);
-                        }
-                        None => return Err(CompileError::UnmatchedEndLoop { index: i }),
-                    };
-                }
-            },
-            _ => (),
+            Instr::Plus => net += 1,
+            Instr::Minus => net -= 1,
+            Instr::EndLoop if body_length > 0 && net.abs() == 1 => {
+                return Some((net == 1, body_length));
+            }
+            _ => return None,
         }
     }
-    if !startloop_locs.is_empty() {
-        Err(CompileError::UnmatchedStartLoops {
-            indicies: startloop_locs,
-        })
-    } else {
-        Ok(hashmap)
-    }
+    None
 }
 
-/// The set of Brainfuck instructions. These are all of the possible
-/// instructions in a Brainfuck program, before any optimizations are applied.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum Instr {
-    Plus,
-    Minus,
-    Left,
-    Right,
-    StartLoop,
-    EndLoop,
+impl ExtendedInstr {
+    /// Returns the number of base Brainfuck steps this instruction represents
+    /// when executed with the current memory cell set to `cell_value`.
+    /// - BaseInstr: Always one step.
+    /// - LoopIfNonzero: One step if the loop is skipped, two steps (one pass
+    ///   through "[]") if it is taken.
+    /// - SetToZeroPlus/SetToZeroMinus: One step for the initial "[", then two
+    ///   steps ("+]" or "-]") for every iteration needed to reach zero.
+    /// - SetToZeroLong: Like SetToZeroPlus/SetToZeroMinus, but each iteration
+    ///   is the body length plus one steps.
+    /// - Move: One step per cell moved.
+    pub fn base_step_cost(&self, cell_value: u8) -> usize {
+        match self {
+            ExtendedInstr::BaseInstr(_) => 1,
+            ExtendedInstr::LoopIfNonzero => {
+                if cell_value == 0 {
+                    1
+                } else {
+                    2
+                }
+            }
+            ExtendedInstr::SetToZeroPlus => 1 + 2 * (0_u8.wrapping_sub(cell_value) as usize),
+            ExtendedInstr::SetToZeroMinus => 1 + 2 * cell_value as usize,
+            ExtendedInstr::SetToZeroLong {
+                increment,
+                body_length,
+            } => 1 + (body_length + 1) * clear_iterations(*increment, cell_value),
+            ExtendedInstr::Move(offset) => offset.unsigned_abs(),
+        }
+    }
 }
 
-impl Instr {
-    // Transform a list of instructions into a human readable String.
-    pub fn to_string(program: &[Instr]) -> String {
-        program.iter().map(|instr| instr.to_string()).collect()
+impl Display for ExtendedInstr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtendedInstr::BaseInstr(base_instr) => write!(f, "{}", base_instr),
+            ExtendedInstr::LoopIfNonzero => write!(f, "L"),
+            ExtendedInstr::SetToZeroPlus => write!(f, "⊞"),
+            ExtendedInstr::SetToZeroMinus => write!(f, "⊟"),
+            ExtendedInstr::SetToZeroLong {
+                increment: true,
+                body_length,
+            } => write!(f, "⊞{}", body_length),
+            ExtendedInstr::SetToZeroLong {
+                increment: false,
+                body_length,
+            } => write!(f, "⊟{}", body_length),
+            ExtendedInstr::Move(offset) if *offset < 0 => write!(f, "<{}", offset.unsigned_abs()),
+            ExtendedInstr::Move(offset) => write!(f, ">{}", offset),
+        }
     }
 }
 
-impl TryFrom<char> for Instr {
+impl TryFrom<char> for ExtendedInstr {
     type Error = ();
 
+    /// Parse a single glyph as displayed by `Display`: a base instruction
+    /// (including "." and ","), "L", "⊞" or "⊟". The counts which follow Move
+    /// and SetToZeroLong glyphs are parsed by `Program::from_extended`.
     fn try_from(value: char) -> Result<Self, Self::Error> {
         match value {
-            '+' => Ok(Instr::Plus),
-            '-' => Ok(Instr::Minus),
-            '<' => Ok(Instr::Left),
-            '>' => Ok(Instr::Right),
-            '[' => Ok(Instr::StartLoop),
-            ']' => Ok(Instr::EndLoop),
-            _ => Err(()),
+            'L' => Ok(ExtendedInstr::LoopIfNonzero),
+            '⊞' => Ok(ExtendedInstr::SetToZeroPlus),
+            '⊟' => Ok(ExtendedInstr::SetToZeroMinus),
+            _ => Instr::try_from_with_io(value).map(ExtendedInstr::BaseInstr),
         }
     }
 }
 
-impl TryFrom<u8> for Instr {
-    type Error = ();
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        Self::try_from(value as char)
+impl ExtendedInstr {
+    /// Returns base instructions which fold back into this instruction. These
+    /// are the original instructions, except for SetToZeroLong, whose body is
+    /// rebuilt as the increment or decrement followed by pairs which cancel
+    /// out, such as "[++-]" for a body of length 3.
+    pub fn to_base_instrs(&self) -> Vec<Instr> {
+        match *self {
+            ExtendedInstr::BaseInstr(instr) => vec![instr],
+            ExtendedInstr::LoopIfNonzero => vec![Instr::StartLoop, Instr::EndLoop],
+            ExtendedInstr::SetToZeroPlus => vec![Instr::StartLoop, Instr::Plus, Instr::EndLoop],
+            ExtendedInstr::SetToZeroMinus => {
+                vec![Instr::StartLoop, Instr::Minus, Instr::EndLoop]
+            }
+            ExtendedInstr::SetToZeroLong {
+                increment,
+                body_length,
+            } => {
+                let (forward, backward) = if increment {
+                    (Instr::Plus, Instr::Minus)
+                } else {
+                    (Instr::Minus, Instr::Plus)
+                };
+                let mut instrs = vec![Instr::StartLoop, forward];
+                for _ in 0..body_length / 2 {
+                    instrs.extend([forward, backward]);
+                }
+                instrs.push(Instr::EndLoop);
+                instrs
+            }
+            ExtendedInstr::Move(offset) => {
+                let instr = if offset < 0 {
+                    Instr::Left
+                } else {
+                    Instr::Right
+                };
+                vec![instr; offset.unsigned_abs()]
+            }
+        }
     }
 }
 
-impl Display for Instr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use Instr::*;
-        let char = match self {
-            Plus => '+',
-            Minus => '-',
+// Find every loop whose body only contains Plus, Minus, Left, Right and Move
+// instructions, and whose body adds zero to every cell and moves the memory
+// pointer back to where it started. Once such a loop is entered, the tape is
+// the same at the start of every iteration, so the loop never exits. Returns a
+// map from the StartLoop of each loop to the lowest memory pointer at which the
+// loop can start without the body hitting the left edge of the tape (where a
+// Left does nothing, which would break the analysis).
+fn no_op_loops(
+    program: &[ExtendedInstr],
+    loop_dict: &HashMap<usize, usize>,
+) -> HashMap<usize, usize> {
+    let mut no_op_loops = HashMap::new();
+    'loops: for (i, &instr) in program.iter().enumerate() {
+        if instr != ExtendedInstr::BaseInstr(Instr::StartLoop) {
+            continue;
+        }
+        let end_loop = loop_dict[&i];
+        let mut offset: isize = 0;
+        let mut min_offset: isize = 0;
+        let mut deltas: HashMap<isize, u8> = HashMap::new();
+        for instr in &program[i + 1..end_loop] {
+            match instr {
+                ExtendedInstr::BaseInstr(Instr::Plus) => {
+                    let delta = deltas.entry(offset).or_insert(0);
+                    *delta = delta.wrapping_add(1);
+                }
+                ExtendedInstr::BaseInstr(Instr::Minus) => {
+                    let delta = deltas.entry(offset).or_insert(0);
+                    *delta = delta.wrapping_sub(1);
+                }
+                ExtendedInstr::BaseInstr(Instr::Left) => offset -= 1,
+                ExtendedInstr::BaseInstr(Instr::Right) => offset += 1,
+                ExtendedInstr::Move(move_offset) => offset += move_offset,
+                _ => continue 'loops,
+            }
+            min_offset = min_offset.min(offset);
+        }
+        if offset == 0 && deltas.values().all(|&delta| delta == 0) {
+            no_op_loops.insert(i, min_offset.unsigned_abs());
+        }
+    }
+    no_op_loops
+}
+
+// Find the StartLoop of every loop which isn't nested inside another loop.
+fn outer_loops(program: &[ExtendedInstr]) -> HashSet<usize> {
+    let mut outer_loops = HashSet::new();
+    let mut depth = 0;
+    for (i, instr) in program.iter().enumerate() {
+        match instr {
+            ExtendedInstr::BaseInstr(Instr::StartLoop) => {
+                if depth == 0 {
+                    outer_loops.insert(i);
+                }
+                depth += 1;
+            }
+            ExtendedInstr::BaseInstr(Instr::EndLoop) => depth -= 1,
+            _ => (),
+        }
+    }
+    outer_loops
+}
+
+// Find the StartLoop of every loop the growing run detector can check: its body
+// contains at least one scan loop (see `scan_direction`), and otherwise only
+// changes cells and moves the pointer.
+fn growing_run_loops(
+    program: &[ExtendedInstr],
+    loop_dict: &HashMap<usize, usize>,
+) -> HashSet<usize> {
+    let mut growing_run_loops = HashSet::new();
+    'loops: for (i, &instr) in program.iter().enumerate() {
+        if instr != ExtendedInstr::BaseInstr(Instr::StartLoop) {
+            continue;
+        }
+        let end_loop = loop_dict[&i];
+        let mut has_scan = false;
+        let mut j = i + 1;
+        while j < end_loop {
+            if scan_direction(&program[j..]).is_some() {
+                has_scan = true;
+                j += 3;
+                continue;
+            }
+            match program[j] {
+                ExtendedInstr::BaseInstr(
+                    Instr::Plus | Instr::Minus | Instr::Left | Instr::Right,
+                )
+                | ExtendedInstr::Move(_)
+                | ExtendedInstr::SetToZeroPlus
+                | ExtendedInstr::SetToZeroMinus
+                | ExtendedInstr::SetToZeroLong { .. } => j += 1,
+                _ => continue 'loops,
+            }
+        }
+        if has_scan {
+            growing_run_loops.insert(i);
+        }
+    }
+    growing_run_loops
+}
+
+// If `program` starts with a loop whose body is a single Left or Right, such as
+// "[<]", returns the direction it scans in: -1 for Left and 1 for Right. The
+// loop stops on the first zero cell in that direction.
+fn scan_direction(program: &[ExtendedInstr]) -> Option<isize> {
+    use Instr::*;
+    match program {
+        [ExtendedInstr::BaseInstr(StartLoop), ExtendedInstr::BaseInstr(Left), ExtendedInstr::BaseInstr(EndLoop), ..] => {
+            Some(-1)
+        }
+        [ExtendedInstr::BaseInstr(StartLoop), ExtendedInstr::BaseInstr(Right), ExtendedInstr::BaseInstr(EndLoop), ..] => {
+            Some(1)
+        }
+        _ => None,
+    }
+}
+
+// A tape used by the growing run detector, which stands for every tape made up
+// of `prefix`, then a run of nonzero cells of any length (including none) and
+// any values, then `suffix`, then zeros. Each method returns None if the result
+// isn't the same for every one of those tapes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RunTape {
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+    pointer: RunPointer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RunPointer {
+    Prefix(usize),
+    // The cell just after the prefix, which is either the first cell of the run
+    // or, if the run is empty, the first cell of the suffix.
+    RunStart,
+    Suffix(usize),
+}
+
+impl RunTape {
+    // Run one iteration of a loop with the given body, which only contains the
+    // instructions allowed by `growing_run_loops`, and then the loop's EndLoop.
+    // Returns None if the loop could exit, or if the pointer doesn't end up
+    // back before the run with only zeros after it, since the next iteration
+    // then wouldn't start on the same kind of tape.
+    fn run_iteration(&mut self, body: &[ExtendedInstr]) -> Option<()> {
+        let mut i = 0;
+        while i < body.len() {
+            if let Some(direction) = scan_direction(&body[i..]) {
+                self.scan(direction)?;
+                i += 3;
+                continue;
+            }
+            match body[i] {
+                ExtendedInstr::BaseInstr(Instr::Plus) => {
+                    let cell = self.cell_mut()?;
+                    *cell = cell.wrapping_add(1);
+                }
+                ExtendedInstr::BaseInstr(Instr::Minus) => {
+                    let cell = self.cell_mut()?;
+                    *cell = cell.wrapping_sub(1);
+                }
+                ExtendedInstr::BaseInstr(Instr::Left) => self.move_by(-1)?,
+                ExtendedInstr::BaseInstr(Instr::Right) => self.move_by(1)?,
+                ExtendedInstr::Move(offset) => self.move_by(offset)?,
+                ExtendedInstr::SetToZeroPlus
+                | ExtendedInstr::SetToZeroMinus
+                | ExtendedInstr::SetToZeroLong { .. } => *self.cell_mut()? = 0,
+                _ => return None,
+            }
+            i += 1;
+        }
+
+        match self.pointer {
+            RunPointer::Prefix(pointer) if self.prefix[pointer] != 0 => (),
+            _ => return None,
+        }
+        // The nonzero cells at the start of the suffix join the end of the run.
+        let joined = self.suffix.iter().take_while(|&&cell| cell != 0).count();
+        if self.suffix[joined..].iter().any(|&cell| cell != 0) {
+            return None;
+        }
+        self.suffix.clear();
+        Some(())
+    }
+
+    fn suffix_cell(&self, index: usize) -> u8 {
+        self.suffix.get(index).copied().unwrap_or(0)
+    }
+
+    // The cell under the pointer, which is unknown at the start of the run.
+    fn cell_mut(&mut self) -> Option<&mut u8> {
+        match self.pointer {
+            RunPointer::Prefix(pointer) => Some(&mut self.prefix[pointer]),
+            RunPointer::RunStart => None,
+            RunPointer::Suffix(pointer) => {
+                if pointer >= self.suffix.len() {
+                    self.suffix.resize(pointer + 1, 0);
+                }
+                Some(&mut self.suffix[pointer])
+            }
+        }
+    }
+
+    // Move the pointer like an Unbounded tape does, stopping at the first cell.
+    // Moving into the run anywhere but its start can't be followed, since the
+    // length of the run is unknown.
+    fn move_by(&mut self, offset: isize) -> Option<()> {
+        let distance = offset.unsigned_abs();
+        self.pointer = match (self.pointer, offset < 0) {
+            (RunPointer::Prefix(pointer), true) => {
+                RunPointer::Prefix(pointer.saturating_sub(distance))
+            }
+            (RunPointer::Prefix(pointer), false) => {
+                match (pointer + distance).cmp(&self.prefix.len()) {
+                    std::cmp::Ordering::Less => RunPointer::Prefix(pointer + distance),
+                    std::cmp::Ordering::Equal => RunPointer::RunStart,
+                    std::cmp::Ordering::Greater => return None,
+                }
+            }
+            (RunPointer::RunStart, true) => {
+                RunPointer::Prefix(self.prefix.len().saturating_sub(distance))
+            }
+            (RunPointer::RunStart, false) => return None,
+            (RunPointer::Suffix(pointer), true) => {
+                RunPointer::Suffix(pointer.checked_sub(distance)?)
+            }
+            (RunPointer::Suffix(pointer), false) => RunPointer::Suffix(pointer + distance),
+        };
+        Some(())
+    }
+
+    // Run a scan loop in the given direction (see `scan_direction`), which
+    // stops on the first zero cell. Every cell of the run is nonzero, so
+    // scanning into the run always crosses all of it.
+    fn scan(&mut self, direction: isize) -> Option<()> {
+        let first_zero_in_suffix = |tape: &RunTape, from: usize| {
+            (from..)
+                .find(|&index| tape.suffix_cell(index) == 0)
+                .unwrap()
+        };
+        let last_zero_in_prefix = |tape: &RunTape, to: usize| {
+            tape.prefix[..=to]
+                .iter()
+                .rposition(|&cell| cell == 0)
+                .map(RunPointer::Prefix)
+        };
+        self.pointer = match (self.pointer, direction > 0) {
+            (RunPointer::Prefix(pointer), true) => {
+                match self.prefix[pointer..].iter().position(|&cell| cell == 0) {
+                    Some(zero) => RunPointer::Prefix(pointer + zero),
+                    None => RunPointer::Suffix(first_zero_in_suffix(self, 0)),
+                }
+            }
+            (RunPointer::RunStart, true) => RunPointer::Suffix(first_zero_in_suffix(self, 0)),
+            (RunPointer::Suffix(pointer), true) => {
+                RunPointer::Suffix(first_zero_in_suffix(self, pointer))
+            }
+            // Running into the first cell means scanning forever, which this
+            // doesn't try to prove.
+            (RunPointer::Prefix(pointer), false) => last_zero_in_prefix(self, pointer)?,
+            (RunPointer::RunStart, false) => return None,
+            (RunPointer::Suffix(pointer), false) => {
+                match (0..=pointer)
+                    .rev()
+                    .find(|&index| self.suffix_cell(index) == 0)
+                {
+                    Some(zero) => RunPointer::Suffix(zero),
+                    None => last_zero_in_prefix(self, self.prefix.len() - 1)?,
+                }
+            }
+        };
+        Some(())
+    }
+}
+
+fn loop_dict(program: &[ExtendedInstr]) -> Result<HashMap<usize, usize>, CompileError> {
+    use Instr::*;
+    let mut hashmap = HashMap::new();
+    let mut startloop_locs = Vec::new();
+    for (i, &instr) in program.iter().enumerate() {
+        match instr {
+            ExtendedInstr::BaseInstr(instr) => match instr {
+                Plus | Minus | Left | Right | Output | Input => (),
+                StartLoop => {
+                    startloop_locs.push(i);
+                }
+                EndLoop => {
+                    match startloop_locs.pop() {
+                        Some(start_loop) => {
+                            hashmap.insert(i, start_loop);
+                            hashmap.insert(start_loop, i);
+                        }
+                        None => return Err(CompileError::UnmatchedEndLoop { index: i }),
+                    };
+                }
+            },
+            _ => (),
+        }
+    }
+    if !startloop_locs.is_empty() {
+        Err(CompileError::UnmatchedStartLoops {
+            indicies: startloop_locs,
+        })
+    } else {
+        Ok(hashmap)
+    }
+}
+
+/// The set of Brainfuck instructions. These are all of the possible
+/// instructions in a Brainfuck program, before any optimizations are applied.
+/// Instructions are ordered in the same lexiographic order as
+/// `generate::lexiographic_order`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Instr {
+    Plus,
+    Minus,
+    Left,
+    Right,
+    StartLoop,
+    EndLoop,
+    Output,
+    Input,
+}
+
+impl Instr {
+    // Transform a list of instructions into a human readable String.
+    pub fn to_string(program: &[Instr]) -> String {
+        program.iter().map(|instr| instr.to_string()).collect()
+    }
+}
+
+impl Instr {
+    // The 3-bit code used for this instruction by Program::pack_many
+    fn to_packed(self) -> u8 {
+        match self {
+            Instr::Plus => 0,
+            Instr::Minus => 1,
+            Instr::Left => 2,
+            Instr::Right => 3,
+            Instr::StartLoop => 4,
+            Instr::EndLoop => 5,
+            Instr::Output => 6,
+            Instr::Input => 7,
+        }
+    }
+
+    // The inverse of to_packed. Returns None if the code is not a valid instruction.
+    fn from_packed(code: u8) -> Option<Instr> {
+        match code {
+            0 => Some(Instr::Plus),
+            1 => Some(Instr::Minus),
+            2 => Some(Instr::Left),
+            3 => Some(Instr::Right),
+            4 => Some(Instr::StartLoop),
+            5 => Some(Instr::EndLoop),
+            6 => Some(Instr::Output),
+            7 => Some(Instr::Input),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<char> for Instr {
+    type Error = ();
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            '+' => Ok(Instr::Plus),
+            '-' => Ok(Instr::Minus),
+            '<' => Ok(Instr::Left),
+            '>' => Ok(Instr::Right),
+            '[' => Ok(Instr::StartLoop),
+            ']' => Ok(Instr::EndLoop),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Instr {
+    // Like TryFrom<char>, but also accepts the I/O instructions "." and ",".
+    fn try_from_with_io(value: char) -> Result<Instr, ()> {
+        match value {
+            '.' => Ok(Instr::Output),
+            ',' => Ok(Instr::Input),
+            _ => Instr::try_from(value),
+        }
+    }
+}
+
+impl TryFrom<u8> for Instr {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::try_from(value as char)
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Instr::*;
+        let char = match self {
+            Plus => '+',
+            Minus => '-',
             Left => '<',
             Right => '>',
             StartLoop => '[',
             EndLoop => ']',
+            Output => '.',
+            Input => ',',
         };
         write!(f, "{}", char)
     }
@@ -682,8 +3818,23 @@ impl Display for Instr {
 /// compiled.
 #[derive(Debug, Clone)]
 pub enum CompileError {
-    UnmatchedEndLoop { index: usize },
-    UnmatchedStartLoops { indicies: Vec<usize> },
+    UnmatchedEndLoop {
+        index: usize,
+    },
+    UnmatchedStartLoops {
+        indicies: Vec<usize>,
+    },
+    /// The count at the character `index` (see `Program::from_extended`)
+    /// follows a glyph which doesn't take a count, or is even after a "⊞" or
+    /// "⊟", which no clear loop body has.
+    InvalidCount {
+        index: usize,
+    },
+    /// The count at the character `index` (see `Program::from_extended`) is
+    /// larger than `MAX_EXTENDED_COUNT`.
+    CountTooLarge {
+        index: usize,
+    },
 }
 
 impl Display for CompileError {
@@ -695,6 +3846,80 @@ impl Display for CompileError {
             CompileError::UnmatchedStartLoops { indicies } => {
                 write!(f, "One or more unmatched start loops at {:?}", indicies)
             }
+            CompileError::InvalidCount { index } => {
+                write!(f, "Invalid count after the instruction at {}", index)
+            }
+            CompileError::CountTooLarge { index } => write!(
+                f,
+                "The count after the instruction at {} is larger than {}",
+                index, MAX_EXTENDED_COUNT
+            ),
+        }
+    }
+}
+
+/// An error specifying why programs could not be packed by Program::pack_many.
+#[derive(Debug, Clone)]
+pub enum PackError {
+    /// The program has too many instructions for its length to fit in a u32.
+    ProgramTooLong { length: usize },
+}
+
+impl Display for PackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackError::ProgramTooLong { length } => {
+                write!(f, "Program is too long to pack ({} instructions)", length)
+            }
+        }
+    }
+}
+
+/// An error specifying why a corpus created by Program::pack_many could not
+/// be unpacked.
+#[derive(Debug, Clone)]
+pub enum UnpackError {
+    /// The corpus ended partway through a record.
+    Truncated,
+    /// The corpus contained a 3-bit code which is not a valid instruction.
+    InvalidInstr(u8),
+    /// One of the programs in the corpus has mismatched braces.
+    CompileError(CompileError),
+}
+
+impl Display for UnpackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnpackError::Truncated => write!(f, "Corpus is truncated"),
+            UnpackError::InvalidInstr(code) => write!(f, "Invalid instruction code {}", code),
+            UnpackError::CompileError(err) => write!(f, "Invalid program in corpus ({})", err),
+        }
+    }
+}
+
+/// An error specifying why bytes could not be decoded by `Snapshot::from_bytes`
+/// or `ExecutionStatus::from_bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes ended partway through a value.
+    Truncated,
+    /// A value was tagged with an unknown variant.
+    InvalidTag(u8),
+    /// The memory pointer of a snapshot is past the end of its tape.
+    PointerOutOfBounds,
+    /// There were bytes left over after the value.
+    TrailingBytes,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "Data is truncated"),
+            DecodeError::InvalidTag(tag) => write!(f, "Invalid tag {}", tag),
+            DecodeError::PointerOutOfBounds => {
+                write!(f, "Memory pointer is past the end of the tape")
+            }
+            DecodeError::TrailingBytes => write!(f, "Unexpected data after the end"),
         }
     }
 }
@@ -707,7 +3932,7 @@ mod tests {
         let mut ctx = ExecutionContext::new(program);
         for _ in 1..max_steps {
             let (_, state) = ctx.step();
-            if state != ExecutionStatus::Running {
+            if !state.is_running() {
                 return Some(state);
             }
         }
@@ -734,8 +3959,19 @@ mod tests {
     #[track_caller]
     fn assert_not_halting_loop_span(program: &str) {
         let program = Program::try_from(program).unwrap();
+        // The no-op loop and returned to start detectors would otherwise catch
+        // some of these first.
+        let detectors = Detectors {
+            no_op_loop: false,
+            returned_to_start: false,
+            ..Detectors::ALL
+        };
+        let mut ctx = ExecutionContext::with_detectors(&program, detectors);
+        let status = (1..9_999_999)
+            .map(|_| ctx.step().1)
+            .find(|status| !status.is_running());
         let result = matches!(
-            eval(&program, 9_999_999).unwrap(),
+            status.unwrap(),
             ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { .. })
         );
         assert!(result);
@@ -761,9 +3997,2060 @@ mod tests {
     }
 
     #[test]
-    fn test_non_halting_loop_span() {
-        assert_not_halting_loop_span("+[<]");
-        assert_not_halting_loop_span("+[-+]");
-        assert_not_halting_loop_span("+[[+]-]");
+    fn test_base_step_cost() {
+        use ExtendedInstr::*;
+        assert_eq!(BaseInstr(Instr::Plus).base_step_cost(0), 1);
+        assert_eq!(BaseInstr(Instr::StartLoop).base_step_cost(7), 1);
+        assert_eq!(LoopIfNonzero.base_step_cost(0), 1);
+        assert_eq!(LoopIfNonzero.base_step_cost(1), 2);
+        assert_eq!(SetToZeroPlus.base_step_cost(0), 1);
+        assert_eq!(SetToZeroPlus.base_step_cost(255), 3);
+        assert_eq!(SetToZeroPlus.base_step_cost(1), 1 + 2 * 255);
+        assert_eq!(SetToZeroMinus.base_step_cost(0), 1);
+        assert_eq!(SetToZeroMinus.base_step_cost(1), 3);
+        assert_eq!(SetToZeroMinus.base_step_cost(255), 1 + 2 * 255);
+        let long_plus = SetToZeroLong {
+            increment: true,
+            body_length: 3,
+        };
+        assert_eq!(long_plus.base_step_cost(0), 1);
+        assert_eq!(long_plus.base_step_cost(255), 5);
+        let long_minus = SetToZeroLong {
+            increment: false,
+            body_length: 3,
+        };
+        assert_eq!(long_minus.base_step_cost(2), 1 + 4 * 2);
+    }
+
+    #[test]
+    fn test_parse() {
+        let program: Program = "+[-]".parse().unwrap();
+        assert_eq!(
+            program.original_instrs(),
+            Program::try_from("+[-]").unwrap().original_instrs()
+        );
+        assert!(matches!(
+            "+]".parse::<Program>(),
+            Err(CompileError::UnmatchedEndLoop { index: 1 })
+        ));
+        assert!(matches!(
+            "[[]".parse::<Program>(),
+            Err(CompileError::UnmatchedStartLoops { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let programs: Vec<_> = crate::generate::brute_force_chain(0..6).take(500).collect();
+        let packed = Program::pack_many(&programs).unwrap();
+        let unpacked = Program::unpack_many(&packed).unwrap();
+        assert_eq!(programs.len(), unpacked.len());
+        for (program, unpacked) in programs.iter().zip(unpacked.iter()) {
+            assert_eq!(program.original_instrs(), unpacked.original_instrs());
+        }
+
+        assert!(matches!(
+            Program::unpack_many(&packed[..packed.len() - 1]),
+            Err(UnpackError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_loop_iteration_counts() {
+        let program = Program::try_from("++[>+++[->+<]<-]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        assert_eq!(ctx.loop_iteration_counts(), None);
+
+        ctx.enable_profiling();
+        while ctx.step().1.is_running() {}
+
+        let counts = ctx.loop_iteration_counts().unwrap();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&2], 2);
+        assert_eq!(counts[&7], 6);
+
+        // Extended instructions: + + + [ > + + ⊟ < - ] [ - > + < ] ⊞
+        let program = Program::try_from("+++[>++[-]<-][->+<]+[+]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        ctx.enable_profiling();
+        while ctx.step().1.is_running() {}
+
+        let counts = ctx.loop_iteration_counts().unwrap();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&3], 3);
+        // [-] runs twice per outer iteration
+        assert_eq!(counts[&7], 6);
+        // [->+<] is skipped, and [+] starts at 1 so it runs 255 times
+        assert_eq!(counts[&18], 255);
+    }
+
+    #[test]
+    fn test_step_count_with_budget() {
+        // Moves a growing counter right forever, without being detected.
+        let program = Program::try_from("+[[->+<]>+]").unwrap();
+        let run = |budget| step_count_with_budget(&program, budget).3;
+        assert_eq!(run(Budget::steps(10_000)), Some(UnknownReason::Steps));
+        let budget = Budget {
+            max_tape_length: Some(10),
+            ..Budget::steps(usize::MAX)
+        };
+        assert_eq!(run(budget), Some(UnknownReason::TapeLength));
+        assert_eq!(step_count_with_budget(&program, budget).2, 11);
+        let budget = Budget {
+            max_time: Some(Duration::ZERO),
+            ..Budget::steps(usize::MAX)
+        };
+        assert_eq!(run(budget), Some(UnknownReason::Time));
+
+        // Resolved programs have no reason, and match step_count.
+        let budget = Budget {
+            max_tape_length: Some(10),
+            max_time: Some(Duration::from_secs(60)),
+            max_steps: 10_000,
+            detectors: Detectors::ALL,
+        };
+        for program in ["+[-]>+", "+[>+]", "+[+>+<]"] {
+            let program = Program::try_from(program).unwrap();
+            let (status, steps, tape_length) = step_count(&program, 10_000);
+            assert_eq!(
+                step_count_with_budget(&program, budget),
+                (status, steps, tape_length, None)
+            );
+        }
+
+        // Without detectors, looping programs run until the budget runs out.
+        let budget = Budget {
+            detectors: Detectors::NONE,
+            ..budget
+        };
+        let program = Program::try_from("+[>+]").unwrap();
+        assert_eq!(
+            step_count_with_budget(&program, budget).3,
+            Some(UnknownReason::TapeLength)
+        );
+        let budget = Budget {
+            max_tape_length: None,
+            max_time: None,
+            ..budget
+        };
+        assert_eq!(
+            step_count_with_budget(&program, budget).3,
+            Some(UnknownReason::Steps)
+        );
+    }
+
+    #[test]
+    fn test_time_limit() {
+        // Only the program which never resolves is cut off. The time limit is
+        // per program, so the programs after it still get their full budget.
+        let budget = Budget {
+            max_time: Some(Duration::from_millis(50)),
+            ..Budget::steps(usize::MAX)
+        };
+        let start = Instant::now();
+        let results: Vec<_> = ["+[-]>+", "+[[->+<]>+]", "+[+>+<]", "+[>+]"]
+            .iter()
+            .map(|program| step_count_with_budget(&Program::try_from(*program).unwrap(), budget))
+            .collect();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(results[1].0, ExecutionStatus::Running);
+        assert_eq!(results[1].3, Some(UnknownReason::Time));
+        assert_eq!(results[0].0, ExecutionStatus::Halted);
+        assert_eq!(results[2].0, ExecutionStatus::Halted);
+        assert!(results[3].0.is_looping());
+        for i in [0, 2, 3] {
+            assert_eq!(results[i].3, None);
+        }
+    }
+
+    #[test]
+    fn test_memory_mut() {
+        let program = Program::try_from("+[->+<]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        ctx.step();
+        assert_eq!(ctx.tape(), &[1]);
+        ctx.memory_mut()[0] = 3;
+        let (_, _, status) = ctx.run_until(1000, |_| false);
+        assert_eq!(status, ExecutionStatus::Halted);
+        assert_eq!(ctx.tape_trimmed(), &[0, 3]);
+    }
+
+    #[test]
+    fn test_steps_taken() {
+        // Folded clears take many steps each, and the Move takes one.
+        let program = Program::try_from("+++[>>++[-]<<-]+[+]>[-]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        let mut total_steps = 0;
+        loop {
+            let (steps, status) = ctx.step();
+            total_steps += steps;
+            assert_eq!(ctx.steps_taken(), total_steps);
+            if !status.is_running() {
+                break;
+            }
+        }
+        assert_eq!(Some(ctx.steps_taken()), step_count(&program, 1000).1);
+
+        // The count is kept by snapshots.
+        let mut restored = ExecutionContext::new(&program);
+        restored.restore(&Snapshot::from_bytes(&ctx.snapshot().to_bytes()).unwrap());
+        assert_eq!(restored.steps_taken(), total_steps);
+    }
+
+    #[test]
+    fn test_step_count_saturates() {
+        // "+[+]" takes 1 + (1 + 2 * 255) = 512 steps
+        let program = Program::try_from("+[+]").unwrap();
+        assert_eq!(step_count(&program, 100).1, Some(512));
+
+        let mut ctx = ExecutionContext::new(&program);
+        ctx.steps_taken = usize::MAX - 100;
+        let (status, steps, _) = run_until_resolved(&mut ctx, 100);
+        assert_eq!(status, ExecutionStatus::Halted);
+        assert_eq!(steps, Some(usize::MAX));
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let program = Program::try_from(">+[>++>+++[-<]>>]+").unwrap();
+        let mut original = ExecutionContext::new(&program);
+        for _ in 0..40 {
+            original.step();
+        }
+        let snapshot = original.snapshot();
+        assert!(snapshot.total_cells_allocated() <= original.total_cells_allocated());
+
+        let mut restored = ExecutionContext::new(&program);
+        restored.restore(&snapshot);
+        assert_resumes_like(&mut original, &mut restored, usize::MAX);
+    }
+
+    // Step both contexts until the original one resolves or `max_calls` calls,
+    // and check that the restored one does exactly the same.
+    fn assert_resumes_like(
+        original: &mut ExecutionContext,
+        restored: &mut ExecutionContext,
+        max_calls: usize,
+    ) {
+        for _ in 0..max_calls {
+            let (original_steps, original_status) = original.step();
+            let (restored_steps, restored_status) = restored.step();
+            assert_eq!(original_steps, restored_steps);
+            assert!(original.logically_eq(restored));
+            assert_eq!(original_status, restored_status);
+            if !original_status.is_running() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_cells_allocated() {
+        // Every iteration of the loop adds a span with a copy of the tape to the
+        // history, which the snapshot keeps.
+        let program = Program::try_from("+[>+<+]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        ctx.run_until(300, |_| false);
+        let snapshot = ctx.snapshot();
+        assert!(ctx.loop_span_history().stats().total_past_spans() > 10);
+        assert_eq!(
+            snapshot.total_cells_allocated(),
+            ctx.total_cells_allocated()
+        );
+        let decoded = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+        assert_eq!(decoded.total_cells_allocated(), ctx.total_cells_allocated());
+    }
+
+    #[test]
+    fn test_logically_eq() {
+        let program = Program::try_from(">+<").unwrap();
+        let fresh = ExecutionContext::new(&program);
+        // Unused cells past the end of the tape don't matter...
+        let preallocated = ExecutionContext::with_memory(program.clone(), vec![0; 5]);
+        assert!(fresh.logically_eq(&preallocated));
+        assert!(preallocated.logically_eq(&fresh));
+        assert_ne!(fresh.tape(), preallocated.tape());
+        // ...but their contents, the pointers and the program do.
+        let seeded = ExecutionContext::with_memory(program.clone(), vec![0, 1]);
+        assert!(!fresh.logically_eq(&seeded));
+        let moved = ExecutionContext::with_memory_and_pointer(program.clone(), vec![0], 1);
+        assert!(!fresh.logically_eq(&moved));
+        let other_program = ExecutionContext::new(&Program::try_from(">+>").unwrap());
+        assert!(!fresh.logically_eq(&other_program));
+
+        // The loop span history and profiling are ignored.
+        let program = Program::try_from("+[->+<]").unwrap();
+        let mut a = ExecutionContext::new(&program);
+        let mut b = ExecutionContext::new(&program);
+        b.enable_profiling();
+        for _ in 0..3 {
+            a.step();
+            b.step();
+        }
+        let b = b.clone_light();
+        assert!(a.logically_eq(&b));
+        a.step();
+        assert!(!a.logically_eq(&b));
+    }
+
+    #[test]
+    fn test_snapshot_restore_generated() {
+        fn run(ctx: &mut ExecutionContext, steps: usize) -> Vec<(usize, ExecutionStatus)> {
+            let mut results = vec![];
+            for _ in 0..steps {
+                let result = ctx.step();
+                let done = !result.1.is_running();
+                results.push(result);
+                if done {
+                    break;
+                }
+            }
+            results
+        }
+
+        for program in crate::generate::brute_force_chain(0..8) {
+            for snapshot_at in 0..30 {
+                let mut original = ExecutionContext::new(&program);
+                let before = run(&mut original, snapshot_at);
+                if matches!(before.last(), Some((_, status)) if !status.is_running()) {
+                    break;
+                }
+                let mut restored = ExecutionContext::new(&program);
+                restored.restore(&original.snapshot());
+                assert_resumes_like(&mut original, &mut restored, 100);
+            }
+        }
+    }
+
+    #[test]
+    fn test_snapshot_preserves_loop_detection() {
+        for program in ["+[>+]", "+[>+<]", "+++[>>[>]+[<]<--]"] {
+            let program = Program::try_from(program).unwrap();
+            let mut original = ExecutionContext::new(&program);
+            for _ in 0..4 {
+                original.step();
+            }
+            let mut restored = ExecutionContext::new(&program);
+            restored.restore(&original.snapshot());
+            let (_, original_steps, original_status) = original.run_until(10000, |_| false);
+            let (_, restored_steps, restored_status) = restored.run_until(10000, |_| false);
+            assert!(matches!(original_status, ExecutionStatus::InfiniteLoop(_)));
+            assert_eq!(restored_status, original_status);
+            assert_eq!(restored_steps, original_steps);
+        }
+    }
+
+    #[test]
+    fn test_detectors() {
+        fn eval_with(program: &str, detectors: Detectors) -> Option<ExecutionStatus> {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext::with_detectors(&program, detectors);
+            for _ in 0..1000 {
+                let (_, state) = ctx.step();
+                if !state.is_running() {
+                    return Some(state);
+                }
+            }
+            None
+        }
+        assert_eq!(
+            eval_with("+[]", Detectors::ALL),
+            Some(ExecutionStatus::InfiniteLoop(LoopReason::LoopIfNonzero))
+        );
+        assert_eq!(eval_with("+[]", Detectors::NONE), None);
+        let only_loop_span = Detectors {
+            loop_span: true,
+            ..Detectors::NONE
+        };
+        assert!(matches!(
+            eval_with("+[]", only_loop_span),
+            Some(ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { .. }))
+        ));
+        assert_eq!(
+            eval_with("[]+", only_loop_span),
+            Some(ExecutionStatus::Halted)
+        );
+        assert_eq!(eval_with("+[-+]", Detectors::NONE), None);
+        let only_loop_if_nonzero = Detectors {
+            loop_if_nonzero: true,
+            ..Detectors::NONE
+        };
+        assert_eq!(eval_with("+[-+]", only_loop_if_nonzero), None);
+        assert!(matches!(
+            eval_with("+[-+]", only_loop_span),
+            Some(ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { .. }))
+        ));
+        assert_eq!(
+            eval_with("+[-+]", Detectors::ALL),
+            Some(ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop))
+        );
+        assert_eq!(
+            eval_with("+[-]", Detectors::NONE),
+            Some(ExecutionStatus::Halted)
+        );
+    }
+
+    #[test]
+    fn test_loop_span_stats() {
+        let program = Program::try_from("+++[>+<-]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        let stats = ctx.loop_span_history().stats();
+        assert_eq!(stats.num_active_spans, 0);
+        assert_eq!(stats.total_past_spans(), 0);
+
+        // Run through the first two iterations of the loop, stopping right at
+        // the start of the third iteration.
+        for _ in 0..(3 + 1 + 5 + 5) {
+            ctx.step();
+        }
+        let stats = ctx.loop_span_history().stats();
+        assert_eq!(stats.num_active_spans, 1);
+        assert_eq!(stats.num_past_spans[&3], 2);
+        assert_eq!(stats.total_past_spans(), 2);
+        assert_eq!(
+            stats.total_cells_allocated,
+            ctx.loop_span_history().total_cells_allocated()
+        );
+
+        // Once the loop exits, its history is cleared.
+        while ctx.step().1.is_running() {}
+        let stats = ctx.loop_span_history().stats();
+        assert_eq!(stats.num_active_spans, 0);
+        assert_eq!(stats.total_past_spans(), 0);
+    }
+
+    #[test]
+    fn test_loop_span_for() {
+        let program = Program::try_from("+[>+[-]<-]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        assert!(ctx.loop_span_for(1).is_none());
+
+        // Step onto the first instruction inside the loop.
+        ctx.step();
+        ctx.step();
+        let span = ctx.loop_span_for(1).unwrap();
+        assert_eq!(
+            Some(span),
+            ctx.loop_span_history().active_loop_spans().get(&1)
+        );
+        // The "[-]" is folded, so it never has a loop span, and neither does an
+        // index which isn't a loop.
+        assert!(ctx.loop_span_for(4).is_none());
+        assert!(ctx.loop_span_for(0).is_none());
+
+        while ctx.step().1.is_running() {}
+        assert!(ctx.loop_span_for(1).is_none());
+    }
+
+    #[test]
+    fn test_pretty() {
+        let program = Program::try_from("+[>+[-]<]").unwrap();
+        let expected = "+\n[\n    >+\n    [\n        -\n    ]\n    <\n]";
+        assert_eq!(program.pretty(), expected);
+
+        for program in crate::generate::brute_force_iterator(5) {
+            let pretty = program.pretty();
+            let stripped: String = pretty.chars().filter(|c| !c.is_whitespace()).collect();
+            assert_eq!(stripped, program.to_string());
+        }
+    }
+
+    #[test]
+    fn test_simplify_identity_runs() {
+        let simplify = |program| {
+            Program::try_from(program)
+                .unwrap()
+                .simplify_identity_runs()
+                .to_string()
+        };
+        assert_eq!(simplify("+-><"), "");
+        assert_eq!(simplify("+><-"), "");
+        assert_eq!(simplify("-+[>+-<]"), "[]");
+        assert_eq!(simplify("+[>+<-]"), "+[>+<-]");
+        // "<>" moves right from the first cell, so it is kept.
+        assert_eq!(simplify("+<>[]"), "+<>[]");
+
+        let max_steps = 10_000;
+        for program in crate::generate::brute_force_chain(0..7) {
+            let simplified = program.simplify_identity_runs();
+            let (status, steps, _) = step_count(&program, max_steps);
+            let (simplified_status, simplified_steps, _) = step_count(&simplified, max_steps);
+            if status.is_running() || simplified_status.is_running() {
+                continue;
+            }
+            assert_eq!(
+                status.is_halted(),
+                simplified_status.is_halted(),
+                "{} simplified to {}",
+                program,
+                simplified
+            );
+            if status.is_halted() {
+                assert!(simplified_steps <= steps);
+                let tape = |program| {
+                    let mut ctx = ExecutionContext::new(program);
+                    ctx.run_until(max_steps, |_| false);
+                    ctx.tape_trimmed().to_vec()
+                };
+                assert_eq!(tape(&program), tape(&simplified), "{}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn test_loop_free() {
+        for program in ["", "+", "><<-", ">>>+<<-<<<<+"] {
+            let program = Program::try_from(program).unwrap();
+            assert!(program.is_loop_free());
+
+            let mut ctx = ExecutionContext::new(&program);
+            let mut total_steps = 0;
+            loop {
+                let (steps, status) = ctx.step();
+                total_steps += steps;
+                if !status.is_running() {
+                    assert_eq!(status, ExecutionStatus::Halted);
+                    break;
+                }
+            }
+            assert_eq!(total_steps, program.original_instrs().len());
+        }
+        assert!(!Program::try_from("+[-]").unwrap().is_loop_free());
+    }
+
+    #[test]
+    fn test_instruction_histogram() {
+        let program = Program::try_from("+[>-<]").unwrap();
+        assert_eq!(program.instruction_histogram(), [1, 1, 1, 1, 1, 1]);
+        assert!(program.uses(Instr::Minus));
+        assert!(!program.uses(Instr::Output));
+
+        // Folded instructions are counted as their source.
+        let program = Program::try_from_with_io("++[-]>>>.").unwrap();
+        assert_eq!(program.instruction_histogram(), [2, 1, 0, 3, 1, 1]);
+        assert!(program.uses(Instr::Output));
+        assert!(!program.uses(Instr::Left));
+        assert_eq!(
+            Program::try_from("").unwrap().instruction_histogram(),
+            [0; 6]
+        );
+    }
+
+    #[test]
+    fn test_loop_free_shortcut() {
+        for program in [
+            "",
+            "+",
+            "<",
+            "<<>",
+            "<>>-<",
+            ">><<<>",
+            ">>>+<<-<<<<+",
+            "[+]>>",
+            "[[-]+]-[-]",
+        ] {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext::new(&program);
+            let expected = run_until_resolved(&mut ctx, 1000);
+            assert_eq!(step_count(&program, 1000), expected, "{}", program);
+        }
+    }
+
+    #[test]
+    fn test_can_halt_trivially() {
+        let can_halt_trivially = |program| {
+            ExecutionContext::new(&Program::try_from(program).unwrap()).can_halt_trivially()
+        };
+        assert_eq!(can_halt_trivially(""), Some(0));
+        assert_eq!(can_halt_trivially("+>"), Some(2));
+        // Each loop which is skipped takes one step.
+        assert_eq!(can_halt_trivially("[+[>]]+"), Some(2));
+        assert_eq!(can_halt_trivially(">[-]<[<]>>"), Some(6));
+        assert_eq!(can_halt_trivially("+[-]"), None);
+        assert_eq!(can_halt_trivially("+>[-]<[]"), None);
+        assert_eq!(can_halt_trivially("-[]"), None);
+
+        // Partway through a program, the rest of it is checked from the current
+        // state, starting with the steps already taken.
+        let program = Program::try_from("+[-]>>[<]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        ctx.step();
+        ctx.step();
+        assert_eq!(ctx.can_halt_trivially(), Some(7));
+        let (_, _, status) = ctx.run_until(1000, |_| false);
+        assert_eq!((status, ctx.steps_taken()), (ExecutionStatus::Halted, 7));
+
+        // It never claims a program halts trivially unless it really does, in
+        // exactly that many steps.
+        for length in 0..=6 {
+            for program in crate::generate::brute_force_iterator(length) {
+                let mut ctx = ExecutionContext::new(&program);
+                if let Some(steps) = ctx.can_halt_trivially() {
+                    let (status, real_steps, _) = run_until_resolved(&mut ctx, 1000);
+                    assert_eq!((status, real_steps), (ExecutionStatus::Halted, Some(steps)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_input_output() {
+        // Without opting in, I/O characters are comments.
+        assert!(Program::try_from(",.")
+            .unwrap()
+            .original_instrs()
+            .is_empty());
+
+        let program = Program::try_from_with_io(",.,.+.,[.,]").unwrap();
+        let mut ctx = ExecutionContext::with_input(&program, "ab".as_bytes()).unwrap();
+        for _ in 0..2 {
+            ctx.step();
+        }
+        assert_eq!(ctx.take_output(), b"a");
+        assert_eq!(ctx.take_output(), b"");
+
+        // ",.+." reads "b", prints it, increments it, and prints "c".
+        for _ in 0..4 {
+            ctx.step();
+        }
+        assert_eq!(ctx.take_output(), b"bc");
+
+        // This "," runs out of input, so the cell is left as "c".
+        ctx.step();
+        assert_eq!(ctx.tape(), b"c");
+
+        // The loop echoes until it reads a zero byte.
+        let snapshot = ctx.snapshot();
+        ctx.push_input(b"xy\0");
+        while ctx.step().1.is_running() {}
+        assert_eq!(ctx.take_output(), b"cxy");
+
+        // Restoring the snapshot does not replay input that was already read.
+        ctx.restore(&snapshot);
+        ctx.push_input(b"z\0");
+        while ctx.step().1.is_running() {}
+        assert_eq!(ctx.take_output(), b"cz");
+    }
+
+    #[test]
+    fn test_string_lossy() {
+        let hello_world = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.\
+            +++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let program = Program::try_from_with_io(hello_world).unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        let (_, _, status) = ctx.run_until(10_000, |_| false);
+        assert_eq!(status, ExecutionStatus::Halted);
+        assert_eq!(ctx.output_as_string_lossy(), "Hello World!\n");
+        // The output is not cleared.
+        assert_eq!(ctx.take_output(), b"Hello World!\n");
+        assert_eq!(ctx.output_as_string_lossy(), "");
+
+        // Writes "Hi" to the tape, followed by an invalid byte.
+        let program = Program::try_from("++++++++[>+++++++++>+++++++++++++>----<<<-]>>+").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        ctx.run_until(10_000, |_| false);
+        assert_eq!(ctx.tape_trimmed(), &[0, b'H', b'i', 0xE0]);
+        assert_eq!(ctx.tape_as_string_lossy(), "\0Hi\u{FFFD}");
+    }
+
+    #[test]
+    fn test_dedup_reflections() {
+        let programs = |strings: &[&str]| -> Vec<Program> {
+            strings
+                .iter()
+                .map(|string| Program::try_from(*string).unwrap())
+                .collect()
+        };
+        let to_strings = |programs: Vec<Program>| -> Vec<String> {
+            programs.iter().map(|program| program.to_string()).collect()
+        };
+
+        // The moves are never executed, so these are equivalent.
+        let deduped = dedup_reflections(programs(&["+[-][>+]", "+[-][<+]", "+-"]), 1000);
+        assert_eq!(to_strings(deduped), vec!["+[-][>+]", "+-"]);
+        assert_eq!(
+            canonical_form(&Program::try_from("+[-][>+]").unwrap(), 1000).to_string(),
+            "+[-][<+]"
+        );
+
+        // "<" at the origin does nothing while ">" does not, so these differ.
+        let deduped = dedup_reflections(programs(&[">+", "<+", "+[>+<-]", "+[<+>-]"]), 1000);
+        assert_eq!(to_strings(deduped), vec![">+", "<+", "+[>+<-]", "+[<+>-]"]);
+    }
+
+    #[test]
+    fn test_source_spans() {
+        // "[-]", "[+]" and "[]" are folded, everything else is not.
+        let program = Program::try_from("+[-]>[>+][+]<[]").unwrap();
+        let spans: Vec<_> = (0..program.extended_instrs().len())
+            .map(|i| program.source_span(i).unwrap())
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                0..1,
+                1..4,
+                4..5,
+                5..6,
+                6..7,
+                7..8,
+                8..9,
+                9..12,
+                12..13,
+                13..15
+            ]
+        );
+        assert_eq!(program.source_span(10), None);
+
+        // Without LoopIfNonzero, "[]" is two instructions.
+        let program = program.without_loop_if_nonzero();
+        assert_eq!(program.source_span(9), Some(13..14));
+        assert_eq!(program.source_span(10), Some(14..15));
+        assert_eq!(program.source_span(11), None);
+
+        let mut ctx = ExecutionContext::new(&Program::try_from("+[-]-").unwrap());
+        assert_eq!(ctx.program_pointer_to_source(), Some(0..1));
+        ctx.step();
+        assert_eq!(ctx.program_pointer_to_source(), Some(1..4));
+        ctx.step();
+        assert_eq!(ctx.program_pointer_to_source(), Some(4..5));
+        ctx.step();
+        assert_eq!(ctx.program_pointer_to_source(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "memory pointer 5 is off the end of the tape (3 cells long)")]
+    fn test_check_on_tape() {
+        check_on_tape(2, 3);
+        check_on_tape(5, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "checked_tape")]
+    #[should_panic(expected = "memory pointer 40 is off the end of the tape")]
+    fn test_checked_tape() {
+        let mut ctx = ExecutionContext::new(&Program::try_from("+").unwrap());
+        ctx.tape.pointer = 40;
+        ctx.step();
+    }
+
+    #[test]
+    fn test_program_text_pointer() {
+        let source = "add one: + clear: [-] and ún more: -";
+        let program = Program::from_source(source).unwrap();
+        assert_eq!(program.to_string(), "+[-]-");
+        let mut ctx = ExecutionContext::new(&program);
+        assert_eq!(ctx.program_text_pointer(), Some(9));
+        ctx.step();
+        // The folded "[-]" points at its "[".
+        assert_eq!(ctx.program_text_pointer(), source.find('['));
+        ctx.step();
+        assert_eq!(ctx.program_text_pointer(), source.rfind('-'));
+        ctx.step();
+        assert_eq!(ctx.program_text_pointer(), None);
+
+        // Without comments, the offsets are the same as the source spans.
+        let program = Program::try_from("+[-]-").unwrap();
+        assert_eq!(program.text_offset(1), Some(1));
+        assert_eq!(program.text_offset(2), Some(4));
+        assert_eq!(program.text_offset(3), None);
+    }
+
+    #[test]
+    fn test_from_source_with_breakpoints() {
+        let (program, breakpoints) =
+            Program::from_source_with_breakpoints("#+ [#-] #>> # > #").unwrap();
+        assert_eq!(program.to_extended_string(), "+⊟>3");
+        // The "#" inside "[-]" stops before the whole folded loop, and so does
+        // the "#" inside the run of moves.
+        assert_eq!(breakpoints, BTreeSet::from([0, 1, 2, 3]));
+        assert_eq!(
+            step_count(&program, 100),
+            step_count(&Program::try_from("+[-]>>>").unwrap(), 100)
+        );
+
+        let (program, breakpoints) = Program::from_source_with_breakpoints("+>#<").unwrap();
+        assert_eq!(program.to_string(), "+><");
+        assert_eq!(breakpoints, BTreeSet::from([2]));
+        let (_, breakpoints) = Program::from_source_with_breakpoints("+[-]").unwrap();
+        assert!(breakpoints.is_empty());
+        assert!(Program::from_source_with_breakpoints("#[").is_err());
+    }
+
+    #[test]
+    fn test_executed_mask() {
+        // The trailing "+-" is never reached because "[]" loops forever.
+        let program = Program::try_from("+[>]<[]+-").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        assert_eq!(ctx.executed_mask(), [false; 8]);
+        while ctx.step().1.is_running() {}
+        assert_eq!(
+            ctx.executed_mask(),
+            [true, true, true, true, true, true, false, false]
+        );
+
+        // The body of a loop which is never entered is never executed.
+        let program = Program::try_from("[>+<-]+").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        while ctx.step().1.is_running() {}
+        assert_eq!(
+            ctx.executed_mask(),
+            [true, false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_diff_execution() {
+        let diff = |a: &str, b: &str| {
+            let a = Program::try_from(a).unwrap();
+            let b = Program::try_from(b).unwrap();
+            diff_execution(&a, &b, 1000)
+        };
+        // Both loops clear the cell in a single step.
+        assert_eq!(diff("+[-]>+<", "+[+]>+<"), None);
+        assert_eq!(diff("+>+", "+>-"), Some(3));
+        // "<" does nothing at the origin, but the first program halts sooner.
+        assert_eq!(diff("+", "+<"), Some(1));
+        // Both never halt, but through different cells.
+        assert_eq!(diff("+[>+]", "+[<+]"), Some(3));
+    }
+
+    #[test]
+    fn test_fold_moves() {
+        let program = Program::try_from("+>>>[<<-]>").unwrap();
+        assert_eq!(
+            program.extended_instrs(),
+            [
+                ExtendedInstr::BaseInstr(Instr::Plus),
+                ExtendedInstr::Move(3),
+                ExtendedInstr::BaseInstr(Instr::StartLoop),
+                ExtendedInstr::Move(-2),
+                ExtendedInstr::BaseInstr(Instr::Minus),
+                ExtendedInstr::BaseInstr(Instr::EndLoop),
+                ExtendedInstr::BaseInstr(Instr::Right),
+            ]
+        );
+        assert_eq!(program.source_span(1), Some(1..4));
+        assert_eq!(ExtendedInstr::Move(-2).base_step_cost(0), 2);
+
+        // The tape is extended once to the final position, and moving left
+        // saturates at the origin.
+        let mut ctx = ExecutionContext::new(&Program::try_from(">>>><<<<<<").unwrap());
+        ctx.step();
+        assert_eq!((ctx.memory_pointer(), ctx.tape_length()), (4, 5));
+        assert_eq!(ctx.step(), (6, ExecutionStatus::Halted));
+        assert_eq!(ctx.memory_pointer(), 0);
+
+        // A folded move inside a loop must still be seen by the loop span detector.
+        assert_not_halting_loop_span("+[>>+<<]");
+        assert_not_halting_loop_span("+[>>>+]");
+    }
+
+    #[test]
+    fn test_fold_long_clears() {
+        let folded = |program: &str| {
+            Program::try_from(program)
+                .unwrap()
+                .extended_instrs()
+                .to_vec()
+        };
+        assert_eq!(
+            folded("+[+-+]"),
+            [
+                ExtendedInstr::BaseInstr(Instr::Plus),
+                ExtendedInstr::SetToZeroLong {
+                    increment: true,
+                    body_length: 3
+                }
+            ]
+        );
+        assert_eq!(
+            folded("[--+]")[0],
+            ExtendedInstr::SetToZeroLong {
+                increment: false,
+                body_length: 3
+            }
+        );
+        // Bodies which change the cell by more than one, or move the pointer,
+        // are not folded.
+        assert_eq!(folded("[++]").len(), 4);
+        assert_eq!(folded("[+-]").len(), 4);
+        assert_eq!(folded("[-<>]").len(), 5);
+        assert_eq!(
+            Program::try_from("+[+-+]").unwrap().source_span(1),
+            Some(1..6)
+        );
+    }
+
+    #[test]
+    fn test_unfolded() {
+        let program = Program::try_from("+[-]>>[+]<<+[>+-+<-]").unwrap();
+        let unfolded = program.unfolded();
+        assert_eq!(unfolded.len(), program.original_instrs().len());
+        assert!(unfolded
+            .extended_instrs()
+            .iter()
+            .all(|instr| matches!(instr, ExtendedInstr::BaseInstr(_))));
+        assert_eq!(unfolded.to_string(), program.to_string());
+
+        // Unfolding never changes how many steps a halting program takes.
+        for program in crate::generate::brute_force_chain(0..7) {
+            let (status, steps, _) = step_count(&program, 10_000);
+            if status.is_halted() {
+                let unfolded = step_count(&program.unfolded(), 100_000);
+                assert_eq!(unfolded.0, ExecutionStatus::Halted, "{}", program);
+                assert_eq!(unfolded.1, steps, "{}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn test_folding_report() {
+        use ExtendedInstr::*;
+        let program = Program::try_from("+[+][-]<<[]>>>[-+-]").unwrap();
+        assert_eq!(
+            program.folding_report(),
+            [
+                (0..1, BaseInstr(Instr::Plus)),
+                (1..4, SetToZeroPlus),
+                (4..7, SetToZeroMinus),
+                (7..9, Move(-2)),
+                (9..11, LoopIfNonzero),
+                (11..14, Move(3)),
+                (
+                    14..19,
+                    SetToZeroLong {
+                        increment: false,
+                        body_length: 3
+                    }
+                ),
+            ]
+        );
+        // The spans cover the whole source without gaps.
+        let report = Program::try_from("+[>[-]+<-]>").unwrap().folding_report();
+        assert_eq!(report[0].0.start, 0);
+        assert!(report
+            .windows(2)
+            .all(|pair| pair[0].0.end == pair[1].0.start));
+        assert_eq!(report.last().unwrap().0.end, 11);
+        assert!(Program::try_from("").unwrap().folding_report().is_empty());
+    }
+
+    #[test]
+    fn test_from_extended() {
+        let program = Program::try_from("+[-+-]>>>[]<<[+]").unwrap();
+        assert_eq!(program.to_extended_string(), "+⊟3>3L<2⊞");
+        let parsed = Program::from_extended("+⊟3>3L<2⊞").unwrap();
+        assert_eq!(parsed.to_string(), "+[--+]>>>[]<<[+]");
+        assert_eq!(parsed.extended_instrs(), program.extended_instrs());
+
+        // I/O instructions are kept, and other characters are ignored.
+        let parsed = Program::from_extended("+ ,⊟ x.").unwrap();
+        assert_eq!(Instr::to_string(parsed.original_instrs()), "+,[-].");
+
+        assert!(matches!(
+            Program::from_extended("+2"),
+            Err(CompileError::InvalidCount { index: 0 })
+        ));
+        assert!(matches!(
+            Program::from_extended("+⊞4"),
+            Err(CompileError::InvalidCount { index: 1 })
+        ));
+        assert!(matches!(
+            Program::from_extended("[⊟"),
+            Err(CompileError::UnmatchedStartLoops { .. })
+        ));
+
+        // Huge counts are rejected instead of overflowing or allocating a huge
+        // program.
+        let largest = format!(">{}<{}", MAX_EXTENDED_COUNT, MAX_EXTENDED_COUNT);
+        assert_eq!(Program::from_extended(&largest).unwrap().len(), 2);
+        for huge in [
+            format!("+>{}", MAX_EXTENDED_COUNT + 1),
+            format!("+<{}", usize::MAX),
+            "+>99999999999999999999999999".to_string(),
+            "+⊞99999999999999999999999999".to_string(),
+        ] {
+            assert!(
+                matches!(
+                    Program::from_extended(&huge),
+                    Err(CompileError::CountTooLarge { index: 1 })
+                ),
+                "{}",
+                huge
+            );
+        }
+
+        // Round tripping through the extended form gives the same extended
+        // instructions, and so the same number of steps.
+        for program in crate::generate::brute_force_chain(0..7) {
+            let parsed = Program::from_extended(&program.to_extended_string()).unwrap();
+            assert_eq!(
+                parsed.extended_instrs(),
+                program.extended_instrs(),
+                "{}",
+                program
+            );
+            assert_eq!(
+                step_count(&parsed, 1000),
+                step_count(&program, 1000),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn test_instruction_at() {
+        let program = Program::try_from("+[-]>>").unwrap();
+        assert_eq!(program.len(), 3);
+        assert!(!program.is_empty());
+        assert_eq!(
+            program.instruction_at(0),
+            Some(&ExtendedInstr::BaseInstr(Instr::Plus))
+        );
+        assert_eq!(
+            program.instruction_at(1),
+            Some(&ExtendedInstr::SetToZeroMinus)
+        );
+        assert_eq!(program.display_at(1).as_deref(), Some("⊟"));
+        assert_eq!(program.display_at(2).as_deref(), Some(">2"));
+        assert_eq!(program.instruction_at(3), None);
+        assert_eq!(program.display_at(3), None);
+        assert!(Program::try_from("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_ring_tape() {
+        let run = |program: &str, length: usize| {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext::with_tape_policy(&program, TapePolicy::Ring(length));
+            let mut status = ExecutionStatus::Running;
+            for _ in 0..1_000_000 {
+                status = ctx.step().1;
+                if !status.is_running() {
+                    break;
+                }
+            }
+            (status, ctx.memory_pointer(), ctx.tape().to_vec())
+        };
+        // Moving left from the first cell wraps to the last cell, and vice versa.
+        assert_eq!(run("<+<<+", 3).2, vec![1, 0, 1]);
+        assert_eq!(
+            run(">>>+>+>>>>+", 3),
+            (ExecutionStatus::Halted, 2, vec![1, 1, 1])
+        );
+        // Scanning right for a zero cell wraps around the ring forever, back to
+        // the cell the scan started on.
+        assert_eq!(
+            run("+>+>+>+[>]", 4).0,
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+        );
+        // On a ring, "+[>+]" keeps incrementing the same cells until one wraps to zero.
+        assert_eq!(run("+[>+]", 2).0, ExecutionStatus::Halted);
+
+        // Every program gets a verdict, since there are only finitely many states.
+        for program in crate::generate::brute_force_chain(0..7) {
+            for length in 1..=2 {
+                let mut ctx =
+                    ExecutionContext::with_tape_policy(&program, TapePolicy::Ring(length));
+                let resolved = (0..1_000_000).any(|_| !ctx.step().1.is_running());
+                assert!(
+                    resolved,
+                    "{} did not resolve on a ring of {}",
+                    program, length
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tape() {
+        let mut tape = Tape::new(TapePolicy::Unbounded);
+        assert_eq!((tape.pointer(), tape.get(), tape.high_water()), (0, 0, 1));
+        tape.set(3);
+        tape.move_right(3);
+        tape.set(1);
+        assert_eq!(tape.len(), 4);
+        tape.move_right(2);
+        assert_eq!((tape.len(), tape.high_water()), (6, 6));
+        assert_eq!(tape.trimmed(), &[3, 0, 0, 1]);
+        assert_eq!(tape.nonzero_cells(), 2);
+        // Moving left off the start stays on the first cell.
+        tape.move_left(7);
+        assert_eq!((tape.pointer(), tape.get()), (0, 3));
+        assert!(tape.hit_left_edge());
+        assert_eq!(tape.high_water(), 6);
+
+        // A tape with preset cells has only visited the cell under the pointer.
+        let tape = Tape::with_cells(vec![1, 2, 3], 1);
+        assert_eq!((tape.get(), tape.len(), tape.high_water()), (2, 3, 2));
+        let tape = Tape::with_cells(vec![], 2);
+        assert_eq!((tape.get(), tape.len()), (0, 3));
+        assert_eq!(tape.trimmed(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn test_tape_move_by() {
+        // A two way tape grows on the left, shifting every cell to the right.
+        let mut tape = Tape::new(TapePolicy::TwoWay);
+        tape.set(5);
+        tape.move_right(1);
+        assert_eq!(tape.move_by(-3), 2);
+        assert_eq!((tape.pointer(), tape.cells()), (0, &[0, 0, 5, 0][..]));
+        assert_eq!(tape.high_water(), 4);
+        assert!(!tape.hit_left_edge());
+
+        let mut tape = Tape::new(TapePolicy::Ring(3));
+        assert_eq!(tape.move_by(-1), 0);
+        assert_eq!(tape.pointer(), 2);
+        tape.move_right(2);
+        assert_eq!((tape.pointer(), tape.len()), (1, 3));
+        assert!(!tape.hit_left_edge());
+
+        let mut tape = Tape::new(TapePolicy::Bounded(3));
+        tape.move_right(5);
+        assert_eq!((tape.pointer(), tape.len(), tape.high_water()), (2, 3, 3));
+        assert!(!tape.hit_left_edge());
+        tape.move_left(4);
+        assert_eq!(tape.pointer(), 0);
+        assert!(tape.hit_left_edge());
+    }
+
+    #[test]
+    fn test_tape_policies() {
+        let run = |program: &str, tape_policy: TapePolicy| {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext::with_tape_policy(&program, tape_policy);
+            let (steps, _, status) = ctx.run_until(100_000, |_| false);
+            (
+                status,
+                steps,
+                ctx.memory_pointer(),
+                ctx.tape().to_vec(),
+                ctx.hit_left_edge(),
+            )
+        };
+        // Move left off the start twice, then scan left for a zero cell.
+        let program = "+<+<+>>>+[<]";
+        assert_eq!(
+            run(program, TapePolicy::Unbounded),
+            (ExecutionStatus::Halted, 10, 2, vec![3, 0, 0, 1], true)
+        );
+        // Every cell of the ring is nonzero by the time the scan starts.
+        let (status, _, _, tape, hit_left_edge) = run(program, TapePolicy::Ring(4));
+        assert!(status.is_looping());
+        assert_eq!((tape, hit_left_edge), (vec![1, 1, 1, 1], false));
+        // Moves past the last cell of a bounded tape do nothing either, so the
+        // scan gets stuck against the left edge.
+        let (status, _, _, tape, hit_left_edge) = run(program, TapePolicy::Bounded(2));
+        assert!(status.is_looping());
+        assert_eq!((tape, hit_left_edge), (vec![3, 1], true));
+        // A two way tape grows to the left instead, so every "+" lands on a new
+        // cell, and the scan stops on a new cell further left.
+        assert_eq!(
+            run(program, TapePolicy::TwoWay),
+            (ExecutionStatus::Halted, 16, 0, vec![0, 1, 1, 1, 1], false)
+        );
+
+        // Drifting right forever is still detected on both infinite tapes. On a
+        // bounded tape, the loop gets stuck against the edge, incrementing the
+        // same cell until it wraps to zero.
+        for tape_policy in [TapePolicy::Unbounded, TapePolicy::TwoWay] {
+            assert!(run("+[>+]", tape_policy).0.is_looping());
+        }
+        assert_eq!(
+            run("+[>+]", TapePolicy::Bounded(3)).0,
+            ExecutionStatus::Halted
+        );
+        // Drifting left can't be detected on a two way tape.
+        assert_eq!(run("+[<+]", TapePolicy::TwoWay).0, ExecutionStatus::Running);
+
+        // The no-op loop detector would be wrong against the right edge, where
+        // "><" moves the pointer left instead of doing nothing.
+        assert_eq!(
+            run(">+[><]", TapePolicy::Unbounded).0,
+            ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop)
+        );
+        assert_eq!(
+            run(">+[><]", TapePolicy::Bounded(2)),
+            (ExecutionStatus::Halted, 6, 0, vec![0, 1], false)
+        );
+    }
+
+    #[test]
+    fn test_custom_tape_policy() {
+        // The same as TapePolicy::Bounded(3).
+        #[derive(Debug)]
+        struct Walls;
+        impl TapeEdges for Walls {
+            fn initial_length(&self) -> usize {
+                3
+            }
+
+            fn on_left(&self, _: usize, _: usize, _: usize) -> EdgeMove {
+                EdgeMove::Stop(0)
+            }
+
+            fn on_right(&self, _: usize, _: usize, length: usize) -> EdgeMove {
+                EdgeMove::Stop(length - 1)
+            }
+        }
+
+        // Grows to the right, but moving left from the first cell wraps around
+        // to the last cell allocated so far.
+        #[derive(Debug)]
+        struct WrapLeft;
+        impl TapeEdges for WrapLeft {
+            fn on_left(&self, pointer: usize, distance: usize, length: usize) -> EdgeMove {
+                EdgeMove::Wrap(ring_position(pointer, -(distance as isize), length))
+            }
+
+            fn on_right(&self, _: usize, _: usize, _: usize) -> EdgeMove {
+                EdgeMove::Grow
+            }
+        }
+
+        // The same as TapePolicy::Unbounded, and says that every detector is
+        // sound on it.
+        #[derive(Debug)]
+        struct AllSound;
+        impl TapeEdges for AllSound {
+            fn on_left(&self, _: usize, _: usize, _: usize) -> EdgeMove {
+                EdgeMove::Stop(0)
+            }
+
+            fn on_right(&self, _: usize, _: usize, _: usize) -> EdgeMove {
+                EdgeMove::Grow
+            }
+
+            fn sound_detectors(&self) -> Detectors {
+                Detectors::ALL
+            }
+        }
+
+        static WALLS: Walls = Walls;
+        static WRAP_LEFT: WrapLeft = WrapLeft;
+        static ALL_SOUND: AllSound = AllSound;
+
+        let run = |program: &Program, tape_policy: TapePolicy| {
+            let mut ctx = ExecutionContext::with_tape_policy(program, tape_policy);
+            let (steps, _, status) = ctx.run_until(1000, |_| false);
+            (
+                status,
+                steps,
+                ctx.memory_pointer(),
+                ctx.tape().to_vec(),
+                ctx.hit_left_edge(),
+            )
+        };
+
+        // Every program which halts on a bounded tape halts the same way on a
+        // custom tape with the same edges.
+        for length in 0..=5 {
+            for program in crate::generate::brute_force_iterator(length) {
+                let bounded = run(&program, TapePolicy::Bounded(3));
+                if bounded.0 == ExecutionStatus::Halted {
+                    assert_eq!(run(&program, TapePolicy::Custom(&WALLS)), bounded);
+                }
+            }
+        }
+        // Only the loop span detector can prove that this loops on a bounded
+        // tape, and it is never used on custom tapes, even one which says it is
+        // sound.
+        let program = Program::try_from("+[>+>+]").unwrap();
+        assert!(run(&program, TapePolicy::Bounded(3)).0.is_looping());
+        for tape_policy in [TapePolicy::Custom(&WALLS), TapePolicy::Custom(&ALL_SOUND)] {
+            assert!(!tape_policy.sound_detectors().loop_span);
+            assert_eq!(run(&program, tape_policy).0, ExecutionStatus::Running);
+        }
+
+        // The no-op loop detector is only used on tapes which say it is sound.
+        // Otherwise the loop is only caught once it jumps back to its start.
+        let program = Program::try_from("+[>-+<]").unwrap();
+        assert!(!TapePolicy::Custom(&WALLS).sound_detectors().no_op_loop);
+        assert_eq!(
+            run(&program, TapePolicy::Custom(&WALLS)).0,
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+        );
+        assert!(TapePolicy::Custom(&ALL_SOUND).sound_detectors().no_op_loop);
+        assert_eq!(
+            run(&program, TapePolicy::Custom(&ALL_SOUND)).0,
+            ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop)
+        );
+        assert!(!TapePolicy::Bounded(3).sound_detectors().no_op_loop);
+        assert_eq!(TapePolicy::Unbounded.sound_detectors(), Detectors::ALL);
+
+        // The growing run detector is only used on an Unbounded tape.
+        for tape_policy in [
+            TapePolicy::Ring(100),
+            TapePolicy::Bounded(100),
+            TapePolicy::TwoWay,
+            TapePolicy::Custom(&ALL_SOUND),
+        ] {
+            assert!(!tape_policy.sound_detectors().growing_run);
+        }
+
+        let program = Program::try_from(">+>++<<<+").unwrap();
+        assert_eq!(
+            run(&program, TapePolicy::Custom(&WRAP_LEFT)),
+            (ExecutionStatus::Halted, 7, 2, vec![0, 1, 3], false)
+        );
+
+        assert_eq!(TapePolicy::Custom(&WALLS), TapePolicy::Custom(&WALLS));
+        assert_ne!(TapePolicy::Custom(&WALLS), TapePolicy::Custom(&WRAP_LEFT));
+    }
+
+    #[test]
+    fn test_no_op_loop() {
+        fn eval_with(program: &str, detectors: Detectors) -> (usize, ExecutionStatus) {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext::with_detectors(&program, detectors);
+            let mut total_steps = 0;
+            loop {
+                let (steps, status) = ctx.step();
+                total_steps += steps;
+                if !status.is_running() {
+                    return (total_steps, status);
+                }
+            }
+        }
+        let without_no_op_loop = Detectors {
+            no_op_loop: false,
+            ..Detectors::ALL
+        };
+        let only_loop_span = Detectors {
+            loop_span: true,
+            ..Detectors::NONE
+        };
+
+        // This loop moves a value to the next cell and back again, leaving the
+        // tape exactly as it was.
+        let program = "++>+<[->+<+>-<]";
+        assert_eq!(
+            eval_with(program, Detectors::ALL),
+            (6, ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop))
+        );
+        // The returned to start and loop span detectors catch it too, just
+        // more slowly.
+        let (steps, status) = eval_with(program, without_no_op_loop);
+        assert_eq!(
+            status,
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+        );
+        assert!(steps > 6);
+        let (steps, status) = eval_with(program, only_loop_span);
+        assert!(matches!(
+            status,
+            ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { .. })
+        ));
+        assert!(steps > 6);
+
+        // Not no-ops: the body changes a cell, moves the pointer, or contains
+        // another loop.
+        for program in ["+[>+<]", "+[>]", "+[[-]+]"] {
+            assert_ne!(
+                eval_with(program, Detectors::ALL).1,
+                ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop),
+                "{}",
+                program
+            );
+        }
+        // Not a no-op at the left edge of the tape, since the "<" does nothing
+        // and the ">" then moves onto a zero cell.
+        assert_eq!(
+            eval_with("+[<>]", Detectors::ALL).1,
+            ExecutionStatus::Halted
+        );
+        assert_eq!(
+            eval_with(">+[<>]", Detectors::ALL).1,
+            ExecutionStatus::InfiniteLoop(LoopReason::NoOpLoop)
+        );
+    }
+
+    #[test]
+    fn test_tape_trimmed() {
+        let mut ctx = ExecutionContext::new(&Program::try_from(">>>+-").unwrap());
+        while ctx.step().1.is_running() {}
+        assert_eq!(ctx.tape(), [0, 0, 0, 0]);
+        assert_eq!(ctx.tape_trimmed(), []);
+        assert_eq!(ctx.memory_pointer(), 3);
+
+        let program =
+            Program::try_from(&*format!("{}+{}", ">".repeat(100), ">".repeat(5))).unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        while ctx.step().1.is_running() {}
+        assert_eq!(ctx.tape().len(), 106);
+        assert_eq!(ctx.tape_trimmed().len(), 101);
+        assert_eq!(ctx.tape_trimmed()[100], 1);
+        assert!(ctx.tape_trimmed()[..100].iter().all(|&cell| cell == 0));
+    }
+
+    #[test]
+    fn test_execution_status_predicates() {
+        let running = ExecutionStatus::Running;
+        let halted = ExecutionStatus::Halted;
+        let looping = ExecutionStatus::InfiniteLoop(LoopReason::LoopIfNonzero);
+        assert!(running.is_running() && !running.is_halted() && !running.is_looping());
+        assert!(!halted.is_running() && halted.is_halted() && !halted.is_looping());
+        assert!(!looping.is_running() && !looping.is_halted() && looping.is_looping());
+        assert_eq!(running.loop_reason(), None);
+        assert_eq!(halted.loop_reason(), None);
+        assert_eq!(looping.loop_reason(), Some(&LoopReason::LoopIfNonzero));
+    }
+
+    #[test]
+    fn test_with_memory_and_pointer() {
+        fn eval(mut ctx: ExecutionContext) -> (usize, ExecutionStatus, ExecutionContext) {
+            let mut total_steps = 0;
+            loop {
+                let (steps, status) = ctx.step();
+                total_steps += steps;
+                if !status.is_running() {
+                    return (total_steps, status, ctx);
+                }
+            }
+        }
+        let seeded = |program: &str, memory: Vec<u8>, pointer| {
+            let program = Program::try_from(program).unwrap();
+            eval(ExecutionContext::with_memory_and_pointer(
+                program, memory, pointer,
+            ))
+        };
+
+        // Halting programs run exactly as they would after setting up the tape
+        // themselves, minus the steps taken to do so.
+        let (steps, status, ctx) = seeded("[<]", vec![0, 5, 5, 5], 3);
+        assert_eq!(status, ExecutionStatus::Halted);
+        assert_eq!(ctx.memory_pointer(), 0);
+        let setup = ">+++++>+++++>+++++";
+        let (fresh_steps, _, fresh_ctx) = eval(ExecutionContext::new(
+            &Program::try_from(&*format!("{}[<]", setup)).unwrap(),
+        ));
+        assert_eq!(ctx.tape(), fresh_ctx.tape());
+        assert_eq!(steps, fresh_steps - setup.len());
+
+        let (_, status, ctx) = seeded("[>]", vec![0, 0, 1, 1, 1, 0], 2);
+        assert_eq!(status, ExecutionStatus::Halted);
+        assert_eq!(ctx.memory_pointer(), 5);
+
+        // The pointer may start past the end of the given memory.
+        let (_, status, ctx) = seeded("+", vec![1], 3);
+        assert_eq!(status, ExecutionStatus::Halted);
+        assert_eq!(ctx.tape(), [1, 0, 0, 1]);
+
+        // Non-halting programs are still detected when the loop starts on a
+        // nonzero cell in the middle of the tape.
+        let (_, status, _) = seeded("[>+]", vec![9, 9, 1], 2);
+        assert!(matches!(
+            status,
+            ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { .. })
+        ));
+        // The cell to the left wraps around back to 3 after 256 iterations,
+        // which returns the tape to how it started.
+        let (_, status, _) = seeded("[<+>-+]", vec![0, 3, 1, 7], 2);
+        assert_eq!(
+            status,
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+        );
+        let (_, status, _) = seeded("[]", vec![0, 0, 4], 2);
+        assert_eq!(
+            status,
+            ExecutionStatus::InfiniteLoop(LoopReason::LoopIfNonzero)
+        );
+    }
+
+    #[test]
+    fn test_loop_reason_display() {
+        let reason = |program| {
+            let (status, _, _) = step_count(&Program::try_from(program).unwrap(), 1000);
+            status.loop_reason().unwrap().to_string()
+        };
+        assert_eq!(reason("+[]"), "LoopIfNonzero on a nonzero cell");
+        assert_eq!(reason("+[-+]"), "Loop body does nothing");
+        assert_eq!(
+            reason("+[>+]"),
+            "Repeated loop span (cells 1..=2, displacement 1)"
+        );
+    }
+
+    #[test]
+    fn test_clone_light() {
+        fn run(mut ctx: ExecutionContext) -> (usize, ExecutionStatus) {
+            let mut total_steps = 0;
+            for _ in 0..100_000 {
+                let (steps, status) = ctx.step();
+                total_steps += steps;
+                if !status.is_running() {
+                    return (total_steps, status);
+                }
+            }
+            panic!("{} did not resolve", ctx.program());
+        }
+
+        for program in crate::generate::brute_force_chain(0..7) {
+            let (expected_steps, expected_status) = run(ExecutionContext::new(&program));
+            // Fork partway through, possibly in the middle of a loop.
+            let mut ctx = ExecutionContext::new(&program);
+            let mut steps_before_fork = 0;
+            for _ in 0..10 {
+                let (steps, status) = ctx.step();
+                steps_before_fork += steps;
+                if !status.is_running() {
+                    break;
+                }
+            }
+            let light = ctx.clone_light();
+            assert_eq!(light.loop_span_history().stats().num_active_spans, 0);
+            let (steps, status) = run(light);
+            if expected_status.is_halted() {
+                assert_eq!(status, ExecutionStatus::Halted, "{}", program);
+                assert_eq!(steps_before_fork + steps, expected_steps, "{}", program);
+            } else {
+                // The loop may be detected by a different method or at a
+                // different step, but it is still detected.
+                assert!(status.is_looping(), "{}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let program = Program::try_from("++>-<[->+<]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        let writes = Arc::new(Mutex::new(vec![]));
+        let moves = Arc::new(Mutex::new(vec![]));
+        {
+            let writes = writes.clone();
+            ctx.set_on_cell_write(move |index, old, new| {
+                writes.lock().unwrap().push((index, old, new))
+            });
+            let moves = moves.clone();
+            ctx.set_on_pointer_move(move |old, new| moves.lock().unwrap().push((old, new)));
+        }
+        // Clones don't share the hooks.
+        let mut clone = ctx.clone();
+        while clone.step().1.is_running() {}
+        assert!(writes.lock().unwrap().is_empty());
+        assert!(moves.lock().unwrap().is_empty());
+
+        while ctx.step().1.is_running() {}
+        assert_eq!(
+            *writes.lock().unwrap(),
+            [
+                (0, 0, 1),
+                (0, 1, 2),
+                (1, 0, 255),
+                (0, 2, 1),
+                (1, 255, 0),
+                (0, 1, 0),
+                (1, 0, 1),
+            ]
+        );
+        assert_eq!(
+            *moves.lock().unwrap(),
+            [(0, 1), (1, 0), (0, 1), (1, 0), (0, 1), (1, 0)]
+        );
+    }
+
+    #[test]
+    fn test_proof_profile() {
+        let profile = |program| proof_profile(&Program::try_from(program).unwrap(), 100_000);
+        assert_eq!(profile("+[-]"), None);
+        assert_eq!(profile("+[>+]").unwrap().iterations_before_detection(), 3);
+
+        // The loop adds one to the left cell each iteration without changing
+        // the right cell, so the tape only returns to how it was when the loop
+        // was entered once the left cell wraps.
+        let program = ">+++++[-<+>+]";
+        let details = profile(program).unwrap();
+        assert_eq!(details.reason, LoopReason::ReturnedToStart);
+        assert_eq!(details.detected_loop, 6);
+        assert_eq!(details.iterations_before_detection(), 257);
+        assert_eq!(
+            Some(details.steps),
+            step_count(&Program::try_from(program).unwrap(), 100_000).1
+        );
+        assert_eq!(details.loop_iterations.len(), 1);
+    }
+
+    #[test]
+    fn test_run_until_tape_grows() {
+        // The steps at which the tape grew, up to `max_steps` apart, for the
+        // first 10 times it grew.
+        fn growth_steps(mut ctx: ExecutionContext, max_steps: usize) -> Vec<usize> {
+            let mut steps = vec![];
+            let mut total_steps = 0;
+            while steps.len() < 10 {
+                let tape_length = ctx.tape_length();
+                let (calls, _, status) =
+                    ctx.run_until(max_steps, |ctx| ctx.tape_length() > tape_length);
+                total_steps += calls;
+                if !status.is_running() || ctx.tape_length() == tape_length {
+                    return steps;
+                }
+                steps.push(total_steps);
+            }
+            steps
+        }
+
+        // Each iteration of the loop moves onto a new cell.
+        let detectors = Detectors {
+            loop_span: false,
+            ..Detectors::ALL
+        };
+        let program = Program::try_from("+[>+]").unwrap();
+        let mut ctx = ExecutionContext::with_detectors(&program, detectors);
+        let (calls, real_steps, status) = ctx.run_until(100, |ctx| ctx.tape_length() > 3);
+        assert_eq!(
+            (calls, real_steps, status),
+            (9, 9, ExecutionStatus::Running)
+        );
+        assert_eq!(ctx.tape_length(), 4);
+        assert_eq!(
+            growth_steps(ExecutionContext::with_detectors(&program, detectors), 100)[..4],
+            [3, 6, 9, 12]
+        );
+
+        // A program which halts or never grows the tape stops at the halt or the
+        // step cap.
+        let program = Program::try_from("+>+<").unwrap();
+        assert_eq!(growth_steps(ExecutionContext::new(&program), 100), [2]);
+        let program = Program::try_from("+[]").unwrap();
+        let mut ctx = ExecutionContext::with_detectors(&program, Detectors::NONE);
+        assert_eq!(
+            ctx.run_until(100, |ctx| ctx.tape_length() > 1),
+            (100, 100, ExecutionStatus::Running)
+        );
+    }
+
+    #[test]
+    fn test_masked_window() {
+        let span = |memory: Vec<u8>, start, moves: &[isize]| {
+            let mut span = LoopSpan::new(memory, start, TapePolicy::Unbounded);
+            for &offset in moves {
+                span.record_move(offset);
+            }
+            span
+        };
+        let memory = vec![1, 2, 3, 4, 5, 0, 0];
+        // Zero displacement: only the touched cells.
+        let zero = span(memory.clone(), 2, &[1, -2, 1]);
+        assert_eq!(zero.displacement(), 0);
+        assert_eq!(zero.masked_window(), [2, 3, 4]);
+        // Negative displacement: everything left of the touched cells too.
+        let negative = span(memory.clone(), 2, &[1, -1, -1]);
+        assert_eq!(negative.displacement(), -1);
+        assert_eq!(negative.masked_window(), [1, 2, 3, 4]);
+        // Positive displacement: everything to the right, to the end of the snapshot.
+        let positive = span(memory.clone(), 2, &[-1, 1, 1]);
+        assert_eq!(positive.displacement(), 1);
+        assert_eq!(positive.masked_window(), [2, 3, 4, 5, 0, 0]);
+        // Touched cells past the last nonzero cell are trimmed.
+        let trimmed = span(memory, 4, &[1, 1, -1, -1]);
+        assert_eq!(trimmed.masked_window(), [5]);
+    }
+
+    #[test]
+    fn test_loop_span_from_parts() {
+        // Spans match if they have the same displacement and masked window,
+        // even if the snapshots differ outside the window.
+        let a = LoopSpan::from_parts(vec![7, 1, 2, 0], 1, 2, 1, 2);
+        let b = LoopSpan::from_parts(vec![9, 1, 2, 0], 1, 2, 1, 2);
+        assert_eq!(a.masked_window(), [1, 2, 0]);
+        assert_eq!(a, b);
+        // The touched cells can be anywhere, as long as they match.
+        let c = LoopSpan::from_parts(vec![3, 0, 1, 2, 0], 2, 3, 2, 3);
+        assert_eq!(a, c);
+        // A different displacement doesn't match, even with the same window.
+        let d = LoopSpan::from_parts(vec![7, 1, 2, 0], 1, 1, 1, 2);
+        assert_eq!(d.masked_window(), [1, 2]);
+        assert_ne!(a, d);
+        // Nor does a different window.
+        let e = LoopSpan::from_parts(vec![7, 1, 3, 0], 1, 2, 1, 2);
+        assert_ne!(a, e);
+        // A leftward drift includes every cell to the left.
+        let f = LoopSpan::from_parts(vec![7, 1, 2], 2, 1, 1, 2);
+        let g = LoopSpan::from_parts(vec![8, 1, 2], 2, 1, 1, 2);
+        assert_eq!(f.masked_window(), [7, 1, 2]);
+        assert_ne!(f, g);
+
+        // Spans built from parts are the same as recorded ones.
+        let mut recorded = LoopSpan::new(vec![7, 1, 2, 0], 1, TapePolicy::Unbounded);
+        recorded.record_move(1);
+        assert_eq!(recorded, a);
+    }
+
+    #[test]
+    #[should_panic(expected = "outside the touched cells")]
+    fn test_loop_span_from_parts_invariants() {
+        LoopSpan::from_parts(vec![0, 0, 0], 1, 2, 0, 1);
+    }
+
+    #[test]
+    fn test_max_past_spans() {
+        // Loops whose spans repeat from one iteration to the next are still
+        // detected with a small limit.
+        for program in ["+[>+]", "+[<]", "+[-+]", "+[[>]+<+]"] {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext::new(&program);
+            ctx.set_max_past_spans(Some(2));
+            let (_, _, status) = ctx.run_until(1000, |_| false);
+            assert!(
+                matches!(status, ExecutionStatus::InfiniteLoop(_)),
+                "{}",
+                program
+            );
+        }
+
+        // But a loop whose spans only repeat after more iterations than the
+        // limit is not. (The returned to start detector would catch this one
+        // without any past spans.)
+        let program = Program::try_from("+[>+<]").unwrap();
+        let only_loop_span = Detectors {
+            loop_span: true,
+            ..Detectors::NONE
+        };
+        let mut ctx = ExecutionContext::with_detectors(&program, only_loop_span);
+        let (_, _, status) = ctx.run_until(10_000, |_| false);
+        assert!(matches!(status, ExecutionStatus::InfiniteLoop(_)));
+        let mut ctx = ExecutionContext::with_detectors(&program, only_loop_span);
+        ctx.set_max_past_spans(Some(2));
+        let (_, _, status) = ctx.run_until(10_000, |_| false);
+        assert_eq!(status, ExecutionStatus::Running);
+
+        // A loop which never repeats a span stores one span per iteration
+        // without a limit, but at most the limit with one, and still halts
+        // after the same number of steps.
+        let program = Program::try_from("+[>+<+]").unwrap();
+        let mut unlimited = ExecutionContext::new(&program);
+        let (_, unlimited_steps, status) = unlimited.run_until(10_000, |_| false);
+        assert_eq!(status, ExecutionStatus::Halted);
+
+        let mut limited = ExecutionContext::new(&program);
+        limited.set_max_past_spans(Some(3));
+        let mut max_past_spans = 0;
+        loop {
+            let (_, _, status) = limited.run_until(1, |_| false);
+            let past_spans = limited.loop_span_history().stats().total_past_spans();
+            max_past_spans = max_past_spans.max(past_spans);
+            if status != ExecutionStatus::Running {
+                assert_eq!(status, ExecutionStatus::Halted);
+                break;
+            }
+        }
+        assert_eq!(max_past_spans, 3);
+        assert_eq!(limited.steps_taken(), unlimited_steps);
+
+        // Lowering the limit forgets the oldest spans straight away.
+        let mut ctx = ExecutionContext::new(&program);
+        ctx.run_until(100, |_| false);
+        assert!(ctx.loop_span_history().stats().total_past_spans() > 1);
+        ctx.set_max_past_spans(Some(1));
+        assert_eq!(ctx.loop_span_history().stats().total_past_spans(), 1);
+    }
+
+    #[test]
+    fn test_parts_round_trip() {
+        for program in [">+[>++>+++[-<]>>]+", "+[->++<]>[-]", "++[>+<-]>>"] {
+            let program = Program::try_from(program).unwrap();
+            let mut expected = ExecutionContext::new(&program);
+            let expected_steps = run_to_end(&mut expected, 0);
+
+            for split_at in [0, 3, 10] {
+                let mut ctx = ExecutionContext::new(&program);
+                let steps_before = run_to_end(&mut ctx, split_at);
+                let parts = ctx.into_parts();
+                assert_eq!(parts.program.to_string(), program.to_string());
+                let mut ctx = ExecutionContext::from_parts(parts);
+                assert_eq!(ctx.loop_span_history().stats().num_active_spans, 0);
+                let steps_after = run_to_end(&mut ctx, 0);
+                assert_eq!(steps_before + steps_after, expected_steps, "{}", program);
+                assert!(ctx.logically_eq(&expected));
+            }
+        }
+
+        // External state may start with the pointers anywhere.
+        let program = Program::try_from("+[-]>[-]").unwrap();
+        let mut ctx = ExecutionContext::from_parts(ContextParts {
+            program,
+            // The second "[-]"
+            program_pointer: 3,
+            memory: vec![7, 0, 3],
+            memory_pointer: 2,
+        });
+        while ctx.step().1.is_running() {}
+        assert_eq!(ctx.tape(), [7, 0, 0]);
+    }
+
+    // Run the context until it stops running, or for `max_steps` steps if that
+    // is nonzero, and return the real steps run.
+    fn run_to_end(ctx: &mut ExecutionContext, max_steps: usize) -> usize {
+        let mut total_steps = 0;
+        for i in 1.. {
+            let (steps, status) = ctx.step();
+            total_steps += steps;
+            if !status.is_running() || i == max_steps {
+                break;
+            }
+        }
+        total_steps
+    }
+
+    #[test]
+    fn test_snapshot_bytes_round_trip() {
+        let program = Program::try_from("+[>+[-<+>]<-]>>+[>++[-]<<+]").unwrap();
+        for steps in [0, 5, 40, 300] {
+            let mut ctx = ExecutionContext::new(&program);
+            ctx.enable_profiling();
+            ctx.run_until(steps, |_| false);
+            let snapshot = ctx.snapshot();
+            let decoded = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+            assert_eq!(decoded.to_bytes(), snapshot.to_bytes());
+
+            let mut restored = ExecutionContext::new(&program);
+            restored.restore(&decoded);
+            assert!(restored.logically_eq(&ctx));
+            assert_eq!(
+                restored.loop_iteration_counts(),
+                ctx.loop_iteration_counts()
+            );
+            assert_resumes_like(&mut ctx, &mut restored, 10_000);
+        }
+
+        let bytes = ExecutionContext::new(&program).snapshot().to_bytes();
+        assert_eq!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(DecodeError::Truncated)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Snapshot::from_bytes(&trailing).err(),
+            Some(DecodeError::TrailingBytes)
+        );
+
+        for program in ["+[>+]", "+++[>>[>]+[<]<--]"] {
+            let (status, _, _) = step_count(&Program::try_from(program).unwrap(), 1000);
+            assert!(status.loop_reason().is_some());
+            assert_eq!(ExecutionStatus::from_bytes(&status.to_bytes()), Ok(status));
+        }
+        assert_eq!(
+            ExecutionStatus::from_bytes(&[9]),
+            Err(DecodeError::InvalidTag(9))
+        );
+    }
+
+    #[test]
+    fn test_sigma() {
+        let sigma = |program| sigma(&Program::try_from(program).unwrap(), 1000);
+        assert_eq!(sigma(""), Some(0));
+        assert_eq!(sigma("+>++>+[-]>+"), Some(3));
+        // Cells which wrap back around to zero don't count.
+        assert_eq!(sigma("+[+]"), Some(0));
+        assert_eq!(sigma("++[-->+<]"), Some(1));
+        assert_eq!(sigma("+[]"), None);
+        assert_eq!(sigma("+[>+]"), None);
+    }
+
+    #[test]
+    fn test_returned_to_start() {
+        let run = |program, detectors| {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext {
+                detectors,
+                ..ExecutionContext::with_memory(program, vec![1])
+            };
+            let (steps, _, status) = ctx.run_until(10_000, |_| false);
+            (steps, status)
+        };
+        // Moves the 1 to the next cell and back, then repeats.
+        let (steps, status) = run("[[->+<]>[-<+>]<]", Detectors::ALL);
+        assert_eq!(
+            status,
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+        );
+        assert_eq!(steps, 16);
+        let only_loop_span = Detectors {
+            loop_span: true,
+            ..Detectors::NONE
+        };
+        assert!(matches!(
+            run("[[->+<]>[-<+>]<]", only_loop_span).1,
+            ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { .. })
+        ));
+
+        // The next cell counts up and only wraps back to zero after 256
+        // iterations, which no other detector notices.
+        assert_eq!(
+            run("[>+<]", Detectors::ALL),
+            (
+                1 + 4 * 256,
+                ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+            )
+        );
+
+        // The state is captured whenever an outer loop is entered, so it is
+        // found on a blank tape too, and in loops after the first.
+        let blank = |program| {
+            let (status, steps, _) = step_count(&Program::try_from(program).unwrap(), 10_000);
+            (status, steps)
+        };
+        for program in ["+[>+<]", "+[>+[-]<]", "+[-]>+[>+<]"] {
+            assert_eq!(
+                blank(program).0,
+                ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart),
+                "{}",
+                program
+            );
+        }
+        // Detected the first time the loop jumps back, after every instruction
+        // has run once.
+        assert_eq!(
+            blank("+[>+[-]<]"),
+            (
+                ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart),
+                Some(9)
+            )
+        );
+    }
+
+    #[test]
+    fn test_growing_run() {
+        let without_growing_run = Detectors {
+            growing_run: false,
+            ..Detectors::ALL
+        };
+        let run = |program, detectors| {
+            let program = Program::try_from(program).unwrap();
+            let mut ctx = ExecutionContext::with_detectors(&program, detectors);
+            let (steps, _, status) = ctx.run_until(100_000, |_| false);
+            (steps, status)
+        };
+        // The guard counts down by two from an odd number, so it never reaches
+        // zero, while every iteration adds a cell to the run after the zero at
+        // cell 1. This is proven after the first iteration.
+        assert_eq!(
+            run("+++[>>[>]+[<]<--]", Detectors::ALL),
+            (14, ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun))
+        );
+        assert_eq!(
+            run("+++[>>[>]+[<]<--]", without_growing_run).1,
+            ExecutionStatus::Running
+        );
+        // From an even number, the guard reaches zero.
+        assert_eq!(
+            run("++++[>>[>]+[<]<--]", Detectors::ALL).1,
+            ExecutionStatus::Halted
+        );
+
+        // The run can hold any nonzero values, and other cells before the run
+        // can change. Here cell 1 only repeats after 256 iterations.
+        for program in [
+            "+[>>[>]++[<]<++]",
+            "+[>>>[>]+[<]<+<--]",
+            "+[++[>>[>]-[<]<--]]",
+        ] {
+            assert_eq!(
+                run(program, Detectors::ALL).1,
+                ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun),
+                "{}",
+                program
+            );
+        }
+
+        // Without the zero before the run, "[<]" runs into the first cell, and
+        // scanning forever there isn't something this detector proves.
+        assert!(!matches!(
+            run("+++[>[>]+[<]<-]", Detectors::ALL).1,
+            ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun)
+        ));
+        // Writing to the middle of the run can't be followed, since the run may
+        // have any length.
+        assert_eq!(
+            run("+++[>>>+<[>]+[<]<--]", Detectors::ALL).1,
+            ExecutionStatus::Running
+        );
+
+        // Only loops whose inner loops are all scans are checked.
+        let growing_run_loops = |program| {
+            let mut loops: Vec<_> = Program::try_from(program)
+                .unwrap()
+                .growing_run_loops
+                .into_iter()
+                .collect();
+            loops.sort();
+            loops
+        };
+        assert_eq!(growing_run_loops("+[>>[>]+[<]<-]"), [1]);
+        assert_eq!(growing_run_loops("+[>[-]<-]>[>>[<]]"), [8]);
+        assert!(growing_run_loops("+[>[>>]+<-]").is_empty());
+        assert!(growing_run_loops("+[>+<-]").is_empty());
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        let depth = |program| Program::try_from(program).unwrap().nesting_depth();
+        assert_eq!(depth("[[[]]]"), 3);
+        assert_eq!(depth("[[-]>[+]]"), 2);
+        assert_eq!(depth("+[-]"), 1);
+        assert_eq!(depth("+>>-<"), 0);
+        assert_eq!(depth(""), 0);
+    }
+
+    #[test]
+    fn test_hits_left_edge() {
+        let hits = |program| hits_left_edge(&Program::try_from(program).unwrap(), 1000);
+        assert!(hits("<+"));
+        assert!(hits("+>+[<]"));
+        assert!(hits(">+<<<"));
+        assert!(!hits(">+<-"));
+        assert!(!hits("+[->+<]>"));
+        // Only moves which are actually executed count.
+        assert!(!hits("[<]+"));
+
+        // The flag survives snapshots.
+        let mut ctx = ExecutionContext::new(&Program::try_from("<+").unwrap());
+        ctx.step();
+        let mut restored = ExecutionContext::new(ctx.program());
+        restored.restore(&ctx.snapshot());
+        assert!(restored.hit_left_edge());
+    }
+
+    #[test]
+    fn test_non_halting_loop_span() {
+        assert_not_halting_loop_span("+[<]");
+        assert_not_halting_loop_span("+[-+]");
+        assert_not_halting_loop_span("+[[+]-]");
+    }
+
+    #[test]
+    fn test_guard_insensitive_loop_span() {
+        let guard_insensitive = Detectors {
+            guard_insensitive_loop_span: true,
+            ..Detectors::ALL
+        };
+        // The first iteration starts on a guard of 1, and every later one on a
+        // guard of 255, left behind by the [+]. Comparing the guards exactly
+        // needs a third iteration to see two matching spans.
+        let program = Program::try_from("+[>+[+]-]").unwrap();
+        let (status, _, _) = step_count(&program, 16);
+        assert_eq!(status, ExecutionStatus::Running);
+        let (status, steps, _) = step_count_with_detectors(&program, 16, guard_insensitive);
+        assert!(matches!(
+            status,
+            ExecutionStatus::InfiniteLoop(LoopReason::LoopSpan { .. })
+        ));
+        assert_eq!(steps, Some(1032));
+        assert_eq!(step_count(&program, 100).1, Some(1547));
+
+        // Loops which write to their guard still compare it exactly.
+        for program in ["+++[>++<-]", "+>++>+++<<[[-]>]", "+++[>+[-]<-]>>"] {
+            let program = Program::try_from(program).unwrap();
+            let (status, _, _) = step_count_with_detectors(&program, 10_000, guard_insensitive);
+            assert_eq!(status, ExecutionStatus::Halted, "{}", program);
+        }
     }
 }
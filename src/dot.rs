@@ -0,0 +1,235 @@
+use std::fmt::Write;
+
+use crate::bf::{LoopReason, LoopSpan};
+
+/// Render the proof behind a `LoopReason::LoopSpan` as a Graphviz DOT graph.
+/// Each span is drawn as a row of the cells which the detector compared (see
+/// LOOP_SPAN.md), with an edge from the cell the loop iteration started on
+/// to the cell it ended on, labeled with the displacement. An edge between the
+/// two spans marks that they matched. Returns None for other loop reasons,
+/// which have no spans to draw.
+pub fn loop_span_proof(reason: &LoopReason) -> Option<String> {
+    let (prior, current) = match reason {
+        LoopReason::LoopSpan { prior, current } => (prior, current),
+        _ => return None,
+    };
+    let mut dot = String::new();
+    writeln!(dot, "digraph loop_span {{").unwrap();
+    writeln!(dot, "    rankdir=LR;").unwrap();
+    writeln!(dot, "    node [shape=box];").unwrap();
+    write_span(&mut dot, "prior", prior);
+    write_span(&mut dot, "current", current);
+    writeln!(
+        dot,
+        "    prior_start -> current_start [style=dashed, label=\"matches\"];"
+    )
+    .unwrap();
+    writeln!(dot, "}}").unwrap();
+    Some(dot)
+}
+
+/// Render the proof behind a `LoopReason::LoopSpan` as plain text, with the
+/// cells of both spans aligned in columns above `tape` (usually the tape at
+/// the step the loop was detected). Cells the detector compared are shown in
+/// hex, while cells it ignored are shown as "..". Below each span, "s" marks
+/// the memory pointer at the start of the loop iteration and "e" marks it at
+/// the end. Returns None for other loop reasons, which have no spans to draw.
+pub fn loop_span_text(reason: &LoopReason, tape: &[u8]) -> Option<String> {
+    let (prior, current) = match reason {
+        LoopReason::LoopSpan { prior, current } => (prior, current),
+        _ => return None,
+    };
+    let columns = [prior, current]
+        .iter()
+        .flat_map(|span| {
+            let mask = span.memory_mask_range();
+            [
+                *mask.start(),
+                *mask.end(),
+                span.starting_memory_pointer,
+                span.current_memory_pointer,
+            ]
+        })
+        .max()
+        .expect("there are two spans");
+    let mut text = String::new();
+    write!(text, "{:<8}", "cell").unwrap();
+    for i in 0..=columns {
+        write!(text, " {:>2}", i).unwrap();
+    }
+    writeln!(text).unwrap();
+    for (name, span) in [("prior", prior), ("current", current)] {
+        let mask = span.memory_mask_range();
+        write!(text, "{:<8}", name).unwrap();
+        for i in 0..=columns {
+            match span.memory_at_loop_start.get(i) {
+                Some(cell) if mask.contains(&i) => write!(text, " {:0>2X}", cell).unwrap(),
+                Some(_) => write!(text, " ..").unwrap(),
+                None => write!(text, "   ").unwrap(),
+            }
+        }
+        writeln!(text).unwrap();
+        write!(text, "{:<8}", "").unwrap();
+        for i in 0..=columns {
+            let start = i == span.starting_memory_pointer;
+            let end = i == span.current_memory_pointer;
+            let marker = match (start, end) {
+                (true, true) => "se",
+                (true, false) => "s",
+                (false, true) => "e",
+                (false, false) => "",
+            };
+            write!(text, " {:>2}", marker).unwrap();
+        }
+        writeln!(text).unwrap();
+    }
+    write!(text, "{:<8}", "tape").unwrap();
+    for i in 0..=columns {
+        write!(text, " {:0>2X}", tape.get(i).copied().unwrap_or(0)).unwrap();
+    }
+    writeln!(text).unwrap();
+    writeln!(
+        text,
+        "displacement {:+}, compared {:?} and {:?}",
+        current.displacement(),
+        prior.masked_window(),
+        current.masked_window()
+    )
+    .unwrap();
+    // Cells past the end of a span's snapshot leave trailing spaces.
+    Some(
+        text.lines()
+            .map(|line| format!("{}\n", line.trim_end()))
+            .collect(),
+    )
+}
+
+// Write a cluster for the span. The nodes for the cells the iteration started
+// and ended on are also given the names `{name}_start` and `{name}_end`.
+fn write_span(dot: &mut String, name: &str, span: &LoopSpan) {
+    let mask = span.memory_mask_range();
+    writeln!(dot, "    subgraph cluster_{} {{", name).unwrap();
+    writeln!(
+        dot,
+        "        label=\"{} span (cells {}..={})\";",
+        name,
+        mask.start(),
+        mask.end()
+    )
+    .unwrap();
+    for i in mask.clone() {
+        writeln!(
+            dot,
+            "        {}_{} [label=\"{}: {:0>2X}\"];",
+            name, i, i, span.memory_at_loop_start[i]
+        )
+        .unwrap();
+    }
+    // The pointers may be outside of the compared cells, such as when the loop
+    // moves onto a cell which was not allocated at the start of the iteration.
+    for (pointer, end) in [
+        (span.starting_memory_pointer, "start"),
+        (span.current_memory_pointer, "end"),
+    ] {
+        if mask.contains(&pointer) {
+            writeln!(
+                dot,
+                "        {}_{} [label=\"{}\", shape=point];",
+                name, end, pointer
+            )
+            .unwrap();
+            writeln!(
+                dot,
+                "        {}_{} -> {}_{} [arrowhead=none];",
+                name, end, name, pointer
+            )
+            .unwrap();
+        } else {
+            writeln!(
+                dot,
+                "        {}_{} [label=\"{}: not compared\", style=dashed];",
+                name, end, pointer
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        dot,
+        "        {}_start -> {}_end [label=\"{:+}\"];",
+        name,
+        name,
+        span.displacement()
+    )
+    .unwrap();
+    writeln!(dot, "    }}").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::bf::{step_count, ExecutionContext, Program};
+
+    #[test]
+    fn test_loop_span_proof() {
+        let program = Program::try_from("+[>+]").unwrap();
+        let (status, _, _) = step_count(&program, 1000);
+        let dot = loop_span_proof(status.loop_reason().unwrap()).unwrap();
+
+        assert!(dot.starts_with("digraph loop_span {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches('{').count(), dot.matches('}').count());
+        assert_eq!(dot.matches("subgraph cluster_").count(), 2);
+
+        // Every edge joins two declared nodes.
+        let mut nodes = HashSet::new();
+        let mut edges = vec![];
+        for line in dot.lines().map(str::trim) {
+            let statement = line.split(" [").next().unwrap().trim_end_matches(';');
+            match statement.split_once(" -> ") {
+                Some((from, to)) => edges.push((from.to_string(), to.to_string())),
+                None if line.contains(" [label=") => {
+                    nodes.insert(statement.to_string());
+                }
+                None => (),
+            }
+        }
+        assert!(nodes.contains("prior_start") && nodes.contains("current_end"));
+        for (from, to) in &edges {
+            assert!(
+                nodes.contains(from) && nodes.contains(to),
+                "{} -> {}",
+                from,
+                to
+            );
+        }
+        assert!(dot.contains("current_start -> current_end [label=\"+1\"];"));
+
+        let (status, _, _) = step_count(&Program::try_from("+[]").unwrap(), 1000);
+        assert_eq!(loop_span_proof(status.loop_reason().unwrap()), None);
+    }
+
+    #[test]
+    fn test_loop_span_text() {
+        let program = Program::try_from("+[>+]").unwrap();
+        let mut ctx = ExecutionContext::new(&program);
+        let (_, _, status) = ctx.run_until(1000, |_| false);
+        let text = loop_span_text(status.loop_reason().unwrap(), ctx.tape()).unwrap();
+        assert_eq!(
+            text,
+            "cell      0  1  2\n\
+             prior    01\n\
+             \x20         s  e\n\
+             current  .. 01\n\
+             \x20            s  e\n\
+             tape     01 01 01\n\
+             displacement +1, compared [1] and [1]\n"
+        );
+        assert!(text.lines().all(|line| line == line.trim_end()));
+
+        let (status, _, _) = step_count(&Program::try_from("+[]").unwrap(), 1000);
+        assert_eq!(loop_span_text(status.loop_reason().unwrap(), &[]), None);
+    }
+}
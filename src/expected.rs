@@ -0,0 +1,277 @@
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+use rayon::prelude::*;
+
+use crate::bf::{step_count, ExecutionStatus, Program};
+use crate::generate::Verdict;
+
+/// A program's verdict according to some other tool, read from a CSV file by
+/// `parse_expected_csv`.
+#[derive(Debug, Clone)]
+pub struct Expected {
+    /// The line (counting from 1) the program was read from.
+    pub line: usize,
+    pub program: Program,
+    pub verdict: Verdict,
+    /// The real steps the program halts in. Only checked for halting programs,
+    /// since other tools may prove non-halting after a different number of steps.
+    pub steps: Option<usize>,
+}
+
+/// An error from reading a CSV file of expected verdicts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvError {
+    /// The line (counting from 1) does not have three fields.
+    Syntax { line: usize },
+    /// The program on the line does not compile.
+    Program { line: usize, reason: String },
+    /// The verdict on the line is not one of "halted", "looping" or "unknown".
+    Verdict { line: usize },
+    /// The steps on the line are not empty or a number.
+    Steps { line: usize },
+}
+
+impl Display for CsvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsvError::Syntax { line } => write!(f, "Line {} is not program,verdict,steps", line),
+            CsvError::Program { line, reason } => {
+                write!(
+                    f,
+                    "Cannot compile the program on line {} ({})",
+                    line, reason
+                )
+            }
+            CsvError::Verdict { line } => write!(f, "Unknown verdict on line {}", line),
+            CsvError::Steps { line } => write!(f, "Invalid steps on line {}", line),
+        }
+    }
+}
+
+/// Read a CSV file with one `program,verdict,steps` row per line, where the
+/// verdict is "halted", "looping" or "unknown" (as in `RunReport`) and steps is
+/// empty or a number. The program is compiled with `Program::try_from`, which
+/// ignores anything that isn't an instruction, so it may be quoted or contain
+/// commas. Blank lines and a header line starting with "program," are skipped.
+pub fn parse_expected_csv(csv: &str) -> Result<Vec<Expected>, CsvError> {
+    let mut expected = vec![];
+    for (i, row) in csv.lines().enumerate() {
+        let line = i + 1;
+        let row = row.trim();
+        if row.is_empty() || (i == 0 && row.starts_with("program,")) {
+            continue;
+        }
+        let mut fields = row.rsplitn(3, ',');
+        let (steps, verdict, program) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(steps), Some(verdict), Some(program)) => {
+                (steps.trim(), verdict.trim(), program.trim())
+            }
+            _ => return Err(CsvError::Syntax { line }),
+        };
+
+        let program = Program::try_from(program).map_err(|err| CsvError::Program {
+            line,
+            reason: err.to_string(),
+        })?;
+        let verdict = match verdict {
+            "halted" => Verdict::Halted,
+            "looping" => Verdict::Looping,
+            "unknown" => Verdict::Unknown,
+            _ => return Err(CsvError::Verdict { line }),
+        };
+        let steps = match steps {
+            "" => None,
+            steps => Some(steps.parse().map_err(|_| CsvError::Steps { line })?),
+        };
+        expected.push(Expected {
+            line,
+            program,
+            verdict,
+            steps,
+        });
+    }
+    Ok(expected)
+}
+
+/// Whether this crate's result for a program matches the expected one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Agreement {
+    /// Both halted in the same number of steps (if the expected steps are
+    /// known), or both were proven to loop.
+    Agrees,
+    /// One halted and the other looped, or both halted in a different number
+    /// of steps.
+    Disagrees,
+    /// Either verdict is unknown, so there is nothing to compare.
+    Unresolved,
+}
+
+/// The result of running an expected program with `step_count`.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub expected: Expected,
+    pub verdict: Verdict,
+    /// The real steps taken until the program halted or was detected as
+    /// looping, or None if the verdict is unknown.
+    pub steps: Option<usize>,
+}
+
+impl Check {
+    pub fn agreement(&self) -> Agreement {
+        match (self.expected.verdict, self.verdict) {
+            (Verdict::Unknown, _) | (_, Verdict::Unknown) => Agreement::Unresolved,
+            (Verdict::Halted, Verdict::Halted) => match self.expected.steps {
+                Some(steps) if Some(steps) != self.steps => Agreement::Disagrees,
+                _ => Agreement::Agrees,
+            },
+            (Verdict::Looping, Verdict::Looping) => Agreement::Agrees,
+            (Verdict::Halted, Verdict::Looping) | (Verdict::Looping, Verdict::Halted) => {
+                Agreement::Disagrees
+            }
+        }
+    }
+}
+
+// Describe a verdict, such as "halted after 4 steps".
+fn describe(verdict: Verdict, steps: Option<usize>) -> String {
+    let verdict = match verdict {
+        Verdict::Halted => "halted",
+        Verdict::Looping => "looping",
+        Verdict::Unknown => "unknown",
+    };
+    match steps {
+        Some(steps) => format!("{} after {} steps", verdict, steps),
+        None => verdict.to_string(),
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: {}: expected {}, but {}",
+            self.expected.line,
+            self.expected.program,
+            describe(self.expected.verdict, self.expected.steps),
+            describe(self.verdict, self.steps)
+        )
+    }
+}
+
+/// The result of `check_expected`.
+#[derive(Debug, Clone)]
+pub struct ExpectedReport {
+    /// Every check, in the order the programs were given.
+    pub checks: Vec<Check>,
+}
+
+impl ExpectedReport {
+    /// Returns the checks with the given agreement, in the order the programs
+    /// were given.
+    pub fn with_agreement(&self, agreement: Agreement) -> impl Iterator<Item = &Check> {
+        self.checks
+            .iter()
+            .filter(move |check| check.agreement() == agreement)
+    }
+}
+
+/// Run every expected program for at most `max_steps` calls to `step` in
+/// parallel (see `step_count`), and compare the results with the expected ones.
+pub fn check_expected(expected: Vec<Expected>, max_steps: usize) -> ExpectedReport {
+    let checks = expected
+        .into_par_iter()
+        .map(|expected| {
+            let (status, steps, _) = step_count(&expected.program, max_steps);
+            let verdict = match status {
+                ExecutionStatus::Halted => Verdict::Halted,
+                ExecutionStatus::InfiniteLoop(_) => Verdict::Looping,
+                ExecutionStatus::Running => Verdict::Unknown,
+            };
+            Check {
+                expected,
+                verdict,
+                steps,
+            }
+        })
+        .collect();
+    ExpectedReport { checks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_expected_csv() {
+        let csv = "program,verdict,steps\n+[-],halted,4\n\n\"+,[-]\",halted,\n+[],looping,\n";
+        let expected = parse_expected_csv(csv).unwrap();
+        assert_eq!(expected.len(), 3);
+        assert_eq!(expected[0].line, 2);
+        assert_eq!(expected[0].program.to_string(), "+[-]");
+        assert_eq!(expected[0].verdict, Verdict::Halted);
+        assert_eq!(expected[0].steps, Some(4));
+        assert_eq!(expected[1].line, 4);
+        assert_eq!(expected[1].program.to_string(), "+[-]");
+        assert_eq!(expected[1].steps, None);
+        assert_eq!(expected[2].verdict, Verdict::Looping);
+
+        // Commas in the program don't need quoting.
+        let expected = parse_expected_csv("+,[-],halted,5").unwrap();
+        assert_eq!(expected[0].program.to_string(), "+[-]");
+
+        assert_eq!(
+            parse_expected_csv("+[-],4").unwrap_err(),
+            CsvError::Syntax { line: 1 }
+        );
+        assert_eq!(
+            parse_expected_csv("+[-],halts,4").unwrap_err(),
+            CsvError::Verdict { line: 1 }
+        );
+        assert_eq!(
+            parse_expected_csv("+[-],halted,4\n+[-],halted,four").unwrap_err(),
+            CsvError::Steps { line: 2 }
+        );
+        assert!(matches!(
+            parse_expected_csv("+[-,halted,4").unwrap_err(),
+            CsvError::Program { line: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_check_expected() {
+        let csv = "\
++[-],halted,4
++[-],halted,5
++[],looping,
++[],halted,10
++[>+],unknown,
++[-],halted,
+";
+        let report = check_expected(parse_expected_csv(csv).unwrap(), 1000);
+        let agreements: Vec<_> = report.checks.iter().map(Check::agreement).collect();
+        assert_eq!(
+            agreements,
+            [
+                Agreement::Agrees,
+                Agreement::Disagrees,
+                Agreement::Agrees,
+                Agreement::Disagrees,
+                Agreement::Unresolved,
+                Agreement::Agrees,
+            ]
+        );
+
+        let disagreements: Vec<_> = report
+            .with_agreement(Agreement::Disagrees)
+            .map(|check| check.to_string())
+            .collect();
+        assert_eq!(disagreements.len(), 2);
+        assert_eq!(
+            disagreements[0],
+            "line 2: +[-]: expected halted after 5 steps, but halted after 4 steps"
+        );
+        assert!(disagreements[1]
+            .starts_with("line 4: +[]: expected halted after 10 steps, but looping after"));
+    }
+}
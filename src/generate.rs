@@ -1,16 +1,352 @@
-use std::{iter::successors, ops::Range};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ops::Range;
 
-use crate::bf::{Instr, Program};
+use crate::bf::{self, ExecutionContext, ExecutionStatus, Instr, Program};
 
 pub fn brute_force_chain(lengths: Range<usize>) -> impl Iterator<Item = Program> {
     lengths.into_iter().flat_map(brute_force_iterator)
 }
 
+/// Return every valid program of the given length, in lexiographic order.
+/// Programs with mismatched braces are skipped.
 pub fn brute_force_iterator(length: usize) -> impl Iterator<Item = Program> {
-    lexiographic_order(length).filter_map(|instrs| Program::new(instrs).ok())
+    brute_force_iterator_indexed(length).map(|(_, program)| program)
 }
 
-pub fn lexiographic_order(length: usize) -> impl Iterator<Item = Vec<Instr>> {
+/// Like `brute_force_iterator`, but also yields the index of each program in
+/// `lexiographic_order`. Since invalid programs are skipped, this index is the
+/// program's position among *all* programs of this length, not its position
+/// among the valid ones. This means the index is a stable coordinate for a
+/// given program, regardless of how many invalid programs came before it.
+pub fn brute_force_iterator_indexed(length: usize) -> impl Iterator<Item = (usize, Program)> {
+    lexiographic_order(length)
+        .enumerate()
+        .filter_map(|(i, instrs)| Program::new(instrs).ok().map(|program| (i, program)))
+}
+
+/// Returns the number of valid programs of the given length, which is the
+/// number of programs `brute_force_iterator(length)` yields. This is computed
+/// directly, so it is fast even for lengths which are too long to enumerate.
+/// Returns None if the number does not fit in a usize, which on 64 bit targets
+/// happens from length 27 onwards.
+pub fn count_valid(length: usize) -> Option<usize> {
+    // ways[depth] is the number of prefixes seen so far which have `depth`
+    // unclosed loops.
+    let mut ways = vec![0_usize; length + 2];
+    ways[0] = 1;
+    for i in 0..length {
+        let mut next = vec![0_usize; length + 2];
+        // Prefixes with more unclosed loops than there are instructions left
+        // can never be closed, so they are not counted. Every other prefix
+        // extends to at least one distinct valid program, so no count exceeds
+        // the result and this only overflows if the result does.
+        let remaining = length - i - 1;
+        for depth in 0..=remaining {
+            // Four instructions keep the depth the same, StartLoop increases it
+            // and EndLoop decreases it.
+            let mut total = ways[depth].checked_mul(4)?.checked_add(ways[depth + 1])?;
+            if depth > 0 {
+                total = total.checked_add(ways[depth - 1])?;
+            }
+            next[depth] = total;
+        }
+        ways = next;
+    }
+    Some(ways[0])
+}
+
+/// Like `brute_force_iterator`, but only yields programs for which `predicate`
+/// returns true. See `has_loop` and `min_nesting` for some useful predicates.
+pub fn brute_force_filtered(
+    length: usize,
+    predicate: impl Fn(&Program) -> bool,
+) -> impl Iterator<Item = Program> {
+    brute_force_iterator(length).filter(move |program| predicate(program))
+}
+
+/// Returns true if the program contains at least one loop. For use with
+/// `brute_force_filtered`, since programs without loops are not interesting
+/// busy beavers.
+pub fn has_loop(program: &Program) -> bool {
+    !program.is_loop_free()
+}
+
+/// Returns a predicate for `brute_force_filtered` which is true if the program
+/// has loops nested at least `depth` deep. For example, "[[]]" has a nesting
+/// depth of 2. Every program has a nesting depth of at least 0.
+pub fn min_nesting(depth: usize) -> impl Fn(&Program) -> bool {
+    move |program| program.nesting_depth() >= depth
+}
+
+/// Returns true if the program has a dead loop: a loop which starts right after
+/// another loop ends, such as the "[<]" in "+[-][<]". A loop only exits once
+/// the current cell is zero, so a loop right after it is never entered, and
+/// only takes the one step to skip over it. (Loops at the very start of the
+/// program are also never entered, but are handled by `canonical_iterator`.)
+pub fn has_dead_loop(program: &Program) -> bool {
+    program
+        .original_instrs()
+        .windows(2)
+        .any(|pair| pair == [Instr::EndLoop, Instr::StartLoop])
+}
+
+/// Returns true if the program has a dead loop (see `has_dead_loop`) which is
+/// not inside another loop, such as the "[<]" in "+[-][<]" but not the one in
+/// "+[[-][<]-]". A dead loop outside every loop is reached at most once, while
+/// one inside a loop is skipped over again on every iteration of that loop.
+pub fn has_top_level_dead_loop(program: &Program) -> bool {
+    let instrs = program.original_instrs();
+    let mut depth = 0;
+    for (i, instr) in instrs.iter().enumerate() {
+        match instr {
+            Instr::StartLoop => depth += 1,
+            Instr::EndLoop => {
+                depth -= 1;
+                if depth == 0 && instrs.get(i + 1) == Some(&Instr::StartLoop) {
+                    return true;
+                }
+            }
+            _ => (),
+        }
+    }
+    false
+}
+
+/// Like `brute_force_iterator`, but skips programs with a dead loop outside
+/// every other loop (see `has_top_level_dead_loop`).
+///
+/// Skipped programs are never busy beavers: removing such a dead loop of length
+/// `k` gives a program of length `length - k` which halts or loops together
+/// with it, in at most one step fewer, since the dead loop is reached at most
+/// once (none fewer if it is never reached). Adding `k` `+`s to the end of that
+/// program instead gives a program of length `length` which takes `k` more
+/// steps, and `k` is at least 2.
+///
+/// Dead loops inside another loop are kept, since skipping over them costs a
+/// step on every iteration of the outer loop. For example, `>+++++[<[][]>-]`
+/// runs for 37 steps, but removing its dead loop gives a program which runs for
+/// 32 steps, and adding `++` to that only brings it up to 34.
+///
+/// This skips 1,356 of the 42,508 programs of length 7 (3.2%) and 69,744 of the
+/// 1,137,400 programs of length 9 (6.1%).
+pub fn live_loop_iterator(length: usize) -> impl Iterator<Item = Program> {
+    brute_force_filtered(length, |program| !has_top_level_dead_loop(program))
+}
+
+/// Return the canonical form of the program, after trimming. Programs whose
+/// canonical forms are the same halt or loop together, and a trimmed program
+/// never takes more steps than the same length program it was trimmed from.
+///
+/// The program is trimmed by removing any loops at the very start, since the
+/// starting cell is zero and so they are always skipped over. What remains is
+/// then canonicalized under reflection (see `bf::canonical_form`).
+pub fn trimmed_canonical_form(program: &Program, max_steps: usize) -> Program {
+    let mut instrs = program.original_instrs();
+    while instrs.first() == Some(&Instr::StartLoop) {
+        let mut depth = 0;
+        let end = instrs
+            .iter()
+            .position(|instr| {
+                match instr {
+                    Instr::StartLoop => depth += 1,
+                    Instr::EndLoop => depth -= 1,
+                    _ => (),
+                }
+                depth == 0
+            })
+            .expect("program already compiled");
+        instrs = &instrs[end + 1..];
+    }
+    let trimmed = Program::new(instrs.to_vec()).expect("trimming keeps braces balanced");
+    bf::canonical_form(&trimmed, max_steps)
+}
+
+/// Like `brute_force_iterator`, but only yields programs which are their own
+/// trimmed canonical form (see `trimmed_canonical_form`). This skips programs
+/// which are equivalent to a shorter program, or to another program of this
+/// length which was already yielded.
+///
+/// Skipped programs are never busy beavers: a program starting with a loop of
+/// length `k` takes one more step than a program of length `length - k`, while
+/// adding a `+` to the end of a program always adds a step. Reflections take
+/// exactly as many steps as the program they were reflected from.
+pub fn canonical_iterator(length: usize, max_steps: usize) -> impl Iterator<Item = Program> {
+    brute_force_iterator(length).filter(move |program| {
+        program.original_instrs().first() != Some(&Instr::StartLoop)
+            && bf::canonical_form(program, max_steps).original_instrs() == program.original_instrs()
+    })
+}
+
+/// Whether a program halted, was detected as looping, or neither within the
+/// step budget. See `Behavior`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    Halted,
+    Looping,
+    Unknown,
+}
+
+/// What can be observed about a program after running it for at most some
+/// number of steps. Programs with the same behavior are grouped together by
+/// `equivalence_classes`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Behavior {
+    pub verdict: Verdict,
+    /// The real steps taken until the program halted or was detected as
+    /// looping, or None if the verdict is unknown.
+    pub steps: Option<usize>,
+    /// The final tape, trimmed as by `ExecutionContext::tape_trimmed`.
+    pub tape: Vec<u8>,
+}
+
+impl Behavior {
+    /// Run the program for at most `max_steps` calls to `step` and return its
+    /// behavior.
+    pub fn of(program: &Program, max_steps: usize) -> Behavior {
+        let mut ctx = ExecutionContext::new(program);
+        let (_, steps, status) = ctx.run_until(max_steps, |_| false);
+        let (verdict, steps) = match status {
+            ExecutionStatus::Halted => (Verdict::Halted, Some(steps)),
+            ExecutionStatus::InfiniteLoop(_) => (Verdict::Looping, Some(steps)),
+            ExecutionStatus::Running => (Verdict::Unknown, None),
+        };
+        Behavior {
+            verdict,
+            steps,
+            tape: ctx.tape_trimmed().to_vec(),
+        }
+    }
+}
+
+/// A group of programs which all have the same `Behavior`. See
+/// `equivalence_classes`.
+#[derive(Debug, Clone)]
+pub struct EquivalenceClass {
+    pub behavior: Behavior,
+    /// The first member in lexiographic order.
+    pub representative: Program,
+    /// Every program with this behavior, in lexiographic order. This includes
+    /// the representative.
+    pub members: Vec<Program>,
+}
+
+/// Partition every valid program of the given length into classes of programs
+/// with the same `Behavior` within `max_steps`. The classes are returned in
+/// lexiographic order of their representatives.
+///
+/// This runs every program of the length, so it is only practical for short
+/// lengths, but it shows how much of the search space is redundant.
+pub fn equivalence_classes(length: usize, max_steps: usize) -> Vec<EquivalenceClass> {
+    let mut classes: Vec<EquivalenceClass> = vec![];
+    let mut class_indices: HashMap<Behavior, usize> = HashMap::new();
+    for program in brute_force_iterator(length) {
+        let behavior = Behavior::of(&program, max_steps);
+        match class_indices.get(&behavior) {
+            Some(&i) => classes[i].members.push(program),
+            None => {
+                class_indices.insert(behavior.clone(), classes.len());
+                classes.push(EquivalenceClass {
+                    behavior,
+                    representative: program.clone(),
+                    members: vec![program],
+                });
+            }
+        }
+    }
+    classes
+}
+
+// The instructions in lexiographic order.
+const INSTRS: [Instr; 6] = [
+    Instr::Plus,
+    Instr::Minus,
+    Instr::Left,
+    Instr::Right,
+    Instr::StartLoop,
+    Instr::EndLoop,
+];
+
+/// Return every program of the given length, including ones with mismatched
+/// braces, in lexiographic order.
+pub fn lexiographic_order(length: usize) -> LexiographicOrder {
+    LexiographicOrder {
+        length,
+        index: 0,
+        total: u32::try_from(length)
+            .ok()
+            .and_then(|length| INSTRS.len().checked_pow(length)),
+        next: Some(vec![Instr::Plus; length]),
+    }
+}
+
+/// The iterator returned by `lexiographic_order`. Its `size_hint` is exactly how
+/// many programs are left, unless there are more than `usize::MAX` programs of
+/// this length, in which case it has no upper bound. That is why this is not an
+/// `ExactSizeIterator`, whose `len` would have to panic for those lengths.
+#[derive(Debug, Clone)]
+pub struct LexiographicOrder {
+    length: usize,
+    // The index of `next`.
+    index: usize,
+    // The number of programs of this length, if it fits in a usize.
+    total: Option<usize>,
+    next: Option<Vec<Instr>>,
+}
+
+impl Iterator for LexiographicOrder {
+    type Item = Vec<Instr>;
+
+    fn next(&mut self) -> Option<Vec<Instr>> {
+        let program = self.next.take()?;
+        self.next = next_program(&program);
+        self.index += 1;
+        Some(program)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.total {
+            Some(total) => (total - self.index, Some(total - self.index)),
+            None => (usize::MAX, None),
+        }
+    }
+
+    // Skip directly to the nth program instead of generating every program
+    // before it.
+    fn nth(&mut self, n: usize) -> Option<Vec<Instr>> {
+        if n == 0 || self.next.is_none() {
+            return self.next();
+        }
+        self.next = self
+            .index
+            .checked_add(n)
+            .and_then(|index| nth_program(self.length, index));
+        self.index = match (self.next.is_some(), self.total) {
+            (false, Some(total)) => total,
+            _ => self.index.saturating_add(n),
+        };
+        self.next()
+    }
+}
+
+// Return the program at the given index of lexiographic_order(length), or None
+// if the index is past the end.
+fn nth_program(length: usize, mut index: usize) -> Option<Vec<Instr>> {
+    let mut program = vec![Instr::Plus; length];
+    for instr in program.iter_mut().rev() {
+        *instr = INSTRS[index % INSTRS.len()];
+        index /= INSTRS.len();
+    }
+    if index == 0 {
+        Some(program)
+    } else {
+        None
+    }
+}
+
+// Return the program after this one in lexiographic order, or None if this is
+// the last program of its length.
+fn next_program(program: &[Instr]) -> Option<Vec<Instr>> {
     fn next(instr: &Instr) -> (bool, Instr) {
         match instr {
             Instr::Plus => (false, Instr::Minus),
@@ -19,32 +355,224 @@ pub fn lexiographic_order(length: usize) -> impl Iterator<Item = Vec<Instr>> {
             Instr::Right => (false, Instr::StartLoop),
             Instr::StartLoop => (false, Instr::EndLoop),
             Instr::EndLoop => (true, Instr::Plus),
+            Instr::Output | Instr::Input => {
+                unreachable!("I/O instructions are never generated")
+            }
+        }
+    }
+
+    let mut next_program = program.to_vec();
+    let mut wrap_count = 0;
+    for instr in next_program.iter_mut().rev() {
+        let (did_wrap, next_instr) = next(instr);
+        *instr = next_instr;
+        if !did_wrap {
+            break;
+        } else {
+            wrap_count += 1;
         }
     }
+    if wrap_count == program.len() {
+        None
+    } else {
+        Some(next_program)
+    }
+}
 
-    fn next_program(program: &[Instr]) -> Option<Vec<Instr>> {
-        let mut next_program = program.to_vec();
-        let mut wrap_count = 0;
-        for instr in next_program.iter_mut().rev() {
-            let (did_wrap, next_instr) = next(instr);
-            *instr = next_instr;
-            if !did_wrap {
-                break;
-            } else {
-                wrap_count += 1;
+/// A single-instruction edit of a program. See `mutations`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutation {
+    /// Insert the instruction before the instruction at this index, or at the
+    /// end of the program if the index is the program's length.
+    Insert(usize, Instr),
+    /// Delete the instruction at this index.
+    Delete(usize),
+    /// Replace the instruction at this index with another instruction.
+    Replace(usize, Instr),
+}
+
+impl Mutation {
+    /// Returns true if this is a `Mutation::Insert`.
+    pub fn is_insertion(&self) -> bool {
+        matches!(self, Mutation::Insert(..))
+    }
+}
+
+/// Return every valid program which is one insertion, deletion or replacement
+/// away from this one, along with the mutation which produced it. Programs
+/// which can be produced by more than one mutation, such as "++" from
+/// inserting a "+" on either side of "+", are only returned once, for the
+/// first such mutation.
+pub fn mutations(program: &Program) -> Vec<(Mutation, Program)> {
+    let instrs = program.original_instrs();
+    let mut candidates = vec![];
+    for i in 0..=instrs.len() {
+        for instr in INSTRS {
+            candidates.push(Mutation::Insert(i, instr));
+        }
+    }
+    for (i, original) in instrs.iter().enumerate() {
+        candidates.push(Mutation::Delete(i));
+        for instr in INSTRS {
+            if instr != *original {
+                candidates.push(Mutation::Replace(i, instr));
             }
         }
-        if wrap_count == program.len() {
-            None
-        } else {
-            Some(next_program)
+    }
+
+    let mut seen = HashSet::new();
+    candidates
+        .into_iter()
+        .filter_map(|mutation| {
+            let mut mutated = instrs.to_vec();
+            match mutation {
+                Mutation::Insert(i, instr) => mutated.insert(i, instr),
+                Mutation::Delete(i) => {
+                    mutated.remove(i);
+                }
+                Mutation::Replace(i, instr) => mutated[i] = instr,
+            }
+            if !seen.insert(mutated.clone()) {
+                return None;
+            }
+            Program::new(mutated)
+                .ok()
+                .map(|program| (mutation, program))
+        })
+        .collect()
+}
+
+/// A resumable iterator over every valid program of every length, in order of
+/// increasing length and then lexiographic order. This is equivalent to
+/// `brute_force_chain(0..)`, but its position can be saved with `position` and
+/// restored with `resume_from`.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    length: usize,
+    // The index in lexiographic_order(length) of `next`.
+    index: usize,
+    // The next program to try, or None if every program of this length has been tried.
+    next: Option<Vec<Instr>>,
+}
+
+impl Cursor {
+    /// Create a cursor starting at the empty program.
+    pub fn new() -> Cursor {
+        Cursor::resume_from((0, 0))
+    }
+
+    /// Create a cursor which continues from a position returned by `position`.
+    pub fn resume_from((length, index): (usize, usize)) -> Cursor {
+        Cursor {
+            length,
+            index,
+            next: nth_program(length, index),
         }
     }
 
-    let starting_program = vec![Instr::Plus; length];
-    successors(Some(starting_program), |this_program| {
-        next_program(this_program)
-    })
+    /// Returns the position of the cursor as a `(length, index)` pair, where
+    /// `index` is the index in `lexiographic_order(length)` of the next program
+    /// to be tried. The next program yielded is at or after this position.
+    pub fn position(&self) -> (usize, usize) {
+        match self.next {
+            Some(_) => (self.length, self.index),
+            None => (self.length + 1, 0),
+        }
+    }
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Cursor::new()
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = Program;
+
+    fn next(&mut self) -> Option<Program> {
+        loop {
+            match self.next.take() {
+                Some(instrs) => {
+                    self.next = next_program(&instrs);
+                    self.index += 1;
+                    if let Ok(program) = Program::new(instrs) {
+                        return Some(program);
+                    }
+                }
+                None => {
+                    self.length += 1;
+                    self.index = 0;
+                    self.next = Some(vec![Instr::Plus; self.length]);
+                }
+            }
+        }
+    }
+}
+
+/// A source of random numbers for `random_valid`.
+pub trait Rng {
+    fn next_u64(&mut self) -> u64;
+
+    /// Returns a random number in `0..n`. `n` must be nonzero.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// A small xorshift PRNG, so that random programs are reproducible without
+/// pulling in an external crate.
+#[derive(Debug, Clone)]
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// Create a generator from a seed. A seed of zero is replaced with a fixed
+    /// nonzero seed, since xorshift only produces zeros from a zero state.
+    pub fn new(seed: u64) -> XorShift {
+        XorShift(if seed == 0 { 0x5EED_BEAF } else { seed })
+    }
+}
+
+impl Rng for XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Generate a random program of exactly the given length with balanced braces.
+/// Every instruction is chosen uniformly from the instructions which still
+/// allow the program to be balanced, so the distribution is close to, but not
+/// exactly, uniform over all valid programs of this length.
+pub fn random_valid(length: usize, rng: &mut impl Rng) -> Program {
+    let mut instrs = Vec::with_capacity(length);
+    let mut open_loops = 0;
+    for i in 0..length {
+        let remaining = length - i;
+        let instr = if open_loops == remaining {
+            // Every remaining instruction is needed to close the open loops.
+            Instr::EndLoop
+        } else {
+            let mut choices = vec![Instr::Plus, Instr::Minus, Instr::Left, Instr::Right];
+            // Only open a loop if there is room to close it afterwards.
+            if open_loops + 1 < remaining {
+                choices.push(Instr::StartLoop);
+            }
+            if open_loops > 0 {
+                choices.push(Instr::EndLoop);
+            }
+            choices[rng.below(choices.len())]
+        };
+        match instr {
+            Instr::StartLoop => open_loops += 1,
+            Instr::EndLoop => open_loops -= 1,
+            _ => (),
+        }
+        instrs.push(instr);
+    }
+    Program::new(instrs).expect("random programs are always balanced")
 }
 
 // enum Node {
@@ -68,3 +596,367 @@ pub fn lexiographic_order(length: usize) -> impl Iterator<Item = Vec<Instr>> {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_indexed_matches_lexiographic_order() {
+        let all_programs: Vec<_> = lexiographic_order(3).collect();
+        for (i, program) in brute_force_iterator_indexed(3) {
+            assert_eq!(program.original_instrs(), all_programs[i].as_slice());
+        }
+        // "+[]" is valid, so it must be reported at its raw lexiographic position
+        let (i, _) = brute_force_iterator_indexed(3)
+            .find(|(_, program)| program.to_string() == "+[]")
+            .unwrap();
+        assert_eq!(i, 4 * 6 + 5);
+    }
+
+    #[test]
+    fn test_brute_force_filtered() {
+        for length in 0..6 {
+            let with_loops: Vec<_> = brute_force_filtered(length, has_loop)
+                .map(|program| program.to_string())
+                .collect();
+            let expected: Vec<_> = brute_force_iterator(length)
+                .map(|program| program.to_string())
+                .filter(|program| program.contains('['))
+                .collect();
+            assert_eq!(with_loops, expected);
+        }
+
+        let nested: Vec<_> = brute_force_filtered(5, min_nesting(2))
+            .map(|program| program.to_string())
+            .collect();
+        assert!(nested.contains(&"+[[]]".to_string()));
+        assert!(nested.contains(&"[[]]-".to_string()));
+        assert!(!nested.contains(&"+[][]".to_string()));
+        assert_eq!(
+            brute_force_filtered(4, min_nesting(0)).count(),
+            brute_force_iterator(4).count()
+        );
+        assert_eq!(brute_force_filtered(5, min_nesting(3)).count(), 0);
+    }
+
+    #[test]
+    fn test_canonical_iterator() {
+        let max_steps = 1000;
+        let step_count = |program: &Program| {
+            let (status, steps, _) = bf::step_count(program, max_steps);
+            ((status.is_halted(), status.is_looping()), steps)
+        };
+
+        let mut emitted = HashSet::new();
+        for length in 0..=6 {
+            let canonical: Vec<_> = canonical_iterator(length, max_steps).collect();
+            let all: Vec<_> = brute_force_iterator(length).collect();
+            assert!(canonical.len() < all.len() || length < 2);
+            for program in &canonical {
+                assert!(emitted.insert(program.to_string()), "{}", program);
+            }
+
+            // Every program is equivalent to a canonical program of at most
+            // the same length, which was already emitted.
+            for program in &all {
+                let form = trimmed_canonical_form(program, max_steps);
+                assert!(emitted.contains(&form.to_string()), "{}", program);
+                assert_eq!(step_count(program).0, step_count(&form).0, "{}", program);
+            }
+
+            // Pruning never loses a busy beaver.
+            let champion = |programs: &[Program]| {
+                programs
+                    .iter()
+                    .map(step_count)
+                    .filter(|((halted, _), _)| *halted)
+                    .map(|(_, steps)| steps.unwrap())
+                    .max()
+            };
+            assert_eq!(champion(&canonical), champion(&all), "length {}", length);
+        }
+        assert_eq!(
+            trimmed_canonical_form(&Program::try_from("[>][-]<+").unwrap(), max_steps).to_string(),
+            "<+"
+        );
+    }
+
+    #[test]
+    fn test_live_loop_iterator() {
+        let max_steps = 1000;
+        assert!(has_dead_loop(&Program::try_from("+[-][<]").unwrap()));
+        assert!(!has_dead_loop(&Program::try_from("+[-]+[<]").unwrap()));
+        assert!(!has_dead_loop(&Program::try_from("+[[-]]").unwrap()));
+        assert!(has_top_level_dead_loop(
+            &Program::try_from("+[-][<]").unwrap()
+        ));
+        // Skipping over a dead loop inside another loop takes a step on every
+        // iteration, so these programs are not skipped.
+        let nested = Program::try_from(">+++++[<[][]>-]").unwrap();
+        assert!(has_dead_loop(&nested));
+        assert!(!has_top_level_dead_loop(&nested));
+        assert_eq!(bf::step_count(&nested, max_steps).1, Some(37));
+
+        for length in 0..=8 {
+            let all: Vec<_> = brute_force_iterator(length).collect();
+            let live: Vec<_> = live_loop_iterator(length).collect();
+            let num_dead = all
+                .iter()
+                .filter(|program| has_top_level_dead_loop(program))
+                .count();
+            assert_eq!(live.len() + num_dead, all.len());
+
+            let steps: Vec<_> = all
+                .par_iter()
+                .map(|program| match bf::step_count(program, max_steps) {
+                    (ExecutionStatus::Halted, steps, _) => steps,
+                    _ => None,
+                })
+                .collect();
+            let champion = |live_only: bool| {
+                all.iter()
+                    .zip(&steps)
+                    .filter(|(program, _)| !live_only || !has_top_level_dead_loop(program))
+                    .filter_map(|(_, steps)| *steps)
+                    .max()
+            };
+            assert_eq!(champion(true), champion(false), "length {}", length);
+        }
+        assert_eq!(live_loop_iterator(7).count(), 42_508 - 1_356);
+
+        // Removing a dead loop outside every loop gives a program which does
+        // the same thing in at most one step fewer.
+        for program in brute_force_iterator(7).filter(has_top_level_dead_loop) {
+            let source = program.to_string();
+            // The start of the first dead loop outside every loop.
+            let mut depth = 0;
+            let start = source
+                .char_indices()
+                .find(|&(i, c)| {
+                    depth += match c {
+                        '[' => 1,
+                        ']' => -1,
+                        _ => 0,
+                    };
+                    c == ']' && depth == 0 && source[i + 1..].starts_with('[')
+                })
+                .unwrap()
+                .0
+                + 1;
+            let mut depth = 0;
+            let end = start
+                + source[start..]
+                    .find(|c| {
+                        depth += match c {
+                            '[' => 1,
+                            ']' => -1,
+                            _ => 0,
+                        };
+                        depth == 0
+                    })
+                    .unwrap();
+            let removed = format!("{}{}", &source[..start], &source[end + 1..]);
+            let (status, steps, _) = bf::step_count(&program, max_steps);
+            let (removed_status, removed_steps, _) =
+                bf::step_count(&Program::try_from(removed.as_str()).unwrap(), max_steps);
+            assert_eq!(
+                status.is_halted(),
+                removed_status.is_halted(),
+                "{}",
+                program
+            );
+            if status.is_halted() {
+                let (steps, removed_steps) = (steps.unwrap(), removed_steps.unwrap());
+                assert!(
+                    steps == removed_steps || steps == removed_steps + 1,
+                    "{}",
+                    program
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_equivalence_classes() {
+        let max_steps = 1000;
+        let classes = equivalence_classes(4, max_steps);
+        let num_members: usize = classes.iter().map(|class| class.members.len()).sum();
+        assert_eq!(Some(num_members), count_valid(4));
+        assert!(classes.len() < num_members);
+
+        let mut seen = HashSet::new();
+        for class in &classes {
+            assert_eq!(
+                class.representative.to_string(),
+                class.members[0].to_string()
+            );
+            assert!(seen.insert(class.behavior.clone()));
+            for member in &class.members {
+                assert_eq!(Behavior::of(member, max_steps), class.behavior);
+                let (status, steps, _) = bf::step_count(member, max_steps);
+                assert_eq!(
+                    status.is_halted(),
+                    class.behavior.verdict == Verdict::Halted
+                );
+                assert_eq!(steps, class.behavior.steps, "{}", member);
+            }
+        }
+
+        let class_of = |program: &str| {
+            classes
+                .iter()
+                .position(|class| {
+                    class
+                        .members
+                        .iter()
+                        .any(|member| member.to_string() == program)
+                })
+                .unwrap()
+        };
+        assert_eq!(class_of("++--"), class_of("+-+-"));
+        assert_eq!(class_of("+>+<"), class_of(">+<+"));
+        assert_ne!(class_of("++--"), class_of("+>-<"));
+        assert_eq!(classes[class_of("+[]-")].behavior.verdict, Verdict::Looping);
+    }
+
+    #[test]
+    fn test_count_valid() {
+        for length in 0..7 {
+            assert_eq!(
+                count_valid(length),
+                Some(brute_force_iterator(length).count())
+            );
+        }
+        assert_eq!(count_valid(2), Some(4 * 4 + 1));
+        // The last length whose count fits in a u64, and the first which doesn't.
+        if usize::BITS == 64 {
+            assert_eq!(count_valid(26), Some(4_769_085_923_339_484_596));
+            assert_eq!(count_valid(27), None);
+            assert_eq!(count_valid(100), None);
+        }
+    }
+
+    #[test]
+    fn test_lexiographic_order_size() {
+        for length in 0..5 {
+            let mut programs = lexiographic_order(length);
+            let total = 6_usize.pow(length as u32);
+            assert_eq!(programs.size_hint(), (total, Some(total)));
+            programs.next();
+            assert_eq!(programs.size_hint(), (total - 1, Some(total - 1)));
+            assert_eq!(programs.by_ref().count(), total - 1);
+            assert_eq!(programs.size_hint(), (0, Some(0)));
+        }
+
+        let mut programs = lexiographic_order(3);
+        assert_eq!(programs.nth(100), lexiographic_order(3).take(101).last());
+        assert_eq!(programs.size_hint(), (216 - 101, Some(216 - 101)));
+        assert_eq!(programs.nth(1000), None);
+        assert_eq!(programs.size_hint(), (0, Some(0)));
+
+        // The valid programs are an unknown subset, so only the upper bound is known.
+        assert_eq!(
+            brute_force_iterator(4).size_hint(),
+            (0, Some(6 * 6 * 6 * 6))
+        );
+        // 6^24 is the largest count which fits in a u64.
+        if usize::BITS == 64 {
+            let total = 6_usize.pow(24);
+            assert_eq!(lexiographic_order(24).size_hint(), (total, Some(total)));
+            assert_eq!(lexiographic_order(25).size_hint(), (usize::MAX, None));
+        }
+        assert_eq!(lexiographic_order(100).size_hint(), (usize::MAX, None));
+    }
+
+    #[test]
+    fn test_mutations() {
+        let program = Program::try_from("+").unwrap();
+        let mut mutated: Vec<_> = mutations(&program)
+            .iter()
+            .map(|(_, program)| program.to_string())
+            .collect();
+        mutated.sort();
+        assert_eq!(
+            mutated,
+            ["", "++", "+-", "+<", "+>", "-", "-+", "<", "<+", ">", ">+"]
+        );
+
+        // Every mutation is valid and produces a different program.
+        let program = Program::try_from("+[->+<]").unwrap();
+        let mutated = mutations(&program);
+        let unique: HashSet<_> = mutated.iter().map(|(_, p)| p.to_string()).collect();
+        assert_eq!(unique.len(), mutated.len());
+        assert!(!unique.contains("+[->+<]"));
+        for (mutation, mutated) in &mutated {
+            let expected_length = match mutation {
+                Mutation::Insert(..) => program.original_instrs().len() + 1,
+                Mutation::Delete(_) => program.original_instrs().len() - 1,
+                Mutation::Replace(..) => program.original_instrs().len(),
+            };
+            assert_eq!(
+                mutated.original_instrs().len(),
+                expected_length,
+                "{:?}",
+                mutation
+            );
+        }
+        assert!(unique.contains("+[->++<]"));
+        assert!(unique.contains("+[>+<]"));
+        assert!(unique.contains("+[-<+<]"));
+    }
+
+    #[test]
+    fn test_random_valid() {
+        let mut rng = XorShift::new(1);
+        for length in 0..50 {
+            for _ in 0..20 {
+                let program = random_valid(length, &mut rng);
+                assert_eq!(program.original_instrs().len(), length);
+            }
+        }
+        // The same seed always gives the same programs.
+        let programs = |seed| {
+            let mut rng = XorShift::new(seed);
+            (0..10)
+                .map(|_| random_valid(12, &mut rng).to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(programs(42), programs(42));
+        assert_ne!(programs(42), programs(43));
+    }
+
+    #[test]
+    fn test_cursor_matches_brute_force_chain() {
+        let expected: Vec<_> = brute_force_chain(0..5).map(|p| p.to_string()).collect();
+        let actual: Vec<_> = Cursor::new()
+            .take(expected.len())
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_cursor_resume() {
+        let mut cursor = Cursor::new();
+        for _ in 0..1000 {
+            cursor.next();
+        }
+        let position = cursor.position();
+        let expected: Vec<_> = cursor.take(2000).map(|p| p.to_string()).collect();
+        let actual: Vec<_> = Cursor::resume_from(position)
+            .take(2000)
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(actual, expected);
+
+        // The remaining programs of length 2 are invalid, so the next length follows.
+        let mut cursor = Cursor::resume_from((2, 4 * 6 + 5));
+        assert_eq!(cursor.next().unwrap().to_string(), "[]");
+        assert_eq!(cursor.position(), (2, 4 * 6 + 5 + 1));
+        assert_eq!(cursor.next().unwrap().to_string(), "+++");
+        assert_eq!(cursor.position(), (3, 1));
+    }
+}
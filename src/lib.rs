@@ -2,4 +2,11 @@
 #![feature(let_chains)]
 
 pub mod bf;
+pub mod dot;
+pub mod expected;
 pub mod generate;
+pub mod macros;
+#[cfg(feature = "model_check")]
+pub mod model_check;
+pub mod report;
+pub mod search;
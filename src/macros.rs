@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Expand all macro definitions and invocations in `source`, returning the
+/// resulting program text, which can then be compiled with `Program::try_from`.
+///
+/// A macro is defined on its own line with `@name = body`, and is invoked by
+/// writing `@name` anywhere in the program or in the body of another macro.
+/// Definition lines are removed from the output, and all other lines are
+/// treated as the program itself. For example:
+/// ```text
+/// @clear = [-]
+/// @move_right = [->+<]
+/// +++ @move_right > @clear
+/// ```
+/// expands to `+++ [->+<] > [-]`.
+pub fn expand_macros(source: &str) -> Result<String, MacroError> {
+    let mut definitions = HashMap::new();
+    let mut program_lines = vec![];
+    for (line_number, line) in source.lines().enumerate() {
+        match parse_definition(line, line_number)? {
+            Some((name, body)) => {
+                if definitions.insert(name, body).is_some() {
+                    return Err(MacroError::DuplicateDefinition {
+                        name: name.to_string(),
+                    });
+                }
+            }
+            None => program_lines.push(line),
+        }
+    }
+
+    let mut expanded = String::new();
+    for (i, line) in program_lines.iter().enumerate() {
+        if i != 0 {
+            expanded.push('\n');
+        }
+        expand(line, &definitions, &mut vec![], &mut expanded)?;
+    }
+    Ok(expanded)
+}
+
+// If the line is a macro definition, return the macro's name and body.
+fn parse_definition(line: &str, line_number: usize) -> Result<Option<(&str, &str)>, MacroError> {
+    let line = line.trim();
+    let rest = match line.strip_prefix('@') {
+        Some(rest) => rest,
+        None => return Ok(None),
+    };
+    let (name, rest) = split_name(rest);
+    match rest.trim_start().strip_prefix('=') {
+        Some(body) if !name.is_empty() => Ok(Some((name, body.trim()))),
+        Some(_) => Err(MacroError::InvalidDefinition { line: line_number }),
+        // A line starting with an invocation is part of the program.
+        None => Ok(None),
+    }
+}
+
+// Split the string into a leading macro name and the remainder of the string.
+fn split_name(string: &str) -> (&str, &str) {
+    let end = string
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(string.len());
+    string.split_at(end)
+}
+
+// Expand the text into `output`. `stack` is the list of macros currently being
+// expanded, and is used to detect recursive macros.
+fn expand<'a>(
+    text: &'a str,
+    definitions: &HashMap<&'a str, &'a str>,
+    stack: &mut Vec<&'a str>,
+    output: &mut String,
+) -> Result<(), MacroError> {
+    let mut rest = text;
+    while let Some(at) = rest.find('@') {
+        output.push_str(&rest[..at]);
+        let (name, after) = split_name(&rest[at + 1..]);
+        let body = match definitions.get(name) {
+            Some(body) => body,
+            None => {
+                return Err(MacroError::UndefinedMacro {
+                    name: name.to_string(),
+                })
+            }
+        };
+        if stack.contains(&name) {
+            return Err(MacroError::RecursiveMacro {
+                name: name.to_string(),
+            });
+        }
+        stack.push(name);
+        expand(body, definitions, stack, output)?;
+        stack.pop();
+        rest = after;
+    }
+    output.push_str(rest);
+    Ok(())
+}
+
+/// An error specifying why a program's macros could not be expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroError {
+    /// A macro was invoked but never defined.
+    UndefinedMacro { name: String },
+    /// A macro was defined more than once.
+    DuplicateDefinition { name: String },
+    /// A macro invokes itself, either directly or through other macros.
+    RecursiveMacro { name: String },
+    /// A line looked like a macro definition, but had no name.
+    InvalidDefinition { line: usize },
+}
+
+impl Display for MacroError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MacroError::UndefinedMacro { name } => write!(f, "Undefined macro @{}", name),
+            MacroError::DuplicateDefinition { name } => {
+                write!(f, "Macro @{} is defined more than once", name)
+            }
+            MacroError::RecursiveMacro { name } => write!(f, "Macro @{} is recursive", name),
+            MacroError::InvalidDefinition { line } => {
+                write!(f, "Invalid macro definition on line {}", line + 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_nested_macros() {
+        let source =
+            "@clear = [-]\n@clear_next = >@clear<\n@clear2 = @clear @clear_next\n+>+@clear2";
+        assert_eq!(expand_macros(source).unwrap(), "+>+[-] >[-]<");
+        assert_eq!(expand_macros("+[-]").unwrap(), "+[-]");
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        assert_eq!(
+            expand_macros("@clear = [-]\n+@clr"),
+            Err(MacroError::UndefinedMacro {
+                name: "clr".to_string()
+            })
+        );
+        assert_eq!(
+            expand_macros("@a = +@b\n@b = -@a\n@a"),
+            Err(MacroError::RecursiveMacro {
+                name: "a".to_string()
+            })
+        );
+        assert_eq!(
+            expand_macros("@a = +\n@a = -"),
+            Err(MacroError::DuplicateDefinition {
+                name: "a".to_string()
+            })
+        );
+        assert_eq!(
+            expand_macros("+\n@ = -"),
+            Err(MacroError::InvalidDefinition { line: 1 })
+        );
+    }
+}
@@ -4,51 +4,98 @@
 
 pub mod visualizer;
 
-use std::{convert::TryFrom, io::Write};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use rayon::prelude::*;
+use thousands::Separable;
 
-use clap::Parser;
+use clap::{ArgEnum, Parser, Subcommand};
 
 use bf_beavers::{
-    bf::{self, ExecutionStatus},
-    generate,
+    bf::{self, step_count, step_count_with_detectors, ExecutionStatus},
+    dot, expected, generate, report, search,
 };
 
-fn step_count(program: &bf::Program, max_steps: usize) -> (ExecutionStatus, Option<usize>, usize) {
-    let mut ctx = bf::ExecutionContext::new(program);
-    let mut total_real_steps = 0;
-    for _ in 1..max_steps {
-        let (real_steps, state) = ctx.step();
-        total_real_steps += real_steps;
-        match state {
-            ExecutionStatus::Halted | ExecutionStatus::InfiniteLoop(_) => {
-                return (state, Some(total_real_steps), ctx.tape_length());
-            }
-            ExecutionStatus::Running => (),
-        }
+// How much diagnostic output to print to stderr. Results are printed to stdout
+// and written to files at every verbosity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+// The verbosity set by --quiet and --verbose, as a Verbosity cast to usize.
+static VERBOSITY: AtomicUsize = AtomicUsize::new(Verbosity::Normal as usize);
+
+fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
     }
-    (ExecutionStatus::Running, None, ctx.tape_length())
+}
+
+// Print a diagnostic to stderr, if the verbosity is at least `level`.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if verbosity() >= $level {
+            eprintln!($($arg)*);
+        }
+    };
 }
 
 struct BusyBeaverResults {
     busy_beavers: (usize, Vec<bf::Program>),
+    // The halting programs which leave the most nonzero cells on the tape, and
+    // how many cells they leave (see bf::sigma).
+    sigma_champions: (usize, Vec<bf::Program>),
     hardest_to_prove: Option<(usize, bf::Program)>,
     max_tape_length: usize,
     unknown_programs: Vec<bf::Program>,
     num_halted: usize,
+    // The number of halting programs which contain no loops. These are also
+    // included in num_halted.
+    num_trivially_halting: usize,
+    // The number of halting programs which halted within the last
+    // NEAR_BUDGET_PERCENT of the step limit (see is_near_budget). These are also
+    // included in num_halted.
+    num_near_budget: usize,
     num_looping: usize,
+    // Every looping program and the reason it was detected as looping. This is
+    // only collected if requested, since there are a lot of looping programs.
+    looping_programs: Vec<(bf::Program, bf::LoopReason)>,
+    // The number of halted, looping and unknown programs at each nesting depth
+    // (see bf::Program::nesting_depth), indexed by depth.
+    by_nesting_depth: Vec<[usize; 3]>,
+    // The number of unknown programs for which each limit of the budget ran out.
+    unknown_reasons: HashMap<bf::UnknownReason, usize>,
 }
 
 impl BusyBeaverResults {
     fn identity() -> BusyBeaverResults {
         BusyBeaverResults {
             busy_beavers: (0, vec![]),
+            sigma_champions: (0, vec![]),
             max_tape_length: 0,
             hardest_to_prove: None,
             unknown_programs: vec![],
             num_halted: 0,
+            num_trivially_halting: 0,
+            num_near_budget: 0,
             num_looping: 0,
+            looping_programs: vec![],
+            by_nesting_depth: vec![],
+            unknown_reasons: HashMap::new(),
         }
     }
 
@@ -56,11 +103,15 @@ impl BusyBeaverResults {
         program: bf::Program,
         steps: usize,
         max_tape_length: usize,
+        sigma: usize,
     ) -> BusyBeaverResults {
+        let num_trivially_halting = if program.is_loop_free() { 1 } else { 0 };
         BusyBeaverResults {
+            sigma_champions: (sigma, vec![program.clone()]),
             busy_beavers: (steps, vec![program]),
             max_tape_length,
             num_halted: 1,
+            num_trivially_halting,
             ..BusyBeaverResults::identity()
         }
     }
@@ -69,197 +120,1807 @@ impl BusyBeaverResults {
         program: bf::Program,
         steps: usize,
         max_tape_length: usize,
+        reason: Option<bf::LoopReason>,
     ) -> BusyBeaverResults {
+        let looping_programs = match reason {
+            Some(reason) => vec![(program.clone(), reason)],
+            None => vec![],
+        };
         BusyBeaverResults {
             max_tape_length,
             hardest_to_prove: Some((steps, program)),
             num_looping: 1,
+            looping_programs,
             ..BusyBeaverResults::identity()
         }
     }
 
-    fn from_unknown(program: bf::Program, max_tape_length: usize) -> BusyBeaverResults {
+    fn from_unknown(
+        program: bf::Program,
+        max_tape_length: usize,
+        reason: bf::UnknownReason,
+    ) -> BusyBeaverResults {
         BusyBeaverResults {
             max_tape_length,
             unknown_programs: vec![program],
+            unknown_reasons: HashMap::from([(reason, 1)]),
             ..BusyBeaverResults::identity()
         }
     }
 }
 
+impl BusyBeaverResults {
+    // If keep_loop_reasons is true, a looping program is kept in looping_programs.
+    // If champions_only is true, a looping program is only counted in
+    // num_looping, and is never kept in hardest_to_prove. `max_steps` is the
+    // step limit the program was run with.
+    fn from_outcome(
+        outcome: search::Outcome,
+        max_steps: usize,
+        keep_loop_reasons: bool,
+        champions_only: bool,
+    ) -> BusyBeaverResults {
+        let search::Outcome {
+            program,
+            status,
+            steps,
+            tape_length,
+            sigma,
+            unknown_reason,
+            ..
+        } = outcome;
+        let mut by_nesting_depth = vec![[0; 3]; program.nesting_depth() + 1];
+        let near_budget = match (&status, steps) {
+            (ExecutionStatus::Halted, Some(steps)) => is_near_budget(steps, max_steps),
+            _ => false,
+        };
+        let (mut results, kind) = match status {
+            ExecutionStatus::InfiniteLoop(_) if champions_only => (
+                BusyBeaverResults {
+                    max_tape_length: tape_length,
+                    num_looping: 1,
+                    ..BusyBeaverResults::identity()
+                },
+                1,
+            ),
+            ExecutionStatus::Running => (
+                BusyBeaverResults::from_unknown(program, tape_length, unknown_reason.unwrap()),
+                2,
+            ),
+            ExecutionStatus::Halted => (
+                BusyBeaverResults::from_halted(
+                    program,
+                    steps.unwrap(),
+                    tape_length,
+                    sigma.unwrap(),
+                ),
+                0,
+            ),
+            ExecutionStatus::InfiniteLoop(reason) => (
+                BusyBeaverResults::from_looping(
+                    program,
+                    steps.unwrap(),
+                    tape_length,
+                    keep_loop_reasons.then_some(reason),
+                ),
+                1,
+            ),
+        };
+        by_nesting_depth.last_mut().unwrap()[kind] = 1;
+        results.by_nesting_depth = by_nesting_depth;
+        results.num_near_budget = near_budget as usize;
+        results
+    }
+
+    // The busy beavers are only proven to be the longest running halting programs
+    // if every program was resolved. An unknown program may halt after more steps
+    // than the busy beavers, no matter how many steps it was run for, so if there
+    // are any, the busy beavers are only a lower bound.
+    fn is_proven(&self) -> bool {
+        self.num_unknown() == 0
+    }
+
+    // The number of programs which were neither halting nor proven to loop.
+    fn num_unknown(&self) -> usize {
+        self.unknown_programs.len()
+    }
+
+    fn combine(mut a: BusyBeaverResults, mut b: BusyBeaverResults) -> BusyBeaverResults {
+        BusyBeaverResults {
+            busy_beavers: combine_champions(a.busy_beavers, b.busy_beavers),
+            sigma_champions: combine_champions(a.sigma_champions, b.sigma_champions),
+            hardest_to_prove: match (a.hardest_to_prove, b.hardest_to_prove) {
+                (Some((a_steps, a_prog)), Some((b_steps, b_prog))) => {
+                    // Break ties lexiographically, so the result doesn't depend
+                    // on the order the programs were combined in.
+                    if (a_steps, b_prog.original_instrs()) > (b_steps, a_prog.original_instrs()) {
+                        Some((a_steps, a_prog))
+                    } else {
+                        Some((b_steps, b_prog))
+                    }
+                }
+                (Some(a), None) => Some(a),
+                (None, Some(b)) => Some(b),
+                (None, None) => None,
+            },
+            max_tape_length: a.max_tape_length.max(b.max_tape_length),
+            unknown_programs: {
+                a.unknown_programs.append(&mut b.unknown_programs);
+                a.unknown_programs
+            },
+            num_halted: a.num_halted + b.num_halted,
+            num_trivially_halting: a.num_trivially_halting + b.num_trivially_halting,
+            num_near_budget: a.num_near_budget + b.num_near_budget,
+            num_looping: a.num_looping + b.num_looping,
+            looping_programs: {
+                a.looping_programs.append(&mut b.looping_programs);
+                a.looping_programs
+            },
+            unknown_reasons: {
+                for (reason, count) in b.unknown_reasons {
+                    *a.unknown_reasons.entry(reason).or_insert(0) += count;
+                }
+                a.unknown_reasons
+            },
+            by_nesting_depth: {
+                if a.by_nesting_depth.len() < b.by_nesting_depth.len() {
+                    std::mem::swap(&mut a.by_nesting_depth, &mut b.by_nesting_depth);
+                }
+                for (a_counts, b_counts) in a.by_nesting_depth.iter_mut().zip(&b.by_nesting_depth) {
+                    for (a_count, b_count) in a_counts.iter_mut().zip(b_counts) {
+                        *a_count += b_count;
+                    }
+                }
+                a.by_nesting_depth
+            },
+        }
+    }
+
+    // Sort the busy beavers and unknown programs lexiographically. The parallel
+    // search combines results in an arbitrary order, so this makes the output
+    // reproducible between runs.
+    fn sort_programs(&mut self) {
+        let by_instrs =
+            |a: &bf::Program, b: &bf::Program| a.original_instrs().cmp(b.original_instrs());
+        self.busy_beavers.1.sort_by(by_instrs);
+        self.sigma_champions.1.sort_by(by_instrs);
+        self.unknown_programs.sort_by(by_instrs);
+        self.looping_programs
+            .sort_by(|(a, _), (b, _)| by_instrs(a, b));
+    }
+}
+
+// How close to the step limit, as a percentage of it, a halting program must
+// halt to be counted in BusyBeaverResults::num_near_budget.
+const NEAR_BUDGET_PERCENT: usize = 5;
+
+// Returns true if a program which halted after `steps` real steps halted within
+// the last NEAR_BUDGET_PERCENT of `max_steps`. The step limit counts calls to
+// step, which are at most one more than the real steps (the last call only
+// reports that the program halted), so this may also count programs which
+// halted well within the limit by running folded instructions, but never misses
+// one which actually halted near it.
+fn is_near_budget(steps: usize, max_steps: usize) -> bool {
+    let max_calls = steps as u128 + 1;
+    max_calls * 100 >= max_steps as u128 * (100 - NEAR_BUDGET_PERCENT) as u128
+}
+
+// Returns true if the outcome is a halting program which took more steps than
+// every program before it, updating `best_so_far` if so.
+fn is_new_record(best_so_far: &AtomicUsize, outcome: &search::Outcome) -> bool {
+    match (&outcome.status, outcome.steps) {
+        (ExecutionStatus::Halted, Some(steps)) => {
+            best_so_far.fetch_max(steps, Ordering::Relaxed) < steps
+        }
+        _ => false,
+    }
+}
+
+// How `beaver` runs a search and what it reports while the search runs. The
+// default runs in rayon's global pool and reports nothing.
+#[derive(Debug, Clone, Default)]
+struct BeaverOptions {
+    // Log every nth program (see --print-every).
+    print_every: Option<usize>,
+    // Keep every looping program and its reason (see --loop-reasons).
+    keep_loop_reasons: bool,
+    // The pool to search in, instead of rayon's global pool (see --threads).
+    pool: Option<Arc<rayon::ThreadPool>>,
+    // Log each new record as it is found (see --live).
+    live: bool,
+    // Write every outcome to stdout as JSON (see --json-lines).
+    json_lines: bool,
+    // Only count looping programs, without keeping any of them (see
+    // --champions-only).
+    champions_only: bool,
+}
+
 fn beaver(
     length: usize,
-    max_steps: usize,
-    print_every: Option<usize>,
+    budget: bf::Budget,
+    options: &BeaverOptions,
 ) -> (BusyBeaverResults, usize) {
-    let programs = generate::brute_force_iterator(length);
-    let results = programs
-        .enumerate()
-        .inspect(|(i, program)| {
-            if let Some(print_every) = print_every && i % print_every == 0 && *i != 0 {
-                eprintln!("{}: {}", i, program)
+    let BeaverOptions {
+        print_every,
+        keep_loop_reasons,
+        pool,
+        live,
+        json_lines,
+        champions_only,
+    } = options.clone();
+    let best_so_far = AtomicUsize::new(0);
+    // The error from writing a JSON record, which stops the search.
+    let mut write_error = None;
+    let results = search::beaver_stream_in(pool, length, budget, None)
+        .map_while(|outcome| {
+            let i = outcome.index;
+            if print_every.is_some_and(|print_every| i % print_every == 0 && i != 0) {
+                log!(Verbosity::Normal, "{}: {}", i, outcome.program)
             }
-        })
-        .par_bridge()
-        .map(|(_, program)| {
-            let (state, steps, max_tape_length) = step_count(&program, max_steps);
-            match state {
-                ExecutionStatus::Running => {
-                    BusyBeaverResults::from_unknown(program, max_tape_length)
-                }
-                ExecutionStatus::Halted => {
-                    BusyBeaverResults::from_halted(program, steps.unwrap(), max_tape_length)
-                }
-                ExecutionStatus::InfiniteLoop(_) => {
-                    BusyBeaverResults::from_looping(program, steps.unwrap(), max_tape_length)
+            if live && is_new_record(&best_so_far, &outcome) {
+                log!(
+                    Verbosity::Normal,
+                    "new record for length {}: {} ({} steps)",
+                    length,
+                    outcome.program,
+                    outcome.steps.unwrap()
+                );
+            }
+            if json_lines {
+                // The outcomes arrive one at a time from the parallel search,
+                // and each record is written to the locked stdout with a single
+                // write, so records are never interleaved.
+                let record = format!("{}\n", report::outcome_to_json(&outcome));
+                if let Err(err) = std::io::stdout().lock().write_all(record.as_bytes()) {
+                    write_error = Some(err);
+                    return None;
                 }
             }
+            Some(outcome)
         })
-        .reduce(
-            || BusyBeaverResults::identity(),
-            |mut a, mut b| BusyBeaverResults {
-                busy_beavers: {
-                    let best_steps = a.busy_beavers.0.max(b.busy_beavers.0);
-                    let best_programs = if a.busy_beavers.0 == b.busy_beavers.0 {
-                        a.busy_beavers.1.append(&mut b.busy_beavers.1);
-                        a.busy_beavers.1
-                    } else if a.busy_beavers.0 > b.busy_beavers.0 {
-                        a.busy_beavers.1
-                    } else {
-                        b.busy_beavers.1
-                    };
-                    (best_steps, best_programs)
-                },
-                hardest_to_prove: match (a.hardest_to_prove, b.hardest_to_prove) {
-                    (Some((a_steps, a_prog)), Some((b_steps, b_prog))) => {
-                        if a_steps > b_steps {
-                            Some((a_steps, a_prog))
-                        } else {
-                            Some((b_steps, b_prog))
-                        }
-                    }
-                    (Some(a), None) => Some(a),
-                    (None, Some(b)) => Some(b),
-                    (None, None) => None,
-                },
-                max_tape_length: a.max_tape_length.max(b.max_tape_length),
-                unknown_programs: {
-                    a.unknown_programs.append(&mut b.unknown_programs);
-                    a.unknown_programs
-                },
-                num_halted: a.num_halted + b.num_halted,
-                num_looping: a.num_looping + b.num_looping,
-            },
-        );
+        .map(|outcome| {
+            BusyBeaverResults::from_outcome(
+                outcome,
+                budget.max_steps,
+                keep_loop_reasons,
+                champions_only,
+            )
+        })
+        .fold(BusyBeaverResults::identity(), BusyBeaverResults::combine);
+
+    // The results are incomplete if the search stopped early, so they can't be
+    // written either. A closed pipe just means the reader has seen enough, such
+    // as `search --json-lines | head`.
+    match write_error {
+        Some(err) if err.kind() == std::io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Some(err) => {
+            eprintln!("Cannot write JSON lines to stdout (reason: {})", err);
+            std::process::exit(1);
+        }
+        None => (),
+    }
 
     let lexiographic_size = 6_usize.pow(length as u32);
     (results, lexiographic_size)
 }
 
+// The detector configurations compared by the bench subcommand
+const DETECTOR_CONFIGS: [(&str, bf::Detectors); 5] = [
+    ("none", bf::Detectors::NONE),
+    (
+        "loop_if_nonzero",
+        bf::Detectors {
+            loop_if_nonzero: true,
+            ..bf::Detectors::NONE
+        },
+    ),
+    (
+        "loop_span",
+        bf::Detectors {
+            loop_span: true,
+            ..bf::Detectors::NONE
+        },
+    ),
+    (
+        "no_op_loop",
+        bf::Detectors {
+            no_op_loop: true,
+            ..bf::Detectors::NONE
+        },
+    ),
+    ("all", bf::Detectors::ALL),
+];
+
+// Print a table of how many programs of each length are proven to not halt
+// by each detector configuration.
+fn compare_detectors(max_length: usize, max_steps: usize) {
+    print!("{:>6} {:>9}", "length", "programs");
+    for (name, _) in DETECTOR_CONFIGS {
+        print!(" {:>15}", name);
+    }
+    println!();
+
+    for length in 0..=max_length {
+        let programs: Vec<_> = generate::brute_force_iterator(length).collect();
+        print!("{:>6} {:>9}", length, programs.len());
+        for (_, detectors) in DETECTOR_CONFIGS {
+            let num_looping = programs
+                .par_iter()
+                .filter(|program| {
+                    let (state, _, _) = step_count_with_detectors(program, max_steps, detectors);
+                    state.is_looping()
+                })
+                .count();
+            print!(" {:>15}", num_looping);
+        }
+        println!();
+    }
+}
+
+// Like step_count, but the tape starts with `tape` instead of being blank.
+fn step_count_on_tape(
+    program: &bf::Program,
+    tape: &[u8],
+    max_steps: usize,
+) -> (ExecutionStatus, Option<usize>) {
+    if tape.is_empty() {
+        let (state, steps, _) = step_count(program, max_steps);
+        return (state, steps);
+    }
+    let mut ctx = bf::ExecutionContext::with_memory(program.clone(), tape.to_vec());
+    // step_count makes at most max_steps - 1 calls to step.
+    let (_, steps, state) = ctx.run_until(max_steps.saturating_sub(1), |_| false);
+    match state {
+        ExecutionStatus::Running => (state, None),
+        _ => (state, Some(steps)),
+    }
+}
+
+// Run a program and print how many steps it took.
+fn print_run(program: &bf::Program, tape: &[u8], max_steps: usize, format: Format) {
+    let (state, steps) = step_count_on_tape(program, tape, max_steps);
+    if format == Format::Toml {
+        print!(
+            "{}",
+            report::RunReport::new(program, &state, steps).to_toml()
+        );
+        return;
+    }
+    match state {
+        ExecutionStatus::Running => {
+            println!("Timed out (runs longer than {} steps)", max_steps)
+        }
+        ExecutionStatus::Halted => println!("Halts in {} steps", steps.unwrap()),
+        ExecutionStatus::InfiniteLoop(reason) => {
+            println!(
+                "Does not halt (reason: {:#?}, at step {})",
+                reason,
+                steps.unwrap()
+            )
+        }
+    }
+}
+
+// Run a program until it resolves, and describe why it never halts, including
+// the matching loop spans if the loop span detector fired.
+fn replay_proof(program: &bf::Program, max_steps: usize, detectors: bf::Detectors) -> String {
+    let mut ctx = bf::ExecutionContext::with_detectors(program, detectors);
+    let (calls, _, status) = ctx.run_until(max_steps, |_| false);
+    let reason = match status {
+        ExecutionStatus::Running => {
+            return format!("Timed out (runs longer than {} steps)\n", max_steps)
+        }
+        ExecutionStatus::Halted => {
+            return format!(
+                "Halts in {} steps, so there is nothing to prove\n",
+                ctx.steps_taken()
+            )
+        }
+        ExecutionStatus::InfiniteLoop(reason) => reason,
+    };
+    let mut proof = format!(
+        "Detected as looping at step {} ({} calls to step): {}\n",
+        ctx.steps_taken(),
+        calls,
+        reason
+    );
+    match dot::loop_span_text(&reason, ctx.tape()) {
+        Some(spans) => proof.push_str(&spans),
+        None => proof.push_str(&format!("tape: {:?}\n", ctx.tape_trimmed())),
+    }
+    proof
+}
+
+// Keep the programs with the highest score, along with that score. Programs
+// which tie are all kept.
+fn combine_champions(
+    mut a: (usize, Vec<bf::Program>),
+    mut b: (usize, Vec<bf::Program>),
+) -> (usize, Vec<bf::Program>) {
+    match a.0.cmp(&b.0) {
+        std::cmp::Ordering::Equal => {
+            a.1.append(&mut b.1);
+            a
+        }
+        std::cmp::Ordering::Greater => a,
+        std::cmp::Ordering::Less => b,
+    }
+}
+
+// The format to output results in.
+#[derive(ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Human readable text
+    Text,
+    /// TOML, with the keys described by bf_beavers::report
+    Toml,
+}
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Interactive mode - run with a BF program to visualize
-    #[clap(short, long, value_name = "bf program", allow_hyphen_values = true)]
-    interactive: Option<String>,
-    /// Interactive mode - start at step n
-    #[clap(long, value_name = "steps", default_value_t = 0)]
-    start_at: usize,
-    /// Simple mode - run a BF program and output the number of steps it took
-    #[clap(long, value_name = "bf program", allow_hyphen_values = true)]
-    run: Option<String>,
-    /// How many steps to run programs for before giving up
-    #[clap(long, value_name = "steps", default_value_t = 50_000)]
+    /// How many steps to run programs for before giving up. Accepts k/M/G suffixes and underscores, such as 50k or 1_000_000
+    #[clap(long, global = true, value_name = "steps", default_value = "50k", parse(try_from_str = parse_count))]
     max_steps: usize,
-    /// Beaver mode - The maximum length of programs to generate
+    /// Don't print progress to stderr, such as from search --print-every and --live. Results are still printed
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print more about what is happening to stderr, such as when each length of a search starts and finishes
+    #[clap(short, long, global = true)]
+    verbose: bool,
+    /// The mode to run in. If no mode is given, search with the default options.
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a BF program and output the number of steps it took
+    Run {
+        /// The program to run. If this is "-" or is not given, the program is read from stdin
+        #[clap(value_name = "bf program", allow_hyphen_values = true, parse(try_from_str = parse_program_arg))]
+        program: Option<ProgramArg>,
+        /// The bytes the tape starts with, separated by commas, such as 3,0,255. The rest of the tape is zero
+        #[clap(long, value_name = "bytes", parse(try_from_str = parse_tape), default_value = "")]
+        initial_tape: Tape,
+        /// The format to output the result in
+        #[clap(long, arg_enum, default_value = "text")]
+        format: Format,
+    },
+    /// Visualize a BF program interactively. A # in the program is a breakpoint, which can be continued to with c
+    Visualize {
+        #[clap(
+            value_name = "bf program",
+            allow_hyphen_values = true,
+            required_unless_present = "load-session",
+            parse(try_from_str = parse_program_with_breakpoints)
+        )]
+        program: Option<ProgramWithBreakpoints>,
+        /// Start at step n. Accepts the same suffixes as --max-steps. Large steps take a while to reach, which is shown as it happens
+        #[clap(long, value_name = "steps", default_value = "0", parse(try_from_str = parse_start_at))]
+        start_at: usize,
+        /// Cache every nth step, to speed up stepping backwards at the cost of memory
+        #[clap(long, value_name = "n", default_value_t = visualizer::DEFAULT_CACHE_INTERVAL, validator = validate_cache_interval)]
+        cache_interval: usize,
+        /// The bytes the tape starts with, separated by commas, such as 3,0,255. The rest of the tape is zero
+        #[clap(long, value_name = "bytes", parse(try_from_str = parse_tape), default_value = "", conflicts_with = "load-session")]
+        initial_tape: Tape,
+        /// Continue from a session saved in the visualizer, instead of starting a program from step 0. The session is saved back to this path
+        #[clap(long, value_name = "path", conflicts_with = "program")]
+        load_session: Option<PathBuf>,
+    },
+    /// Search for the busy beavers of each length, writing the results to length_n.txt
+    Search(SearchArgs),
+    /// Count how many programs up to --max-length each combination of infinite loop detectors proves to be non-halting
+    Bench {
+        /// The maximum length of programs to generate
+        #[clap(long, value_name = "length", default_value_t = 8)]
+        max_length: usize,
+    },
+    /// Check every program up to --max-length against a simple reference interpreter, and output any whose step counts or halting status disagree
+    #[cfg(feature = "model_check")]
+    ModelCheck {
+        /// The maximum length of programs to check
+        #[clap(long, value_name = "length", default_value_t = 7)]
+        max_length: usize,
+    },
+    /// Search the programs formed by inserting one instruction into a seed program, and output the ones which halt in more steps than the seed
+    Extend {
+        #[clap(value_name = "bf program", allow_hyphen_values = true, parse(try_from_str = parse_program))]
+        seed: bf::Program,
+    },
+    /// Find the shortest halting program which runs for at least the given number of steps, trying each length in increasing order
+    Shortest {
+        /// The number of steps to reach. Accepts the same suffixes as --max-steps
+        #[clap(value_name = "steps", parse(try_from_str = parse_count))]
+        target: usize,
+        /// The maximum length of programs to try
+        #[clap(long, value_name = "length", default_value_t = 8)]
+        max_length: usize,
+    },
+    /// Run a program until it is detected as looping, and output the proof that it never halts. Matching loop spans are shown aligned above the tape
+    ReplayProof {
+        #[clap(value_name = "bf program", allow_hyphen_values = true, parse(try_from_str = parse_program))]
+        program: bf::Program,
+        /// Only use the loop span detector, so that the proof is always a pair of matching loop spans
+        #[clap(long)]
+        loop_span_only: bool,
+    },
+    /// Run every program in a corpus file (see search --export-corpus) and output the number of steps each took. Exits with status 1 if the corpus can't be read
+    Verify {
+        #[clap(value_name = "path")]
+        corpus: PathBuf,
+    },
+    /// Run every program in a CSV file of verdicts from another tool, with one program,verdict,steps row per line, and output the programs whose results disagree. The verdict is halted, looping or unknown, and the steps may be empty. Exits with status 1 if any result disagrees or the file can't be read
+    CheckExpected {
+        #[clap(value_name = "path")]
+        csv: PathBuf,
+    },
+}
+
+#[derive(Parser, Debug)]
+struct SearchArgs {
+    /// The maximum length of programs to generate
     #[clap(long, value_name = "length", default_value_t = 8)]
     max_length: usize,
-    /// Beaver mode - Print the nth program
+    /// Append the busy beavers and unknown programs of each length to a corpus file
+    #[clap(long, value_name = "path")]
+    export_corpus: Option<PathBuf>,
+    /// Print every nth program (n counts all programs in lexiographic order, including invalid ones)
     #[clap(short, value_name = "n", long)]
     print_every: Option<usize>,
+    /// List every looping program and why it was detected as looping in the output. This keeps every looping program in memory
+    #[clap(long)]
+    loop_reasons: bool,
+    /// Print the number of programs of each length and a rough estimate of how long searching them would take, without searching
+    #[clap(long)]
+    dry_run: bool,
+    /// Run programs of length n for base^n steps instead of --max-steps, so that longer programs get more steps. --max-steps is still used as the minimum
+    #[clap(long, value_name = "base")]
+    step_base: Option<usize>,
+    /// Mark busy beavers which move left from the first cell of the tape. Moving left there does nothing, so their results depend on the tape only being infinite to the right
+    #[clap(long)]
+    left_edge: bool,
+    /// The number of threads to search with. If this is not given, rayon chooses the number of threads automatically
+    #[clap(long, value_name = "n")]
+    threads: Option<usize>,
+    /// Print each halting program which takes more steps than every program found before it to stderr as soon as it is found
+    #[clap(long)]
+    live: bool,
+    /// Give up on a program once its tape is longer than this many cells. The output counts how many programs were given up on because of each limit
+    #[clap(long, value_name = "cells")]
+    max_tape: Option<usize>,
+    /// Give up on a program once it has run for this many milliseconds
+    #[clap(long, value_name = "ms")]
+    time_limit: Option<u64>,
+    /// The format to write the results of each length in. TOML results are written to length_n.toml instead of length_n.txt
+    #[clap(long, arg_enum, default_value = "text")]
+    format: Format,
+    /// Only find the busy beavers and the unknown programs. Looping programs are still detected, but are only counted, so the hardest program to prove is not found
+    #[clap(long, conflicts_with = "loop-reasons")]
+    champions_only: bool,
+    /// Also print the result of every program to stdout as soon as it is run, as one JSON object per line with the keys program, status, steps and tape_length. The results of each length are still written to files
+    #[clap(long)]
+    json_lines: bool,
+}
+
+// The number of steps to run programs of the given length for. This is
+// max_steps for every length, unless step_base is given, in which case it
+// is step_base^length, but never less than max_steps.
+fn steps_for_length(step_base: Option<usize>, max_steps: usize, length: usize) -> usize {
+    match step_base {
+        Some(base) => base
+            .checked_pow(length as u32)
+            .unwrap_or(usize::MAX)
+            .max(max_steps),
+        None => max_steps,
+    }
+}
+
+// Return the program given on the command line, or read it from `stdin` until
+// EOF and compile it if it is "-" or missing.
+fn read_program(program: Option<ProgramArg>, mut stdin: impl Read) -> Result<bf::Program, String> {
+    match program {
+        Some(ProgramArg::Program(program)) => Ok(program),
+        Some(ProgramArg::Stdin) | None => {
+            let mut program = String::new();
+            stdin
+                .read_to_string(&mut program)
+                .map_err(|err| format!("Cannot read program from stdin (reason: {})", err))?;
+            parse_program(&program)
+                .map_err(|err| format!("Cannot compile {} (reason: {})", program.trim_end(), err))
+        }
+    }
+}
+
+// Parse a count such as "50000", "50_000", "50k", "1M" or "2G".
+fn parse_count(count: &str) -> Result<usize, String> {
+    let digits = count.replace('_', "");
+    let (digits, multiplier) = match digits.char_indices().last() {
+        Some((i, 'k')) | Some((i, 'K')) => (&digits[..i], 1_000),
+        Some((i, 'M')) => (&digits[..i], 1_000_000),
+        Some((i, 'G')) => (&digits[..i], 1_000_000_000),
+        Some((_, c)) if !c.is_ascii_digit() => {
+            return Err(format!("invalid suffix '{}' (expected k, M or G)", c))
+        }
+        _ => (digits.as_str(), 1),
+    };
+    let value: usize = digits
+        .parse()
+        .map_err(|err| format!("invalid count '{}' ({})", count, err))?;
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("count '{}' is too large", count))
 }
+
+// The initial contents of the tape, as parsed by parse_tape. An empty tape
+// means the tape starts out blank. (This is a newtype so that clap parses the
+// whole list at once instead of treating it as multiple values.)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tape(Vec<u8>);
+
+// Compile a program given on the command line, so that clap reports programs
+// which don't compile as usage errors.
+fn parse_program(program: &str) -> Result<bf::Program, String> {
+    program
+        .parse()
+        .map_err(|err: bf::CompileError| err.to_string())
+}
+
+// A program given on the command line, or "-" to read it from stdin (see
+// read_program).
+#[derive(Debug, Clone)]
+enum ProgramArg {
+    Stdin,
+    Program(bf::Program),
+}
+
+// Like parse_program, but "-" means the program is read from stdin. (Other
+// programs starting with "-" are compiled as is.)
+fn parse_program_arg(program: &str) -> Result<ProgramArg, String> {
+    match program {
+        "-" => Ok(ProgramArg::Stdin),
+        program => parse_program(program).map(ProgramArg::Program),
+    }
+}
+
+// A program given to the visualizer, and its breakpoints (see
+// bf::Program::from_source_with_breakpoints).
+#[derive(Debug, Clone)]
+struct ProgramWithBreakpoints(bf::Program, BTreeSet<usize>);
+
+// Like parse_program, but a "#" in the program is a breakpoint.
+fn parse_program_with_breakpoints(program: &str) -> Result<ProgramWithBreakpoints, String> {
+    bf::Program::from_source_with_breakpoints(program)
+        .map(|(program, breakpoints)| ProgramWithBreakpoints(program, breakpoints))
+        .map_err(|err| err.to_string())
+}
+
+// Parse a comma separated list of bytes, such as "3, 0,255". Whitespace around
+// each byte is ignored, and the empty string is a blank tape.
+fn parse_tape(tape: &str) -> Result<Tape, String> {
+    if tape.trim().is_empty() {
+        return Ok(Tape(vec![]));
+    }
+    tape.split(',')
+        .map(|byte| {
+            let byte = byte.trim();
+            byte.parse::<u8>()
+                .map_err(|err| format!("invalid byte '{}' ({})", byte, err))
+        })
+        .collect::<Result<_, _>>()
+        .map(Tape)
+}
+
+// Parse --start-at, which is a count that can't be larger than
+// visualizer::MAX_START_STEP.
+fn parse_start_at(step: &str) -> Result<usize, String> {
+    match parse_count(step)? {
+        step if step > visualizer::MAX_START_STEP => Err(format!(
+            "must be at most {}",
+            visualizer::MAX_START_STEP.separate_with_commas()
+        )),
+        step => Ok(step),
+    }
+}
+
+fn validate_cache_interval(interval: &str) -> Result<(), String> {
+    match interval.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// Write a section listing each looping program and why it loops.
+fn write_loop_reasons(
+    f: &mut impl Write,
+    looping_programs: &[(bf::Program, bf::LoopReason)],
+) -> std::io::Result<()> {
+    writeln!(f, "Looping programs ({})", looping_programs.len())?;
+    for (program, reason) in looping_programs {
+        writeln!(f, "{}: {}", program, reason)?;
+    }
+    Ok(())
+}
+
+// Describe where a non-halting program was detected, and how many times each
+// loop ran before then.
+fn describe_proof(profile: &bf::ProofProfile) -> String {
+    let mut loop_iterations: Vec<_> = profile.loop_iterations.iter().collect();
+    loop_iterations.sort();
+    let loop_iterations: String = loop_iterations
+        .iter()
+        .map(|(loop_index, iterations)| format!("@{}: {}", loop_index, iterations))
+        .intersperse(", ".to_string())
+        .collect();
+    format!(
+        "detected in loop @{} after {} iterations ({}), iterations per loop: {}",
+        profile.detected_loop,
+        profile.iterations_before_detection(),
+        profile.reason,
+        loop_iterations
+    )
+}
+
+// The number of random programs of each length timed by estimate_search.
+const ESTIMATE_SAMPLES: usize = 300;
+
+// Print how many programs of each length up to max_length there are, and
+// estimate how long searching them would take by timing a sample of random
+// programs of each length.
+fn estimate_search(args: &SearchArgs, max_steps: usize) {
+    let threads = rayon::current_num_threads();
+    println!(
+        "{:>6} {:>20} {:>15} {:>15}",
+        "length", "programs", "per program", "estimate"
+    );
+    let mut total = std::time::Duration::ZERO;
+    for length in 0..=args.max_length {
+        let max_steps = steps_for_length(args.step_base, max_steps, length);
+        let programs = match generate::count_valid(length) {
+            Some(programs) => programs,
+            None => {
+                // Every longer length has even more programs.
+                println!("{:>6} {:>20}", length, "too many to count");
+                break;
+            }
+        };
+        let mut rng = generate::XorShift::new(length as u64);
+        let samples: Vec<_> = (0..ESTIMATE_SAMPLES)
+            .map(|_| generate::random_valid(length, &mut rng))
+            .collect();
+        let start = std::time::Instant::now();
+        for program in &samples {
+            step_count(program, max_steps);
+        }
+        let per_program = start.elapsed() / ESTIMATE_SAMPLES as u32;
+        let estimate = per_program.mul_f64(programs as f64 / threads as f64);
+        total += estimate;
+        println!(
+            "{:>6} {:>20} {:>15?} {:>15?}",
+            length,
+            programs.separate_with_commas(),
+            per_program,
+            estimate
+        );
+    }
+    println!(
+        "Approximate total: {:?} on {} threads. This is only a rough estimate, since random programs are not a representative sample of the search.",
+        total, threads
+    );
+}
+
+// Build a thread pool for --threads.
+fn thread_pool(threads: usize) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("Cannot create thread pool")
+}
+
+// Search for the busy beavers of each length up to args.max_length.
+fn search(args: &SearchArgs, max_steps: usize) {
+    let pool = args.threads.map(|threads| Arc::new(thread_pool(threads)));
+    if args.dry_run {
+        match &pool {
+            Some(pool) => pool.install(|| estimate_search(args, max_steps)),
+            None => estimate_search(args, max_steps),
+        }
+        return;
+    }
+    for i in 0..=args.max_length {
+        let max_steps = steps_for_length(args.step_base, max_steps, i);
+        log!(
+            Verbosity::Verbose,
+            "searching length {} ({} programs, {} steps each)",
+            i,
+            generate::count_valid(i)
+                .map_or_else(|| "too many".to_string(), |count| count.to_string()),
+            max_steps
+        );
+        let start = std::time::Instant::now();
+        let budget = bf::Budget {
+            max_steps,
+            max_tape_length: args.max_tape,
+            max_time: args.time_limit.map(Duration::from_millis),
+            detectors: bf::Detectors::ALL,
+        };
+        let options = BeaverOptions {
+            print_every: args.print_every,
+            keep_loop_reasons: args.loop_reasons,
+            pool: pool.clone(),
+            live: args.live,
+            json_lines: args.json_lines,
+            champions_only: args.champions_only,
+        };
+        let (mut results, lexiographic_size) = beaver(i, budget, &options);
+        let elapsed = start.elapsed();
+        log!(Verbosity::Verbose, "finished length {} in {:?}", i, elapsed);
+        // This must happen before removing reflections, since only the first
+        // program of each mirror image pair is kept.
+        results.sort_programs();
+        // Mirror images of a champion are not interesting as separate champions.
+        results.busy_beavers.1 = bf::dedup_reflections(results.busy_beavers.1, max_steps);
+        results.sigma_champions.1 = bf::dedup_reflections(results.sigma_champions.1, max_steps);
+
+        match args.format {
+            Format::Text => write_atomically(format!("length_{}.txt", i), |f| {
+                write_text_summary(f, args, i, max_steps, &results, lexiographic_size, elapsed)
+            })
+            .unwrap(),
+            Format::Toml => write_atomically(format!("length_{}.toml", i), |f| {
+                f.write_all(
+                    length_report(i, max_steps, &results, elapsed)
+                        .to_toml()
+                        .as_bytes(),
+                )
+            })
+            .unwrap(),
+        }
+
+        if let Some(path) = &args.export_corpus {
+            let programs: Vec<_> = results
+                .busy_beavers
+                .1
+                .iter()
+                .chain(results.unknown_programs.iter())
+                .cloned()
+                .collect();
+            match bf::Program::pack_many(&programs) {
+                Ok(packed) => {
+                    let mut corpus = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .unwrap();
+                    corpus.write_all(&packed).unwrap();
+                }
+                Err(err) => eprintln!("Cannot export length {} (reason: {})", i, err),
+            }
+        }
+    }
+}
+
+// Build the summary of searching every program of length `length`, which took
+// `elapsed`.
+fn length_report(
+    length: usize,
+    max_steps: usize,
+    results: &BusyBeaverResults,
+    elapsed: Duration,
+) -> report::LengthReport {
+    let to_strings =
+        |programs: &[bf::Program]| programs.iter().map(|program| program.to_string()).collect();
+    report::LengthReport {
+        length,
+        max_steps,
+        proven: results.is_proven(),
+        busy_beaver_steps: results.busy_beavers.0,
+        busy_beavers: to_strings(&results.busy_beavers.1),
+        sigma: results.sigma_champions.0,
+        sigma_champions: to_strings(&results.sigma_champions.1),
+        unknown_programs: to_strings(&results.unknown_programs),
+        num_halted: results.num_halted,
+        num_looping: results.num_looping,
+        max_tape_length: results.max_tape_length,
+        elapsed_ms: Some(elapsed.as_millis() as usize),
+    }
+}
+
+// Write the file at `path` with `write`, so that the file only appears once
+// `write` has succeeded. The contents are written to a temporary file next to
+// `path` first, which is renamed over `path` at the end, so anything reading
+// the results never sees a half written file, even if the search is killed. If
+// `write` fails, the temporary file is removed and `path` is left untouched.
+fn write_atomically(
+    path: impl AsRef<Path>,
+    write: impl FnOnce(&mut std::io::BufWriter<std::fs::File>) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let result = (|| {
+        let mut f = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+        write(&mut f)?;
+        f.into_inner()?.sync_all()?;
+        std::fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// Write the results of searching every program of length `i`, which took
+// `elapsed`, to `f`.
+fn write_text_summary(
+    f: &mut impl Write,
+    args: &SearchArgs,
+    i: usize,
+    max_steps: usize,
+    results: &BusyBeaverResults,
+    lexiographic_size: usize,
+    elapsed: Duration,
+) -> std::io::Result<()> {
+    let proven = if results.is_proven() {
+        "proven".to_string()
+    } else {
+        format!(
+            "UNPROVEN LOWER BOUND, {} programs unresolved",
+            results.num_unknown()
+        )
+    };
+    writeln!(f,
+        "Best Busy Beavers for Length {} ({})\nTotal steps: {} (or best runs for longer than {} steps)",
+        i, proven, results.busy_beavers.0, max_steps
+    )?;
+    writeln!(f, "Time taken: {:.3?}", elapsed)?;
+
+    for program in &results.busy_beavers.1 {
+        if args.left_edge && bf::hits_left_edge(program, max_steps) {
+            writeln!(f, "{} (hits the left edge of the tape)", program)?;
+        } else {
+            writeln!(f, "{}", program)?;
+        }
+    }
+
+    writeln!(
+        f,
+        "Sigma champions ({} nonzero cells left on the tape)",
+        results.sigma_champions.0
+    )?;
+    for program in &results.sigma_champions.1 {
+        writeln!(f, "{}", program)?;
+    }
+
+    writeln!(
+        f,
+        "Unknown programs (did not halt after {} steps)",
+        max_steps
+    )?;
+
+    for program in &results.unknown_programs {
+        writeln!(f, "{}", program)?;
+    }
+    if args.loop_reasons {
+        write_loop_reasons(f, &results.looping_programs)?;
+    }
+    let total = results.num_halted + results.num_looping + results.num_unknown();
+    writeln!(
+        f,
+        "halted/looping/unknown = {} + {} + {} = {}",
+        results.num_halted,
+        results.num_looping,
+        results.num_unknown(),
+        total
+    )?;
+    let unknown_because_of = |reason| results.unknown_reasons.get(&reason).unwrap_or(&0);
+    writeln!(
+        f,
+        "unknown because of the step/tape length/time limit = {} + {} + {}",
+        unknown_because_of(bf::UnknownReason::Steps),
+        unknown_because_of(bf::UnknownReason::TapeLength),
+        unknown_because_of(bf::UnknownReason::Time),
+    )?;
+    writeln!(
+        f,
+        "trivially halting (no loops): {}",
+        results.num_trivially_halting
+    )?;
+    writeln!(
+        f,
+        "halted within the last {}% of the step limit: {}",
+        NEAR_BUDGET_PERCENT, results.num_near_budget
+    )?;
+    if results.num_near_budget > 0 {
+        writeln!(
+            f,
+            "(the step limit may be too small to have found the true busy beaver)"
+        )?;
+    }
+    writeln!(
+        f,
+        "L + ratio: {}/{} ({:.1}%)",
+        total,
+        lexiographic_size,
+        100.0 * total as f32 / lexiographic_size as f32
+    )?;
+    writeln!(f, "max tape length: {}", results.max_tape_length)?;
+    for (depth, [halted, looping, unknown]) in results.by_nesting_depth.iter().enumerate() {
+        writeln!(
+            f,
+            "nesting depth {}: halted/looping/unknown = {} + {} + {}",
+            depth, halted, looping, unknown
+        )?;
+    }
+
+    if let Some((steps, program)) = &results.hardest_to_prove {
+        writeln!(
+            f,
+            "hardest to prove: {} ({} steps required)",
+            program, steps,
+        )?;
+        if let Some(profile) = bf::proof_profile(program, max_steps) {
+            writeln!(f, "{}", describe_proof(&profile))?;
+        }
+    }
+    Ok(())
+}
+
+// The verbosity given by --quiet and --verbose.
+fn verbosity_from_args(args: &Args) -> Verbosity {
+    match (args.quiet, args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (false, true) => Verbosity::Verbose,
+        (false, false) => Verbosity::Normal,
+    }
+}
+
 fn main() {
     let args = Args::parse();
-    if let Some(program) = args.run {
-        match bf::Program::try_from(program.as_str()) {
+    VERBOSITY.store(verbosity_from_args(&args) as usize, Ordering::Relaxed);
+    let max_steps = args.max_steps;
+    match args.command {
+        Some(Command::Run {
+            program,
+            initial_tape: Tape(tape),
+            format,
+        }) => match read_program(program, std::io::stdin()) {
             Ok(program) => {
-                let (state, steps, _) = step_count(&program, args.max_steps);
-                match state {
-                    ExecutionStatus::Running => {
-                        println!("Timed out (runs longer than {} steps)", args.max_steps)
+                if !tape.is_empty() && format == Format::Text {
+                    println!("Initial tape: {:?}", tape);
+                }
+                print_run(&program, &tape, max_steps, format)
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Command::Bench { max_length }) => compare_detectors(max_length, max_steps),
+        #[cfg(feature = "model_check")]
+        Some(Command::ModelCheck { max_length }) => {
+            let report = bf_beavers::model_check::model_check_range(0..max_length + 1, max_steps);
+            for mismatch in &report.mismatches {
+                println!("{}", mismatch);
+                let shrunk = bf_beavers::model_check::shrink_mismatch(&mismatch.program, max_steps)
+                    .filter(|shrunk| {
+                        shrunk.program.original_instrs() != mismatch.program.original_instrs()
+                    });
+                if let Some(shrunk) = shrunk {
+                    println!("  shrunk to {}", shrunk);
+                }
+            }
+            println!(
+                "{} mismatches in {} programs",
+                report.mismatches.len(),
+                report.num_checked
+            );
+        }
+        Some(Command::ReplayProof {
+            program,
+            loop_span_only,
+        }) => {
+            let detectors = if loop_span_only {
+                bf::Detectors {
+                    loop_span: true,
+                    ..bf::Detectors::NONE
+                }
+            } else {
+                bf::Detectors::ALL
+            };
+            print!("{}", replay_proof(&program, max_steps, detectors));
+        }
+        Some(Command::Verify { corpus: path }) => {
+            match std::fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| bf::Program::unpack_many(&bytes).map_err(|err| err.to_string()))
+            {
+                Ok(programs) => {
+                    for program in programs {
+                        print!("{}: ", program);
+                        print_run(&program, &[], max_steps, Format::Text);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("Cannot import {} (reason: {})", path.display(), err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::CheckExpected { csv: path }) => {
+            match std::fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|csv| expected::parse_expected_csv(&csv).map_err(|err| err.to_string()))
+            {
+                Ok(programs) => {
+                    let report = expected::check_expected(programs, max_steps);
+                    for check in report.with_agreement(expected::Agreement::Disagrees) {
+                        println!("{}", check);
                     }
-                    ExecutionStatus::Halted => println!("Halts in {} steps", steps.unwrap()),
-                    ExecutionStatus::InfiniteLoop(reason) => {
-                        println!(
-                            "Does not halt (reason: {:#?}, at step {})",
-                            reason,
-                            steps.unwrap()
-                        )
+                    println!(
+                        "agree/disagree/unresolved = {} + {} + {}",
+                        report.with_agreement(expected::Agreement::Agrees).count(),
+                        report
+                            .with_agreement(expected::Agreement::Disagrees)
+                            .count(),
+                        report
+                            .with_agreement(expected::Agreement::Unresolved)
+                            .count(),
+                    );
+                    if report
+                        .with_agreement(expected::Agreement::Disagrees)
+                        .next()
+                        .is_some()
+                    {
+                        std::process::exit(1);
                     }
                 }
+                Err(err) => {
+                    eprintln!("Cannot read {} (reason: {})", path.display(), err);
+                    std::process::exit(1);
+                }
             }
-            Err(err) => println!("Cannot compile {} (reason: {})", program, err),
         }
-    } else if let Some(program) = args.interactive {
-        match bf::Program::try_from(program.as_str()) {
-            Ok(program) => {
-                println!("Visualizing {}", program);
-                visualizer::run(&program, args.start_at);
-                println!("Exiting...");
+        Some(Command::Extend { seed }) => {
+            let extended = search::extend_seed(&seed, max_steps);
+            if extended.is_empty() {
+                println!("No insertion into {} halts in more steps", seed);
+            }
+            for (program, steps) in extended {
+                println!("{}: halts in {} steps", program, steps);
             }
-            Err(err) => println!("Cannot compile {} (reason: {})", program, err),
         }
-    } else {
-        for i in 0..=args.max_length {
-            let (results, lexiographic_size) = beaver(i, args.max_steps, args.print_every);
+        Some(Command::Shortest { target, max_length }) => {
+            match search::shortest_reaching(target, max_length, max_steps) {
+                Some((program, steps)) => println!(
+                    "{} (length {}) halts in {} steps",
+                    program,
+                    program.original_instrs().len(),
+                    steps
+                ),
+                None => println!(
+                    "No program up to length {} halts in at least {} steps",
+                    max_length, target
+                ),
+            }
+        }
+        Some(Command::Visualize {
+            program,
+            start_at,
+            cache_interval,
+            initial_tape: Tape(tape),
+            load_session,
+        }) => {
+            let session = match &load_session {
+                Some(path) => std::fs::read(path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|bytes| {
+                        visualizer::Session::from_bytes(&bytes).map_err(|err| err.to_string())
+                    })
+                    .map_err(|err| {
+                        format!("Cannot load session {} (reason: {})", path.display(), err)
+                    }),
+                None => {
+                    let ProgramWithBreakpoints(program, breakpoints) =
+                        program.expect("clap requires a program without --load-session");
+                    if !tape.is_empty() {
+                        println!("Initial tape: {:?}", tape);
+                    }
+                    Ok(visualizer::Session::new(
+                        &program,
+                        breakpoints,
+                        tape,
+                        start_at,
+                        cache_interval,
+                    ))
+                }
+            };
+            match session {
+                Ok(session) => {
+                    println!("Visualizing {}", session.program());
+                    let session_path = load_session
+                        .unwrap_or_else(|| PathBuf::from(visualizer::DEFAULT_SESSION_PATH));
+                    visualizer::run(session, &session_path);
+                    println!("Exiting...");
+                }
+                Err(err) => println!("{}", err),
+            }
+        }
+        Some(Command::Search(search_args)) => search(&search_args, max_steps),
+        None => search(&SearchArgs::parse_from(["search"]), max_steps),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_beaver_tiny_lengths() {
+        // The only program of length 0 is the empty program, which halts immediately.
+        let (results, lexiographic_size) =
+            beaver(0, bf::Budget::steps(1000), &BeaverOptions::default());
+        assert_eq!(lexiographic_size, 1);
+        assert_eq!(results.busy_beavers.0, 0);
+        assert_eq!(results.busy_beavers.1.len(), 1);
+        assert_eq!(results.busy_beavers.1[0].to_string(), "");
+        assert_eq!(results.num_halted, 1);
+        assert_eq!(results.num_trivially_halting, 1);
+        assert_eq!(results.num_looping, 0);
+        assert!(results.unknown_programs.is_empty());
+        assert_eq!(results.max_tape_length, 1);
+        assert!(results.hardest_to_prove.is_none());
 
-            let mut f = std::fs::File::create(format!("length_{}.txt", i)).unwrap();
-            writeln!(f,
-                "Best Busy Beavers for Length {}\nTotal steps: {} (or best runs for longer than {} steps)",
-                i, results.busy_beavers.0, args.max_steps
-            ).unwrap();
+        // "[" and "]" don't compile, leaving the four single instruction programs.
+        let (results, lexiographic_size) =
+            beaver(1, bf::Budget::steps(1000), &BeaverOptions::default());
+        assert_eq!(lexiographic_size, 6);
+        assert_eq!(results.busy_beavers.0, 1);
+        let mut beavers: Vec<_> = results
+            .busy_beavers
+            .1
+            .iter()
+            .map(|program| program.to_string())
+            .collect();
+        beavers.sort();
+        assert_eq!(beavers, vec!["+", "-", "<", ">"]);
+        assert_eq!(results.num_halted, 4);
+        assert_eq!(results.num_trivially_halting, 4);
+        assert_eq!(results.num_looping, 0);
+        assert!(results.unknown_programs.is_empty());
+        assert_eq!(results.max_tape_length, 2);
+    }
 
-            for program in results.busy_beavers.1 {
-                writeln!(f, "{}", program).unwrap();
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_count("50000"), Ok(50_000));
+        assert_eq!(parse_count("50_000"), Ok(50_000));
+        assert_eq!(parse_count("50k"), Ok(50_000));
+        assert_eq!(parse_count("50K"), Ok(50_000));
+        assert_eq!(parse_count("1M"), Ok(1_000_000));
+        assert_eq!(parse_count("2G"), Ok(2_000_000_000));
+        assert_eq!(parse_count("1_5k"), Ok(15_000));
+        assert_eq!(
+            parse_count("5m"),
+            Err("invalid suffix 'm' (expected k, M or G)".to_string())
+        );
+        assert!(parse_count("").is_err());
+        assert!(parse_count("k").is_err());
+        assert!(parse_count("1.5k").is_err());
+        assert!(parse_count("99999999999999999999G").is_err());
+    }
+
+    #[test]
+    fn test_program_args() {
+        let args = Args::try_parse_from(["bf_beavers", "extend", "+[-]"]).unwrap();
+        match args.command {
+            Some(Command::Extend { seed }) => assert_eq!(seed.to_string(), "+[-]"),
+            command => panic!("parsed as {:?}", command),
+        }
+
+        for command in ["extend", "replay-proof", "run", "visualize"] {
+            let err = Args::try_parse_from(["bf_beavers", command, "+]"]).unwrap_err();
+            assert_eq!(err.kind(), clap::ErrorKind::ValueValidation);
+            // Usage errors are printed to stderr and exit with a nonzero status.
+            assert!(err.use_stderr());
+            assert!(
+                err.to_string().contains("Unmatched end loop at 1"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_shortest() {
+        let args = Args::try_parse_from(["bf_beavers", "shortest", "1k", "--max-length", "5"]);
+        match args.unwrap().command {
+            Some(Command::Shortest { target, max_length }) => {
+                assert_eq!((target, max_length), (1000, 5))
             }
+            command => panic!("parsed as {:?}", command),
+        }
+        let err = Args::try_parse_from(["bf_beavers", "shortest", "5m"]).unwrap_err();
+        assert_eq!(err.kind(), clap::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_parse_check_expected() {
+        let args = Args::try_parse_from(["bf_beavers", "check-expected", "verdicts.csv"]);
+        match args.unwrap().command {
+            Some(Command::CheckExpected { csv }) => assert_eq!(csv, PathBuf::from("verdicts.csv")),
+            command => panic!("parsed as {:?}", command),
+        }
+    }
 
-            writeln!(
-                f,
-                "Unknown programs (did not halt after {} steps)",
-                args.max_steps
+    #[test]
+    fn test_parse_start_at() {
+        let args = Args::try_parse_from(["bf_beavers", "visualize", "+[-]", "--start-at", "10M"]);
+        match args.unwrap().command {
+            Some(Command::Visualize { start_at, .. }) => assert_eq!(start_at, 10_000_000),
+            command => panic!("parsed as {:?}", command),
+        }
+        assert_eq!(parse_start_at("1G"), Ok(visualizer::MAX_START_STEP));
+        assert!(parse_start_at("2G").is_err());
+    }
+
+    #[test]
+    fn test_read_program() {
+        let piped = "+[>+\n++<-]\n".as_bytes();
+        let read = |program: Option<&str>| {
+            let program = program.map(|program| parse_program_arg(program).unwrap());
+            read_program(program, piped).map(|program| program.to_string())
+        };
+        assert_eq!(read(None).unwrap(), "+[>+++<-]");
+        assert_eq!(read(Some("-")).unwrap(), "+[>+++<-]");
+        // The stdin is not read when the program is given as an argument.
+        assert_eq!(read(Some("-[+]")).unwrap(), "-[+]");
+        // A program from stdin which doesn't compile is an error.
+        assert_eq!(
+            read_program(None, "+]\n".as_bytes()).unwrap_err(),
+            "Cannot compile +] (reason: Unmatched end loop at 1)"
+        );
+
+        let from_stdin = read_program(None, piped).unwrap();
+        let from_arg = bf::Program::try_from("+[>+++<-]").unwrap();
+        assert_eq!(from_stdin.to_string(), from_arg.to_string());
+        assert_eq!(step_count(&from_stdin, 1000), step_count(&from_arg, 1000));
+    }
+
+    #[test]
+    fn test_sort_programs() {
+        let champions = || {
+            let (mut results, _) = beaver(6, bf::Budget::steps(1000), &BeaverOptions::default());
+            results.sort_programs();
+            let hardest = results.hardest_to_prove.unwrap().1.to_string();
+            let champions: Vec<_> = bf::dedup_reflections(results.busy_beavers.1, 1000)
+                .iter()
+                .map(|program| program.to_string())
+                .collect();
+            (champions, hardest)
+        };
+        let (first, hardest) = champions();
+        for _ in 0..3 {
+            assert_eq!(champions(), (first.clone(), hardest.clone()));
+        }
+
+        let mut results = BusyBeaverResults::identity();
+        for program in ["+[-]", "-[-]", "+[+]", "->+<"] {
+            let program = bf::Program::try_from(program).unwrap();
+            results = BusyBeaverResults::combine(
+                BusyBeaverResults::from_halted(program, 3, 1, 1),
+                results,
+            );
+        }
+        results.sort_programs();
+        let sorted: Vec<_> = results
+            .busy_beavers
+            .1
+            .iter()
+            .map(|program| program.to_string())
+            .collect();
+        // ">" comes before "[" in lexiographic order, unlike in ASCII.
+        assert_eq!(sorted, vec!["+[+]", "+[-]", "->+<", "-[-]"]);
+    }
+
+    #[test]
+    fn test_loop_reasons() {
+        let (results, _) = beaver(3, bf::Budget::steps(1000), &BeaverOptions::default());
+        assert!(results.looping_programs.is_empty());
+
+        let (mut results, _) = beaver(
+            3,
+            bf::Budget::steps(1000),
+            &BeaverOptions {
+                keep_loop_reasons: true,
+                ..BeaverOptions::default()
+            },
+        );
+        results.sort_programs();
+        assert_eq!(results.looping_programs.len(), results.num_looping);
+        let mut section = vec![];
+        write_loop_reasons(&mut section, &results.looping_programs).unwrap();
+        assert_eq!(
+            String::from_utf8(section).unwrap(),
+            "Looping programs (2)\n+[]: LoopIfNonzero on a nonzero cell\n-[]: LoopIfNonzero on a nonzero cell\n"
+        );
+    }
+
+    #[test]
+    fn test_describe_proof() {
+        let program = bf::Program::try_from("+[>++[->+<]<]").unwrap();
+        let profile = bf::proof_profile(&program, 100_000).unwrap();
+        assert_eq!(
+            describe_proof(&profile),
+            format!(
+                "detected in loop @1 after {} iterations ({}), iterations per loop: @1: {}, @5: {}",
+                profile.loop_iterations[&1],
+                profile.reason,
+                profile.loop_iterations[&1],
+                profile.loop_iterations[&5]
             )
-            .unwrap();
+        );
+    }
 
-            for program in &results.unknown_programs {
-                writeln!(f, "{}", program).unwrap();
-            }
-            let total = results.num_halted + results.num_looping + results.unknown_programs.len();
-            writeln!(
-                f,
-                "halted/looping/unknown = {} + {} + {} = {}",
-                results.num_halted,
-                results.num_looping,
-                results.unknown_programs.len(),
-                total
+    #[test]
+    fn test_threads() {
+        let args = SearchArgs::parse_from(["search", "--threads", "1"]);
+        assert_eq!(args.threads, Some(1));
+        assert_eq!(SearchArgs::parse_from(["search"]).threads, None);
+
+        let pool = Arc::new(thread_pool(1));
+        assert_eq!(pool.current_num_threads(), 1);
+        let (mut expected, _) = beaver(
+            6,
+            bf::Budget::steps(1000),
+            &BeaverOptions {
+                keep_loop_reasons: true,
+                ..BeaverOptions::default()
+            },
+        );
+        let (mut actual, _) = beaver(
+            6,
+            bf::Budget::steps(1000),
+            &BeaverOptions {
+                keep_loop_reasons: true,
+                pool: Some(pool),
+                ..BeaverOptions::default()
+            },
+        );
+        expected.sort_programs();
+        actual.sort_programs();
+        // Programs are compared by their text, since loop_dict is a HashMap.
+        let summary = |results: &BusyBeaverResults| {
+            let names = |programs: &[bf::Program]| -> Vec<String> {
+                programs.iter().map(|program| program.to_string()).collect()
+            };
+            (
+                results.busy_beavers.0,
+                names(&results.busy_beavers.1),
+                results
+                    .hardest_to_prove
+                    .as_ref()
+                    .map(|(steps, program)| (*steps, program.to_string())),
+                results.max_tape_length,
+                names(&results.unknown_programs),
+                (
+                    results.num_halted,
+                    results.num_trivially_halting,
+                    results.num_looping,
+                ),
+                results
+                    .looping_programs
+                    .iter()
+                    .map(|(program, reason)| format!("{}: {}", program, reason))
+                    .collect::<Vec<_>>(),
             )
-            .unwrap();
-            writeln!(
-                f,
-                "L + ratio: {}/{} ({:.1}%)",
-                total,
-                lexiographic_size,
-                100.0 * total as f32 / lexiographic_size as f32
+        };
+        assert_eq!(summary(&actual), summary(&expected));
+    }
+
+    #[test]
+    fn test_live_records() {
+        let best_so_far = AtomicUsize::new(0);
+        let records: Vec<_> = search::beaver_stream(5, 1000)
+            .filter(|outcome| is_new_record(&best_so_far, outcome))
+            .map(|outcome| outcome.steps.unwrap())
+            .collect();
+        assert!(records.windows(2).all(|pair| pair[0] < pair[1]));
+        let (results, _) = beaver(
+            5,
+            bf::Budget::steps(1000),
+            &BeaverOptions {
+                live: true,
+                ..BeaverOptions::default()
+            },
+        );
+        assert_eq!(records.last(), Some(&results.busy_beavers.0));
+        assert_eq!(best_so_far.load(Ordering::Relaxed), results.busy_beavers.0);
+    }
+
+    #[test]
+    fn test_sigma_champions() {
+        let (mut results, _) = beaver(5, bf::Budget::steps(1000), &BeaverOptions::default());
+        results.sort_programs();
+        assert_eq!(results.sigma_champions.0, 3);
+        let champions: Vec<_> = results
+            .sigma_champions
+            .1
+            .iter()
+            .map(|program| program.to_string())
+            .collect();
+        assert!(champions.contains(&"+>+>+".to_string()));
+        for program in &results.sigma_champions.1 {
+            assert_eq!(bf::sigma(program, 1000), Some(3));
+        }
+        // The step champion is a different program entirely.
+        assert!(!champions.contains(&results.busy_beavers.1[0].to_string()));
+    }
+
+    #[test]
+    fn test_by_nesting_depth() {
+        let (results, _) = beaver(4, bf::Budget::steps(1000), &BeaverOptions::default());
+        // Length 4 programs have at most two nested loops, and the partition
+        // covers every program.
+        assert_eq!(results.by_nesting_depth.len(), 3);
+        let [halted, looping, unknown] = results
+            .by_nesting_depth
+            .iter()
+            .fold([0; 3], |[h, l, u], [halted, looping, unknown]| {
+                [h + halted, l + looping, u + unknown]
+            });
+        assert_eq!(halted, results.num_halted);
+        assert_eq!(looping, results.num_looping);
+        assert_eq!(unknown, results.unknown_programs.len());
+        // Every program without loops halts.
+        assert_eq!(results.by_nesting_depth[0][1..], [0, 0]);
+        assert_eq!(
+            results.by_nesting_depth[0][0],
+            results.num_trivially_halting
+        );
+    }
+
+    #[test]
+    fn test_parse_tape() {
+        assert_eq!(parse_tape(""), Ok(Tape(vec![])));
+        assert_eq!(parse_tape("3,0, 255"), Ok(Tape(vec![3, 0, 255])));
+        assert!(parse_tape("1,256").is_err());
+        assert!(parse_tape("1,-1").is_err());
+        assert!(parse_tape("1,,2").is_err());
+    }
+
+    #[test]
+    fn test_step_count_on_tape() {
+        let program = bf::Program::try_from("[->+<]>").unwrap();
+        // On a blank tape, the loop is skipped.
+        assert_eq!(
+            step_count_on_tape(&program, &[], 1000),
+            (ExecutionStatus::Halted, Some(2))
+        );
+        // Entering the loop takes a step, and each iteration takes 5 more.
+        assert_eq!(
+            step_count_on_tape(&program, &[3], 1000),
+            (ExecutionStatus::Halted, Some(1 + 3 * 5 + 1))
+        );
+        let program = bf::Program::try_from("[>]").unwrap();
+        assert_eq!(
+            step_count_on_tape(&program, &[1, 1], 1000),
+            (ExecutionStatus::Halted, Some(5))
+        );
+    }
+
+    #[test]
+    fn test_replay_proof() {
+        let program = bf::Program::try_from("+[-+]").unwrap();
+        let loop_span_only = bf::Detectors {
+            loop_span: true,
+            ..bf::Detectors::NONE
+        };
+        // Otherwise, the no-op loop detector fires as soon as the loop is entered.
+        let (status, steps, _) = step_count_with_detectors(&program, 1000, loop_span_only);
+        assert!(matches!(
+            status.loop_reason(),
+            Some(bf::LoopReason::LoopSpan { .. })
+        ));
+        let proof = replay_proof(&program, 1000, loop_span_only);
+        assert_eq!(
+            proof,
+            format!(
+                "Detected as looping at step {} (8 calls to step): {}\n\
+                 cell      0\n\
+                 prior    01\n\
+                 \x20        se\n\
+                 current  01\n\
+                 \x20        se\n\
+                 tape     01\n\
+                 displacement +0, compared [1] and [1]\n",
+                steps.unwrap(),
+                status.loop_reason().unwrap()
             )
-            .unwrap();
-            writeln!(f, "max tape length: {}", results.max_tape_length).unwrap();
-            if let Some((steps, program)) = results.hardest_to_prove {
-                writeln!(
-                    f,
-                    "hardest to prove: {} ({} steps required)",
-                    program, steps,
-                )
-                .unwrap();
-            }
+        );
+        assert_eq!(steps, Some(8));
+
+        let proof = replay_proof(&program, 1000, bf::Detectors::ALL);
+        assert!(proof.starts_with("Detected as looping at step 2"));
+        assert!(proof.ends_with("tape: [1]\n"));
+        let halting = bf::Program::try_from("+[-]").unwrap();
+        assert_eq!(
+            replay_proof(&halting, 1000, bf::Detectors::ALL),
+            "Halts in 4 steps, so there is nothing to prove\n"
+        );
+    }
+
+    #[test]
+    fn test_length_report_round_trip() {
+        let (mut results, _) = beaver(5, bf::Budget::steps(1000), &BeaverOptions::default());
+        results.sort_programs();
+        let length_report = length_report(5, 1000, &results, Duration::from_millis(1500));
+        assert_eq!(length_report.elapsed_ms, Some(1500));
+        assert_eq!(length_report.busy_beaver_steps, results.busy_beavers.0);
+        assert_eq!(
+            length_report.busy_beavers.len(),
+            results.busy_beavers.1.len()
+        );
+        assert_eq!(
+            report::LengthReport::from_toml(&length_report.to_toml()),
+            Ok(length_report)
+        );
+    }
+
+    #[test]
+    fn test_unknown_reasons() {
+        let (results, _) = beaver(5, bf::Budget::steps(20), &BeaverOptions::default());
+        assert!(!results.unknown_programs.is_empty());
+        assert_eq!(
+            results.unknown_reasons,
+            HashMap::from([(bf::UnknownReason::Steps, results.unknown_programs.len())])
+        );
+
+        // Programs which move right give up as soon as they do, even if they
+        // would have halted.
+        let budget = bf::Budget {
+            max_tape_length: Some(1),
+            ..bf::Budget::steps(1000)
+        };
+        let (results, _) = beaver(5, budget, &BeaverOptions::default());
+        assert_eq!(
+            results.unknown_reasons.values().sum::<usize>(),
+            results.unknown_programs.len()
+        );
+        assert!(results.unknown_reasons[&bf::UnknownReason::TapeLength] > 0);
+        assert!(results
+            .unknown_programs
+            .iter()
+            .all(|program| program.original_instrs().contains(&bf::Instr::Right)));
+    }
+
+    #[test]
+    fn test_near_budget() {
+        assert!(is_near_budget(100, 100));
+        assert!(is_near_budget(94, 100));
+        assert!(!is_near_budget(93, 100));
+        assert!(is_near_budget(usize::MAX, usize::MAX));
+
+        let (results, _) = beaver(5, bf::Budget::steps(1000), &BeaverOptions::default());
+        assert_eq!(results.num_near_budget, 0);
+        // With a step limit just big enough for the busy beaver, it halts near
+        // the limit. Halting takes one more call to step than there are real
+        // steps.
+        let busy_beaver_steps = results.busy_beavers.0;
+        let budget = bf::Budget::steps(busy_beaver_steps + 1);
+        let (results, _) = beaver(5, budget, &BeaverOptions::default());
+        assert_eq!(results.busy_beavers.0, busy_beaver_steps);
+        assert!(results.num_near_budget >= results.busy_beavers.1.len());
+        assert!(results.num_near_budget <= results.num_halted);
+    }
+
+    #[test]
+    fn test_champions_only() {
+        let budget = bf::Budget::steps(1000);
+        let (mut full, _) = beaver(6, budget, &BeaverOptions::default());
+        let options = BeaverOptions {
+            champions_only: true,
+            ..BeaverOptions::default()
+        };
+        let (mut champions_only, _) = beaver(6, budget, &options);
+        full.sort_programs();
+        champions_only.sort_programs();
+        let to_strings = |programs: &[bf::Program]| -> Vec<String> {
+            programs.iter().map(|program| program.to_string()).collect()
+        };
+        assert_eq!(champions_only.busy_beavers.0, full.busy_beavers.0);
+        assert_eq!(
+            to_strings(&champions_only.busy_beavers.1),
+            to_strings(&full.busy_beavers.1)
+        );
+        assert_eq!(champions_only.num_halted, full.num_halted);
+
+        // The looping programs are detected just the same, but only counted.
+        assert_eq!(champions_only.num_looping, full.num_looping);
+        assert!(full.hardest_to_prove.is_some());
+        assert!(champions_only.hardest_to_prove.is_none());
+        assert_eq!(
+            to_strings(&champions_only.unknown_programs),
+            to_strings(&full.unknown_programs)
+        );
+        assert_eq!(champions_only.by_nesting_depth, full.by_nesting_depth);
+        assert_eq!(champions_only.is_proven(), full.is_proven());
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("bf_beavers_atomic_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("length_3.txt");
+        let temp_path = dir.join("length_3.txt.tmp");
+
+        // A write which fails partway through leaves neither the file nor the
+        // temporary file behind.
+        let result = write_atomically(&path, |f| {
+            writeln!(f, "Best Busy Beavers for Length 3")?;
+            Err(std::io::Error::other("killed"))
+        });
+        assert!(result.is_err());
+        assert!(!path.exists());
+        assert!(!temp_path.exists());
+
+        write_atomically(&path, |f| writeln!(f, "first")).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\n");
+        assert!(!temp_path.exists());
+
+        // A failed write leaves the previous file untouched.
+        let result = write_atomically(&path, |f| {
+            writeln!(f, "second")?;
+            Err(std::io::Error::other("killed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_steps_for_length() {
+        // The default is the same number of steps for every length.
+        for length in 0..20 {
+            assert_eq!(steps_for_length(None, 50_000, length), 50_000);
         }
+        assert_eq!(steps_for_length(Some(10), 5, 0), 5);
+        assert_eq!(steps_for_length(Some(10), 5, 1), 10);
+        assert_eq!(steps_for_length(Some(10), 5, 4), 10_000);
+        assert_eq!(steps_for_length(Some(10), 50_000, 4), 50_000);
+        assert_eq!(steps_for_length(Some(10), 5, 100), usize::MAX);
+
+        // "+[>+]" needs more than 5 steps, which it gets with a base of 3.
+        let is_unknown = |max_steps| {
+            let (results, _) = beaver(5, bf::Budget::steps(max_steps), &BeaverOptions::default());
+            results
+                .unknown_programs
+                .iter()
+                .any(|program| program.to_string() == "+[>+]")
+        };
+        assert!(is_unknown(steps_for_length(None, 5, 5)));
+        assert!(!is_unknown(steps_for_length(Some(3), 5, 5)));
+    }
+
+    #[test]
+    fn test_beaver_proven() {
+        let (results, _) = beaver(4, bf::Budget::steps(1000), &BeaverOptions::default());
+        assert!(results.unknown_programs.is_empty());
+        assert!(results.is_proven());
+
+        // "+[>+]" needs more than 5 steps to be detected as an infinite loop.
+        let (results, _) = beaver(5, bf::Budget::steps(5), &BeaverOptions::default());
+        assert!(results
+            .unknown_programs
+            .iter()
+            .any(|program| program.to_string() == "+[>+]"));
+        assert!(!results.is_proven());
     }
 }
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::bf::{Detectors, ExecutionContext, ExecutionStatus, Instr, Program};
+use crate::generate;
+
+/// A deliberately simple interpreter which runs the original instructions of a
+/// program one at a time, with no folding and no loop detection. The real
+/// ExecutionContext is checked against this.
+#[derive(Debug)]
+struct ReferenceContext {
+    memory: Vec<u8>,
+    memory_pointer: usize,
+    program: Vec<Instr>,
+    program_pointer: usize,
+    loop_dict: HashMap<usize, usize>,
+}
+
+impl ReferenceContext {
+    fn new(program: &Program) -> ReferenceContext {
+        let program = program.original_instrs().to_vec();
+        let mut loop_dict = HashMap::new();
+        let mut startloop_locs = vec![];
+        for (i, instr) in program.iter().enumerate() {
+            match instr {
+                Instr::StartLoop => startloop_locs.push(i),
+                Instr::EndLoop => {
+                    let start_loop = startloop_locs.pop().expect("program already compiled");
+                    loop_dict.insert(i, start_loop);
+                    loop_dict.insert(start_loop, i);
+                }
+                _ => (),
+            }
+        }
+        ReferenceContext {
+            memory: vec![0; 256],
+            memory_pointer: 0,
+            program,
+            program_pointer: 0,
+            loop_dict,
+        }
+    }
+
+    fn is_halted(&self) -> bool {
+        self.program_pointer >= self.program.len()
+    }
+
+    // Execute one instruction, returning whether the program has halted and
+    // the number of instructions executed.
+    fn step(&mut self) -> (bool, usize) {
+        let instruction = match self.program.get(self.program_pointer) {
+            Some(instruction) => instruction,
+            None => return (true, 0),
+        };
+        match instruction {
+            Instr::Plus => {
+                self.memory[self.memory_pointer] = self.memory[self.memory_pointer].wrapping_add(1)
+            }
+            Instr::Minus => {
+                self.memory[self.memory_pointer] = self.memory[self.memory_pointer].wrapping_sub(1)
+            }
+            Instr::Left => self.memory_pointer = self.memory_pointer.saturating_sub(1),
+            Instr::Right => {
+                self.memory_pointer += 1;
+                if self.memory_pointer >= self.memory.len() {
+                    self.memory.push(0);
+                }
+            }
+            // Generated programs never perform I/O
+            Instr::Output | Instr::Input => (),
+            Instr::StartLoop if self.memory[self.memory_pointer] == 0 => {
+                self.program_pointer = self.loop_dict[&self.program_pointer];
+            }
+            Instr::EndLoop if self.memory[self.memory_pointer] != 0 => {
+                self.program_pointer = self.loop_dict[&self.program_pointer];
+            }
+            Instr::StartLoop | Instr::EndLoop => (),
+        }
+        self.program_pointer += 1;
+        (self.program_pointer >= self.program.len(), 1)
+    }
+}
+
+/// The result of running a program on both the real ExecutionContext and the
+/// reference interpreter.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub program: Program,
+    /// The status returned by the real ExecutionContext, and the real steps it took.
+    pub status: ExecutionStatus,
+    pub steps: usize,
+    /// Whether the reference interpreter halted, and how many steps it took.
+    pub reference_halted: bool,
+    pub reference_steps: usize,
+}
+
+impl Comparison {
+    /// Returns true if the two interpreters disagree. They agree if both halt
+    /// in the same number of steps, or if neither halts. (A program detected as
+    /// looping is run on the reference interpreter for twice as many steps as
+    /// it took to detect, so detections which are wrong but only by a little
+    /// aren't caught.)
+    pub fn is_mismatch(&self) -> bool {
+        match (&self.status, self.reference_halted) {
+            (ExecutionStatus::Halted, true) => self.steps != self.reference_steps,
+            (ExecutionStatus::Running | ExecutionStatus::InfiniteLoop(_), false) => false,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reference = if self.reference_halted {
+            format!("halted in {} steps", self.reference_steps)
+        } else {
+            format!("running after {} steps", self.reference_steps)
+        };
+        write!(
+            f,
+            "{}: {:?} after {} steps, but the reference interpreter {}",
+            self.program, self.status, self.steps, reference
+        )
+    }
+}
+
+/// Run the program on the real ExecutionContext for at most `max_steps` calls
+/// to `step`, then on the reference interpreter, and compare the results.
+pub fn compare(program: &Program, max_steps: usize) -> Comparison {
+    compare_with(program, max_steps, run_real)
+}
+
+// Like `compare`, but uses `run` in place of the real ExecutionContext.
+fn compare_with(
+    program: &Program,
+    max_steps: usize,
+    run: impl Fn(&Program, usize) -> (ExecutionStatus, usize),
+) -> Comparison {
+    let (status, steps) = run(program, max_steps);
+    let reference_max_steps = match status {
+        ExecutionStatus::Halted => steps,
+        ExecutionStatus::Running => max_steps,
+        ExecutionStatus::InfiniteLoop(_) => steps.saturating_mul(2),
+    };
+
+    let mut reference = ReferenceContext::new(program);
+    let mut reference_halted = reference.is_halted();
+    let mut reference_steps = 0;
+    while !reference_halted && reference_steps < reference_max_steps {
+        let (halted, steps) = reference.step();
+        reference_steps += steps;
+        reference_halted = halted;
+    }
+    Comparison {
+        program: program.clone(),
+        status,
+        steps,
+        reference_halted,
+        reference_steps,
+    }
+}
+
+// Run the program on the real ExecutionContext, returning its final status and
+// the real steps taken.
+fn run_real(program: &Program, max_steps: usize) -> (ExecutionStatus, usize) {
+    run_real_with_detectors(program, max_steps, Detectors::ALL)
+}
+
+// Like `run_real`, but with only the given detectors turned on.
+fn run_real_with_detectors(
+    program: &Program,
+    max_steps: usize,
+    detectors: Detectors,
+) -> (ExecutionStatus, usize) {
+    let mut ctx = ExecutionContext::with_detectors(program, detectors);
+    let mut status = ExecutionStatus::Running;
+    for _ in 0..max_steps {
+        status = ctx.step().1;
+        if !status.is_running() {
+            break;
+        }
+    }
+    (status, ctx.steps_taken())
+}
+
+/// Shrink a program on which the real ExecutionContext and the reference
+/// interpreter disagree (see `compare`) to a smaller one on which they still
+/// disagree, or return None if they agree on it. This repeatedly deletes runs of
+/// instructions, halving the length of the runs tried whenever none of them can
+/// be deleted, and also deletes matching pairs of brackets, until no single
+/// deletion keeps the mismatch. The result is not necessarily the smallest such
+/// program, but no instruction (or pair of brackets) can be removed from it.
+pub fn shrink_mismatch(program: &Program, max_steps: usize) -> Option<Comparison> {
+    shrink_mismatch_with(program, max_steps, run_real)
+}
+
+// Like `shrink_mismatch`, but uses `run` in place of the real ExecutionContext.
+fn shrink_mismatch_with(
+    program: &Program,
+    max_steps: usize,
+    run: impl Fn(&Program, usize) -> (ExecutionStatus, usize),
+) -> Option<Comparison> {
+    let mut smallest = compare_with(program, max_steps, &run);
+    if !smallest.is_mismatch() {
+        return None;
+    }
+
+    // Returns the comparison for the instructions, if they compile and the
+    // interpreters still disagree on them.
+    let try_instrs = |instrs: Vec<Instr>| {
+        let program = Program::new(instrs).ok()?;
+        Some(compare_with(&program, max_steps, &run)).filter(Comparison::is_mismatch)
+    };
+
+    let mut run_length = smallest.program.original_instrs().len() / 2;
+    loop {
+        let instrs = smallest.program.original_instrs().to_vec();
+        if instrs.is_empty() {
+            return Some(smallest);
+        }
+        run_length = run_length.clamp(1, instrs.len());
+
+        let shrunk = (0..=instrs.len() - run_length)
+            .map(|start| {
+                let mut instrs = instrs.clone();
+                instrs.drain(start..start + run_length);
+                instrs
+            })
+            .chain(matching_brackets(&instrs).map(|(start, end)| {
+                let mut instrs = instrs.clone();
+                instrs.remove(end);
+                instrs.remove(start);
+                instrs
+            }))
+            .find_map(&try_instrs);
+
+        match shrunk {
+            Some(comparison) => smallest = comparison,
+            None if run_length == 1 => return Some(smallest),
+            None => run_length /= 2,
+        }
+    }
+}
+
+// Returns the indices of every pair of matching brackets in the instructions,
+// which must be balanced.
+fn matching_brackets(instrs: &[Instr]) -> impl Iterator<Item = (usize, usize)> {
+    let mut startloop_locs = vec![];
+    let mut pairs = vec![];
+    for (i, instr) in instrs.iter().enumerate() {
+        match instr {
+            Instr::StartLoop => startloop_locs.push(i),
+            Instr::EndLoop => {
+                let start_loop = startloop_locs.pop().expect("program already compiled");
+                pairs.push((start_loop, i));
+            }
+            _ => (),
+        }
+    }
+    pairs.into_iter()
+}
+
+/// The result of `model_check_range`.
+#[derive(Debug, Clone)]
+pub struct ModelCheckReport {
+    /// The number of programs compared.
+    pub num_checked: usize,
+    /// Every program on which the interpreters disagreed, in lexiographic order.
+    pub mismatches: Vec<Comparison>,
+}
+
+/// Compare every valid program with a length in `lengths` (see `compare`) in
+/// parallel, and report any on which the real ExecutionContext disagrees with
+/// the reference interpreter.
+pub fn model_check_range(lengths: Range<usize>, max_steps: usize) -> ModelCheckReport {
+    model_check_range_with(lengths, max_steps, run_real)
+}
+
+/// Like `model_check_range`, but with only the given detectors turned on in the
+/// real ExecutionContext. Useful for checking opt-in detectors such as
+/// `Detectors::guard_insensitive_loop_span`.
+pub fn model_check_range_with_detectors(
+    lengths: Range<usize>,
+    max_steps: usize,
+    detectors: Detectors,
+) -> ModelCheckReport {
+    model_check_range_with(lengths, max_steps, |program, max_steps| {
+        run_real_with_detectors(program, max_steps, detectors)
+    })
+}
+
+// Like `model_check_range`, but uses `run` in place of the real ExecutionContext.
+fn model_check_range_with(
+    lengths: Range<usize>,
+    max_steps: usize,
+    run: impl Fn(&Program, usize) -> (ExecutionStatus, usize) + Sync,
+) -> ModelCheckReport {
+    let (num_checked, mut mismatches) = generate::brute_force_chain(lengths)
+        .par_bridge()
+        .map(|program| {
+            let comparison = compare_with(&program, max_steps, &run);
+            (1, Some(comparison).filter(Comparison::is_mismatch))
+        })
+        .fold(
+            || (0, vec![]),
+            |(num_checked, mut mismatches), (checked, mismatch)| {
+                mismatches.extend(mismatch);
+                (num_checked + checked, mismatches)
+            },
+        )
+        .reduce(
+            || (0, vec![]),
+            |(a_checked, mut a_mismatches), (b_checked, mut b_mismatches)| {
+                a_mismatches.append(&mut b_mismatches);
+                (a_checked + b_checked, a_mismatches)
+            },
+        );
+    mismatches.sort_by(|a, b| {
+        let a = a.program.original_instrs();
+        let b = b.program.original_instrs();
+        (a.len(), a).cmp(&(b.len(), b))
+    });
+    ModelCheckReport {
+        num_checked,
+        mismatches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_model_check_catches_step_bug() {
+        let report = model_check_range(0..6, 1000);
+        assert_eq!(
+            Some(report.num_checked),
+            (0..6).map(generate::count_valid).sum::<Option<usize>>()
+        );
+        assert!(report.mismatches.is_empty(), "{}", report.mismatches[0]);
+
+        // Miscount the steps of "[-]", as if it were folded wrong.
+        let off_by_one = |program: &Program, max_steps| {
+            let (status, steps) = run_real(program, max_steps);
+            if program.to_string().contains("+[-]") {
+                (status, steps + 1)
+            } else {
+                (status, steps)
+            }
+        };
+        let report = model_check_range_with(0..6, 1000, off_by_one);
+        assert!(!report.mismatches.is_empty());
+        assert_eq!(report.mismatches[0].program.to_string(), "+[-]");
+        assert!(report
+            .mismatches
+            .iter()
+            .all(|mismatch| mismatch.program.to_string().contains("+[-]")));
+    }
+
+    #[test]
+    fn test_compare_at_step_limit() {
+        // "+>+<+" takes one call to `step` per instruction.
+        let program = Program::try_from("+>+<+").unwrap();
+        assert_eq!(run_real(&program, 5), (ExecutionStatus::Halted, 5));
+        assert_eq!(run_real(&program, 4), (ExecutionStatus::Running, 4));
+
+        // Halting exactly at the limit agrees with the reference interpreter.
+        let comparison = compare(&program, 5);
+        assert!(comparison.reference_halted);
+        assert_eq!(comparison.reference_steps, 5);
+        assert!(!comparison.is_mismatch());
+
+        // One step short of halting, the reference interpreter is given the
+        // same number of steps and doesn't halt either.
+        let comparison = compare(&program, 4);
+        assert!(!comparison.reference_halted);
+        assert_eq!(comparison.reference_steps, 4);
+        assert!(!comparison.is_mismatch());
+
+        // The empty program halts without taking a step.
+        let comparison = compare(&Program::try_from("").unwrap(), 1);
+        assert!(comparison.reference_halted);
+        assert!(!comparison.is_mismatch());
+    }
+
+    #[test]
+    fn test_shrink_mismatch() {
+        // Miscount the steps of any program which clears a cell after moving
+        // right, as if "[-]" were folded wrong after a move.
+        let wrong_after_move = |program: &Program, max_steps| {
+            let (status, steps) = run_real(program, max_steps);
+            if program.to_string().contains(">+[-]") {
+                (status, steps + 1)
+            } else {
+                (status, steps)
+            }
+        };
+
+        let program = Program::try_from("++>+<[->>++<<]>>+[-]+++[>+<-]").unwrap();
+        let shrunk = shrink_mismatch_with(&program, 1000, wrong_after_move).unwrap();
+        assert!(shrunk.is_mismatch());
+        assert_eq!(shrunk.program.to_string(), ">+[-]");
+
+        // Programs without a mismatch can't be shrunk.
+        assert!(shrink_mismatch(&program, 1000).is_none());
+        assert!(
+            shrink_mismatch_with(&Program::try_from("+[-]").unwrap(), 1000, wrong_after_move)
+                .is_none()
+        );
+    }
+}
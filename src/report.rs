@@ -0,0 +1,547 @@
+//! Reports of search results, written as TOML (and JSON, for `search
+//! --json-lines`). The TOML and JSON are written and parsed by hand instead of
+//! with serde, so that the library only depends on rayon. Only the small subset
+//! of each format that the reports use is supported. Strings are escaped the
+//! same way in both, so `parse_string` reads back strings from either.
+
+use std::{collections::HashMap, fmt::Display};
+
+use crate::bf::{ExecutionStatus, Program};
+use crate::search::Outcome;
+
+/// The result of running a single program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunReport {
+    pub program: String,
+    /// One of "halted", "looping" or "unknown".
+    pub status: String,
+    /// The step the program halted or was detected as looping on. This is None
+    /// for unknown programs.
+    pub steps: Option<usize>,
+    /// Why the program was detected as looping, for looping programs.
+    pub reason: Option<String>,
+}
+
+impl RunReport {
+    pub fn new(program: &Program, status: &ExecutionStatus, steps: Option<usize>) -> RunReport {
+        let (name, reason) = match status {
+            ExecutionStatus::Running => ("unknown", None),
+            ExecutionStatus::Halted => ("halted", None),
+            ExecutionStatus::InfiniteLoop(reason) => ("looping", Some(reason.to_string())),
+        };
+        RunReport {
+            program: program.to_string(),
+            status: name.to_string(),
+            steps,
+            reason,
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut toml = String::new();
+        write_entry(&mut toml, "program", &Value::String(self.program.clone()));
+        write_entry(&mut toml, "status", &Value::String(self.status.clone()));
+        if let Some(steps) = self.steps {
+            write_entry(&mut toml, "steps", &Value::Integer(steps));
+        }
+        if let Some(reason) = &self.reason {
+            write_entry(&mut toml, "reason", &Value::String(reason.clone()));
+        }
+        toml
+    }
+
+    pub fn from_toml(toml: &str) -> Result<RunReport, TomlError> {
+        let mut table = parse_toml(toml)?;
+        Ok(RunReport {
+            program: take_string(&mut table, "program")?,
+            status: take_string(&mut table, "status")?,
+            steps: take_optional(&mut table, "steps", take_integer)?,
+            reason: take_optional(&mut table, "reason", take_string)?,
+        })
+    }
+}
+
+/// Format the outcome of running a program during a search as a JSON object on a
+/// single line, such as
+/// `{"program":"+[-]","status":"halted","steps":4,"tape_length":1}`. The status
+/// is as in RunReport, and steps is null for unknown programs. This is the
+/// format of each line written by `search --json-lines`.
+pub fn outcome_to_json(outcome: &Outcome) -> String {
+    let report = RunReport::new(&outcome.program, &outcome.status, outcome.steps);
+    let mut json = "{\"program\":".to_string();
+    write_string(&mut json, &report.program);
+    json.push_str(",\"status\":");
+    write_string(&mut json, &report.status);
+    json.push_str(",\"steps\":");
+    match report.steps {
+        Some(steps) => json.push_str(&steps.to_string()),
+        None => json.push_str("null"),
+    }
+    json.push_str(",\"tape_length\":");
+    json.push_str(&outcome.tape_length.to_string());
+    json.push('}');
+    json
+}
+
+/// The summary of searching every program of one length, with the same results
+/// as the busy beaver search writes to length_n.txt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthReport {
+    pub length: usize,
+    pub max_steps: usize,
+    /// True if every program of this length either halted or was detected as looping.
+    pub proven: bool,
+    pub busy_beaver_steps: usize,
+    pub busy_beavers: Vec<String>,
+    /// The number of nonzero cells the sigma champions leave on the tape.
+    pub sigma: usize,
+    pub sigma_champions: Vec<String>,
+    pub unknown_programs: Vec<String>,
+    pub num_halted: usize,
+    pub num_looping: usize,
+    pub max_tape_length: usize,
+    /// How long searching this length took, in milliseconds. This is None for
+    /// reports written before the time was recorded.
+    pub elapsed_ms: Option<usize>,
+}
+
+impl LengthReport {
+    pub fn to_toml(&self) -> String {
+        let mut toml = String::new();
+        write_entry(&mut toml, "length", &Value::Integer(self.length));
+        write_entry(&mut toml, "max_steps", &Value::Integer(self.max_steps));
+        write_entry(&mut toml, "proven", &Value::Boolean(self.proven));
+        write_entry(
+            &mut toml,
+            "busy_beaver_steps",
+            &Value::Integer(self.busy_beaver_steps),
+        );
+        write_entry(
+            &mut toml,
+            "busy_beavers",
+            &Value::Strings(self.busy_beavers.clone()),
+        );
+        write_entry(&mut toml, "sigma", &Value::Integer(self.sigma));
+        write_entry(
+            &mut toml,
+            "sigma_champions",
+            &Value::Strings(self.sigma_champions.clone()),
+        );
+        write_entry(
+            &mut toml,
+            "unknown_programs",
+            &Value::Strings(self.unknown_programs.clone()),
+        );
+        write_entry(&mut toml, "num_halted", &Value::Integer(self.num_halted));
+        write_entry(&mut toml, "num_looping", &Value::Integer(self.num_looping));
+        write_entry(
+            &mut toml,
+            "max_tape_length",
+            &Value::Integer(self.max_tape_length),
+        );
+        if let Some(elapsed_ms) = self.elapsed_ms {
+            write_entry(&mut toml, "elapsed_ms", &Value::Integer(elapsed_ms));
+        }
+        toml
+    }
+
+    pub fn from_toml(toml: &str) -> Result<LengthReport, TomlError> {
+        let mut table = parse_toml(toml)?;
+        Ok(LengthReport {
+            length: take_integer(&mut table, "length")?,
+            max_steps: take_integer(&mut table, "max_steps")?,
+            proven: take_boolean(&mut table, "proven")?,
+            busy_beaver_steps: take_integer(&mut table, "busy_beaver_steps")?,
+            busy_beavers: take_strings(&mut table, "busy_beavers")?,
+            sigma: take_integer(&mut table, "sigma")?,
+            sigma_champions: take_strings(&mut table, "sigma_champions")?,
+            unknown_programs: take_strings(&mut table, "unknown_programs")?,
+            num_halted: take_integer(&mut table, "num_halted")?,
+            num_looping: take_integer(&mut table, "num_looping")?,
+            max_tape_length: take_integer(&mut table, "max_tape_length")?,
+            elapsed_ms: take_optional(&mut table, "elapsed_ms", take_integer)?,
+        })
+    }
+}
+
+/// An error from reading a report back from TOML. Only the subset of TOML
+/// which the reports are written in is understood: top level keys whose values
+/// are integers, booleans, strings or arrays of strings. TOML integers are 64
+/// bit signed, so counts larger than `i64::MAX` are written as strings of
+/// digits instead, which are read back as integers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TomlError {
+    /// The line (counting from 1) is not a `key = value` pair.
+    Syntax { line: usize },
+    /// The key appears more than once.
+    DuplicateKey(String),
+    /// A key which the report requires is missing.
+    MissingKey(&'static str),
+    /// The key's value has the wrong type for the report.
+    WrongType(&'static str),
+}
+
+impl Display for TomlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TomlError::Syntax { line } => write!(f, "Invalid TOML on line {}", line),
+            TomlError::DuplicateKey(key) => write!(f, "Key {} appears more than once", key),
+            TomlError::MissingKey(key) => write!(f, "Missing key {}", key),
+            TomlError::WrongType(key) => write!(f, "Key {} has the wrong type", key),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Integer(usize),
+    Boolean(bool),
+    String(String),
+    Strings(Vec<String>),
+}
+
+fn write_entry(toml: &mut String, key: &str, value: &Value) {
+    toml.push_str(key);
+    toml.push_str(" = ");
+    match value {
+        Value::Integer(integer) if *integer > i64::MAX as usize => {
+            write_string(toml, &integer.to_string())
+        }
+        Value::Integer(integer) => toml.push_str(&integer.to_string()),
+        Value::Boolean(boolean) => toml.push_str(&boolean.to_string()),
+        Value::String(string) => write_string(toml, string),
+        Value::Strings(strings) => {
+            toml.push('[');
+            for (i, string) in strings.iter().enumerate() {
+                if i != 0 {
+                    toml.push_str(", ");
+                }
+                write_string(toml, string);
+            }
+            toml.push(']');
+        }
+    }
+    toml.push('\n');
+}
+
+// Write a string as a TOML basic string, which is also a valid JSON string.
+// Neither format allows control characters in strings, so any besides newlines
+// are written as \uXXXX escapes.
+fn write_string(toml: &mut String, string: &str) {
+    toml.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => toml.push_str("\\\""),
+            '\\' => toml.push_str("\\\\"),
+            '\n' => toml.push_str("\\n"),
+            c if c.is_control() => toml.push_str(&format!("\\u{:04x}", c as u32)),
+            c => toml.push(c),
+        }
+    }
+    toml.push('"');
+}
+
+fn parse_toml(toml: &str) -> Result<HashMap<String, Value>, TomlError> {
+    let mut table = HashMap::new();
+    for (i, line) in toml.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let syntax_error = TomlError::Syntax { line: i + 1 };
+        let (key, value) = line.split_once('=').ok_or_else(|| syntax_error.clone())?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(syntax_error);
+        }
+        let value = parse_value(value.trim()).ok_or(syntax_error)?;
+        if table.insert(key.to_string(), value).is_some() {
+            return Err(TomlError::DuplicateKey(key.to_string()));
+        }
+    }
+    Ok(table)
+}
+
+// Parse a whole value, returning None if there is anything after it.
+fn parse_value(value: &str) -> Option<Value> {
+    match value {
+        "true" => Some(Value::Boolean(true)),
+        "false" => Some(Value::Boolean(false)),
+        _ if value.starts_with('"') => match parse_string(value)? {
+            (string, "") => Some(Value::String(string)),
+            _ => None,
+        },
+        _ if value.starts_with('[') => {
+            let mut rest = value[1..].trim_start();
+            let mut strings = vec![];
+            while !rest.starts_with(']') {
+                let (string, after) = parse_string(rest)?;
+                strings.push(string);
+                rest = after.trim_start();
+                if let Some(after) = rest.strip_prefix(',') {
+                    rest = after.trim_start();
+                } else if !rest.starts_with(']') {
+                    return None;
+                }
+            }
+            match rest[1..].trim() {
+                "" => Some(Value::Strings(strings)),
+                _ => None,
+            }
+        }
+        _ => value.replace('_', "").parse().ok().map(Value::Integer),
+    }
+}
+
+// Parse a basic string from the start of `value`, returning it and the rest of
+// `value` after the closing quote.
+fn parse_string(value: &str) -> Option<(String, &str)> {
+    let mut chars = value.strip_prefix('"')?.char_indices();
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((string, &value[i + 2..])),
+            '\\' => match chars.next()?.1 {
+                '"' => string.push('"'),
+                '\\' => string.push('\\'),
+                'n' => string.push('\n'),
+                'u' => {
+                    let digits: String = (0..4)
+                        .map(|_| chars.next().map(|(_, c)| c))
+                        .collect::<Option<_>>()
+                        .filter(|digits: &String| digits.chars().all(|c| c.is_ascii_hexdigit()))?;
+                    let code = u32::from_str_radix(&digits, 16).ok()?;
+                    string.push(char::from_u32(code)?);
+                }
+                _ => return None,
+            },
+            c => string.push(c),
+        }
+    }
+    None
+}
+
+fn take_integer(table: &mut HashMap<String, Value>, key: &'static str) -> Result<usize, TomlError> {
+    match table.remove(key) {
+        Some(Value::Integer(integer)) => Ok(integer),
+        // See `write_entry`.
+        Some(Value::String(string)) => string.parse().map_err(|_| TomlError::WrongType(key)),
+        Some(_) => Err(TomlError::WrongType(key)),
+        None => Err(TomlError::MissingKey(key)),
+    }
+}
+
+fn take_boolean(table: &mut HashMap<String, Value>, key: &'static str) -> Result<bool, TomlError> {
+    match table.remove(key) {
+        Some(Value::Boolean(boolean)) => Ok(boolean),
+        Some(_) => Err(TomlError::WrongType(key)),
+        None => Err(TomlError::MissingKey(key)),
+    }
+}
+
+fn take_string(table: &mut HashMap<String, Value>, key: &'static str) -> Result<String, TomlError> {
+    match table.remove(key) {
+        Some(Value::String(string)) => Ok(string),
+        Some(_) => Err(TomlError::WrongType(key)),
+        None => Err(TomlError::MissingKey(key)),
+    }
+}
+
+fn take_strings(
+    table: &mut HashMap<String, Value>,
+    key: &'static str,
+) -> Result<Vec<String>, TomlError> {
+    match table.remove(key) {
+        Some(Value::Strings(strings)) => Ok(strings),
+        Some(_) => Err(TomlError::WrongType(key)),
+        None => Err(TomlError::MissingKey(key)),
+    }
+}
+
+// Like `take`, but returns None instead of an error if the key is missing.
+fn take_optional<T>(
+    table: &mut HashMap<String, Value>,
+    key: &'static str,
+    take: impl Fn(&mut HashMap<String, Value>, &'static str) -> Result<T, TomlError>,
+) -> Result<Option<T>, TomlError> {
+    if table.contains_key(key) {
+        take(table, key).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::bf::step_count;
+
+    #[test]
+    fn test_run_report_round_trip() {
+        for program in ["+[-]", "+[]", "+[>+]", "+[+>+<]"] {
+            let program = Program::try_from(program).unwrap();
+            let (status, steps, _) = step_count(&program, 100);
+            let report = RunReport::new(&program, &status, steps);
+            assert_eq!(RunReport::from_toml(&report.to_toml()), Ok(report));
+        }
+
+        let program = Program::try_from("+[-]").unwrap();
+        let (status, steps, _) = step_count(&program, 100);
+        assert_eq!(
+            RunReport::new(&program, &status, steps).to_toml(),
+            "program = \"+[-]\"\nstatus = \"halted\"\nsteps = 4\n"
+        );
+    }
+
+    #[test]
+    fn test_outcome_to_json() {
+        let outcome = |program, max_steps| {
+            let program = Program::try_from(program).unwrap();
+            let (status, steps, tape_length) = step_count(&program, max_steps);
+            Outcome {
+                index: 0,
+                program,
+                status,
+                steps,
+                tape_length,
+                sigma: None,
+                unknown_reason: None,
+            }
+        };
+        assert_eq!(
+            outcome_to_json(&outcome("+[->+<]", 100)),
+            r#"{"program":"+[->+<]","status":"halted","steps":7,"tape_length":2}"#
+        );
+        let looping = outcome_to_json(&outcome("+[>+]", 100));
+        assert!(looping.starts_with(r#"{"program":"+[>+]","status":"looping","steps":"#));
+        assert_eq!(
+            outcome_to_json(&outcome("+[->+<]", 3)),
+            r#"{"program":"+[->+<]","status":"unknown","steps":null,"tape_length":1}"#
+        );
+    }
+
+    #[test]
+    fn test_string_escaping() {
+        let strings = [
+            "",
+            "+[-]",
+            "\"quoted\"",
+            "back\\slash \\\"",
+            "line\nbreak\r\n",
+            "tab\tnul\0bell\u{7}del\u{7f}",
+            "unicode \u{e9}\u{1f980}",
+        ];
+        for string in strings {
+            let mut written = String::new();
+            write_string(&mut written, string);
+            assert!(
+                !written.chars().any(char::is_control),
+                "{:?} was written as {:?}",
+                string,
+                written
+            );
+            assert_eq!(parse_string(&written), Some((string.to_string(), "")));
+
+            // The same escaping round trips through a report, and is used in
+            // the JSON lines.
+            let report = RunReport {
+                program: string.to_string(),
+                status: "halted".to_string(),
+                steps: None,
+                reason: Some(string.to_string()),
+            };
+            assert_eq!(RunReport::from_toml(&report.to_toml()), Ok(report));
+        }
+
+        let mut written = String::new();
+        write_string(&mut written, "\"\\\n\t\u{1b}");
+        assert_eq!(written, r#""\"\\\n\u0009\u001b""#);
+        assert_eq!(
+            parse_string(r#""\u00e9\u0041""#),
+            Some(("\u{e9}A".to_string(), ""))
+        );
+        assert_eq!(parse_string(r#""\u00""#), None);
+        assert_eq!(parse_string(r#""\u+041""#), None);
+        assert_eq!(parse_string(r#""\ud800""#), None);
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let report = LengthReport {
+            length: 4,
+            max_steps: 1000,
+            proven: true,
+            busy_beaver_steps: 4,
+            busy_beavers: vec!["++++".to_string(), "\"quoted\" \\ \n".to_string()],
+            sigma: 4,
+            sigma_champions: vec![],
+            unknown_programs: vec!["a, b".to_string()],
+            num_halted: 10,
+            num_looping: 2,
+            max_tape_length: 5,
+            elapsed_ms: Some(1234),
+        };
+        assert_eq!(
+            LengthReport::from_toml(&report.to_toml()),
+            Ok(report.clone())
+        );
+
+        // Counts too large for a TOML integer are written as strings.
+        let huge = LengthReport {
+            max_steps: usize::MAX,
+            busy_beaver_steps: i64::MAX as usize + 1,
+            num_looping: i64::MAX as usize,
+            ..report.clone()
+        };
+        let toml = huge.to_toml();
+        assert!(toml.contains(&format!("max_steps = \"{}\"\n", usize::MAX)));
+        assert!(toml.contains(&format!(
+            "busy_beaver_steps = \"{}\"\n",
+            i64::MAX as usize + 1
+        )));
+        assert!(toml.contains(&format!("num_looping = {}\n", i64::MAX)));
+        assert_eq!(LengthReport::from_toml(&toml), Ok(huge));
+        assert_eq!(
+            RunReport::from_toml("program = \"+\"\nstatus = \"halted\"\nsteps = \"x\""),
+            Err(TomlError::WrongType("steps"))
+        );
+
+        // Reports written before the time was recorded don't have elapsed_ms.
+        let toml = report.to_toml().replace("elapsed_ms = 1234\n", "");
+        assert_eq!(
+            LengthReport::from_toml(&toml),
+            Ok(LengthReport {
+                elapsed_ms: None,
+                ..report
+            })
+        );
+
+        let table = parse_toml("# comment\n\na = [ \"x\" , \"y\" ]\nb = 1_000\n").unwrap();
+        assert_eq!(
+            table["a"],
+            Value::Strings(vec!["x".to_string(), "y".to_string()])
+        );
+        assert_eq!(table["b"], Value::Integer(1000));
+
+        assert_eq!(parse_toml("a = 1\nb"), Err(TomlError::Syntax { line: 2 }));
+        assert_eq!(parse_toml("a = \"x"), Err(TomlError::Syntax { line: 1 }));
+        assert_eq!(
+            parse_toml("a = [\"x\" \"y\"]"),
+            Err(TomlError::Syntax { line: 1 })
+        );
+        assert_eq!(
+            parse_toml("a = 1\na = 2"),
+            Err(TomlError::DuplicateKey("a".to_string()))
+        );
+        assert_eq!(
+            RunReport::from_toml("program = \"+\""),
+            Err(TomlError::MissingKey("status"))
+        );
+        assert_eq!(
+            RunReport::from_toml("program = \"+\"\nstatus = 1"),
+            Err(TomlError::WrongType("status"))
+        );
+    }
+}
@@ -0,0 +1,527 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+
+use rayon::{prelude::*, ThreadPool};
+
+use crate::bf::{
+    sigma, step_count, step_count_with_budget, Budget, ExecutionContext, ExecutionStatus, Instr,
+    Program, Snapshot, UnknownReason,
+};
+use crate::generate;
+
+// How many outcomes the workers can get ahead of the consumer before blocking.
+const STREAM_BUFFER_SIZE: usize = 1024;
+
+// How many prefixes a PrefixCache holds before it is cleared.
+const PREFIX_CACHE_SIZE: usize = 4096;
+
+/// Caches the execution of program prefixes, so that programs which start with
+/// the same instructions only execute those instructions once. This is useful
+/// because consecutive programs in lexiographic order usually share all but
+/// their last few instructions.
+///
+/// Only prefixes which are balanced, such as "+[-]>" in "+[-]>[+]", are cached.
+/// A program only leaves a balanced prefix by running off the end of it, so
+/// until then it runs exactly like the prefix on its own. If the prefix loops
+/// forever or runs out of steps, so does every program starting with it.
+/// Otherwise, the program is resumed from where the prefix halted.
+///
+/// Cloning a PrefixCache gives an empty cache, so that each worker of a
+/// parallel search can have its own cache.
+#[derive(Debug)]
+pub struct PrefixCache {
+    max_steps: usize,
+    prefixes: HashMap<Vec<Instr>, PrefixResult>,
+}
+
+#[derive(Debug)]
+enum PrefixResult {
+    // The prefix looped or timed out, as returned by step_count.
+    Resolved(ExecutionStatus, Option<usize>, usize),
+    // The prefix halted after this many calls to step. The snapshot includes
+    // the real steps taken.
+    Halted { snapshot: Snapshot, calls: usize },
+}
+
+impl PrefixCache {
+    /// Create an empty cache for programs run for at most `max_steps`.
+    pub fn new(max_steps: usize) -> PrefixCache {
+        PrefixCache {
+            max_steps,
+            prefixes: HashMap::new(),
+        }
+    }
+
+    /// Equivalent to `step_count(program, max_steps)`.
+    pub fn step_count(&mut self, program: &Program) -> (ExecutionStatus, Option<usize>, usize) {
+        let instrs = program.original_instrs();
+        let prefix_length = match balanced_prefix_length(instrs) {
+            Some(prefix_length) if !program.is_loop_free() => prefix_length,
+            _ => return step_count(program, self.max_steps),
+        };
+
+        if self.prefixes.len() >= PREFIX_CACHE_SIZE {
+            self.prefixes.clear();
+        }
+        let max_steps = self.max_steps;
+        let prefix = self
+            .prefixes
+            .entry(instrs[..prefix_length].to_vec())
+            .or_insert_with_key(|prefix| run_prefix(prefix, max_steps));
+        match prefix {
+            PrefixResult::Resolved(status, steps, tape_length) => {
+                (status.clone(), *steps, *tape_length)
+            }
+            PrefixResult::Halted { snapshot, calls } => {
+                let mut ctx = ExecutionContext::new(program);
+                ctx.restore(snapshot);
+                // step_count makes at most max_steps - 1 calls to step.
+                for _ in (*calls + 1)..max_steps {
+                    let (_, state) = ctx.step();
+                    if !state.is_running() {
+                        return (state, Some(ctx.steps_taken()), ctx.tape_length());
+                    }
+                }
+                (ExecutionStatus::Running, None, ctx.tape_length())
+            }
+        }
+    }
+}
+
+impl Clone for PrefixCache {
+    fn clone(&self) -> Self {
+        PrefixCache::new(self.max_steps)
+    }
+}
+
+// Run the prefix on its own, until it halts or step_count would give up on it.
+fn run_prefix(prefix: &[Instr], max_steps: usize) -> PrefixResult {
+    let program = Program::new(prefix.iter().copied()).expect("prefix must be balanced");
+    let mut ctx = ExecutionContext::new(&program);
+    for calls in 1..max_steps {
+        let (_, state) = ctx.step();
+        match state {
+            ExecutionStatus::Running => (),
+            ExecutionStatus::Halted => {
+                return PrefixResult::Halted {
+                    snapshot: ctx.snapshot(),
+                    calls,
+                }
+            }
+            ExecutionStatus::InfiniteLoop(_) => {
+                return PrefixResult::Resolved(state, Some(ctx.steps_taken()), ctx.tape_length())
+            }
+        }
+    }
+    PrefixResult::Resolved(ExecutionStatus::Running, None, ctx.tape_length())
+}
+
+// Return the length of the longest balanced proper prefix of the program which
+// compiles to a prefix of the program's extended instructions, or None if there
+// isn't one. A prefix can't end in the middle of a run of moves, since the run
+// would be folded into a single instruction.
+fn balanced_prefix_length(instrs: &[Instr]) -> Option<usize> {
+    let mut depth = 0;
+    let mut best = None;
+    for (i, window) in instrs.windows(2).enumerate() {
+        match window[0] {
+            Instr::StartLoop => depth += 1,
+            Instr::EndLoop => depth -= 1,
+            _ => (),
+        }
+        let splits_run = matches!(
+            (window[0], window[1]),
+            (Instr::Left, Instr::Left) | (Instr::Right, Instr::Right)
+        );
+        if depth == 0 && !splits_run {
+            best = Some(i + 1);
+        }
+    }
+    best
+}
+
+/// The result of running a single program during a search.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// The index of the program in `generate::lexiographic_order`.
+    pub index: usize,
+    pub program: Program,
+    pub status: ExecutionStatus,
+    /// The number of real steps the program ran before it was resolved, or
+    /// None if it was still running after `max_steps`.
+    pub steps: Option<usize>,
+    pub tape_length: usize,
+    /// The number of nonzero cells on the tape when the program halted, or
+    /// None if it didn't halt (see `bf::sigma`).
+    pub sigma: Option<usize>,
+    /// Which limit of the budget ran out, if the program was not resolved.
+    pub unknown_reason: Option<UnknownReason>,
+}
+
+/// The halting program which took the most real steps out of the outcomes seen
+/// so far. A search given one behind an `Arc<Mutex<Best>>` (see
+/// `beaver_stream_in`) updates it with every outcome, so that other threads can
+/// poll it for the current champion while the search runs.
+#[derive(Debug, Clone, Default)]
+pub struct Best {
+    /// The champion so far, or None if no program has halted yet.
+    pub program: Option<Program>,
+    /// The real steps the champion took, or 0 if there is no champion yet.
+    pub steps: usize,
+}
+
+impl Best {
+    /// Make the outcome the new champion if it halted in more steps than the
+    /// current one, and return whether it did. Ties keep the current champion,
+    /// so the steps only ever increase.
+    pub fn update(&mut self, outcome: &Outcome) -> bool {
+        match (&outcome.status, outcome.steps) {
+            (ExecutionStatus::Halted, Some(steps))
+                if self.program.is_none() || steps > self.steps =>
+            {
+                self.program = Some(outcome.program.clone());
+                self.steps = steps;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Run every valid program of the given length in parallel, yielding the
+/// outcome of each program as soon as it is resolved. Outcomes are not yielded
+/// in lexiographic order.
+///
+/// The search runs on a background thread. Dropping the returned iterator
+/// cancels the search, although programs which are already running are
+/// finished first.
+pub fn beaver_stream(length: usize, max_steps: usize) -> impl Iterator<Item = Outcome> {
+    beaver_stream_in(None, length, Budget::steps(max_steps), None)
+}
+
+/// Like `beaver_stream`, but runs the search in the given thread pool instead
+/// of rayon's global pool, if a pool is given, and runs each program until any
+/// limit of the budget runs out. The iterator should not be consumed from
+/// inside the same pool, since a worker blocked waiting on the iterator cannot
+/// run the search.
+///
+/// If `best` is given, every halting outcome is also recorded in it (see
+/// `Best::update`) as soon as its program is resolved, even before the outcome
+/// is yielded, so that it can be polled for the champion so far from another
+/// thread. Otherwise, no lock is taken.
+pub fn beaver_stream_in(
+    pool: Option<Arc<ThreadPool>>,
+    length: usize,
+    budget: Budget,
+    best: Option<Arc<Mutex<Best>>>,
+) -> impl Iterator<Item = Outcome> {
+    let (sender, receiver) = mpsc::sync_channel(STREAM_BUFFER_SIZE);
+    std::thread::spawn(move || {
+        let search = move || run_search(sender, length, budget, best);
+        match pool {
+            Some(pool) => pool.install(search),
+            None => search(),
+        }
+    });
+    receiver.into_iter()
+}
+
+// Run every program of the given length, sending the outcomes to `sender` and
+// recording them in `best`, if given.
+fn run_search(
+    sender: mpsc::SyncSender<Outcome>,
+    length: usize,
+    budget: Budget,
+    best: Option<Arc<Mutex<Best>>>,
+) {
+    let max_steps = budget.max_steps;
+    // The prefix cache only knows about step limits and the default detectors.
+    let steps_only = budget == Budget::steps(max_steps);
+    // A send error means the receiver was dropped, which stops the search.
+    let _ = generate::brute_force_iterator_indexed(length)
+        .par_bridge()
+        .try_for_each_with(
+            (sender, PrefixCache::new(max_steps)),
+            |(sender, cache), (index, program)| {
+                let (status, steps, tape_length, unknown_reason) = if steps_only {
+                    let (status, steps, tape_length) = cache.step_count(&program);
+                    let unknown_reason = status.is_running().then_some(UnknownReason::Steps);
+                    (status, steps, tape_length, unknown_reason)
+                } else {
+                    step_count_with_budget(&program, budget)
+                };
+                // Halting programs are run again to find their final tape,
+                // which is cheap since they don't run for long on average.
+                let sigma = match status {
+                    ExecutionStatus::Halted => sigma(&program, max_steps),
+                    _ => None,
+                };
+                let outcome = Outcome {
+                    index,
+                    program,
+                    status,
+                    steps,
+                    tape_length,
+                    sigma,
+                    unknown_reason,
+                };
+                if let Some(best) = &best {
+                    if outcome.status == ExecutionStatus::Halted {
+                        best.lock().unwrap().update(&outcome);
+                    }
+                }
+                sender.send(outcome).map_err(|_| ())
+            },
+        );
+}
+
+/// Run every valid program formed by inserting one instruction into `seed`,
+/// and return the ones which halt in more steps than the seed does, along with
+/// their step counts. The results are sorted by decreasing step count, and
+/// then in lexiographic order.
+///
+/// The busy beaver of length n + 1 is often the busy beaver of length n with
+/// one more instruction, so this is a quick way to find good programs of
+/// lengths which are too long to search exhaustively. If the seed does not
+/// halt within `max_steps`, every halting insertion is returned.
+pub fn extend_seed(seed: &Program, max_steps: usize) -> Vec<(Program, usize)> {
+    let seed_steps = match step_count(seed, max_steps) {
+        (ExecutionStatus::Halted, Some(steps), _) => steps,
+        _ => 0,
+    };
+    let mut better: Vec<_> = generate::mutations(seed)
+        .into_par_iter()
+        .filter(|(mutation, _)| mutation.is_insertion())
+        .filter_map(|(_, program)| match step_count(&program, max_steps) {
+            (ExecutionStatus::Halted, Some(steps), _) if steps > seed_steps => {
+                Some((program, steps))
+            }
+            _ => None,
+        })
+        .collect();
+    better.sort_by(|(a, a_steps), (b, b_steps)| {
+        b_steps
+            .cmp(a_steps)
+            .then_with(|| a.original_instrs().cmp(b.original_instrs()))
+    });
+    better
+}
+
+/// Find the shortest halting program which runs for at least `target` steps,
+/// trying every length up to and including `max_length` in increasing order.
+/// Returns the first such program in lexiographic order of the shortest length
+/// that has one, along with its step count, or None if no program up to
+/// `max_length` reaches the target within `max_steps`.
+///
+/// This is the inverse of the busy beaver search: instead of asking how long
+/// programs of a length can run, it asks how long a program has to be to run
+/// for a given number of steps.
+pub fn shortest_reaching(
+    target: usize,
+    max_length: usize,
+    max_steps: usize,
+) -> Option<(Program, usize)> {
+    generate::brute_force_chain(0..max_length + 1).find_map(|program| {
+        match step_count(&program, max_steps) {
+            (ExecutionStatus::Halted, Some(steps), _) if steps >= target => Some((program, steps)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::bf::LoopReason;
+
+    #[test]
+    fn test_beaver_stream_matches_sequential_run() {
+        let mut expected: Vec<_> = generate::brute_force_iterator_indexed(5)
+            .map(|(index, program)| {
+                let (status, steps, tape_length) = step_count(&program, 1000);
+                (index, status, steps, tape_length)
+            })
+            .collect();
+        let mut actual: Vec<_> = beaver_stream(5, 1000)
+            .map(|outcome| {
+                assert_eq!(
+                    outcome.program.original_instrs(),
+                    generate::lexiographic_order(5).nth(outcome.index).unwrap()
+                );
+                (
+                    outcome.index,
+                    outcome.status,
+                    outcome.steps,
+                    outcome.tape_length,
+                )
+            })
+            .collect();
+        expected.sort_by_key(|(index, ..)| *index);
+        actual.sort_by_key(|(index, ..)| *index);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_beaver_stream_returned_to_start() {
+        // The second cell counts up until it wraps back to zero, which leaves
+        // the tape as it was when the loop was entered.
+        let outcome = beaver_stream(6, 2000)
+            .find(|outcome| outcome.program.to_string() == "+[>+<]")
+            .unwrap();
+        assert_eq!(
+            outcome.status,
+            ExecutionStatus::InfiniteLoop(LoopReason::ReturnedToStart)
+        );
+        assert_eq!(outcome.steps, Some(2 + 4 * 256));
+    }
+
+    #[test]
+    fn test_best() {
+        let outcome = |program: &str, status, steps| Outcome {
+            index: 0,
+            program: Program::try_from(program).unwrap(),
+            status,
+            steps,
+            tape_length: 1,
+            sigma: None,
+            unknown_reason: None,
+        };
+        let mut best = Best::default();
+        assert!(best.program.is_none());
+        assert!(best.update(&outcome("", ExecutionStatus::Halted, Some(0))));
+        assert_eq!(best.program.as_ref().unwrap().to_string(), "");
+        assert!(best.update(&outcome("+[-]", ExecutionStatus::Halted, Some(4))));
+        assert!(!best.update(&outcome("-[+]", ExecutionStatus::Halted, Some(4))));
+        assert!(!best.update(&outcome("+", ExecutionStatus::Halted, Some(1))));
+        assert!(!best.update(&outcome("+[]", ExecutionStatus::Running, None)));
+        assert_eq!(best.program.as_ref().unwrap().to_string(), "+[-]");
+        assert_eq!(best.steps, 4);
+    }
+
+    #[test]
+    fn test_beaver_stream_polled_best() {
+        let best = Arc::new(Mutex::new(Best::default()));
+        let done = Arc::new(AtomicBool::new(false));
+        let poller = {
+            let best = best.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                let mut polled = vec![];
+                while !done.load(Ordering::Relaxed) {
+                    polled.push(best.lock().unwrap().steps);
+                    std::thread::yield_now();
+                }
+                polled
+            })
+        };
+        let mut champion_steps = 0;
+        let mut champions = vec![];
+        for outcome in beaver_stream_in(None, 6, Budget::steps(1000), Some(best.clone())) {
+            if let (ExecutionStatus::Halted, Some(steps)) = (&outcome.status, outcome.steps) {
+                if steps > champion_steps {
+                    champion_steps = steps;
+                    champions.clear();
+                }
+                if steps == champion_steps {
+                    champions.push(outcome.program.to_string());
+                }
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        let polled = poller.join().unwrap();
+        assert!(polled.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let best = best.lock().unwrap();
+        assert_eq!(best.steps, champion_steps);
+        assert!(champions.contains(&best.program.as_ref().unwrap().to_string()));
+    }
+
+    #[test]
+    fn test_prefix_cache_matches_step_count() {
+        for max_steps in [3, 10, 1000] {
+            let mut cache = PrefixCache::new(max_steps);
+            for program in generate::brute_force_chain(0..8) {
+                assert_eq!(
+                    cache.step_count(&program),
+                    step_count(&program, max_steps),
+                    "{} (max_steps = {})",
+                    program,
+                    max_steps
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_extend_seed() {
+        let seed = Program::try_from("+[->+<]").unwrap();
+        let (_, seed_steps, _) = step_count(&seed, 1000);
+        let extended = extend_seed(&seed, 1000);
+        assert!(!extended.is_empty());
+        for (program, steps) in &extended {
+            assert_eq!(program.original_instrs().len(), 8);
+            assert!(*steps > seed_steps.unwrap());
+            assert_eq!(step_count(program, 1000).1, Some(*steps));
+        }
+        // Adding another + to the counter makes the loop run twice.
+        assert!(extended
+            .iter()
+            .any(|(program, _)| program.to_string() == "++[->+<]"));
+        assert!(extended.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+        // A seed which loops forever is beaten by any halting insertion.
+        let extended = extend_seed(&Program::try_from("+[]").unwrap(), 1000);
+        assert!(extended
+            .iter()
+            .any(|(program, steps)| program.to_string() == "+[-]" && *steps > 0));
+    }
+
+    #[test]
+    fn test_shortest_reaching() {
+        let max_steps = 1000;
+        let (program, steps) = shortest_reaching(10, 8, max_steps).unwrap();
+        assert!(steps >= 10);
+        // No shorter program reaches the target.
+        let length = program.original_instrs().len();
+        for program in generate::brute_force_chain(0..length) {
+            if let (ExecutionStatus::Halted, Some(steps), _) = step_count(&program, max_steps) {
+                assert!(steps < 10, "{} halts in {} steps", program, steps);
+            }
+        }
+        assert_eq!(program.to_string(), "+[+]");
+
+        // A program of length n takes n steps without loops.
+        assert_eq!(
+            shortest_reaching(3, 8, max_steps).map(|(program, steps)| (program.to_string(), steps)),
+            Some(("+++".to_string(), 3))
+        );
+        assert_eq!(
+            shortest_reaching(0, 8, max_steps).map(|(program, steps)| (program.to_string(), steps)),
+            Some(("".to_string(), 0))
+        );
+        assert!(shortest_reaching(1000, 3, max_steps).is_none());
+    }
+
+    #[test]
+    fn test_balanced_prefix_length() {
+        let prefix_length =
+            |program| balanced_prefix_length(Program::try_from(program).unwrap().original_instrs());
+        assert_eq!(prefix_length(""), None);
+        assert_eq!(prefix_length("+"), None);
+        assert_eq!(prefix_length("+[-]>[+]"), Some(5));
+        assert_eq!(prefix_length("+[-]>>"), Some(4));
+        assert_eq!(prefix_length("[[-]+]"), None);
+        assert_eq!(prefix_length("[]<>"), Some(3));
+    }
+
+    #[test]
+    fn test_beaver_stream_cancel() {
+        // Length 12 has billions of programs, so this only finishes if dropping
+        // the stream stops the search.
+        let outcomes: Vec<_> = beaver_stream(12, 1000).take(10).collect();
+        assert_eq!(outcomes.len(), 10);
+    }
+}
@@ -1,4 +1,10 @@
-use std::{collections::BTreeMap, io::stdout};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryInto,
+    fmt::Display,
+    io::stdout,
+    path::Path,
+};
 
 use bf_beavers::bf::{LoopReason, LoopSpan};
 use crossterm::{
@@ -11,77 +17,207 @@ use crossterm::{
 use owo_colors::{AnsiColors, OwoColorize};
 use thousands::Separable;
 
-use crate::bf::{ExecutionContext, ExecutionStatus, Program};
+use crate::bf::{CompileError, DecodeError, ExecutionContext, ExecutionStatus, Program, Snapshot};
 
 #[derive(Debug, Clone)]
 struct HistoryData {
-    real_steps: usize,
     status: ExecutionStatus,
     exec_ctx: ExecutionContext,
 }
 
 impl HistoryData {
-    fn new(program: &Program) -> HistoryData {
+    // The program before its first step, with the tape starting as
+    // `initial_tape` (or blank, if it is empty).
+    fn new(program: &Program, initial_tape: &[u8]) -> HistoryData {
+        let exec_ctx = if initial_tape.is_empty() {
+            ExecutionContext::new(program)
+        } else {
+            ExecutionContext::with_memory(program.clone(), initial_tape.to_vec())
+        };
         HistoryData {
-            real_steps: 0,
             status: ExecutionStatus::Running,
-            exec_ctx: ExecutionContext::new(program),
+            exec_ctx,
         }
     }
 
     fn step(&mut self) {
-        let (delta, new_status) = self.exec_ctx.step();
-        self.real_steps += delta;
+        let (_, new_status) = self.exec_ctx.step();
         self.status = new_status;
     }
 }
 
+// A cached step in the History. This stores a Snapshot instead of a full
+// ExecutionContext to reduce memory usage. Stepping forward from a restored
+// snapshot gives the same status at every step as an uninterrupted run.
+#[derive(Debug, Clone)]
+struct CachedStep {
+    status: ExecutionStatus,
+    snapshot: Snapshot,
+}
+
+// The most steps to take when looking for the tape to grow.
+const MAX_GROWTH_STEPS: usize = 100_000;
+
+// The most steps to take when continuing to the next breakpoint.
+const MAX_CONTINUE_STEPS: usize = 1_000_000;
+
+/// The default number of steps between cached steps in the History.
+pub const DEFAULT_CACHE_INTERVAL: usize = 1000;
+
+/// The largest step the visualizer can be started at. Getting there means
+/// running the program for that many steps before anything is shown, which
+/// takes minutes at this size.
+pub const MAX_START_STEP: usize = 1_000_000_000;
+
+// The number of steps between progress updates when fast-forwarding to the
+// starting step. Only one step per chunk is cached, instead of every
+// `interval`th step.
+const FAST_FORWARD_CHUNK: usize = 100_000;
+
+/// Where sessions are saved if the visualizer wasn't started from a session.
+pub const DEFAULT_SESSION_PATH: &str = "visualizer.session";
+
+// The first bytes of a session file, followed by the format version. The
+// version must be bumped whenever the format of a session, Snapshot or
+// ExecutionStatus changes.
+const SESSION_MAGIC: &[u8; 8] = b"BFVSESSN";
+const SESSION_VERSION: u32 = 8;
+
 struct History {
-    history: BTreeMap<usize, HistoryData>,
+    history: BTreeMap<usize, CachedStep>,
     program: Program,
+    // The indices into the program's extended instructions which have a
+    // breakpoint before them (see Program::from_source_with_breakpoints).
+    breakpoints: BTreeSet<usize>,
+    // The tape the program starts with. Empty for a blank tape.
+    initial_tape: Vec<u8>,
     cells_allocated: usize,
+    // Every step which is a multiple of this is cached when it is computed.
+    interval: usize,
 }
 
 impl History {
+    #[cfg(test)]
     fn new(program: &Program) -> History {
+        History::with_interval(program, DEFAULT_CACHE_INTERVAL)
+    }
+
+    /// Create a History which caches every `interval`th step. Smaller intervals
+    /// make backtracking faster, at the cost of more memory. The interval must
+    /// be nonzero.
+    fn with_interval(program: &Program, interval: usize) -> History {
+        assert!(interval != 0, "cache interval must be nonzero");
         History {
             history: BTreeMap::new(),
             program: program.clone(),
+            breakpoints: BTreeSet::new(),
+            initial_tape: vec![],
             cells_allocated: 0,
+            interval,
+        }
+    }
+
+    /// Replace the program with the one compiled from `source`, discarding every
+    /// cached step. If `source` does not compile, the current program is kept.
+    /// The new program starts with the same initial tape, and any "#" in
+    /// `source` is a breakpoint.
+    fn set_program(&mut self, source: &str) -> Result<(), CompileError> {
+        let (program, breakpoints) = Program::from_source_with_breakpoints(source)?;
+        let initial_tape = std::mem::take(&mut self.initial_tape);
+        *self = History::with_interval(&program, self.interval);
+        self.initial_tape = initial_tape;
+        self.breakpoints = breakpoints;
+        Ok(())
+    }
+
+    /// The program's source with a "#" at each breakpoint, which compiles back
+    /// to the same program and breakpoints with `set_program`.
+    fn source_with_breakpoints(&self) -> String {
+        let original_instrs = self.program.original_instrs();
+        let mut source = String::new();
+        for i in 0..=self.program.extended_instrs().len() {
+            if self.breakpoints.contains(&i) {
+                source.push('#');
+            }
+            if let Some(span) = self.program.source_span(i) {
+                source.extend(original_instrs[span].iter().map(|instr| instr.to_string()));
+            }
         }
+        source
     }
 
     /// Return the HistoryData corresponding to step `step`. This function attempts to cache results when possible.
     fn get(&mut self, step: usize) -> HistoryData {
-        // Get the nearest entry below the step count.
-        let nearest_lower_entry = self.history.range(..step).next_back();
+        // Get the nearest entry at or below the step count.
+        let nearest_lower_entry = self.history.range(..=step).next_back();
         let (steps_to_run, mut data) = match nearest_lower_entry {
-            Some((&lower_steps, history_data)) => {
+            Some((&lower_steps, cached_step)) => {
+                let data = self.restore(cached_step);
                 if lower_steps == step {
-                    return history_data.clone();
+                    return data;
                 } else {
-                    (step - lower_steps, history_data.clone())
+                    (step - lower_steps, data)
                 }
             }
-            None => (step, HistoryData::new(&self.program)),
+            None => (step, HistoryData::new(&self.program, &self.initial_tape)),
         };
 
         // Advance the execution context to the desired step.
         for i in 0..steps_to_run {
-            let step = (step - steps_to_run) + i;
+            // The step that `data` is at after this iteration.
+            let step = (step - steps_to_run) + i + 1;
             data.step();
 
-            // We cache every 1000th step here because it is likely that the user will want to keep going backwards.
+            // We cache every `interval`th step here because it is likely that the user will want to keep going backwards.
             // Caching some intermediate steps avoids having to recompute a lot of work each time.
-            if step % 1000 == 0 && !self.history.contains_key(&step) {
-                self.insert_step(step, data.clone());
+            if step.is_multiple_of(self.interval) && !self.history.contains_key(&step) {
+                self.insert_step(step, &data);
             }
         }
 
-        self.insert_step(step, data.clone());
+        if !self.history.contains_key(&step) {
+            self.insert_step(step, &data);
+        }
         data
     }
 
+    /// Like `get`, but for jumping far ahead, such as to the starting step. The
+    /// steps are run in chunks of FAST_FORWARD_CHUNK, and only the end of each
+    /// chunk is cached, so that the jump doesn't fill memory with a cached step
+    /// every `interval` steps. `progress` is called with the step reached after
+    /// each chunk, and fast-forwarding stops early if it returns false. Returns
+    /// the HistoryData and the step it is at, which is `step` unless stopped.
+    fn fast_forward(
+        &mut self,
+        step: usize,
+        mut progress: impl FnMut(usize) -> bool,
+    ) -> (HistoryData, usize) {
+        let (mut curr_step, mut data) = match self.history.range(..=step).next_back() {
+            Some((&lower_steps, cached_step)) => (lower_steps, self.restore(cached_step)),
+            None => (0, HistoryData::new(&self.program, &self.initial_tape)),
+        };
+        while curr_step < step {
+            // Stepping a halted program does nothing, so skip straight to the end.
+            let chunk_end = if data.status.is_halted() {
+                step
+            } else {
+                (curr_step + FAST_FORWARD_CHUNK).min(step)
+            };
+            while curr_step < chunk_end && !data.status.is_halted() {
+                data.step();
+                curr_step += 1;
+            }
+            curr_step = chunk_end;
+            if !self.history.contains_key(&curr_step) {
+                self.insert_step(curr_step, &data);
+            }
+            if !progress(curr_step) {
+                break;
+            }
+        }
+        (data, curr_step)
+    }
+
     /// Return the HistoryData corresponding to the soonest time when execution reaches the end
     /// the current loop. This does not cache intermediate steps of the loop. This function
     /// also bails out after 10000 steps.
@@ -94,8 +230,8 @@ impl History {
                     data.step();
                     step = step.saturating_add_signed(step_size);
 
-                    if step % 1000 == 0 && !self.history.contains_key(&step) {
-                        self.insert_step(step, data.clone());
+                    if step.is_multiple_of(self.interval) && !self.history.contains_key(&step) {
+                        self.insert_step(step, &data);
                     }
 
                     let inside_loop = (start..end).contains(&data.exec_ctx.program_pointer());
@@ -109,10 +245,64 @@ impl History {
         (data, step)
     }
 
-    fn insert_step(&mut self, step: usize, data: HistoryData) {
+    /// Return the HistoryData for the next step after `step` at which the tape
+    /// grows, along with that step. This stops early if the program halts or is
+    /// detected as looping, and gives up after MAX_GROWTH_STEPS steps, since
+    /// the tape may never grow. Intermediate steps are not cached.
+    fn get_until_tape_grows(&mut self, step: usize) -> (HistoryData, usize) {
+        let mut data = self.get(step);
+        let tape_length = data.exec_ctx.tape_length();
+        let breakpoints = &self.breakpoints;
+        let (steps, _, status) = data.exec_ctx.run_until(MAX_GROWTH_STEPS, |ctx| {
+            ctx.tape_length() > tape_length || breakpoints.contains(&ctx.program_pointer())
+        });
+        data.status = status;
+
+        let step = step + steps;
+        if !self.history.contains_key(&step) {
+            self.insert_step(step, &data);
+        }
+        (data, step)
+    }
+
+    /// Return the HistoryData for the next step after `step` at which the
+    /// program reaches a breakpoint, along with that step. Like
+    /// `get_until_tape_grows`, this stops early if the program halts or is
+    /// detected as looping, gives up after MAX_CONTINUE_STEPS steps, and does
+    /// not cache intermediate steps.
+    fn get_until_breakpoint(&mut self, step: usize) -> (HistoryData, usize) {
+        let mut data = self.get(step);
+        let breakpoints = &self.breakpoints;
+        let (steps, _, status) = data.exec_ctx.run_until(MAX_CONTINUE_STEPS, |ctx| {
+            breakpoints.contains(&ctx.program_pointer())
+        });
+        data.status = status;
+
+        let step = step + steps;
+        if !self.history.contains_key(&step) {
+            self.insert_step(step, &data);
+        }
+        (data, step)
+    }
+
+    fn insert_step(&mut self, step: usize, data: &HistoryData) {
         assert!(!self.history.contains_key(&step));
-        self.cells_allocated += data.exec_ctx.total_cells_allocated();
-        self.history.insert(step, data);
+        let cached_step = CachedStep {
+            status: data.status.clone(),
+            snapshot: data.exec_ctx.snapshot(),
+        };
+        self.cells_allocated += cached_step.snapshot.total_cells_allocated();
+        self.history.insert(step, cached_step);
+    }
+
+    // Rebuild the HistoryData for a cached step.
+    fn restore(&self, cached_step: &CachedStep) -> HistoryData {
+        let mut exec_ctx = ExecutionContext::new(&self.program);
+        exec_ctx.restore(&cached_step.snapshot);
+        HistoryData {
+            status: cached_step.status.clone(),
+            exec_ctx,
+        }
     }
 
     fn total_cells_allocated(&self) -> usize {
@@ -120,16 +310,270 @@ impl History {
     }
 }
 
-pub fn run(program: &Program, starting_step: usize) {
-    fn print_state(history: &mut History, curr_step: usize) {
+/// The state of the visualizer, which can be saved to a file and loaded later
+/// to continue from the same step without recomputing the cached steps.
+pub struct Session {
+    history: History,
+    step: usize,
+}
+
+impl Session {
+    /// Create a session for the program, starting at `step`. The program starts
+    /// with `initial_tape` on the tape, or a blank tape if it is empty, and
+    /// stops at `breakpoints` (see Program::from_source_with_breakpoints) when
+    /// continuing.
+    pub fn new(
+        program: &Program,
+        breakpoints: BTreeSet<usize>,
+        initial_tape: Vec<u8>,
+        step: usize,
+        cache_interval: usize,
+    ) -> Session {
+        let mut history = History::with_interval(program, cache_interval);
+        history.breakpoints = breakpoints;
+        history.initial_tape = initial_tape;
+        Session { history, step }
+    }
+
+    /// The program being visualized.
+    pub fn program(&self) -> &Program {
+        &self.history.program
+    }
+
+    /// Encode the session as bytes, which can be decoded with `from_bytes`.
+    ///
+    /// The layout is the magic bytes and the version as a little endian u32,
+    /// then the program source (with a "#" at each breakpoint), the initial
+    /// tape, the cache interval, the current step and every cached step. Lists
+    /// and variable length values are prefixed with their length, and integers
+    /// are little endian u64s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn put_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+            bytes.extend((value.len() as u64).to_le_bytes());
+            bytes.extend(value);
+        }
+        let mut bytes = SESSION_MAGIC.to_vec();
+        bytes.extend(SESSION_VERSION.to_le_bytes());
+        put_bytes(
+            &mut bytes,
+            self.history.source_with_breakpoints().as_bytes(),
+        );
+        put_bytes(&mut bytes, &self.history.initial_tape);
+        bytes.extend((self.history.interval as u64).to_le_bytes());
+        bytes.extend((self.step as u64).to_le_bytes());
+        bytes.extend((self.history.history.len() as u64).to_le_bytes());
+        for (&step, cached_step) in &self.history.history {
+            bytes.extend((step as u64).to_le_bytes());
+            put_bytes(&mut bytes, &cached_step.status.to_bytes());
+            put_bytes(&mut bytes, &cached_step.snapshot.to_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a session encoded by `to_bytes`.
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Session, SessionError> {
+        fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Result<&'a [u8], SessionError> {
+            if bytes.len() < length {
+                return Err(SessionError::Truncated);
+            }
+            let (taken, rest) = bytes.split_at(length);
+            *bytes = rest;
+            Ok(taken)
+        }
+        fn take_usize(bytes: &mut &[u8]) -> Result<usize, SessionError> {
+            let value = take(bytes, 8)?;
+            Ok(u64::from_le_bytes(value.try_into().unwrap()) as usize)
+        }
+        fn take_bytes<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], SessionError> {
+            let length = take_usize(bytes)?;
+            take(bytes, length)
+        }
+
+        if bytes.len() < SESSION_MAGIC.len() || &bytes[..SESSION_MAGIC.len()] != SESSION_MAGIC {
+            return Err(SessionError::NotASession);
+        }
+        take(&mut bytes, SESSION_MAGIC.len())?;
+        let version = u32::from_le_bytes(take(&mut bytes, 4)?.try_into().unwrap());
+        if version != SESSION_VERSION {
+            return Err(SessionError::UnsupportedVersion { found: version });
+        }
+        let source = String::from_utf8_lossy(take_bytes(&mut bytes)?).into_owned();
+        let (program, breakpoints) =
+            Program::from_source_with_breakpoints(&source).map_err(SessionError::InvalidProgram)?;
+        let initial_tape = take_bytes(&mut bytes)?.to_vec();
+        let interval = take_usize(&mut bytes)?;
+        if interval == 0 {
+            return Err(SessionError::ZeroInterval);
+        }
+        let step = take_usize(&mut bytes)?;
+
+        let mut history = History::with_interval(&program, interval);
+        history.breakpoints = breakpoints;
+        history.initial_tape = initial_tape;
+        for _ in 0..take_usize(&mut bytes)? {
+            let step = take_usize(&mut bytes)?;
+            let status = ExecutionStatus::from_bytes(take_bytes(&mut bytes)?)
+                .map_err(SessionError::InvalidStep)?;
+            let snapshot =
+                Snapshot::from_bytes(take_bytes(&mut bytes)?).map_err(SessionError::InvalidStep)?;
+            history.cells_allocated += snapshot.total_cells_allocated();
+            history
+                .history
+                .insert(step, CachedStep { status, snapshot });
+        }
+        if !bytes.is_empty() {
+            return Err(SessionError::InvalidStep(DecodeError::TrailingBytes));
+        }
+        Ok(Session { history, step })
+    }
+}
+
+/// An error specifying why a session could not be loaded.
+#[derive(Debug, Clone)]
+pub enum SessionError {
+    /// The file does not start with the session magic bytes.
+    NotASession,
+    /// The session was saved by a different version of the visualizer.
+    UnsupportedVersion { found: u32 },
+    /// The session ended partway through.
+    Truncated,
+    /// The session's program does not compile.
+    InvalidProgram(CompileError),
+    /// The session's cache interval is zero.
+    ZeroInterval,
+    /// One of the cached steps is corrupt.
+    InvalidStep(DecodeError),
+}
+
+impl Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::NotASession => write!(f, "Not a visualizer session"),
+            SessionError::UnsupportedVersion { found } => write!(
+                f,
+                "Session is version {}, but only version {} is supported",
+                found, SESSION_VERSION
+            ),
+            SessionError::Truncated => write!(f, "Session is truncated"),
+            SessionError::InvalidProgram(err) => write!(f, "Invalid program in session ({})", err),
+            SessionError::ZeroInterval => write!(f, "Session has a cache interval of zero"),
+            SessionError::InvalidStep(err) => write!(f, "Invalid cached step in session ({})", err),
+        }
+    }
+}
+
+// The keybindings listed in the help overlay.
+const KEYBINDINGS: [(&str, &str); 10] = [
+    ("Right / d", "Step forwards"),
+    ("Left / a", "Step backwards"),
+    ("Shift + Right / d", "Step forwards out of this loop"),
+    ("Shift + Left / a", "Step backwards out of this loop"),
+    ("g", "Step forwards until the tape grows"),
+    ("c", "Continue to the next breakpoint (#)"),
+    ("e", "Edit the program, adding # for breakpoints"),
+    ("w", "Save the session"),
+    ("?", "Show/hide this help"),
+    ("Esc / q", "Quit"),
+];
+
+// Draw the help overlay over the top of the screen.
+fn print_help(cache_interval: usize) {
+    let mut lines = vec!["Keybindings".to_string()];
+    lines.extend(
+        KEYBINDINGS
+            .iter()
+            .map(|(key, description)| format!("{:<17}  {}", key, description)),
+    );
+    lines.push(String::new());
+    lines.push(format!("Caching every {} steps", cache_interval));
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+
+    crossterm::execute! { stdout(), cursor::MoveTo(0,0) }.unwrap();
+    println!("+{}+", "-".repeat(width + 2));
+    for line in lines {
+        println!("| {:<width$} |", line, width = width);
+    }
+    println!("+{}+", "-".repeat(width + 2));
+}
+
+// Let the user edit `text` on a single line. Returns the edited text, or None if
+// the user cancelled the edit.
+fn edit_line(text: &str) -> Option<String> {
+    let mut chars: Vec<char> = text.chars().collect();
+    let mut cursor_pos = chars.len();
+    loop {
         crossterm::execute! { stdout(), cursor::MoveTo(0,0) }.unwrap();
         crossterm::execute! { stdout(), Clear(ClearType::All) }.unwrap();
+        println!("Edit program (Enter to apply, Esc to cancel):");
+        println!("{}", chars.iter().collect::<String>());
+        println!("{}^", " ".repeat(cursor_pos));
 
-        let HistoryData {
-            status,
-            real_steps,
-            exec_ctx,
-        } = &history.get(curr_step);
+        crossterm::terminal::enable_raw_mode().unwrap();
+        let event = crossterm::event::read().unwrap();
+        crossterm::terminal::disable_raw_mode().unwrap();
+
+        if let Event::Key(event) = event {
+            match event.code {
+                KeyCode::Enter => return Some(chars.into_iter().collect()),
+                KeyCode::Esc => return None,
+                KeyCode::Left => cursor_pos = cursor_pos.saturating_sub(1),
+                KeyCode::Right => cursor_pos = (cursor_pos + 1).min(chars.len()),
+                KeyCode::Home => cursor_pos = 0,
+                KeyCode::End => cursor_pos = chars.len(),
+                KeyCode::Backspace if cursor_pos > 0 => {
+                    cursor_pos -= 1;
+                    chars.remove(cursor_pos);
+                }
+                KeyCode::Delete if cursor_pos < chars.len() => {
+                    chars.remove(cursor_pos);
+                }
+                KeyCode::Char(c) => {
+                    chars.insert(cursor_pos, c);
+                    cursor_pos += 1;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// Return true if Esc has been pressed, without waiting for a key.
+fn esc_pressed() -> bool {
+    crossterm::terminal::enable_raw_mode().unwrap();
+    let mut pressed = false;
+    while crossterm::event::poll(std::time::Duration::ZERO).unwrap() {
+        if let Event::Key(event) = crossterm::event::read().unwrap() {
+            pressed |= event.code == KeyCode::Esc;
+        }
+    }
+    crossterm::terminal::disable_raw_mode().unwrap();
+    pressed
+}
+
+// Return the step to show after the program was edited while at `curr_step`.
+// The step is kept unless the new program halts before reaching it, in which
+// case this returns to the start.
+fn step_after_edit(history: &mut History, curr_step: usize) -> usize {
+    if curr_step > 0 && history.get(curr_step - 1).status.is_halted() {
+        0
+    } else {
+        curr_step
+    }
+}
+
+/// Run the visualizer, starting from the session. Pressing 'w' saves the session
+/// to `session_path`.
+pub fn run(session: Session, session_path: &Path) {
+    fn print_state(
+        history: &mut History,
+        curr_step: usize,
+        show_help: bool,
+        message: Option<&str>,
+    ) {
+        crossterm::execute! { stdout(), cursor::MoveTo(0,0) }.unwrap();
+        crossterm::execute! { stdout(), Clear(ClearType::All) }.unwrap();
+
+        let HistoryData { status, exec_ctx } = &history.get(curr_step);
 
         let displayed_status = crossterm::style::style(format!("{:?}", status));
         let displayed_status = match status {
@@ -139,7 +583,9 @@ pub fn run(program: &Program, starting_step: usize) {
         };
         println!(
             "Steps: {} (Actual: {}), Status: {}",
-            curr_step, real_steps, displayed_status
+            curr_step,
+            exec_ctx.steps_taken(),
+            displayed_status
         );
         println!(
             "Total cells allocated: {} (in {} cached steps)",
@@ -147,19 +593,65 @@ pub fn run(program: &Program, starting_step: usize) {
             history.history.len()
         );
 
+        let span_stats = exec_ctx.loop_span_history().stats();
+        println!(
+            "Loop spans: {} active, {} past ({} cells)",
+            span_stats.num_active_spans,
+            span_stats.total_past_spans(),
+            span_stats.total_cells_allocated.separate_with_commas()
+        );
+
         println!("{}", ExecutionContextFormatter(exec_ctx));
+        if !history.breakpoints.is_empty() {
+            println!("Breakpoints: {}", history.source_with_breakpoints());
+        }
+
+        if let Some(message) = message {
+            println!("{}", message);
+        }
+
+        if show_help {
+            print_help(history.interval);
+        }
     }
-    let mut history = History::new(program);
-    let mut curr_step = starting_step;
+    let Session {
+        mut history,
+        step: mut curr_step,
+    } = session;
+    let mut show_help = false;
+    // A message to show until the next key is pressed, such as a compile error.
+    let mut message: Option<String> = None;
 
     crossterm::execute! { stdout(), EnterAlternateScreen }.unwrap();
-    print_state(&mut history, curr_step);
+    if curr_step > FAST_FORWARD_CHUNK && !history.history.contains_key(&curr_step) {
+        let target = curr_step;
+        curr_step = history
+            .fast_forward(target, |step| {
+                crossterm::execute! { stdout(), cursor::MoveTo(0,0) }.unwrap();
+                crossterm::execute! { stdout(), Clear(ClearType::All) }.unwrap();
+                println!(
+                    "Fast-forwarding to step {}: {}/{} ({:.0}%)",
+                    target.separate_with_commas(),
+                    step.separate_with_commas(),
+                    target.separate_with_commas(),
+                    100.0 * step as f64 / target as f64
+                );
+                println!("Press Esc to stop here");
+                !esc_pressed()
+            })
+            .1;
+        if curr_step < target {
+            message = Some(format!("Stopped fast-forwarding at step {}", curr_step));
+        }
+    }
+    print_state(&mut history, curr_step, show_help, message.as_deref());
 
     'outer: loop {
         crossterm::terminal::enable_raw_mode().unwrap();
         let event = crossterm::event::read().unwrap();
         crossterm::terminal::disable_raw_mode().unwrap();
 
+        message = None;
         if let Event::Key(event) = event {
             // If shift is held, jump to the end/start of this loop.
             let shift_held = event.modifiers.contains(KeyModifiers::SHIFT);
@@ -178,11 +670,48 @@ pub fn run(program: &Program, starting_step: usize) {
                         curr_step += 1;
                     }
                 }
+                KeyCode::Char('g') => curr_step = history.get_until_tape_grows(curr_step).1,
+                KeyCode::Char('c') => {
+                    if history.breakpoints.is_empty() {
+                        message = Some("No breakpoints (add a # with e)".to_string());
+                    } else {
+                        curr_step = history.get_until_breakpoint(curr_step).1;
+                    }
+                }
+                KeyCode::Char('e') => {
+                    if let Some(source) = edit_line(&history.source_with_breakpoints()) {
+                        match history.set_program(&source) {
+                            Ok(()) => curr_step = step_after_edit(&mut history, curr_step),
+                            Err(err) => {
+                                message =
+                                    Some(format!("Cannot compile {} (reason: {})", source, err))
+                            }
+                        }
+                    }
+                }
+                KeyCode::Char('w') => {
+                    history = {
+                        let session = Session {
+                            history,
+                            step: curr_step,
+                        };
+                        message = Some(match std::fs::write(session_path, session.to_bytes()) {
+                            Ok(()) => format!("Saved session to {}", session_path.display()),
+                            Err(err) => format!(
+                                "Cannot save session to {} (reason: {})",
+                                session_path.display(),
+                                err
+                            ),
+                        });
+                        session.history
+                    };
+                }
+                KeyCode::Char('?') => show_help = !show_help,
                 KeyCode::Esc | KeyCode::Char('q') => break 'outer,
                 _ => (),
             }
         }
-        print_state(&mut history, curr_step);
+        print_state(&mut history, curr_step, show_help, message.as_deref());
     }
     stdout().execute(LeaveAlternateScreen).unwrap();
 }
@@ -208,19 +737,31 @@ impl<'a> std::fmt::Display for ExecutionContextFormatter<'a> {
             .extended_instrs()
             .iter()
             .enumerate()
-            .map(|(i, _)| {
+            .map(|(i, instr)| {
+                // Some instructions are displayed with more than one character.
+                let width = instr.to_string().chars().count();
                 if i == self.0.program_pointer() {
-                    "^".to_string()
+                    "^".repeat(width)
                 } else {
-                    " ".to_string()
+                    " ".repeat(width)
                 }
             })
             .collect::<String>();
 
+        // Highlight every source instruction covered by the current instruction,
+        // so that folded instructions such as "[-]" are highlighted in full.
+        let source = self.0.program().to_string();
+        let source_ptr = match self.0.program_pointer_to_source() {
+            Some(span) => format!("{}{}", " ".repeat(span.start), "^".repeat(span.len())),
+            None => String::new(),
+        };
+
         writeln!(f, "Memory: {}", memory)?;
         writeln!(f, "        {}", memory_pointer)?;
         writeln!(f, "Program: {}", program)?;
         writeln!(f, "         {}", program_ptr)?;
+        writeln!(f, "Source:  {}", source)?;
+        writeln!(f, "         {}", source_ptr)?;
 
         // Sort the active loop spans by the loop id in ascending order
         // This allows the printed active span list to look more like a
@@ -277,6 +818,16 @@ impl<'a> std::fmt::Display for LoopReasonFormatter<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0 {
             LoopReason::LoopIfNonzero => write!(f, "LoopIfNonzero instruction triggered"),
+            LoopReason::NoOpLoop => write!(f, "Entered a loop whose body does nothing"),
+            LoopReason::ReturnedToStart => {
+                write!(
+                    f,
+                    "Returned to the tape and memory pointer an outer loop started with"
+                )
+            }
+            LoopReason::GrowingRun => {
+                write!(f, "Loop can only ever grow a run of nonzero cells")
+            }
             LoopReason::LoopSpan { prior, current } => write!(
                 f,
                 "LoopSpan triggered. prior span:\n{}\ncurrent span:\n{}",
@@ -300,6 +851,7 @@ impl<'a> std::fmt::Display for LoopSpanFormatter<'a> {
             self.0.max_index,
             self.0.displacement()
         )?;
+        writeln!(f, "compared: {}", array_to_string(self.0.masked_window()))?;
 
         for i in 0..self.0.memory_at_loop_start.len() {
             let (fg, bg) = if i == self.0.starting_memory_pointer {
@@ -360,3 +912,245 @@ fn highlight(index: usize) -> String {
         .intersperse(" ")
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_interval() {
+        let program = Program::try_from("+[>++++[>+++<-]<+]").unwrap();
+        let mut coarse = History::new(&program);
+        let mut fine = History::with_interval(&program, 10);
+        for step in [5000, 1234, 4321, 20, 0] {
+            let expected = coarse.get(step);
+            let actual = fine.get(step);
+            assert_eq!(
+                actual.exec_ctx.steps_taken(),
+                expected.exec_ctx.steps_taken()
+            );
+            assert_eq!(actual.status, expected.status);
+            assert!(actual.exec_ctx.logically_eq(&expected.exec_ctx));
+        }
+        assert!(fine.history.len() > coarse.history.len());
+        assert!(fine.history.contains_key(&4990));
+        assert!(!coarse.history.contains_key(&4990));
+    }
+
+    #[test]
+    fn test_status_independent_of_cache() {
+        for program in ["+[>+]", "+[>+<]", "+[>+>+<]"] {
+            let program = Program::try_from(program).unwrap();
+            let mut uncached = HistoryData::new(&program, &[]);
+            let mut detected_at = 0;
+            while uncached.status.is_running() {
+                uncached.step();
+                detected_at += 1;
+            }
+            assert!(uncached.status.is_looping());
+            // Reaching the step where the loop is detected from any earlier
+            // cached step gives the same status.
+            for cached_at in 0..detected_at {
+                let mut history = History::with_interval(&program, usize::MAX);
+                history.get(cached_at);
+                assert_eq!(history.get(detected_at).status, uncached.status);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        // This halts after about 390,000 calls to step.
+        let program = Program::try_from("-[>-[>-[-]<-]<-]").unwrap();
+        let mut history = History::new(&program);
+        let mut progress = vec![];
+        let target = 2 * FAST_FORWARD_CHUNK + 12345;
+        let (data, step) = history.fast_forward(target, |step| {
+            progress.push(step);
+            true
+        });
+        assert_eq!(step, target);
+        assert_eq!(
+            progress,
+            [FAST_FORWARD_CHUNK, 2 * FAST_FORWARD_CHUNK, target]
+        );
+        // Only the end of each chunk is cached.
+        assert_eq!(history.history.len(), 3);
+
+        let expected = History::new(&program).get(target);
+        assert_eq!(data.status, expected.status);
+        assert_eq!(data.exec_ctx.steps_taken(), expected.exec_ctx.steps_taken());
+        assert!(data.exec_ctx.logically_eq(&expected.exec_ctx));
+        // Stepping around afterwards works from the cached chunks.
+        let data = history.get(target - 1);
+        let expected = History::new(&program).get(target - 1);
+        assert!(data.exec_ctx.logically_eq(&expected.exec_ctx));
+
+        // Stopping early leaves the history at the end of the last chunk.
+        let mut history = History::new(&program);
+        let (_, step) = history.fast_forward(target, |_| false);
+        assert_eq!(step, FAST_FORWARD_CHUNK);
+
+        // A program which halts early skips straight to the target.
+        let mut history = History::new(&Program::try_from("+[-]").unwrap());
+        let (data, step) = history.fast_forward(usize::MAX / 2, |_| true);
+        assert_eq!(step, usize::MAX / 2);
+        assert!(data.status.is_halted());
+        assert_eq!(data.exec_ctx.steps_taken(), 4);
+    }
+
+    #[test]
+    fn test_get_until_tape_grows() {
+        let mut history = History::new(&Program::try_from(">>+[[-]+>]").unwrap());
+        // ">>" is a single step.
+        let (data, step) = history.get_until_tape_grows(0);
+        assert_eq!((step, data.exec_ctx.tape_length()), (1, 3));
+        let (data, step) = history.get_until_tape_grows(step);
+        assert_eq!((step, data.exec_ctx.tape_length()), (6, 4));
+        // The result matches stepping there directly.
+        let (data, step) = history.get_until_tape_grows(step);
+        let expected = History::new(&history.program).get(step);
+        assert!(data.exec_ctx.logically_eq(&expected.exec_ctx));
+        assert_eq!(data.exec_ctx.steps_taken(), expected.exec_ctx.steps_taken());
+        assert_eq!(
+            history.get(step).exec_ctx.steps_taken(),
+            expected.exec_ctx.steps_taken()
+        );
+
+        // The tape never grows here, so this stops at the loop detection.
+        let mut history = History::new(&Program::try_from("+[-+]").unwrap());
+        let (data, _) = history.get_until_tape_grows(0);
+        assert!(data.status.is_looping());
+    }
+
+    #[test]
+    fn test_initial_tape() {
+        let mut history = History::new(&Program::try_from("[->+<]").unwrap());
+        history.initial_tape = vec![2, 5];
+        let data = history.get(100);
+        assert_eq!(data.status, ExecutionStatus::Halted);
+        assert_eq!(data.exec_ctx.tape_trimmed(), [0, 7]);
+
+        // Editing the program keeps the initial tape.
+        assert!(history.set_program("[->++<]").is_ok());
+        assert_eq!(history.get(100).exec_ctx.tape_trimmed(), [0, 9]);
+    }
+
+    #[test]
+    fn test_set_program() {
+        let mut history = History::with_interval(&Program::try_from("+[->+<]").unwrap(), 10);
+        history.get(50);
+        let interval = history.interval;
+
+        // A compile error keeps the old program and its cached steps.
+        assert!(history.set_program("+[->+<").is_err());
+        assert_eq!(history.program.to_string(), "+[->+<]");
+        assert!(!history.history.is_empty());
+
+        assert!(history.set_program("++[->+<]").is_ok());
+        assert_eq!(history.program.to_string(), "++[->+<]");
+        assert!(history.history.is_empty());
+        assert_eq!(history.interval, interval);
+        assert_eq!(history.get(6).exec_ctx.tape(), [1, 1]);
+
+        // The step is kept if the new program is still running...
+        assert_eq!(step_after_edit(&mut history, 5), 5);
+        assert_eq!(step_after_edit(&mut history, 0), 0);
+        // ...but not if it halts first.
+        assert!(history.set_program("+>").is_ok());
+        assert_eq!(step_after_edit(&mut history, 2), 2);
+        assert_eq!(step_after_edit(&mut history, 5), 0);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut history = History::new(&Program::try_from("+").unwrap());
+        assert!(history.set_program("++[->+#>+<<]>#>").is_ok());
+        // A "#" inside a folded instruction moves to the start of it.
+        assert_eq!(history.source_with_breakpoints(), "++[->+#>+<<]#>>");
+        let breakpoints: Vec<_> = history.breakpoints.iter().copied().collect();
+
+        // Continuing stops just before the instruction after each "#", every
+        // time round the loop.
+        let (data, step) = history.get_until_breakpoint(0);
+        assert_eq!(data.exec_ctx.program_pointer(), breakpoints[0]);
+        assert_eq!(data.exec_ctx.tape(), [1, 1]);
+        let (data, step) = history.get_until_breakpoint(step);
+        assert_eq!(data.exec_ctx.program_pointer(), breakpoints[0]);
+        assert_eq!(data.exec_ctx.tape(), [0, 2, 1]);
+        let (data, step) = history.get_until_breakpoint(step);
+        assert_eq!(data.exec_ctx.program_pointer(), breakpoints[1]);
+        assert_eq!(data.exec_ctx.tape_trimmed(), [0, 2, 2]);
+        // With no more breakpoints, it runs until the program halts.
+        let (data, last_step) = history.get_until_breakpoint(step);
+        assert!(data.status.is_halted());
+        assert_eq!(history.get(last_step).status, ExecutionStatus::Halted);
+
+        // The breakpoints don't change how the program runs.
+        let mut without_breakpoints = History::new(&Program::try_from("++[->+>+<<]>>").unwrap());
+        assert!(without_breakpoints
+            .get(last_step)
+            .exec_ctx
+            .logically_eq(&history.get(last_step).exec_ctx));
+
+        // Stepping until the tape grows also stops at breakpoints.
+        assert!(history.set_program("+#>>>").is_ok());
+        assert_eq!(history.get_until_tape_grows(0).1, 1);
+        assert_eq!(history.get_until_tape_grows(1).1, 2);
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let (program, breakpoints) =
+            Program::from_source_with_breakpoints("+[>++++[>+++<-]<+#]").unwrap();
+        let mut session = Session::new(&program, breakpoints, vec![3], 2500, 100);
+        session.history.get(2500);
+        let bytes = session.to_bytes();
+        let mut loaded = Session::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded.step, 2500);
+        assert_eq!(loaded.program().to_string(), "+[>++++[>+++<-]<+]");
+        assert_eq!(loaded.history.breakpoints, session.history.breakpoints);
+        assert_eq!(loaded.history.interval, 100);
+        assert_eq!(loaded.history.initial_tape, [3]);
+        assert_eq!(
+            loaded.history.cells_allocated,
+            session.history.cells_allocated
+        );
+        assert!(loaded
+            .history
+            .history
+            .keys()
+            .eq(session.history.history.keys()));
+        for (step, cached_step) in &session.history.history {
+            let loaded_step = &loaded.history.history[step];
+            assert_eq!(loaded_step.status.to_bytes(), cached_step.status.to_bytes());
+            assert_eq!(
+                loaded_step.snapshot.to_bytes(),
+                cached_step.snapshot.to_bytes()
+            );
+        }
+        let expected = session.history.get(2600);
+        let data = loaded.history.get(2600);
+        assert!(data.exec_ctx.logically_eq(&expected.exec_ctx));
+        assert_eq!(data.exec_ctx.steps_taken(), expected.exec_ctx.steps_taken());
+
+        let mut other_version = bytes.clone();
+        other_version[SESSION_MAGIC.len()..SESSION_MAGIC.len() + 4]
+            .copy_from_slice(&1u32.to_le_bytes());
+        assert!(matches!(
+            Session::from_bytes(&other_version),
+            Err(SessionError::UnsupportedVersion { found: 1 })
+        ));
+        assert!(matches!(
+            Session::from_bytes(b"not a session"),
+            Err(SessionError::NotASession)
+        ));
+        assert!(matches!(
+            Session::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SessionError::Truncated)
+        ));
+    }
+}
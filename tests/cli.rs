@@ -0,0 +1,147 @@
+#[cfg(all(test, feature = "interactive"))]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::path::PathBuf;
+    use std::process::{Command, Output, Stdio};
+
+    // Run the binary in a fresh directory, since searches write their results
+    // to the current directory.
+    fn run_in(name: &str, args: &[&str]) -> (Output, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("bf_beavers_cli_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_bf_beavers"))
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        (output, dir)
+    }
+
+    #[test]
+    fn test_quiet() {
+        let search = [
+            "search",
+            "--max-length",
+            "3",
+            "--print-every",
+            "1",
+            "--live",
+        ];
+        let (output, dir) = run_in("normal", &search);
+        assert!(output.status.success());
+        assert!(!output.stderr.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let (output, dir) = run_in("quiet", &[&["--quiet"], &search[..]].concat());
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+        let results = std::fs::read_to_string(dir.join("length_3.txt")).unwrap();
+        assert!(results.starts_with("Best Busy Beavers for Length 3"));
+        assert!(results.contains("\nTime taken: "), "{}", results);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let (output, dir) = run_in("quiet_run", &["-q", "run", "+[-]"]);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Halts in 4 steps\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let (output, dir) = run_in("verbose", &["-v", "search", "--max-length", "1"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("searching length 1"), "{}", stderr);
+        assert!(stderr.contains("finished length 1"), "{}", stderr);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let (output, dir) = run_in("conflict", &["-q", "-v", "run", "+"]);
+        assert!(!output.status.success());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let (output, dir) = run_in(
+            "json_lines",
+            &["-q", "search", "--max-length", "4", "--json-lines"],
+        );
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<_> = stdout.lines().collect();
+        // One record for every valid program of each length up to 4.
+        let num_valid: Option<usize> = (0..=4).map(bf_beavers::generate::count_valid).sum();
+        assert_eq!(Some(lines.len()), num_valid);
+        for line in &lines {
+            assert!(line.starts_with("{\"program\":\""), "{}", line);
+            assert!(line.contains(",\"tape_length\":"), "{}", line);
+            assert!(line.ends_with('}'), "{}", line);
+        }
+        assert!(
+            lines.contains(&r#"{"program":"+[-]","status":"halted","steps":4,"tape_length":1}"#)
+        );
+        // The results are still written to files.
+        assert!(dir.join("length_4.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_lines_closed_pipe() {
+        // Like `search --json-lines | head -1`. Length 7 writes far more than a
+        // pipe holds, so the search is still writing when the pipe is closed.
+        let dir =
+            std::env::temp_dir().join(format!("bf_beavers_cli_closed_pipe_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bf_beavers"))
+            .args(["-q", "search", "--max-length", "7", "--json-lines"])
+            .current_dir(&dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut first_line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut first_line)
+            .unwrap();
+        assert!(first_line.starts_with("{\"program\":"), "{}", first_line);
+
+        let output = child.wait_with_output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}: {}", output.status, stderr);
+        assert_eq!(stderr, "");
+        // The search stopped without writing its incomplete results.
+        assert!(!dir.join("length_7.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_expected_exit_status() {
+        let (output, dir) = run_in("check_expected_missing", &["check-expected", "missing.csv"]);
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("Cannot read missing.csv"), "{}", stderr);
+
+        std::fs::write(dir.join("agree.csv"), "+[-],halted,4\n+[],looping,\n").unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_bf_beavers"))
+            .args(["check-expected", "agree.csv"])
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        std::fs::write(dir.join("disagree.csv"), "+[-],halted,4\n+[],halted,2\n").unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_bf_beavers"))
+            .args(["check-expected", "disagree.csv"])
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.starts_with("line 2: +[]: expected"), "{}", stdout);
+
+        let (output, _) = run_in("check_expected_missing", &["verify", "missing.corpus"]);
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,226 +1,233 @@
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, convert::TryFrom};
+    use std::convert::TryFrom;
 
     use bf_beavers::{
-        bf::{self, CompileError, ExecutionContext, ExecutionStatus, Instr, Program},
-        generate,
+        bf::{self, Detectors, ExecutionContext, ExecutionStatus, LoopReason, Program},
+        generate::{self, Rng, XorShift},
+        model_check::{self, Comparison},
     };
 
-    #[derive(Debug)]
-    struct SimpleExecutionContext {
-        memory: Vec<u8>,
-        memory_pointer: usize,
-        program: Vec<bf::Instr>,
-        program_pointer: usize,
-        loop_dict: HashMap<usize, usize>,
+    fn assert_model_matches(program: &Program, max_steps: usize) -> Comparison {
+        let comparison = model_check::compare(program, max_steps);
+        assert!(
+            !comparison.is_mismatch(),
+            "Mismatch for program {}",
+            comparison
+        );
+        comparison
     }
 
-    impl SimpleExecutionContext {
-        fn new(program: &bf::Program) -> SimpleExecutionContext {
-            let program = program.original_instrs().to_vec();
+    fn assert_halting(program: &Program, max_steps: usize) {
+        let comparison = assert_model_matches(program, max_steps);
+        assert!(comparison.reference_halted);
+        assert_eq!(comparison.status, ExecutionStatus::Halted);
+    }
 
-            let loop_dict = loop_dict(&program).unwrap();
+    #[test]
+    fn test_specific_halting() {
+        let program = Program::try_from(">").unwrap();
+        assert_halting(&program, 10_000);
 
-            SimpleExecutionContext {
-                memory: vec![0; 256],
-                memory_pointer: 0,
-                program_pointer: 0,
-                program,
-                loop_dict,
-            }
-        }
+        let program = Program::try_from(">>>>>>>+[<+]").unwrap();
+        assert_halting(&program, 10_000);
 
-        fn step(&mut self) -> (SimpleExecutionState, usize) {
-            let instruction = self.program.get(self.program_pointer);
-
-            match instruction {
-                None => (SimpleExecutionState::Halted, 0),
-                Some(instruction) => {
-                    match instruction {
-                        Instr::Plus => {
-                            self.memory[self.memory_pointer] =
-                                self.memory[self.memory_pointer].wrapping_add(1)
-                        }
-                        Instr::Minus => {
-                            self.memory[self.memory_pointer] =
-                                self.memory[self.memory_pointer].wrapping_sub(1)
-                        }
-                        Instr::Left => {
-                            self.memory_pointer = self.memory_pointer.saturating_sub(1);
-                        }
-                        Instr::Right => {
-                            self.memory_pointer += 1;
-                            if self.memory_pointer >= self.memory.len() {
-                                self.memory.push(0);
-                            }
-                        }
-                        Instr::StartLoop => {
-                            if self.memory[self.memory_pointer] == 0 {
-                                let end_loop = self.loop_dict[&self.program_pointer];
-                                self.program_pointer = end_loop;
-                            }
-                        }
-                        Instr::EndLoop => {
-                            if self.memory[self.memory_pointer] != 0 {
-                                let start_loop = self.loop_dict[&self.program_pointer];
-                                self.program_pointer = start_loop;
-                            }
-                        }
-                    }
-                    self.program_pointer += 1;
-                    if self.program.get(self.program_pointer).is_none() {
-                        (SimpleExecutionState::Halted, 1)
-                    } else {
-                        (SimpleExecutionState::Running, 1)
-                    }
-                }
-            }
-        }
-    }
+        let program = Program::try_from(">>+>>>>>>>>-<<<<<<<<[>+]").unwrap();
+        assert_halting(&program, 10_000);
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    enum SimpleExecutionState {
-        Halted,
-        Running,
+        let program = Program::try_from("++>---[<[-]++>+]").unwrap();
+        assert_halting(&program, 10_000);
     }
 
-    fn loop_dict(program: &[Instr]) -> Result<HashMap<usize, usize>, CompileError> {
-        use Instr::*;
-        let mut hashmap = HashMap::new();
-        let mut startloop_locs = Vec::new();
-        for (i, &instr) in program.iter().enumerate() {
-            match instr {
-                Plus | Minus | Left | Right => (),
-                StartLoop => {
-                    startloop_locs.push(i);
-                }
-                EndLoop => {
-                    match startloop_locs.pop() {
-                        Some(start_loop) => {
-                            hashmap.insert(i, start_loop);
-                            hashmap.insert(start_loop, i);
-                        }
-                        None => return Err(CompileError::UnmatchedEndLoop { index: i }),
-                    };
-                }
-            }
+    #[test]
+    fn test_model_checked() {
+        let report = model_check::model_check_range(0..8, 10_000);
+        for mismatch in &report.mismatches {
+            println!("Mismatch for program {}", mismatch);
         }
+        assert!(report.mismatches.is_empty());
+        assert_eq!(
+            Some(report.num_checked),
+            (0..8).map(generate::count_valid).sum::<Option<usize>>()
+        );
+    }
 
-        if !startloop_locs.is_empty() {
-            Err(CompileError::UnmatchedStartLoops {
-                indicies: startloop_locs,
-            })
-        } else {
-            Ok(hashmap)
+    #[test]
+    fn test_guard_insensitive_model_checked() {
+        let detectors = Detectors {
+            guard_insensitive_loop_span: true,
+            ..Detectors::ALL
+        };
+        let report = model_check::model_check_range_with_detectors(0..8, 10_000, detectors);
+        for mismatch in &report.mismatches {
+            println!("Mismatch for program {}", mismatch);
         }
+        assert!(report.mismatches.is_empty());
     }
 
-    fn eval(
-        program: &Program,
-        max_steps: usize,
-    ) -> ((ExecutionStatus, usize), (SimpleExecutionState, usize)) {
-        let mut real_ctx = ExecutionContext::new(program);
-        let mut real_state = ExecutionStatus::Running;
-        let mut real_steps = 0;
-        for _ in 0..max_steps {
-            let (delta, state) = real_ctx.step();
-            real_steps += delta;
-            real_state = state;
-            if real_state != ExecutionStatus::Running {
-                break;
+    #[test]
+    fn test_can_halt_trivially() {
+        // Every program which is said to halt trivially halts on the reference
+        // interpreter in the claimed number of steps, and `step_count`, which
+        // takes the same shortcut, agrees.
+        let mut num_with_loops = 0;
+        for length in 0..8 {
+            for program in generate::brute_force_iterator(length) {
+                let steps = match ExecutionContext::new(&program).can_halt_trivially() {
+                    Some(steps) => steps,
+                    None => continue,
+                };
+                let comparison = assert_model_matches(&program, 10_000);
+                assert!(comparison.reference_halted, "{}", comparison);
+                assert_eq!(comparison.reference_steps, steps, "{}", comparison);
+                let (status, shortcut_steps, _) = bf::step_count(&program, 10_000);
+                assert_eq!(status, ExecutionStatus::Halted, "{}", comparison);
+                assert_eq!(
+                    shortcut_steps,
+                    Some(comparison.reference_steps),
+                    "{}",
+                    comparison
+                );
+                if !program.is_loop_free() {
+                    num_with_loops += 1;
+                }
             }
         }
+        // Loops which are never entered don't stop a program halting trivially.
+        assert!(num_with_loops > 0);
+    }
 
-        let max_steps = match real_state {
-            ExecutionStatus::Halted => real_steps,
-            ExecutionStatus::Running => max_steps,
-            ExecutionStatus::InfiniteLoop(_) => real_steps * 2,
-        };
-
-        let mut simple_ctx = SimpleExecutionContext::new(program);
-        let mut simple_state = SimpleExecutionState::Running;
-        let mut simple_steps = 0;
-        for _ in 0..=max_steps {
-            let (state, steps) = simple_ctx.step();
-            simple_steps += steps;
-            simple_state = state;
-            if simple_state == SimpleExecutionState::Halted {
-                break;
-            }
+    #[test]
+    fn test_random_model_checked() {
+        let mut rng = XorShift::new(0x5EED_BEAF);
+        for _ in 0..5_000 {
+            let length = 8 + rng.below(7);
+            let program = generate::random_valid(length, &mut rng);
+            assert_eq!(program.original_instrs().len(), length);
+            assert_model_matches(&program, 10_000);
         }
-
-        ((real_state, real_steps), (simple_state, simple_steps))
     }
 
-    fn assert_model_matches(
-        program: &Program,
-        max_steps: usize,
-    ) -> ((ExecutionStatus, usize), (SimpleExecutionState, usize)) {
-        let ((real_state, real_steps), (simple_state, simple_steps)) = eval(program, max_steps);
-        match (&real_state, simple_state) {
-            (ExecutionStatus::Running, SimpleExecutionState::Running) => (),
-            (ExecutionStatus::InfiniteLoop(_), SimpleExecutionState::Running) => (),
-            (ExecutionStatus::Halted, SimpleExecutionState::Halted) => {
-                assert_eq!(real_steps, simple_steps, "Program: {}", program);
+    #[test]
+    fn test_scan_heavy() {
+        // Programs dominated by runs of moves, which are folded into single
+        // Move instructions.
+        for program in [
+            ">>>>>>+<<<<<<",
+            "+>>>+>>>+<<<<<<<<<<+",
+            "++++[>>>+<<<-]>>>[<<+>>-]",
+            "+[>>>>+<<<<-+>>>>-+<<<<-]",
+            ">>>>>>>>+[<<+]",
+            "+>>+>>+>>+<<<<<<[>>]",
+            "++[>>>[>>+<<-]<<<-]",
+            "<<<<+>>>>>>>>[<<<<<<<<]",
+        ] {
+            let program = Program::try_from(program).unwrap();
+            assert_model_matches(&program, 10_000);
+        }
+        assert_halting(&Program::try_from("++[>>>[>>+<<-]<<<-]").unwrap(), 10_000);
+        assert_halting(&Program::try_from("+>>+>>+>>+<<<<<<[>>]").unwrap(), 10_000);
+
+        // Random programs built mostly from runs of moves.
+        let mut rng = XorShift::new(0x5CA7_5CA7);
+        for _ in 0..2_000 {
+            let mut source = String::new();
+            for _ in 0..4 {
+                let chunk = match rng.below(5) {
+                    0 => ">".repeat(1 + rng.below(4)),
+                    1 => "<".repeat(1 + rng.below(4)),
+                    2 => "+".to_string(),
+                    3 => "[".to_string(),
+                    _ => "]".to_string(),
+                };
+                source.push_str(&chunk);
             }
-            (real_state, simple_state) => {
-                println!(
-                    "Mismatch for program {}\n(Real: {:#?}, Simple: {:#?})",
-                    program, real_state, simple_state
-                );
-                panic!();
+            if let Ok(program) = Program::try_from(source.as_str()) {
+                assert_model_matches(&program, 10_000);
             }
         }
-        ((real_state, real_steps), (simple_state, simple_steps))
-    }
-
-    fn assert_halting(program: &Program, max_steps: usize) {
-        let ((real_state, real_steps), (simple_state, simple_steps)) = eval(program, max_steps);
-        assert_eq!(simple_state, SimpleExecutionState::Halted);
-        assert_eq!(real_state, ExecutionStatus::Halted);
-        assert_eq!(real_steps, simple_steps);
     }
 
     #[test]
-    fn test_specific_halting() {
-        let program = Program::try_from(">").unwrap();
-        assert_halting(&program, 10_000);
-
-        let program = Program::try_from(">>>>>>>+[<+]").unwrap();
-        assert_halting(&program, 10_000);
+    fn test_guard_decrement_with_tape_growth() {
+        // A loop guarded on cell 0 which subtracts `decrement` from it every
+        // iteration, while appending `growth` to a growing run of nonzero
+        // cells. This halts exactly when the largest power of two dividing
+        // `decrement` divides the guard (see LOOP_SPAN.md). The others are
+        // proven to loop by the growing run detector.
+        for growth in [1, 2] {
+            for decrement in [1, 2, 4] {
+                for guard in [1, 2, 3, 4, 6, 7, 8] {
+                    let source = format!(
+                        "{}[>>[>]{}[<]<{}]",
+                        "+".repeat(guard),
+                        "+".repeat(growth),
+                        "-".repeat(decrement)
+                    );
+                    let program = Program::try_from(source.as_str()).unwrap();
+                    if guard % decrement == 0 {
+                        assert_halting(&program, 20_000);
+                    } else {
+                        let comparison = assert_model_matches(&program, 20_000);
+                        assert_eq!(
+                            comparison.status,
+                            ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun),
+                            "{}",
+                            source
+                        );
+                    }
+                }
+            }
+        }
 
-        let program = Program::try_from(">>+>>>>>>>>-<<<<<<<<[>+]").unwrap();
-        assert_halting(&program, 10_000);
+        // The longest running member of the family halts after 255 iterations.
+        let source = format!("{}[>>[>]+[<]<-]", "+".repeat(255));
+        assert_halting(&Program::try_from(source.as_str()).unwrap(), 200_000);
 
-        let program = Program::try_from("++>---[<[-]++>+]").unwrap();
-        assert_halting(&program, 10_000);
+        // Without a zero cell between the guard and the growing cells, the
+        // inner [<] loops forever at the left edge of the tape.
+        let comparison =
+            assert_model_matches(&Program::try_from("+++[>[>]+[<]<-]").unwrap(), 20_000);
+        assert!(comparison.status.is_looping());
     }
 
     #[test]
-    fn test_model_checked() {
-        for length in 0..8 {
-            let mut num_halted = 0;
-            let mut num_looping = 0;
-            let mut num_unknown = 0;
-
-            for (i, program) in generate::brute_force_iterator(length).enumerate() {
-                if i % 10000 == 0 {
-                    eprintln!("{}", program);
-                }
-                let max_steps = 10_000;
-                let ((real_state, _), _) = assert_model_matches(&program, max_steps);
-                match real_state {
-                    ExecutionStatus::Halted => num_halted += 1,
-                    ExecutionStatus::InfiniteLoop(_) => num_looping += 1,
-                    ExecutionStatus::Running => num_unknown += 1,
-                }
+    fn test_growing_run_random() {
+        // Random loops built from the pieces the growing run detector follows,
+        // some inside another loop. Every loop it proves is still running long
+        // after it was detected.
+        let pieces = [">", "<", "+", "-", ">>", "<<", "[>]", "[<]", "[-]"];
+        let mut rng = XorShift::new(0x6120_5EED);
+        let mut num_proven = 0;
+        for _ in 0..20_000 {
+            let mut source = String::new();
+            for _ in 0..rng.below(6) {
+                source.push_str([">", "<", "+", "-", "+"][rng.below(5)]);
+            }
+            let nested = rng.below(3) == 0;
+            if nested {
+                source.push_str("+[");
+            }
+            source.push('[');
+            for _ in 0..1 + rng.below(8) {
+                source.push_str(pieces[rng.below(pieces.len())]);
+            }
+            source.push(']');
+            if nested {
+                source.push_str(pieces[rng.below(pieces.len())]);
+                source.push(']');
+            }
+            let program = Program::try_from(source.as_str()).unwrap();
+            let (_, _, status) = ExecutionContext::new(&program).run_until(10_000, |_| false);
+            if status == ExecutionStatus::InfiniteLoop(LoopReason::GrowingRun) {
+                num_proven += 1;
+                assert_model_matches(&program, 10_000);
+                let mut ctx = ExecutionContext::with_detectors(&program, Detectors::NONE);
+                let (_, _, status) = ctx.run_until(100_000, |_| false);
+                assert_eq!(status, ExecutionStatus::Running, "{}", source);
             }
-            println!(
-                "length: {}, halt: {}, loop: {}, unknown: {}",
-                length, num_halted, num_looping, num_unknown
-            );
         }
+        assert!(num_proven > 100);
     }
 
     #[test]
@@ -248,4 +255,21 @@ mod tests {
             assert_model_matches(&program, max_steps);
         }
     }
+
+    #[test]
+    fn test_set_to_zero_long() {
+        let max_steps = 50_000;
+        for body in ["+-+", "-+-", "--+", "++-", "+-+-+", "-++--"] {
+            for i in (0..=255).step_by(5) {
+                let source = format!("{}[{}]>+", "+".repeat(i), body);
+                let program = Program::try_from(source.as_str()).unwrap();
+                assert!(program.len() < source.len(), "{} was not folded", source);
+                assert_halting(&program, max_steps);
+            }
+        }
+        for program in ["+[>+[--+]+<-]", "--[+-+]>+[+--+]", "+[-<>]", "+[>+<-+]"] {
+            let program = Program::try_from(program).unwrap();
+            assert_model_matches(&program, max_steps);
+        }
+    }
 }